@@ -0,0 +1,182 @@
+//! Procedural macros for rocket-session-store.
+//!
+//! These are re-exported by `rocket-session-store` with the `macros`
+//! feature, and shouldn't be used directly.
+
+use proc_macro::TokenStream;
+use proc_macro_error::{
+	abort,
+	abort_call_site,
+	proc_macro_error,
+};
+use quote::{
+	quote,
+	quote_spanned,
+};
+use syn::{
+	parse_macro_input,
+	parse_quote,
+	punctuated::Punctuated,
+	spanned::Spanned,
+	Attribute,
+	Data,
+	DeriveInput,
+	LitStr,
+	Path,
+	Token,
+	Type,
+};
+
+/// Checks at compile time that a string literal is a valid cookie name.
+///
+/// It expands to the literal itself, so it can be given wherever the name
+/// of the session cookie is expected. Names that aren't an RFC 6265 token,
+/// such as names with spaces or separators, are a compile error.
+#[proc_macro]
+#[proc_macro_error]
+pub fn session_name(input: TokenStream) -> TokenStream {
+	let name = parse_macro_input!(input as LitStr);
+	if let Err(reason) = check_cookie_name(&name.value()) {
+		abort_call_site!("`{}` is not a valid cookie name: {}", name.value(), reason);
+	}
+	quote!(#name).into()
+}
+
+/// Checks that the name is a token as defined by RFC 6265.
+fn check_cookie_name(name: &str) -> Result<(), String> {
+	if name.is_empty() {
+		return Err("it is empty".into());
+	}
+	match name.chars().find(|&c| !is_token_char(c)) {
+		Some(c) => Err(format!("{:?} isn't allowed", c)),
+		None => Ok(()),
+	}
+}
+
+fn is_token_char(c: char) -> bool {
+	c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c)
+}
+
+/// Implements `SessionValueMarker` for a type, checking that it can be
+/// stored in a session.
+///
+/// Each field is checked to implement `Clone`, so that a field that doesn't
+/// is reported on its own, and the marker requires the type to be `Clone`,
+/// serializable and deserializable.
+#[proc_macro_derive(SessionValue)]
+#[proc_macro_error]
+pub fn derive_session_value(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+	let checks = field_types(&input.data).into_iter().map(|ty| {
+		quote_spanned! {ty.span()=>
+			assert_clone::<#ty>();
+		}
+	});
+	quote! {
+		const _: () = {
+			fn assert_clone<T: ::core::clone::Clone>() {}
+			#[allow(dead_code)]
+			fn check_fields #impl_generics () #where_clause {
+				#(#checks)*
+			}
+		};
+
+		impl #impl_generics ::rocket_session_store::SessionValueMarker for #name #ty_generics
+			#where_clause
+		{
+		}
+	}
+	.into()
+}
+
+/// Derives what a session value needs: `Clone`, `Serialize`, `Deserialize`
+/// and `SessionValue`.
+///
+/// Traits already derived on the type are left out, and the serde derives
+/// go through the serde of rocket, so the application doesn't need to
+/// depend on serde itself.
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn session_value(args: TokenStream, input: TokenStream) -> TokenStream {
+	if !args.is_empty() {
+		abort_call_site!("`session_value` takes no arguments");
+	}
+	let input = parse_macro_input!(input as DeriveInput);
+	let derived = derived_traits(&input.attrs);
+	let is_derived = |name: &str| derived.iter().any(|path| path.is_ident(name));
+	let mut derives: Vec<Path> = Vec::new();
+	if !is_derived("Clone") {
+		derives.push(parse_quote!(::core::clone::Clone));
+	}
+	let serde = !is_derived("Serialize") || !is_derived("Deserialize");
+	if !is_derived("Serialize") {
+		derives.push(parse_quote!(::rocket::serde::Serialize));
+	}
+	if !is_derived("Deserialize") {
+		derives.push(parse_quote!(::rocket::serde::Deserialize));
+	}
+	derives.push(parse_quote!(::rocket_session_store::SessionValue));
+	let has_serde_crate = input
+		.attrs
+		.iter()
+		.any(|attr| attr.path().is_ident("serde") && quote!(#attr).to_string().contains("crate"));
+	let serde_crate =
+		(serde && !has_serde_crate).then(|| quote!(#[serde(crate = "rocket::serde")]));
+	quote! {
+		#[derive(#(#derives),*)]
+		#serde_crate
+		#input
+	}
+	.into()
+}
+
+/// Get the types of the fields of a struct or enum.
+fn field_types(data: &Data) -> Vec<&Type> {
+	match data {
+		Data::Struct(data) => data.fields.iter().map(|field| &field.ty).collect(),
+		Data::Enum(data) => data
+			.variants
+			.iter()
+			.flat_map(|variant| variant.fields.iter().map(|field| &field.ty))
+			.collect(),
+		Data::Union(data) => abort!(
+			data.union_token,
+			"session values can't be unions, as they can't be serialized"
+		),
+	}
+}
+
+/// Get the traits derived by the `derive` attributes, by their last path
+/// segment.
+fn derived_traits(attrs: &[Attribute]) -> Vec<Path> {
+	attrs
+		.iter()
+		.filter(|attr| attr.path().is_ident("derive"))
+		.filter_map(|attr| {
+			attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)
+				.ok()
+		})
+		.flatten()
+		.filter_map(|path| {
+			let ident = &path.segments.last()?.ident;
+			Some(Path::from(ident.clone()))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::check_cookie_name;
+
+	#[test]
+	fn cookie_name_test() {
+		assert!(check_cookie_name("session").is_ok());
+		assert!(check_cookie_name("__Host-session_id.v2").is_ok());
+		assert!(check_cookie_name("").is_err());
+		assert!(check_cookie_name("my session").is_err());
+		assert!(check_cookie_name("session;").is_err());
+		assert!(check_cookie_name("séance").is_err());
+	}
+}
@@ -0,0 +1,211 @@
+//! Routes to inspect and revoke sessions, enabled with the `admin` feature.
+//!
+//! [routes] returns three routes to mount wherever the application keeps its
+//! administration endpoints:
+//!
+//! - `GET /sessions?offset=&limit=` lists the ids of live sessions, sorted,
+//!   along with their total count. `limit` defaults to 50 and is capped at
+//!   1000.
+//! - `GET /sessions/<id>` shows the remaining time to live, the creation time
+//!   and the value of a session, or answers 404.
+//! - `DELETE /sessions/<id>` removes a session, answering 204, or 404 if it
+//!   doesn't exist.
+//!
+//! The ids are the keys under which the store keeps the sessions. They are
+//! the tokens of the cookies with [TokenScheme::Plain](crate::TokenScheme),
+//! and don't reveal the tokens with
+//! [TokenScheme::SplitVerifier](crate::TokenScheme::SplitVerifier).
+//!
+//! [routes] redacts the values, while [routes_with_values] renders them as
+//! JSON. The store has to support [Store::list_ids](crate::Store::list_ids)
+//! and [Store::count](crate::Store::count), otherwise listing answers 501.
+//!
+//! The routes don't check who is calling them. They should be mounted behind
+//! the application's own access control, such as a catch-all guard on the
+//! mount point or a reverse proxy.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{admin, memory::MemoryStore, SessionStore};
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/admin", admin::routes_with_values::<String>());
+//! ```
+
+use std::time::UNIX_EPOCH;
+
+use rocket::{
+	http::{
+		ContentType,
+		Method,
+		Status,
+	},
+	route::{
+		Handler,
+		Outcome,
+	},
+	Data,
+	Request,
+	Route,
+	State,
+};
+use serde::Serialize;
+use serde_json::{
+	json,
+	Value,
+};
+
+use crate::SessionStore;
+
+/// Number of sessions listed when no limit is given.
+const DEFAULT_LIMIT: usize = 50;
+/// Largest number of sessions listed at once.
+const MAX_LIMIT: usize = 1000;
+
+/// Get the admin routes, with the values of sessions redacted.
+pub fn routes<T>() -> Vec<Route>
+where
+	T: Send + Sync + 'static,
+{
+	admin_routes::<T>(|_| json!("[redacted]"))
+}
+
+/// Get the admin routes, with the values of sessions rendered as JSON.
+pub fn routes_with_values<T>() -> Vec<Route>
+where
+	T: Serialize + Send + Sync + 'static,
+{
+	admin_routes::<T>(|value| serde_json::to_value(value).unwrap_or(Value::Null))
+}
+
+fn admin_routes<T>(render: fn(&T) -> Value) -> Vec<Route>
+where
+	T: Send + Sync + 'static,
+{
+	let handler = |op| AdminHandler { op, render };
+	vec![
+		Route::new(Method::Get, "/sessions", handler(Op::List)),
+		Route::new(Method::Get, "/sessions/<id>", handler(Op::Show)),
+		Route::new(Method::Delete, "/sessions/<id>", handler(Op::Revoke)),
+	]
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+	List,
+	Show,
+	Revoke,
+}
+
+struct AdminHandler<T> {
+	op: Op,
+	render: fn(&T) -> Value,
+}
+
+impl<T> Clone for AdminHandler<T> {
+	fn clone(&self) -> Self {
+		Self {
+			op: self.op,
+			render: self.render,
+		}
+	}
+}
+
+impl<T> AdminHandler<T>
+where
+	T: Send + Sync + 'static,
+{
+	async fn list(&self, store: &SessionStore<T>, request: &Request<'_>) -> (Status, Value) {
+		let query = |name| request.query_value::<usize>(name).and_then(Result::ok);
+		let offset = query("offset").unwrap_or(0);
+		let limit = query("limit").unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+		let listed = async {
+			let ids = store.store.list_ids(offset, limit).await?;
+			let total = store.store.count().await?;
+			Ok::<_, crate::SessionError>((ids, total))
+		};
+		match listed.await {
+			Ok((ids, total)) => (
+				Status::Ok,
+				json!({ "sessions": ids, "offset": offset, "limit": limit, "total": total }),
+			),
+			Err(_) => (
+				Status::NotImplemented,
+				json!({ "error": "the store can't list its sessions" }),
+			),
+		}
+	}
+
+	async fn show(&self, store: &SessionStore<T>, id: &str) -> (Status, Value) {
+		let session = match store.store.get_with_expiry(id).await {
+			Ok(Some(session)) => session,
+			Ok(None) => return (Status::NotFound, json!({ "error": "no such session" })),
+			Err(_) => return error(),
+		};
+		let (value, ttl) = session;
+		let created_at = store
+			.store
+			.created_at(id)
+			.await
+			.ok()
+			.flatten()
+			.and_then(|time| {
+				time.duration_since(UNIX_EPOCH)
+					.ok()
+					.map(|since| since.as_secs())
+			});
+		let body = json!({
+			"id": id,
+			"ttl_secs": ttl.as_secs(),
+			"created_at": created_at,
+			"value": (self.render)(&value),
+		});
+		(Status::Ok, body)
+	}
+
+	async fn revoke(&self, store: &SessionStore<T>, id: &str) -> (Status, Value) {
+		match store.store.remove(id).await {
+			Ok(true) => {
+				store.meter().active_sessions(-1.0);
+				(Status::NoContent, Value::Null)
+			}
+			Ok(false) => (Status::NotFound, json!({ "error": "no such session" })),
+			Err(_) => error(),
+		}
+	}
+}
+
+fn error() -> (Status, Value) {
+	(
+		Status::InternalServerError,
+		json!({ "error": "the session store failed" }),
+	)
+}
+
+#[rocket::async_trait]
+impl<T> Handler for AdminHandler<T>
+where
+	T: Send + Sync + 'static,
+{
+	async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+		let Some(store) = State::<SessionStore<T>>::get(request.rocket()) else {
+			log::error!("the admin routes are mounted without the session store fairing");
+			return Outcome::from(request, Status::InternalServerError);
+		};
+		let id = request.param::<&str>(1).and_then(Result::ok);
+		let (status, body) = match (self.op, id) {
+			(Op::List, _) => self.list(store, request).await,
+			(Op::Show, Some(id)) => self.show(store, id).await,
+			(Op::Revoke, Some(id)) => self.revoke(store, id).await,
+			(_, None) => return Outcome::forward(data, Status::NotFound),
+		};
+		if status == Status::NoContent {
+			return Outcome::from(request, status);
+		}
+		Outcome::from(request, (status, (ContentType::JSON, body.to_string())))
+	}
+}
@@ -0,0 +1,301 @@
+//! Sessions holding values of several types.
+//!
+//! A [SessionStore] keeps a single value type per session, so storing
+//! unrelated values, such as a user and a shopping cart, would otherwise take
+//! one store and one cookie for each of them. This module provides
+//! [AnySessionStore], whose sessions hold a [TypeMap] with at most one value
+//! of each type, all behind a single cookie.
+//!
+//! Values are kept as [Any], so they can't be serialized on their own. This
+//! works with stores keeping values in memory, such as
+//! [MemoryStore](crate::memory::MemoryStore). For stores that need to
+//! serialize them, like the redis store, each type is registered with
+//! [register_session_type] and the map is encoded with [TypeMapCodec].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use rocket::post;
+//! # use rocket_session_store::{any::AnySession, SessionResult};
+//! #[derive(Clone)]
+//! struct Cart(Vec<String>);
+//!
+//! #[post("/cart/<item>")]
+//! async fn add_to_cart(item: String, session: AnySession<'_>) -> SessionResult<()> {
+//!     let mut cart = session.get_value::<Cart>().await?.unwrap_or(Cart(Vec::new()));
+//!     cart.0.push(item);
+//!     session.set_value(cart).await
+//! }
+//! ```
+
+use std::{
+	any::{
+		Any,
+		TypeId,
+	},
+	collections::HashMap,
+	sync::{
+		Arc,
+		OnceLock,
+		RwLock,
+	},
+};
+
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+use serde_json::Value;
+
+use crate::{
+	codec::SessionCodec,
+	Session,
+	SessionError,
+	SessionResult,
+	SessionStore,
+};
+
+/// A set of values of different types, holding at most one value per type.
+#[derive(Clone, Default)]
+pub struct TypeMap(HashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl TypeMap {
+	/// Create an empty map.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Get the value of the given type.
+	pub fn get<U: Any>(&self) -> Option<&U> {
+		self.0.get(&TypeId::of::<U>())?.downcast_ref()
+	}
+
+	/// Insert a value, replacing the previous value of its type.
+	pub fn insert<U: Any + Send + Sync>(&mut self, value: U) {
+		self.0.insert(TypeId::of::<U>(), Arc::new(value));
+	}
+
+	/// Remove the value of the given type.
+	pub fn remove<U: Any>(&mut self) {
+		self.0.remove(&TypeId::of::<U>());
+	}
+
+	/// Whether the map holds no value.
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+}
+
+/// A session store whose sessions hold values of several types.
+pub type AnySessionStore = SessionStore<TypeMap>;
+
+/// The request guard of an [AnySessionStore].
+pub type AnySession<'s> = Session<'s, TypeMap>;
+
+impl<'s> Session<'s, TypeMap> {
+	/// Get the value of the given type from the session.
+	///
+	/// Returns [None] if the session holds no value of that type.
+	pub async fn get_value<U: Any + Clone>(&self) -> SessionResult<Option<U>> {
+		Ok(self
+			.get_opt()
+			.await?
+			.and_then(|map| map.get::<U>().cloned()))
+	}
+
+	/// Store a value in the session, replacing the previous value of its
+	/// type and keeping the values of other types.
+	///
+	/// The map is read and written back, so concurrent requests setting
+	/// values in the same session may overwrite each other.
+	pub async fn set_value<U: Any + Send + Sync>(&self, value: U) -> SessionResult<()> {
+		let mut map = self.get_opt().await?.unwrap_or_default();
+		map.insert(value);
+		self.set(map).await
+	}
+
+	/// Remove the value of the given type from the session, keeping the
+	/// values of other types.
+	pub async fn remove_value<U: Any>(&self) -> SessionResult<()> {
+		if let Some(mut map) = self.get_opt().await? {
+			map.remove::<U>();
+			self.set(map).await?;
+		}
+		Ok(())
+	}
+}
+
+/// Turns a value of a registered type into JSON.
+type Serializer = Box<dyn Fn(&dyn Any) -> SessionResult<Value> + Send + Sync>;
+
+/// Turns JSON back into a value of a registered type.
+type Deserializer = Box<dyn Fn(Value) -> SessionResult<Arc<dyn Any + Send + Sync>> + Send + Sync>;
+
+struct RegisteredType {
+	name: String,
+	serialize: Serializer,
+	deserialize: Deserializer,
+}
+
+/// The types a [TypeMap] can hold when it is serialized.
+///
+/// Each type is registered under a name, which is what is stored along
+/// with its value, since a [TypeId] isn't stable between builds.
+#[derive(Default)]
+pub struct SessionTypeRegistry {
+	by_id: HashMap<TypeId, RegisteredType>,
+	by_name: HashMap<String, TypeId>,
+}
+
+impl SessionTypeRegistry {
+	/// Create an empty registry.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Register a type under its [type name](std::any::type_name).
+	pub fn register<U>(&mut self)
+	where
+		U: Serialize + DeserializeOwned + Send + Sync + 'static,
+	{
+		self.register_as::<U>(std::any::type_name::<U>());
+	}
+
+	/// Register a type under the given name.
+	///
+	/// The name is stored with the values, so it should stay the same when
+	/// the type is renamed or moved. Registering a type or a name again
+	/// replaces the previous registration.
+	pub fn register_as<U>(&mut self, name: impl Into<String>)
+	where
+		U: Serialize + DeserializeOwned + Send + Sync + 'static,
+	{
+		let name = name.into();
+		let serialize: Serializer = Box::new(|value| {
+			let value = value.downcast_ref::<U>().ok_or(SessionError::Other)?;
+			serde_json::to_value(value).map_err(|e| {
+				log::warn!("could not serialize the session value: {}", e);
+				SessionError::Other
+			})
+		});
+		let deserialize: Deserializer = Box::new(|value| {
+			let value: U = serde_json::from_value(value).map_err(|e| {
+				log::warn!("could not deserialize the session value: {}", e);
+				SessionError::Other
+			})?;
+			Ok(Arc::new(value))
+		});
+		if let Some(previous) = self.by_id.remove(&TypeId::of::<U>()) {
+			self.by_name.remove(&previous.name);
+		}
+		self.by_name.insert(name.clone(), TypeId::of::<U>());
+		self.by_id.insert(
+			TypeId::of::<U>(),
+			RegisteredType {
+				name,
+				serialize,
+				deserialize,
+			},
+		);
+	}
+
+	/// Serialize a map as a JSON object of the values by the name of their
+	/// type.
+	///
+	/// Fails if the map holds a value of a type that isn't registered.
+	pub fn serialize(&self, map: &TypeMap) -> SessionResult<Value> {
+		let mut object = serde_json::Map::new();
+		for (id, value) in &map.0 {
+			let Some(registered) = self.by_id.get(id) else {
+				log::warn!("a session holds a value of a type that isn't registered");
+				return Err(SessionError::Other);
+			};
+			object.insert(registered.name.clone(), (registered.serialize)(&**value)?);
+		}
+		Ok(Value::Object(object))
+	}
+
+	/// Deserialize a map produced by [SessionTypeRegistry::serialize].
+	///
+	/// Values of types that aren't registered anymore are left out.
+	pub fn deserialize(&self, value: Value) -> SessionResult<TypeMap> {
+		let Value::Object(object) = value else {
+			log::warn!("a serialized type map isn't an object");
+			return Err(SessionError::Other);
+		};
+		let mut map = TypeMap::new();
+		for (name, value) in object {
+			let Some(id) = self.by_name.get(&name) else {
+				log::warn!("leaving out a session value of the unknown type {}", name);
+				continue;
+			};
+			map.0.insert(*id, (self.by_id[id].deserialize)(value)?);
+		}
+		Ok(map)
+	}
+
+	/// Get the registry used by [TypeMapCodec].
+	pub fn global() -> &'static RwLock<SessionTypeRegistry> {
+		static REGISTRY: OnceLock<RwLock<SessionTypeRegistry>> = OnceLock::new();
+		REGISTRY.get_or_init(Default::default)
+	}
+}
+
+/// Register a type in the [global](SessionTypeRegistry::global) registry,
+/// so that [TypeMapCodec] can serialize it.
+///
+/// Types should be registered before the rocket is launched, as sessions
+/// holding values of other types can't be stored.
+pub fn register_session_type<U>()
+where
+	U: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+	SessionTypeRegistry::global()
+		.write()
+		.expect("session type registry poisoned")
+		.register::<U>();
+}
+
+/// A codec serializing a [TypeMap] to JSON, with the types of the
+/// [global](SessionTypeRegistry::global) registry.
+///
+/// It works with any [RawStore](crate::codec::RawStore), such as the redis
+/// one.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use rocket_session_store::{
+/// #     any::{register_session_type, AnySessionStore, TypeMapCodec},
+/// #     codec::CodecStore,
+/// #     memory::MemoryRawStore,
+/// # };
+/// register_session_type::<String>();
+/// let store: AnySessionStore = AnySessionStore::new(
+///     CodecStore::new(TypeMapCodec, MemoryRawStore::new()),
+///     "token",
+///     Duration::from_secs(3600),
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TypeMapCodec;
+
+impl SessionCodec<TypeMap> for TypeMapCodec {
+	fn encode(&self, map: &TypeMap) -> SessionResult<Vec<u8>> {
+		let registry = SessionTypeRegistry::global()
+			.read()
+			.expect("session type registry poisoned");
+		let value = registry.serialize(map)?;
+		serde_json::to_vec(&value).map_err(|_| SessionError::Other)
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<TypeMap> {
+		let value = serde_json::from_slice(bytes).map_err(|e| {
+			log::warn!("could not deserialize the session value: {}", e);
+			SessionError::Other
+		})?;
+		SessionTypeRegistry::global()
+			.read()
+			.expect("session type registry poisoned")
+			.deserialize(value)
+	}
+}
@@ -0,0 +1,170 @@
+//! Logging users in and out, enabled with the `auth` feature.
+//!
+//! Sessions holding a user implement [UserSession], which gives their
+//! [Session] the methods of [SessionAuth]:
+//!
+//! - [login](SessionAuth::login) moves the session to a new token, then
+//!   stores the user in it.
+//! - [logout](SessionAuth::logout) removes the session and the cookie.
+//! - [current_user](SessionAuth::current_user) gets the user, or
+//!   [Unauthorized], which answers 401, when nobody is logged in.
+//!
+//! Logging in regenerates the token before the user is stored, so a token
+//! planted by an attacker before the login, known as session fixation, never
+//! refers to the logged in session. Storing the user first and regenerating
+//! after would leave it under the old token for a moment, and for good if
+//! the regeneration failed.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::{get, post};
+//! # use rocket_session_store::{
+//! #     auth::{SessionAuth, Unauthorized, UserSession},
+//! #     memory::MemoryStore,
+//! #     Session,
+//! #     SessionResult,
+//! #     SessionStore,
+//! # };
+//! #[derive(Clone, Default)]
+//! struct Visit {
+//!     user_id: Option<u64>,
+//!     cart: Vec<String>,
+//! }
+//!
+//! impl UserSession for Visit {
+//!     type UserId = u64;
+//!
+//!     fn user_id(&self) -> Option<u64> {
+//!         self.user_id
+//!     }
+//!
+//!     fn with_user_id(self, user_id: u64) -> Self {
+//!         Self {
+//!             user_id: Some(user_id),
+//!             ..self
+//!         }
+//!     }
+//! }
+//!
+//! #[post("/login/<user_id>")]
+//! async fn login(user_id: u64, session: Session<'_, Visit>) -> SessionResult<()> {
+//!     // Once the credentials are checked.
+//!     session.login(user_id).await
+//! }
+//!
+//! #[get("/me")]
+//! async fn me(session: Session<'_, Visit>) -> Result<String, Unauthorized> {
+//!     Ok(session.current_user().await?.to_string())
+//! }
+//!
+//! let store: SessionStore<Visit> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/", rocket::routes![login, me]);
+//! ```
+
+use rocket::{
+	http::Status,
+	request::Request,
+	response::{
+		self,
+		Responder,
+	},
+};
+use thiserror::Error;
+
+use crate::{
+	Session,
+	SessionResult,
+};
+
+/// A session value which can hold a logged in user.
+///
+/// The default value is the session of a visitor who isn't logged in.
+pub trait UserSession: Default {
+	/// The type identifying a user.
+	type UserId;
+
+	/// Get the logged in user, if any.
+	fn user_id(&self) -> Option<Self::UserId>;
+
+	/// Get the value with the user logged in.
+	///
+	/// The rest of the value can be kept, such as the cart of a visitor
+	/// logging in.
+	fn with_user_id(self, user_id: Self::UserId) -> Self;
+}
+
+/// Error returned by [SessionAuth::current_user] when nobody is logged in,
+/// answering 401 as a response.
+#[derive(Error, Debug)]
+#[error("not logged in")]
+pub struct Unauthorized;
+
+impl<'r> Responder<'r, 'static> for Unauthorized {
+	fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'static> {
+		Err(Status::Unauthorized)
+	}
+}
+
+/// Logging in and out with a [Session], see the [module](self)
+/// documentation.
+#[rocket::async_trait]
+pub trait SessionAuth {
+	/// The type identifying a user.
+	type UserId;
+
+	/// Logs a user in, moving the session to a new token before storing the
+	/// user in it.
+	///
+	/// The current value is kept, with the user set on it, or the default
+	/// value if the session has none. If the token can't be regenerated, the
+	/// user isn't stored.
+	async fn login(&self, user_id: Self::UserId) -> SessionResult<()>;
+
+	/// Logs the user out, removing the session and the cookie.
+	///
+	/// Sessions read through a bearer token have no cookie to remove, and
+	/// don't get their token back in the response.
+	async fn logout(&self) -> SessionResult<()>;
+
+	/// Get the logged in user.
+	///
+	/// Fails with [Unauthorized] when there is no session or it holds no
+	/// user, and when the store can't be reached.
+	async fn current_user(&self) -> Result<Self::UserId, Unauthorized>;
+}
+
+#[rocket::async_trait]
+impl<'s, T> SessionAuth for Session<'s, T>
+where
+	T: UserSession + Send + Sync + Clone + 'static,
+	T::UserId: Send,
+{
+	type UserId = T::UserId;
+
+	async fn login(&self, user_id: T::UserId) -> SessionResult<()> {
+		let value = self.get_opt().await?.unwrap_or_default();
+		self.regenerate_token().await?;
+		self.set(value.with_user_id(user_id)).await
+	}
+
+	async fn logout(&self) -> SessionResult<()> {
+		self.remove().await?;
+		self.state.clear_cookie();
+		Ok(())
+	}
+
+	async fn current_user(&self) -> Result<T::UserId, Unauthorized> {
+		match self.get_opt().await {
+			Ok(value) => value.and_then(|value| value.user_id()).ok_or(Unauthorized),
+			Err(e) => {
+				log::warn!("could not read the session, answering 401: {}", e);
+				Err(Unauthorized)
+			}
+		}
+	}
+}
@@ -0,0 +1,109 @@
+//! Sessions for API clients, without cookies.
+//!
+//! A [BearerSession] reads the token from the `Authorization: Bearer`
+//! header rather than from the session cookie, and sends a new or
+//! regenerated token back in the `X-Session-Token` response header instead
+//! of `Set-Cookie`. It uses the same [SessionStore](crate::SessionStore) as
+//! [Session], so browsers and API clients can share sessions.
+//!
+//! When a request sends both a bearer token and a session cookie, the
+//! bearer token is used and a warning is logged. Without a bearer token,
+//! the cookie is read as usual, but the token is still sent back in the
+//! header. Bearer tokens holding characters that a cookie can't hold are
+//! ignored.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::get;
+//! # use rocket_session_store::{
+//! #     bearer::BearerSession,
+//! #     memory::MemoryStore,
+//! #     SessionResult,
+//! #     SessionStore,
+//! # };
+//! #[get("/api/profile")]
+//! async fn profile(session: BearerSession<'_, String>) -> SessionResult<String> {
+//!     session.get().await
+//! }
+//!
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/", rocket::routes![profile]);
+//! ```
+
+use std::ops::Deref;
+
+use rocket::{
+	request::{
+		FromRequest,
+		Outcome,
+	},
+	Request,
+};
+
+use crate::{
+	is_cookie_octet,
+	Session,
+	TokenSource,
+};
+
+/// The response header carrying the token of a [BearerSession].
+pub const TOKEN_HEADER: &str = "X-Session-Token";
+
+/// A request guard retrieving the session from the `Authorization: Bearer`
+/// header.
+///
+/// It works exactly like [Session], which it dereferences to, except for
+/// where the token is read from and sent back to. The first session guard
+/// of a request decides where the token comes from, so a route shouldn't
+/// mix [Session] and [BearerSession] guards of the same store.
+pub struct BearerSession<'s, T: 'static> {
+	session: Session<'s, T>,
+}
+
+impl<'s, T: 'static> BearerSession<'s, T> {
+	/// Get back the underlying session.
+	pub fn into_inner(self) -> Session<'s, T> {
+		self.session
+	}
+}
+
+impl<'s, T: 'static> Deref for BearerSession<'s, T> {
+	type Target = Session<'s, T>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.session
+	}
+}
+
+/// Get the token of an `Authorization` header using the `Bearer` scheme.
+///
+/// Tokens are held to the characters allowed in the session cookie, as they
+/// are sent back in a header.
+pub(crate) fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+	let header = request.headers().get_one("Authorization")?;
+	let (scheme, token) = header.split_once(' ')?;
+	let token = token.trim();
+	let valid = !token.is_empty() && token.bytes().all(is_cookie_octet);
+	(scheme.eq_ignore_ascii_case("Bearer") && valid).then_some(token)
+}
+
+#[rocket::async_trait]
+impl<'r, 's, T> FromRequest<'r> for BearerSession<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+	'r: 's,
+{
+	type Error = ();
+
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let session = Session::from_source(request, TokenSource::Bearer)
+			.await
+			.expect("Session store must be set in fairing");
+		Outcome::Success(Self { session })
+	}
+}
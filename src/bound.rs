@@ -0,0 +1,169 @@
+//! Sessions bound to the browser that created them.
+//!
+//! A [BoundSession] stores a hash of the `User-Agent` header of the request
+//! setting the value along with it, and treats a request sending the same
+//! token with another `User-Agent` as a stolen token: the session is dropped
+//! and the request starts a new, empty session under a new token. The
+//! request isn't rejected, so the check can't be probed to learn which
+//! `User-Agent` a session expects.
+//!
+//! This makes a stolen cookie harder to replay, but the `User-Agent` is
+//! chosen by the client, so it is no substitute for keeping tokens secret.
+//!
+//! Values are kept in a [Bound] wrapper, stored as
+//! `{"__ua_hash": ..., "value": ...}` by serializing stores, so the session
+//! store holds `Bound<T>` rather than `T`.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::get;
+//! # use rocket_session_store::{
+//! #     bound::{BoundSession, BoundSessionStore},
+//! #     memory::MemoryStore,
+//! #     SessionResult,
+//! # };
+//! #[get("/profile")]
+//! async fn profile(session: BoundSession<'_, String>) -> SessionResult<Option<String>> {
+//!     session.get().await
+//! }
+//!
+//! let store: BoundSessionStore<String> =
+//!     BoundSessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/", rocket::routes![profile]);
+//! ```
+
+use std::time::Duration;
+
+use rocket::{
+	request::{
+		FromRequest,
+		Outcome,
+	},
+	serde::{
+		Deserialize,
+		Serialize,
+	},
+	Request,
+};
+use sha2::{
+	Digest,
+	Sha256,
+};
+
+use crate::{
+	Session,
+	SessionResult,
+	SessionStore,
+};
+
+/// A session value along with a hash of the `User-Agent` it is bound to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Bound<T> {
+	#[serde(rename = "__ua_hash")]
+	ua_hash: String,
+	value: T,
+}
+
+impl<T> Bound<T> {
+	/// Get the value.
+	pub fn value(&self) -> &T {
+		&self.value
+	}
+
+	/// Get back the value.
+	pub fn into_value(self) -> T {
+		self.value
+	}
+}
+
+/// A session store whose sessions are bound to a `User-Agent`.
+pub type BoundSessionStore<T> = SessionStore<Bound<T>>;
+
+/// The request guard of a [BoundSessionStore].
+///
+/// It works like [Session], checking the `User-Agent` of the request
+/// against the one of the request that set the value.
+pub struct BoundSession<'s, T: 'static> {
+	session: Session<'s, Bound<T>>,
+	ua_hash: String,
+}
+
+impl<'s, T> BoundSession<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	/// Get the underlying session, holding the value with its binding.
+	pub fn session(&self) -> &Session<'s, Bound<T>> {
+		&self.session
+	}
+
+	/// Get the current value of the session.
+	///
+	/// If the value was set with another `User-Agent`, the session is
+	/// removed, a new token is issued and [None] is returned.
+	pub async fn get(&self) -> SessionResult<Option<T>> {
+		match self.session.get_opt().await? {
+			Some(bound) if bound.ua_hash == self.ua_hash => Ok(Some(bound.value)),
+			Some(_) => {
+				log::warn!("a session was sent with another User-Agent, starting a new session");
+				self.session
+					.regenerate_token_with_migration(|_| None)
+					.await?;
+				Ok(None)
+			}
+			None => Ok(None),
+		}
+	}
+
+	/// Set the value of the session, binding it to the `User-Agent` of the
+	/// request.
+	pub async fn set(&self, value: T) -> SessionResult<()> {
+		let bound = Bound {
+			ua_hash: self.ua_hash.clone(),
+			value,
+		};
+		self.session.set(bound).await
+	}
+
+	/// Extend the session by the duration of the store, returning its
+	/// remaining time to live.
+	pub async fn touch(&self) -> SessionResult<Option<Duration>> {
+		self.session.touch().await
+	}
+
+	/// Remove the session from the store, returning whether there was a live
+	/// session to remove.
+	pub async fn remove(&self) -> SessionResult<bool> {
+		self.session.remove().await
+	}
+}
+
+/// Hash a `User-Agent`, a missing header hashing as an empty one.
+fn ua_hash(user_agent: Option<&str>) -> String {
+	Sha256::digest(user_agent.unwrap_or_default().as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
+#[rocket::async_trait]
+impl<'r, 's, T> FromRequest<'r> for BoundSession<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+	'r: 's,
+{
+	type Error = ();
+
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let session = Session::of(request)
+			.await
+			.expect("Session store must be set in fairing");
+		let ua_hash = ua_hash(request.headers().get_one("User-Agent"));
+		Outcome::Success(Self { session, ua_hash })
+	}
+}
@@ -0,0 +1,712 @@
+//! Stores of bytes, and codecs turning session values into bytes.
+//!
+//! This module splits a store in two layers. A [RawStore] keeps sessions as
+//! bytes and only deals with the backend, while a [SessionCodec] turns values
+//! into bytes and back. [CodecStore] puts the two together into a [Store] of
+//! values, so a new backend can be written without caring about serde, and a
+//! wrapper working on bytes, such as encryption or compression, runs exactly
+//! once whatever the value type.
+//!
+//! [VersionedCodec] stores a version along with each value, and upgrades
+//! values stored by older versions of an application. With the `bincode`
+//! feature, [BincodeCodec] stores values in a compact binary format, and
+//! with the `redis-msgpack` feature, [MessagePackCodec] stores them as
+//! MessagePack. With the `compression` feature, [CompressedCodec] compresses
+//! the values of another codec once they are large enough to benefit from it.
+//! [HashedCodec] stores the hash of each value, so it is read without
+//! decoding the value.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{
+//! #     codec::{CodecStore, JsonCodec},
+//! #     memory::MemoryRawStore,
+//! #     SessionStore,
+//! # };
+//! let store: SessionStore<String> = SessionStore::new(
+//!     CodecStore::new(JsonCodec, MemoryRawStore::new()),
+//!     "token",
+//!     Duration::from_secs(3600),
+//! );
+//! ```
+
+use std::{
+	hash::Hash,
+	marker::PhantomData,
+	time::{
+		Duration,
+		SystemTime,
+	},
+};
+
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+
+use crate::{
+	hash_value,
+	ExpiredCleanup,
+	SessionError,
+	SessionResult,
+	Store,
+};
+
+/// A store keeping session values as bytes.
+///
+/// This is implemented for every [Store] of `Vec<u8>`, so a backend only
+/// has to implement [Store] with bytes as its value.
+pub trait RawStore: Store<Value = Vec<u8>> {}
+
+impl<S: Store<Value = Vec<u8>> + ?Sized> RawStore for S {}
+
+/// Conversion of session values to and from bytes.
+pub trait SessionCodec<T>: Send + Sync {
+	/// Turn a value into bytes.
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>>;
+
+	/// Turn bytes produced by [SessionCodec::encode] back into a value.
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T>;
+
+	/// Turn bytes back into a value, along with whether they are in an older
+	/// format and should be encoded again.
+	///
+	/// [CodecStore] decodes values with this, and writes the value back,
+	/// keeping its remaining time to live, when it is outdated. The default
+	/// implementation decodes with [SessionCodec::decode] and never reports
+	/// the value as outdated.
+	fn decode_versioned(&self, bytes: &[u8]) -> SessionResult<(T, bool)> {
+		Ok((self.decode(bytes)?, false))
+	}
+
+	/// Get the hash of the value stored in the bytes, without decoding it.
+	///
+	/// [CodecStore] returns it from [Store::value_hash]. Codecs that don't
+	/// store a hash return an error, which is what the default
+	/// implementation does.
+	fn value_hash(&self, _bytes: &[u8]) -> SessionResult<u64> {
+		Err(SessionError::Other)
+	}
+}
+
+/// A codec serializing values to JSON with serde.
+///
+/// This is the format used by the redis store.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl<T> SessionCodec<T> for JsonCodec
+where
+	T: Serialize + DeserializeOwned,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		serde_json::to_vec(value).map_err(|e| {
+			log::warn!("could not serialize the session value: {}", e);
+			SessionError::Other
+		})
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		serde_json::from_slice(bytes).map_err(|e| {
+			log::warn!("could not deserialize the session value: {}", e);
+			SessionError::Other
+		})
+	}
+}
+
+/// A codec encoding values with bincode, enabled with the `bincode` feature.
+///
+/// Values take much less space than with [JsonCodec], as field names aren't
+/// stored, but the format isn't self-describing: changing the fields of the
+/// value type makes stored sessions fail to decode. Values are encoded with
+/// the standard configuration of bincode.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rocket_session_store::{
+/// #     codec::{BincodeCodec, CodecStore},
+/// #     memory::MemoryRawStore,
+/// #     SessionStore,
+/// # };
+/// #[derive(bincode::Encode, bincode::Decode)]
+/// struct UserSession {
+///     user_id: u64,
+///     role: u8,
+/// }
+///
+/// let store: SessionStore<UserSession> = SessionStore::new(
+///     CodecStore::new(BincodeCodec, MemoryRawStore::new()),
+///     "token",
+///     Duration::from_secs(3600),
+/// );
+/// ```
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T> SessionCodec<T> for BincodeCodec
+where
+	T: bincode::Encode + bincode::Decode<()>,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		bincode::encode_to_vec(value, bincode::config::standard()).map_err(|e| {
+			log::warn!("could not serialize the session value: {}", e);
+			SessionError::Other
+		})
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		let (value, _) =
+			bincode::decode_from_slice(bytes, bincode::config::standard()).map_err(|e| {
+				log::warn!("could not deserialize the session value: {}", e);
+				SessionError::Other
+			})?;
+		Ok(value)
+	}
+}
+
+/// A codec serializing values to MessagePack with serde, enabled with the
+/// `redis-msgpack` feature.
+///
+/// Values are smaller than with [JsonCodec] and work with the same serde
+/// derives. Structs are stored with their field names, so fields can be
+/// added or reordered like with JSON.
+#[cfg(feature = "rmp-serde")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "rmp-serde")]
+impl<T> SessionCodec<T> for MessagePackCodec
+where
+	T: Serialize + DeserializeOwned,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		rmp_serde::to_vec_named(value).map_err(|e| {
+			log::warn!("could not serialize the session value: {}", e);
+			SessionError::Other
+		})
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		rmp_serde::from_slice(bytes).map_err(|e| {
+			log::warn!("could not deserialize the session value: {}", e);
+			SessionError::Other
+		})
+	}
+}
+
+/// A function upgrading a value stored with an older version.
+///
+/// It receives the version of the stored value and its data, and returns
+/// [None] when the value can't be upgraded.
+pub type Upgrader<T> = fn(u32, serde_json::Value) -> Option<T>;
+
+/// A codec serializing values to JSON in an envelope carrying a version.
+///
+/// Values are stored as `{"v": version, "data": value}`. Values stored with
+/// an older version are handed to the upgrader, and [CodecStore] writes the
+/// upgraded value back. Values stored without an envelope, such as by
+/// [JsonCodec] before switching to this codec, are treated as version 0.
+/// Values that can't be decoded or upgraded are an error, as with
+/// [JsonCodec].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use serde_json::Value;
+/// # use rocket_session_store::{
+/// #     codec::{CodecStore, VersionedCodec},
+/// #     memory::MemoryRawStore,
+/// #     SessionStore,
+/// # };
+/// // Version 1 stored the name of the user, version 2 stores the name and
+/// // the role.
+/// fn upgrade(version: u32, data: Value) -> Option<(String, String)> {
+///     match version {
+///         1 => Some((serde_json::from_value(data).ok()?, "user".into())),
+///         _ => None,
+///     }
+/// }
+///
+/// let store: SessionStore<(String, String)> = SessionStore::new(
+///     CodecStore::new(VersionedCodec::new(2, upgrade), MemoryRawStore::new()),
+///     "token",
+///     Duration::from_secs(3600),
+/// );
+/// ```
+pub struct VersionedCodec<T> {
+	version: u32,
+	upgrader: Upgrader<T>,
+}
+
+impl<T> VersionedCodec<T> {
+	/// Create a codec storing values with the given version.
+	pub fn new(version: u32, upgrader: Upgrader<T>) -> Self {
+		Self { version, upgrader }
+	}
+
+	/// Get the version and data of stored bytes.
+	fn open(bytes: &[u8]) -> SessionResult<(u32, serde_json::Value)> {
+		let value: serde_json::Value = serde_json::from_slice(bytes).map_err(|e| {
+			log::warn!("could not deserialize the session value: {}", e);
+			SessionError::Other
+		})?;
+		match value {
+			serde_json::Value::Object(mut envelope) if is_envelope(&envelope) => {
+				let version = envelope["v"].as_u64().unwrap_or_default() as u32;
+				Ok((version, envelope.remove("data").unwrap_or_default()))
+			}
+			value => Ok((0, value)),
+		}
+	}
+}
+
+fn is_envelope(object: &serde_json::Map<String, serde_json::Value>) -> bool {
+	object.len() == 2 && object.contains_key("data") && object.get("v").is_some_and(|v| v.is_u64())
+}
+
+impl<T> SessionCodec<T> for VersionedCodec<T>
+where
+	T: Serialize + DeserializeOwned,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		let envelope = serde_json::json!({ "v": self.version, "data": value });
+		JsonCodec.encode(&envelope)
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		self.decode_versioned(bytes).map(|(value, _)| value)
+	}
+
+	fn decode_versioned(&self, bytes: &[u8]) -> SessionResult<(T, bool)> {
+		let (version, data) = Self::open(bytes)?;
+		if version >= self.version {
+			let value = serde_json::from_value(data).map_err(|e| {
+				log::warn!("could not deserialize the session value: {}", e);
+				SessionError::Other
+			})?;
+			return Ok((value, false));
+		}
+		let value = (self.upgrader)(version, data).ok_or_else(|| {
+			log::warn!(
+				"could not upgrade the session value from version {} to {}",
+				version,
+				self.version
+			);
+			SessionError::Other
+		})?;
+		Ok((value, true))
+	}
+}
+
+/// The prefix of values stored as encoded by the inner codec.
+#[cfg(feature = "compression")]
+const UNCOMPRESSED: u8 = 0;
+/// The prefix of values compressed with DEFLATE.
+#[cfg(feature = "compression")]
+const DEFLATED: u8 = 1;
+
+/// A codec compressing the values encoded by another codec, enabled with the
+/// `compression` feature.
+///
+/// Values encoded in more than `threshold` bytes are compressed with
+/// DEFLATE, while smaller ones are stored as is, as compressing them costs
+/// time and rarely saves space. Compressed values larger than the original
+/// are stored as is too. Each value starts with a byte telling whether it is
+/// compressed, so both kinds are decoded, and the threshold can change
+/// without invalidating the sessions. Values stored before switching to this
+/// codec lack that byte and fail to decode.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rocket_session_store::{
+/// #     codec::{CodecStore, CompressedCodec, JsonCodec},
+/// #     memory::MemoryRawStore,
+/// #     SessionStore,
+/// # };
+/// let store: SessionStore<String> = SessionStore::new(
+///     CodecStore::new(CompressedCodec::new(JsonCodec, 512), MemoryRawStore::new()),
+///     "token",
+///     Duration::from_secs(3600),
+/// );
+/// ```
+#[cfg(feature = "compression")]
+#[derive(Clone, Copy, Debug)]
+pub struct CompressedCodec<C> {
+	inner: C,
+	threshold: usize,
+}
+
+#[cfg(feature = "compression")]
+impl<C> CompressedCodec<C> {
+	/// Create a codec compressing the values encoded by `inner` in more than
+	/// `threshold` bytes.
+	pub fn new(inner: C, threshold: usize) -> Self {
+		Self { inner, threshold }
+	}
+
+	/// Get the bytes encoded by the inner codec.
+	fn open(bytes: &[u8]) -> SessionResult<std::borrow::Cow<'_, [u8]>> {
+		use std::io::Read;
+
+		match bytes.split_first() {
+			Some((&UNCOMPRESSED, rest)) => Ok(rest.into()),
+			Some((&DEFLATED, rest)) => {
+				let mut inflated = Vec::new();
+				flate2::read::DeflateDecoder::new(rest)
+					.read_to_end(&mut inflated)
+					.map_err(|e| {
+						log::warn!("could not decompress the session value: {}", e);
+						SessionError::Other
+					})?;
+				Ok(inflated.into())
+			}
+			_ => {
+				log::warn!("could not decompress the session value: unknown format");
+				Err(SessionError::Other)
+			}
+		}
+	}
+}
+
+#[cfg(feature = "compression")]
+impl<C, T> SessionCodec<T> for CompressedCodec<C>
+where
+	C: SessionCodec<T>,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		use std::io::Write;
+
+		let encoded = self.inner.encode(value)?;
+		if encoded.len() > self.threshold {
+			let mut encoder = flate2::write::DeflateEncoder::new(
+				vec![DEFLATED],
+				flate2::Compression::default(),
+			);
+			let compressed = encoder
+				.write_all(&encoded)
+				.and_then(|_| encoder.finish())
+				.map_err(|e| {
+					log::warn!("could not compress the session value: {}", e);
+					SessionError::Other
+				})?;
+			if compressed.len() <= encoded.len() {
+				return Ok(compressed);
+			}
+		}
+		let mut bytes = Vec::with_capacity(1 + encoded.len());
+		bytes.push(UNCOMPRESSED);
+		bytes.extend_from_slice(&encoded);
+		Ok(bytes)
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		self.inner.decode(&Self::open(bytes)?)
+	}
+
+	fn decode_versioned(&self, bytes: &[u8]) -> SessionResult<(T, bool)> {
+		self.inner.decode_versioned(&Self::open(bytes)?)
+	}
+
+	fn value_hash(&self, bytes: &[u8]) -> SessionResult<u64> {
+		self.inner.value_hash(&Self::open(bytes)?)
+	}
+}
+
+/// A codec storing the hash of each value ahead of the bytes of another
+/// codec.
+///
+/// [CodecStore] returns the hash from [Store::value_hash] without decoding
+/// the value, so that [Session::value_hash](crate::Session::value_hash)
+/// doesn't deserialize values written by other requests. Values are hashed
+/// with [DefaultHasher](std::collections::hash_map::DefaultHasher), as with
+/// [SessionStore::with_value_hash](crate::SessionStore::with_value_hash).
+/// Wrapping a [CompressedCodec] rather than the other way around reads the
+/// hash without decompressing the value. Values stored before switching to
+/// this codec lack the hash and fail to decode.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rocket_session_store::{
+/// #     codec::{CodecStore, HashedCodec, JsonCodec},
+/// #     memory::MemoryRawStore,
+/// #     SessionStore,
+/// # };
+/// let store: SessionStore<String> = SessionStore::new(
+///     CodecStore::new(HashedCodec::new(JsonCodec), MemoryRawStore::new()),
+///     "token",
+///     Duration::from_secs(3600),
+/// )
+/// .with_value_hash();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct HashedCodec<C> {
+	inner: C,
+}
+
+/// The length of the hash stored by [HashedCodec].
+const HASH_LEN: usize = std::mem::size_of::<u64>();
+
+impl<C> HashedCodec<C> {
+	/// Create a codec storing the hash of values ahead of the bytes encoded
+	/// by `inner`.
+	pub fn new(inner: C) -> Self {
+		Self { inner }
+	}
+
+	/// Get the hash and the bytes encoded by the inner codec.
+	fn open(bytes: &[u8]) -> SessionResult<(u64, &[u8])> {
+		if bytes.len() < HASH_LEN {
+			log::warn!("could not read the hash of the session value: too short");
+			return Err(SessionError::Other);
+		}
+		let (hash, rest) = bytes.split_at(HASH_LEN);
+		let hash = u64::from_be_bytes(hash.try_into().expect("hash of 8 bytes"));
+		Ok((hash, rest))
+	}
+}
+
+impl<C, T> SessionCodec<T> for HashedCodec<C>
+where
+	C: SessionCodec<T>,
+	T: Hash,
+{
+	fn encode(&self, value: &T) -> SessionResult<Vec<u8>> {
+		let encoded = self.inner.encode(value)?;
+		let mut bytes = Vec::with_capacity(HASH_LEN + encoded.len());
+		bytes.extend_from_slice(&hash_value(value).to_be_bytes());
+		bytes.extend_from_slice(&encoded);
+		Ok(bytes)
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<T> {
+		self.inner.decode(Self::open(bytes)?.1)
+	}
+
+	fn decode_versioned(&self, bytes: &[u8]) -> SessionResult<(T, bool)> {
+		self.inner.decode_versioned(Self::open(bytes)?.1)
+	}
+
+	fn value_hash(&self, bytes: &[u8]) -> SessionResult<u64> {
+		Ok(Self::open(bytes)?.0)
+	}
+}
+
+/// A [Store] of values, running a [SessionCodec] over a [RawStore].
+pub struct CodecStore<C, R, T> {
+	codec: C,
+	raw: R,
+	_marker: PhantomData<fn() -> T>,
+}
+
+impl<C, R, T> CodecStore<C, R, T> {
+	/// Create a store encoding values with the codec and keeping them in the
+	/// raw store.
+	pub fn new(codec: C, raw: R) -> Self {
+		Self {
+			codec,
+			raw,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Get the raw store.
+	pub fn inner(&self) -> &R {
+		&self.raw
+	}
+
+	/// Get back the raw store.
+	pub fn into_inner(self) -> R {
+		self.raw
+	}
+}
+
+impl<C, R, T> CodecStore<C, R, T>
+where
+	C: SessionCodec<T>,
+	R: RawStore,
+{
+	/// Writes a value decoded from an outdated format back in the current
+	/// format, keeping its time to live. Failures are logged, as the value
+	/// was read successfully.
+	async fn rewrite(&self, id: &str, value: &T, ttl: Duration) {
+		let result = match self.codec.encode(value) {
+			Ok(bytes) => self.raw.set(id, bytes, ttl).await,
+			Err(e) => Err(e),
+		};
+		if let Err(e) = result {
+			log::warn!("could not rewrite the outdated session value: {}", e);
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl<C, R, T> Store for CodecStore<C, R, T>
+where
+	C: SessionCodec<T> + 'static,
+	R: RawStore + 'static,
+	T: Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		let Some(bytes) = self.raw.get(id).await? else {
+			return Ok(None);
+		};
+		let (value, outdated) = self.codec.decode_versioned(&bytes)?;
+		if outdated {
+			match self.raw.ttl(id).await {
+				Ok(Some(ttl)) => self.rewrite(id, &value, ttl).await,
+				Ok(None) => {}
+				Err(e) => log::warn!("could not rewrite the outdated session value: {}", e),
+			}
+		}
+		Ok(Some(value))
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		let bytes = self.codec.encode(&value)?;
+		self.raw.set(id, bytes, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.raw.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.raw.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.raw.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.raw.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		let Some((bytes, ttl)) = self.raw.get_with_expiry(id).await? else {
+			return Ok(None);
+		};
+		let (value, outdated) = self.codec.decode_versioned(&bytes)?;
+		if outdated {
+			self.rewrite(id, &value, ttl).await;
+		}
+		Ok(Some((value, ttl)))
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.raw.created_at(id).await
+	}
+
+	async fn value_hash(&self, id: &str) -> SessionResult<Option<u64>> {
+		let Some(bytes) = self.raw.get(id).await? else {
+			return Ok(None);
+		};
+		self.codec.value_hash(&bytes).map(Some)
+	}
+
+	fn encode_token(&self, value: Option<&T>, duration: Duration) -> SessionResult<Option<String>> {
+		let bytes = value.map(|value| self.codec.encode(value)).transpose()?;
+		self.raw.encode_token(bytes.as_ref(), duration)
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.raw.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.raw.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.raw.dissociate(user_key, id).await
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<T>>> {
+		// Outdated values are decoded but not rewritten, as that would take
+		// a round trip per value.
+		let values = self.raw.get_many(ids).await?;
+		values
+			.into_iter()
+			.map(|bytes| bytes.map(|bytes| self.codec.decode(&bytes)).transpose())
+			.collect()
+	}
+
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		let bytes = self.codec.encode(&value)?;
+		let previous = self.raw.replace(id, bytes, duration).await?;
+		previous.map(|bytes| self.codec.decode(&bytes)).transpose()
+	}
+
+	/// The expected value is encoded again and compared with the stored
+	/// bytes, so it only matches with a codec encoding equal values to the
+	/// same bytes. Serializing a [HashMap](std::collections::HashMap) to
+	/// JSON doesn't, as its order changes between maps, unlike a
+	/// [BTreeMap](std::collections::BTreeMap).
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		let expected = expected.map(|expected| self.codec.encode(&expected)).transpose()?;
+		let bytes = self.codec.encode(&value)?;
+		self.raw.compare_and_swap(id, expected, bytes, duration).await
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		self.raw.remove_many(ids).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.raw.rename(old_id, new_id, duration).await
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let bytes = self.codec.encode(&value)?;
+		self.raw.rename_with(old_id, new_id, bytes, duration).await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.raw.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.raw.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.raw.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.raw.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		self.raw.drain().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.raw.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.raw.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.raw.expired_cleanup()
+	}
+}
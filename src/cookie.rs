@@ -0,0 +1,191 @@
+//! A store that keeps session values on the client.
+//!
+//! This module provides [CookieStore], an implementation of [Store] which
+//! doesn't store anything on the server. Instead, the value of the session is
+//! serialized into the session cookie itself and signed with HMAC-SHA256, so
+//! it can be read back but not tampered with. This is suited for small,
+//! non-sensitive state such as preferences: the value is signed, not
+//! encrypted, so the client can read it.
+//!
+//! Since the value lives in the cookie, the token of a session changes every
+//! time its value is set, and a session can't be revoked on the server
+//! before it expires. Tokens grow with the value, so
+//! [SessionStore::max_token_length](crate::SessionStore::max_token_length)
+//! usually needs to be raised to fit them.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{cookie::CookieStore, SessionStore};
+//! let store: SessionStore<String> = SessionStore::new(
+//!     CookieStore::new(b"a secret key of at least 32 bytes!"),
+//!     "prefs",
+//!     Duration::from_secs(3600 * 24 * 30),
+//! );
+//! ```
+
+use std::{
+	marker::PhantomData,
+	time::{
+		Duration,
+		SystemTime,
+		UNIX_EPOCH,
+	},
+};
+
+use base64::{
+	engine::general_purpose::URL_SAFE_NO_PAD,
+	Engine,
+};
+use hmac::{
+	Hmac,
+	Mac,
+};
+use serde::{
+	de::DeserializeOwned,
+	Serialize,
+};
+use serde_json::{
+	json,
+	Value,
+};
+use sha2::Sha256;
+
+use crate::{
+	new_id,
+	SessionError,
+	SessionResult,
+	Store,
+	ID_CHARSET_ALPHANUMERIC,
+	ID_LENGTH,
+	NO_EXPIRY,
+};
+
+/// A store which keeps the session value in the cookie, signed with HMAC.
+///
+/// The token of a session is `payload.signature`, where both parts are
+/// base64url encoded and the payload is a JSON document holding the value and
+/// its expiration time, in milliseconds since the unix epoch. Tokens with an
+/// invalid signature or that have expired are treated as a session without a
+/// value.
+///
+/// As the store keeps no state, [Store::set], [Store::touch] and
+/// [Store::remove] do nothing on their own: a new value is sent to the client
+/// through the token returned by [Store::encode_token]. In particular, touching
+/// a session can't extend its expiration time, only setting it again can.
+pub struct CookieStore<T> {
+	key: Vec<u8>,
+	_marker: PhantomData<fn() -> T>,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+impl<T> CookieStore<T> {
+	/// Create a new cookie store signing its cookies with the given key.
+	///
+	/// The key should be random and at least 32 bytes long. Changing it
+	/// invalidates every session issued with the previous key.
+	pub fn new(key: &[u8]) -> Self {
+		Self {
+			key: key.to_vec(),
+			_marker: PhantomData,
+		}
+	}
+
+	fn mac(&self) -> HmacSha256 {
+		HmacSha256::new_from_slice(&self.key).expect("HMAC can take a key of any size")
+	}
+
+	/// Verify the signature of a token and return its payload.
+	fn verify(&self, token: &str) -> Option<Value> {
+		let (payload, signature) = token.rsplit_once('.')?;
+		let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+		let mut mac = self.mac();
+		mac.update(payload.as_bytes());
+		mac.verify_slice(&signature).ok()?;
+		let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+		serde_json::from_slice(&payload).ok()
+	}
+
+	/// Time left before a verified payload expires, if it hasn't yet.
+	///
+	/// Payloads without an expiry never expire.
+	fn remaining(payload: &Value) -> Option<Duration> {
+		let expires = match payload.get("expires")? {
+			Value::Null => return Some(NO_EXPIRY),
+			expires => UNIX_EPOCH + Duration::from_millis(expires.as_u64()?),
+		};
+		expires.duration_since(SystemTime::now()).ok()
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Store for CookieStore<T>
+where
+	T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
+		let value = self.verify(id).and_then(|mut payload| {
+			Self::remaining(&payload)?;
+			serde_json::from_value(payload.get_mut("value")?.take()).ok()
+		});
+		Ok(value)
+	}
+
+	async fn set(&self, _id: &str, _value: Self::Value, _duration: Duration) -> SessionResult<()> {
+		Ok(())
+	}
+
+	/// The expiry is part of the signed token, so touching can't extend it.
+	async fn touch(&self, id: &str, _duration: Duration) -> SessionResult<Option<Duration>> {
+		Ok(self.verify(id).as_ref().and_then(Self::remaining))
+	}
+
+	/// The session is removed along with the cookie, so this only tells
+	/// whether the token holds a live session.
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		Ok(self.verify(id).as_ref().and_then(Self::remaining).is_some())
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		Ok(self.verify(id).as_ref().and_then(Self::remaining))
+	}
+
+	fn backend(&self) -> &'static str {
+		"cookie"
+	}
+
+	fn encode_token(
+		&self,
+		value: Option<&Self::Value>,
+		duration: Duration,
+	) -> SessionResult<Option<String>> {
+		let value = match value {
+			Some(value) => value,
+			None => return Ok(Some(new_id(ID_LENGTH, ID_CHARSET_ALPHANUMERIC))),
+		};
+		let expires = if duration == NO_EXPIRY {
+			None
+		} else {
+			let expires = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_err(|_| SessionError::Other)?
+				.saturating_add(duration)
+				.as_millis();
+			Some(u64::try_from(expires).unwrap_or(u64::MAX))
+		};
+		let payload = json!({
+			"value": serde_json::to_value(value).map_err(|_| SessionError::Other)?,
+			"expires": expires,
+		});
+		let payload = serde_json::to_vec(&payload).map_err(|_| SessionError::Other)?;
+		let payload = URL_SAFE_NO_PAD.encode(payload);
+		let mut mac = self.mac();
+		mac.update(payload.as_bytes());
+		let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+		Ok(Some(format!("{}.{}", payload, signature)))
+	}
+}
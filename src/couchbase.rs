@@ -0,0 +1,285 @@
+//! A Couchbase implementation of a session store, enabled with the
+//! `couchbase` feature.
+//!
+//! This module provides [CouchbaseStore], which keeps each session as a
+//! document of a Couchbase collection and lets its native document expiry
+//! expire sessions, and [CouchbaseRawStore], the same store keeping values
+//! as bytes, to be used with another
+//! [SessionCodec](crate::codec::SessionCodec).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use couchbase::{
+//! #     authenticator::PasswordAuthenticator,
+//! #     cluster::Cluster,
+//! #     options::cluster_options::ClusterOptions,
+//! # };
+//! # use rocket_session_store::{couchbase::CouchbaseStore, SessionStore};
+//! # rocket::async_test(async {
+//! let options = ClusterOptions::new(PasswordAuthenticator::new("user", "password").into());
+//! let cluster = Cluster::connect("couchbase://127.0.0.1", options)
+//!     .await
+//!     .expect("Failed to connect to couchbase");
+//! let collection = cluster.bucket("sessions").default_collection();
+//! let store: SessionStore<String> =
+//!     SessionStore::new(CouchbaseStore::new(collection), "token", Duration::from_secs(3600));
+//! # });
+//! ```
+
+use std::time::{
+	Duration,
+	SystemTime,
+	UNIX_EPOCH,
+};
+
+use couchbase::{
+	collection::Collection,
+	error::{
+		Error,
+		ErrorKind,
+	},
+	options::kv_options::{
+		GetOptions,
+		UpsertOptions,
+	},
+	results::kv_results::GetResult,
+	transcoding::raw_binary,
+};
+use rocket::serde::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{
+	codec::{
+		CodecStore,
+		JsonCodec,
+	},
+	SessionError,
+	SessionResult,
+	Store,
+	NO_EXPIRY,
+};
+
+/// The shortest expiry Couchbase accepts, as it keeps them in whole seconds.
+const MIN_EXPIRY: Duration = Duration::from_secs(1);
+
+/// A Couchbase implementation for [Store].
+///
+/// Values are serialized to JSON. This is a [CodecStore] running a
+/// [JsonCodec] over a [CouchbaseRawStore].
+pub struct CouchbaseStore<T> {
+	inner: CodecStore<JsonCodec, CouchbaseRawStore, T>,
+}
+
+impl<T> CouchbaseStore<T> {
+	/// Creates a new store keeping sessions in a collection.
+	pub fn new(collection: Collection) -> Self {
+		Self::from_raw(CouchbaseRawStore::new(collection))
+	}
+
+	/// Creates a new store keeping values in the given raw store.
+	pub fn from_raw(raw: CouchbaseRawStore) -> Self {
+		Self {
+			inner: CodecStore::new(JsonCodec, raw),
+		}
+	}
+
+	/// Get the raw store keeping the serialized values.
+	pub fn inner(&self) -> &CouchbaseRawStore {
+		self.inner.inner()
+	}
+}
+
+/// A Couchbase implementation for [RawStore](crate::codec::RawStore),
+/// keeping values as binary documents.
+///
+/// Sessions are stored under their id, so the collection should be
+/// dedicated to them. Couchbase keeps expiries in whole seconds, and
+/// durations under a second are rounded up to one.
+pub struct CouchbaseRawStore {
+	collection: Collection,
+}
+
+impl CouchbaseRawStore {
+	/// Creates a new store keeping sessions in a collection.
+	pub fn new(collection: Collection) -> Self {
+		Self { collection }
+	}
+
+	/// Get the collection of the store.
+	pub fn collection(&self) -> &Collection {
+		&self.collection
+	}
+
+	/// Get a document along with its expiry, or [None] if there is none.
+	async fn get_document(&self, id: &str, expiry: bool) -> SessionResult<Option<GetResult>> {
+		// Asking for the expiry turns the get into a sub-document lookup.
+		let options = expiry.then(|| GetOptions::new().expiry(true));
+		match self.collection.get(id, options).await {
+			Ok(result) => Ok(Some(result)),
+			Err(e) if is_not_found(&e) => Ok(None),
+			Err(e) => Err(store_error("get", e)),
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl Store for CouchbaseRawStore {
+	type Value = Vec<u8>;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Vec<u8>>> {
+		match self.get_document(id, false).await? {
+			Some(result) => decode(&result).map(Some),
+			None => Ok(None),
+		}
+	}
+
+	async fn set(&self, id: &str, value: Vec<u8>, duration: Duration) -> SessionResult<()> {
+		let (content, flags) = raw_binary::encode(&value).map_err(|e| store_error("set", e))?;
+		// Upserting without an expiry clears the one the document had.
+		let options = match duration {
+			NO_EXPIRY => UpsertOptions::new(),
+			duration => UpsertOptions::new().expiry(duration.max(MIN_EXPIRY)),
+		};
+		self.collection
+			.upsert_raw(id, content, flags, options)
+			.await
+			.map_err(|e| store_error("set", e))?;
+
+		Ok(())
+	}
+
+	/// Reads the expiry before touching the document, so that sessions
+	/// without an expiry keep it.
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let ttl = match self.ttl(id).await? {
+			None => return Ok(None),
+			Some(NO_EXPIRY) if duration != NO_EXPIRY => return Ok(Some(NO_EXPIRY)),
+			Some(_) => duration,
+		};
+		// An expiry of zero makes the document permanent.
+		let expiry = match ttl {
+			NO_EXPIRY => Duration::ZERO,
+			ttl => ttl.max(MIN_EXPIRY),
+		};
+		match self.collection.touch(id, expiry, None).await {
+			Ok(_) => Ok(Some(ttl)),
+			Err(e) if is_not_found(&e) => Ok(None),
+			Err(e) => Err(store_error("touch", e)),
+		}
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		match self.collection.remove(id, None).await {
+			Ok(_) => Ok(true),
+			Err(e) if is_not_found(&e) => Ok(false),
+			Err(e) => Err(store_error("remove", e)),
+		}
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		Ok(self.get_document(id, true).await?.map(|result| ttl(&result)))
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Vec<u8>, Duration)>> {
+		match self.get_document(id, true).await? {
+			Some(result) => Ok(Some((decode(&result)?, ttl(&result)))),
+			None => Ok(None),
+		}
+	}
+
+	fn backend(&self) -> &'static str {
+		"couchbase"
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Store for CouchbaseStore<T>
+where
+	T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(id).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.inner.rename(old_id, new_id, duration).await
+	}
+
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		self.inner
+			.compare_and_swap(id, expected, value, duration)
+			.await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.inner.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.inner.release_lock(id, holder).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		self.inner.get_with_expiry(id).await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+}
+
+/// Get the bytes of a document stored by [CouchbaseRawStore].
+fn decode(result: &GetResult) -> SessionResult<Vec<u8>> {
+	let (content, flags) = result.content_as_raw();
+	let bytes = raw_binary::decode(content, flags).map_err(|e| store_error("get", e))?;
+	Ok(bytes.to_vec())
+}
+
+/// Get the remaining time to live of a document read with its expiry.
+///
+/// Documents without an expiry are reported as expiring at the Unix epoch.
+fn ttl(result: &GetResult) -> Duration {
+	let millis = result.expiry_time().map_or(0, |expiry| expiry.timestamp_millis());
+	if millis <= 0 {
+		return NO_EXPIRY;
+	}
+	let expiry = UNIX_EPOCH + Duration::from_millis(millis as u64);
+	expiry
+		.duration_since(SystemTime::now())
+		.unwrap_or(Duration::ZERO)
+}
+
+fn is_not_found(e: &Error) -> bool {
+	matches!(e.kind(), ErrorKind::DocumentNotFound)
+}
+
+fn store_error(op: &str, e: Error) -> SessionError {
+	log::warn!("couchbase {} failed: {}", op, e);
+	SessionError::StoreUnavailable
+}
@@ -0,0 +1,194 @@
+//! Double-submit CSRF cookies issued along with the session cookie.
+//!
+//! Single page applications often protect against cross-site request
+//! forgery by echoing the value of a cookie readable from JavaScript in a
+//! request header: another site can make the browser send the cookie, but
+//! can't read it to set the header. With a [CsrfConfig] set through
+//! [SessionStore::with_csrf], the fairing issues such a companion cookie
+//! whenever it issues the session cookie, and removes it along with the
+//! session cookie.
+//!
+//! The value of the companion cookie is derived from the session token with
+//! HMAC-SHA256, so nothing more is stored, and it changes whenever the token
+//! is regenerated. The [CsrfCheck] guard accepts a request when the header
+//! matches the cookie and the cookie was derived from the session token the
+//! request carries, so a companion cookie planted by a sibling subdomain is
+//! rejected too. Bearer sessions aren't sent by browsers on their own and
+//! get no companion cookie.
+//!
+//! The guard checks every request it is used on, so it belongs on the
+//! routes changing state rather than on `GET` routes, which the first page
+//! load of a visitor without a session goes through.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::post;
+//! # use rocket_session_store::{
+//! #     csrf::{CsrfCheck, CsrfConfig},
+//! #     memory::MemoryStore,
+//! #     Session,
+//! #     SessionResult,
+//! #     SessionStore,
+//! # };
+//! #[post("/transfer/<amount>")]
+//! async fn transfer(
+//!     amount: u64,
+//!     _csrf: CsrfCheck<String>,
+//!     session: Session<'_, String>,
+//! ) -> SessionResult<()> {
+//!     session.set(format!("sent {}", amount)).await
+//! }
+//!
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+//!         .with_csrf(CsrfConfig::new(b"a secret key of at least 32 bytes!"));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/", rocket::routes![transfer]);
+//! ```
+
+use std::marker::PhantomData;
+
+use base64::{
+	engine::general_purpose::URL_SAFE_NO_PAD,
+	Engine,
+};
+use hmac::{
+	Hmac,
+	Mac,
+};
+use rocket::{
+	http::Status,
+	request::{
+		FromRequest,
+		Outcome,
+	},
+	Request,
+};
+use sha2::Sha256;
+
+use crate::SessionStore;
+
+/// The name of the companion cookie unless another one is set.
+pub const DEFAULT_CSRF_COOKIE: &str = "csrf_token";
+
+/// The request header echoing the companion cookie unless another one is
+/// set.
+pub const DEFAULT_CSRF_HEADER: &str = "X-CSRF-Token";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The options of the companion CSRF cookie, see the [module](self)
+/// documentation.
+///
+/// The cookie takes the path, `SameSite`, `Secure` and `Max-Age` of the
+/// session cookie, but is never `HttpOnly`, so scripts can read it.
+#[derive(Clone)]
+pub struct CsrfConfig {
+	/// The name of the companion cookie.
+	///
+	/// It must be a valid cookie name other than the name of the session
+	/// cookie, which is checked when the rocket ignites.
+	pub cookie_name: String,
+	/// The name of the request header checked by [CsrfCheck].
+	pub header_name: String,
+	key: Vec<u8>,
+}
+
+impl CsrfConfig {
+	/// Create the options of a companion cookie derived with the given key,
+	/// named [DEFAULT_CSRF_COOKIE] and echoed in [DEFAULT_CSRF_HEADER].
+	///
+	/// The key should be random and at least 32 bytes long. Changing it
+	/// makes the companion cookies issued before fail the check until they
+	/// are issued again.
+	pub fn new(key: &[u8]) -> Self {
+		Self {
+			cookie_name: DEFAULT_CSRF_COOKIE.to_owned(),
+			header_name: DEFAULT_CSRF_HEADER.to_owned(),
+			key: key.to_vec(),
+		}
+	}
+
+	/// Sets the name of the companion cookie.
+	pub fn with_cookie_name(mut self, name: impl Into<String>) -> Self {
+		self.cookie_name = name.into();
+		self
+	}
+
+	/// Sets the name of the request header echoing the companion cookie.
+	pub fn with_header_name(mut self, name: impl Into<String>) -> Self {
+		self.header_name = name.into();
+		self
+	}
+
+	fn mac(&self, token: &str) -> HmacSha256 {
+		let mut mac =
+			HmacSha256::new_from_slice(&self.key).expect("HMAC can take a key of any size");
+		mac.update(token.as_bytes());
+		mac
+	}
+
+	/// Get the value of the companion cookie of a session token.
+	///
+	/// This is what the fairing issues, for applications rendering it in
+	/// their pages instead.
+	pub fn token_for(&self, token: &str) -> String {
+		URL_SAFE_NO_PAD.encode(self.mac(token).finalize().into_bytes())
+	}
+
+	/// Whether a companion cookie was derived from the session token.
+	fn verify(&self, token: &str, value: &str) -> bool {
+		let Ok(value) = URL_SAFE_NO_PAD.decode(value) else {
+			return false;
+		};
+		self.mac(token).verify_slice(&value).is_ok()
+	}
+}
+
+/// A request guard checking the double-submitted CSRF token of the session
+/// store of values of type `T`, see the [module](self) documentation.
+///
+/// It fails with [Status::Forbidden] when the header is missing, doesn't
+/// match the companion cookie, or the cookie wasn't derived from the session
+/// token, and with [Status::InternalServerError] when the store issues no
+/// companion cookie.
+pub struct CsrfCheck<T> {
+	session: PhantomData<fn() -> T>,
+}
+
+#[rocket::async_trait]
+impl<'r, T> FromRequest<'r> for CsrfCheck<T>
+where
+	T: Send + Sync + 'static,
+{
+	type Error = ();
+
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let store = request
+			.rocket()
+			.state::<SessionStore<T>>()
+			.expect("Session store must be set in fairing");
+		let Some(ref csrf) = store.csrf else {
+			log::error!("The `{}` session store issues no CSRF cookie to check.", store.name);
+			return Outcome::Error((Status::InternalServerError, ()));
+		};
+		let header = request.headers().get_one(&csrf.header_name);
+		let cookie = request.cookies().get(&csrf.cookie_name);
+		let valid = match (header, cookie, store.cookie_token(request)) {
+			(Some(header), Some(cookie), Some(token)) => {
+				header == cookie.value() && csrf.verify(&token, cookie.value())
+			}
+			_ => false,
+		};
+		if !valid {
+			log::warn!("Rejecting a request failing the `{}` CSRF check.", store.name);
+			return Outcome::Error((Status::Forbidden, ()));
+		}
+		Outcome::Success(Self {
+			session: PhantomData,
+		})
+	}
+}
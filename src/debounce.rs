@@ -0,0 +1,87 @@
+//! Keeps track of when sessions were last touched, so that touching them
+//! again right away can be skipped, see
+//! [SessionStore::touch_debounce](crate::SessionStore::touch_debounce).
+
+use std::{
+	collections::HashMap,
+	sync::{
+		Arc,
+		Mutex,
+	},
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+/// Number of sessions tracked before the first pruning.
+const MIN_PRUNE_AT: usize = 1024;
+
+/// A window during which sessions renewed in the store aren't touched
+/// again, with when they were renewed.
+///
+/// Clones share when sessions were renewed.
+#[derive(Clone)]
+pub struct TouchDebounce {
+	window: Duration,
+	log: Arc<Mutex<Touches>>,
+}
+
+struct Touches {
+	/// When sessions were last renewed in the store, and the duration they
+	/// were renewed with, by key.
+	renewed: HashMap<String, (Instant, Duration)>,
+	/// Number of tracked sessions above which the ones renewed outside of
+	/// the window are dropped.
+	prune_at: usize,
+}
+
+impl TouchDebounce {
+	/// Creates a debounce with the given window, with no session renewed yet.
+	pub fn new(window: Duration) -> Self {
+		Self {
+			window,
+			log: Arc::new(Mutex::new(Touches {
+				renewed: HashMap::new(),
+				prune_at: MIN_PRUNE_AT,
+			})),
+		}
+	}
+
+	/// Get the window during which sessions aren't touched again.
+	pub fn window(&self) -> Duration {
+		self.window
+	}
+
+	/// Get the time to live left to the session, from the duration it was
+	/// last renewed with, if it was renewed within the window.
+	pub(crate) fn remaining(&self, key: &str) -> Option<Duration> {
+		let touches = self.log.lock().expect("touch log poisoned");
+		let (renewed, duration) = touches.renewed.get(key)?;
+		let elapsed = renewed.elapsed();
+		(elapsed < self.window).then(|| duration.saturating_sub(elapsed))
+	}
+
+	/// Records that the session was just renewed for the given duration,
+	/// forgetting the sessions renewed outside of the window once enough of
+	/// them are tracked.
+	pub(crate) fn renewed(&self, key: &str, duration: Duration) {
+		let mut touches = self.log.lock().expect("touch log poisoned");
+		touches
+			.renewed
+			.insert(key.to_owned(), (Instant::now(), duration));
+		if touches.renewed.len() > touches.prune_at {
+			let window = self.window;
+			touches
+				.renewed
+				.retain(|_, (renewed, _)| renewed.elapsed() < window);
+			touches.prune_at = MIN_PRUNE_AT.max(touches.renewed.len() * 2);
+		}
+	}
+
+	/// Forgets the session, so that it is touched in the store next time.
+	pub(crate) fn forget(&self, key: &str) {
+		let mut touches = self.log.lock().expect("touch log poisoned");
+		touches.renewed.remove(key);
+	}
+}
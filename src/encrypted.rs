@@ -0,0 +1,371 @@
+//! Encryption of session values at rest, enabled with the `encryption`
+//! feature.
+//!
+//! [EncryptedStore] is a [RawStore] wrapper encrypting values with
+//! XChaCha20-Poly1305 before they reach the backend, so that a leaked
+//! database doesn't leak the sessions. Each value starts with the id of the
+//! key it was encrypted with, so keys can be rotated without invalidating
+//! the sessions: the store encrypts with its first key and decrypts with any
+//! of them, and values read with an older key can be rewritten under the
+//! current one, see [EncryptedStore::with_reencrypt_on_read] and
+//! [EncryptedStore::rotate].
+//!
+//! Values are bound to the id of their session and to the id of their key,
+//! which are authenticated along with them. Values that can't be decrypted,
+//! because their key was dropped, they were tampered with or copied under
+//! another session, are treated as missing. As a result, moving a session
+//! to a new id with [Store::rename] decrypts and encrypts its value again,
+//! rather than moving it atomically in the backend.
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use rocket_session_store::{
+//! #     codec::{CodecStore, JsonCodec},
+//! #     encrypted::{EncryptedStore, EncryptionKey},
+//! #     memory::MemoryRawStore,
+//! #     SessionStore,
+//! # };
+//! let keys = vec![
+//!     EncryptionKey::new(2, [2; 32]),
+//!     // The previous key, still accepted for decryption.
+//!     EncryptionKey::new(1, [1; 32]),
+//! ];
+//! let raw = EncryptedStore::new(MemoryRawStore::new(), keys).with_reencrypt_on_read(true);
+//! let store: SessionStore<String> = SessionStore::new(
+//!     CodecStore::new(JsonCodec, raw),
+//!     "token",
+//!     Duration::from_secs(3600),
+//! );
+//! ```
+
+use std::time::{
+	Duration,
+	SystemTime,
+};
+
+use chacha20poly1305::{
+	aead::{
+		Aead,
+		KeyInit,
+		Payload,
+	},
+	Key,
+	XChaCha20Poly1305,
+	XNonce,
+};
+use rand::{
+	rngs::OsRng,
+	RngCore,
+};
+
+use crate::{
+	codec::RawStore,
+	ExpiredCleanup,
+	SessionError,
+	SessionResult,
+	Store,
+};
+
+/// Length of the nonce following the key id.
+const NONCE_LENGTH: usize = 24;
+
+/// The data authenticated along with a value: the id of its key and the id
+/// of its session.
+fn associated_data(id: &str, key_id: u8) -> Vec<u8> {
+	let mut aad = Vec::with_capacity(1 + id.len());
+	aad.push(key_id);
+	aad.extend_from_slice(id.as_bytes());
+	aad
+}
+
+/// A key of an [EncryptedStore], along with the id stored in front of the
+/// values it encrypts.
+#[derive(Clone)]
+pub struct EncryptionKey {
+	id: u8,
+	cipher: XChaCha20Poly1305,
+}
+
+impl EncryptionKey {
+	/// Creates a key from 32 secret bytes, identified by `id`.
+	pub fn new(id: u8, key: [u8; 32]) -> Self {
+		Self {
+			id,
+			cipher: XChaCha20Poly1305::new(Key::from_slice(&key)),
+		}
+	}
+
+	/// Get the id of the key.
+	pub fn id(&self) -> u8 {
+		self.id
+	}
+}
+
+/// A [RawStore] encrypting values before storing them in another one, see
+/// the [module](self) documentation.
+pub struct EncryptedStore<R> {
+	raw: R,
+	/// The keys, the first one encrypting new values.
+	keys: Vec<EncryptionKey>,
+	reencrypt_on_read: bool,
+}
+
+impl<R> EncryptedStore<R>
+where
+	R: RawStore,
+{
+	/// Creates a store encrypting values with the first key, and decrypting
+	/// them with any of the keys.
+	///
+	/// # Panics
+	///
+	/// Panics if there are no keys, or if two keys have the same id.
+	pub fn new(raw: R, keys: Vec<EncryptionKey>) -> Self {
+		assert!(!keys.is_empty(), "an encrypted store needs a key");
+		for (i, key) in keys.iter().enumerate() {
+			assert!(
+				keys[..i].iter().all(|other| other.id != key.id),
+				"the key id {} is used twice",
+				key.id
+			);
+		}
+		Self {
+			raw,
+			keys,
+			reencrypt_on_read: false,
+		}
+	}
+
+	/// Rewrites values read with an older key under the current one, keeping
+	/// their time to live.
+	///
+	/// This spreads a key rotation over the sessions as they are used, after
+	/// which the old key can be dropped. It needs a store implementing
+	/// [Store::ttl], and a value set between the read and the rewrite is
+	/// overwritten. Disabled by default.
+	pub fn with_reencrypt_on_read(mut self, reencrypt: bool) -> Self {
+		self.reencrypt_on_read = reencrypt;
+		self
+	}
+
+	/// Get the raw store keeping the encrypted values.
+	pub fn inner(&self) -> &R {
+		&self.raw
+	}
+
+	/// Rewrites a session under the current key if it was encrypted with an
+	/// older one, keeping its time to live.
+	///
+	/// Returns whether the session was rewritten.
+	pub async fn rotate(&self, id: &str) -> SessionResult<bool> {
+		let Some((bytes, ttl)) = self.raw.get_with_expiry(id).await? else {
+			return Ok(false);
+		};
+		match self.decrypt(id, &bytes) {
+			Some((value, false)) => {
+				self.raw.set(id, self.encrypt(id, &value)?, ttl).await?;
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+
+	fn current(&self) -> &EncryptionKey {
+		&self.keys[0]
+	}
+
+	fn encrypt(&self, id: &str, value: &[u8]) -> SessionResult<Vec<u8>> {
+		let key = self.current();
+		let mut nonce = [0; NONCE_LENGTH];
+		OsRng.fill_bytes(&mut nonce);
+		let payload = Payload {
+			msg: value,
+			aad: &associated_data(id, key.id),
+		};
+		let ciphertext = key
+			.cipher
+			.encrypt(XNonce::from_slice(&nonce), payload)
+			.map_err(|_| SessionError::Other)?;
+		let mut bytes = Vec::with_capacity(1 + NONCE_LENGTH + ciphertext.len());
+		bytes.push(key.id);
+		bytes.extend_from_slice(&nonce);
+		bytes.extend_from_slice(&ciphertext);
+		Ok(bytes)
+	}
+
+	/// Decrypts the value of a session, also returning whether it was
+	/// encrypted with the current key, or [None] if no key decrypts it.
+	fn decrypt(&self, id: &str, bytes: &[u8]) -> Option<(Vec<u8>, bool)> {
+		let decrypted = bytes
+			.split_first()
+			.filter(|(_, rest)| rest.len() >= NONCE_LENGTH)
+			.and_then(|(&key_id, rest)| {
+				let key = self.keys.iter().find(|key| key.id == key_id)?;
+				let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+				let payload = Payload {
+					msg: ciphertext,
+					aad: &associated_data(id, key.id),
+				};
+				let value = key
+					.cipher
+					.decrypt(XNonce::from_slice(nonce), payload)
+					.ok()?;
+				Some((value, key_id == self.current().id))
+			});
+		if decrypted.is_none() {
+			log::warn!("could not decrypt a session value, treating it as missing");
+		}
+		decrypted
+	}
+
+	/// Decrypts a value read from the store, rewriting it under the current
+	/// key if needed.
+	async fn read(&self, id: &str, bytes: Option<Vec<u8>>) -> SessionResult<Option<Vec<u8>>> {
+		let Some((value, current)) = bytes.and_then(|bytes| self.decrypt(id, &bytes)) else {
+			return Ok(None);
+		};
+		if !current && self.reencrypt_on_read {
+			match self.raw.ttl(id).await {
+				Ok(Some(ttl)) => self.raw.set(id, self.encrypt(id, &value)?, ttl).await?,
+				Ok(None) => {}
+				Err(e) => log::warn!("could not re-encrypt a session: {}", e),
+			}
+		}
+		Ok(Some(value))
+	}
+}
+
+#[rocket::async_trait]
+impl<R> Store for EncryptedStore<R>
+where
+	R: RawStore + 'static,
+{
+	type Value = Vec<u8>;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Vec<u8>>> {
+		let bytes = self.raw.get(id).await?;
+		self.read(id, bytes).await
+	}
+
+	async fn set(&self, id: &str, value: Vec<u8>, duration: Duration) -> SessionResult<()> {
+		self.raw.set(id, self.encrypt(id, &value)?, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.raw.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.raw.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.raw.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.raw.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Vec<u8>, Duration)>> {
+		let Some((bytes, ttl)) = self.raw.get_with_expiry(id).await? else {
+			return Ok(None);
+		};
+		Ok(self.read(id, Some(bytes)).await?.map(|value| (value, ttl)))
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.raw.created_at(id).await
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.raw.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.raw.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.raw.dissociate(user_key, id).await
+	}
+
+	/// Values read with an older key are decrypted but not rewritten, as
+	/// that would take a round trip per value.
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Vec<u8>>>> {
+		let values = self.raw.get_many(ids).await?;
+		Ok(ids
+			.iter()
+			.zip(values)
+			.map(|(id, bytes)| {
+				bytes
+					.and_then(|bytes| self.decrypt(id, &bytes))
+					.map(|(value, _)| value)
+			})
+			.collect())
+	}
+
+	async fn replace(
+		&self,
+		id: &str,
+		value: Vec<u8>,
+		duration: Duration,
+	) -> SessionResult<Option<Vec<u8>>> {
+		let previous = self
+			.raw
+			.replace(id, self.encrypt(id, &value)?, duration)
+			.await?;
+		Ok(previous
+			.and_then(|bytes| self.decrypt(id, &bytes))
+			.map(|(value, _)| value))
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		self.raw.remove_many(ids).await
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: Vec<u8>,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let bytes = self.encrypt(new_id, &value)?;
+		self.raw.rename_with(old_id, new_id, bytes, duration).await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.raw.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.raw.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.raw.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.raw.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		self.raw.drain().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.raw.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.raw.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.raw.expired_cleanup()
+	}
+}
@@ -0,0 +1,100 @@
+//! Lifecycle callbacks for sessions.
+//!
+//! This module provides [SessionEvents], a set of callbacks that a
+//! [SessionStore](crate::SessionStore) invokes whenever a session is created,
+//! destroyed or has its token regenerated. This is useful for auditing
+//! without having to wrap every call site of [Session](crate::Session).
+//!
+//! It also provides [SessionAuditRecord], the facts about each request passed
+//! to the
+//! [security observer](crate::SessionStore::with_security_observer).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::{sync::Arc, time::Duration};
+//! # use rocket_session_store::{
+//! #     events::SessionEvents,
+//! #     memory::MemoryStore,
+//! #     SessionResult,
+//! #     SessionStore,
+//! # };
+//! struct AuditLog;
+//!
+//! #[rocket::async_trait]
+//! impl SessionEvents<String> for AuditLog {
+//!     async fn destroyed(&self, id: &str) -> SessionResult<()> {
+//!         println!("session {} logged out", id);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+//!         .with_events(Arc::new(AuditLog));
+//! ```
+
+use std::{
+	net::IpAddr,
+	time::Duration,
+};
+
+use crate::SessionResult;
+
+/// Callbacks invoked on the lifecycle of a session.
+///
+/// Every callback has a default implementation that does nothing, so
+/// implementors only need to override the events they care about.
+///
+/// Errors returned from a callback are logged and never turned into
+/// a failure of the request that triggered them.
+#[rocket::async_trait]
+pub trait SessionEvents<T>: Send + Sync {
+	/// Called when a value is stored under a session that had no value.
+	async fn created(&self, _id: &str, _value: &T) -> SessionResult<()>
+	where
+		T: Sync,
+	{
+		Ok(())
+	}
+
+	/// Called when a session is explicitly removed.
+	async fn destroyed(&self, _id: &str) -> SessionResult<()> {
+		Ok(())
+	}
+
+	/// Called when the token of a session is regenerated.
+	async fn regenerated(&self, _old_id: &str, _new_id: &str) -> SessionResult<()> {
+		Ok(())
+	}
+}
+
+/// Status of the token sent by the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenStatus {
+	/// The client didn't send a session cookie.
+	New,
+	/// The token refers to a session with a value.
+	Valid,
+	/// The token is well formed but has no session value, usually because the
+	/// session expired or was removed.
+	Expired,
+	/// The token is malformed or too long, and was ignored.
+	InvalidFormat,
+}
+
+/// Callback receiving the audit record of every request.
+pub type SecurityObserver = dyn Fn(&SessionAuditRecord) + Send + Sync;
+
+/// Security relevant facts about a request, passed to the security observer.
+#[derive(Clone, Debug)]
+pub struct SessionAuditRecord {
+	/// Status of the token sent by the client.
+	pub status: TokenStatus,
+	/// Path of the request.
+	pub path: String,
+	/// IP address of the client, if known.
+	pub client_ip: Option<IpAddr>,
+	/// Time elapsed between receiving the request and issuing its response.
+	pub elapsed: Duration,
+}
@@ -0,0 +1,147 @@
+//! Request guards for types extracted from the session value.
+//!
+//! Handlers often only need a part of the session, such as the logged in
+//! user. A type implementing [FromSession] for the session value can be
+//! requested with the [SessionExtract] guard, which reads the session and
+//! forwards the request to the catcher of [FromSession::MISSING] when there
+//! is no session or nothing to extract from it.
+//!
+//! The value is read through [Session::get], so it is cached for the
+//! request like any other read: a handler taking both a [SessionExtract]
+//! and a [Session] of the same store reaches the store once.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::{
+//! #     get,
+//! #     request::{FromRequest, Outcome},
+//! #     Request,
+//! # };
+//! # use rocket_session_store::{
+//! #     extract::{FromSession, SessionExtract},
+//! #     memory::MemoryStore,
+//! #     SessionStore,
+//! # };
+//! #[derive(Clone)]
+//! struct AppSession {
+//!     user_id: Option<u64>,
+//! }
+//!
+//! struct CurrentUser(u64);
+//!
+//! impl FromSession<AppSession> for CurrentUser {
+//!     fn from_session(session: &AppSession) -> Option<Self> {
+//!         session.user_id.map(CurrentUser)
+//!     }
+//! }
+//!
+//! #[get("/dashboard")]
+//! fn dashboard(user: SessionExtract<CurrentUser, AppSession>) -> String {
+//!     format!("Hello, user {}", user.0)
+//! }
+//!
+//! // Or, to take the type itself as a guard:
+//! #[rocket::async_trait]
+//! impl<'r> FromRequest<'r> for CurrentUser {
+//!     type Error = ();
+//!
+//!     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+//!         SessionExtract::<CurrentUser, AppSession>::from_request(request)
+//!             .await
+//!             .map(SessionExtract::into_inner)
+//!     }
+//! }
+//!
+//! #[get("/profile")]
+//! fn profile(user: CurrentUser) -> String {
+//!     format!("Profile of user {}", user.0)
+//! }
+//!
+//! let store: SessionStore<AppSession> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.fairing())
+//!     .mount("/", rocket::routes![dashboard, profile]);
+//! ```
+
+use std::{
+	marker::PhantomData,
+	ops::Deref,
+};
+
+use rocket::{
+	http::Status,
+	request::{
+		FromRequest,
+		Outcome,
+	},
+	Request,
+};
+
+use crate::Session;
+
+/// A type which can be extracted from a session value of type `T`.
+pub trait FromSession<T>: Sized {
+	/// The status the request fails with when there is no session, or when
+	/// [from_session](Self::from_session) extracts nothing.
+	const MISSING: Status = Status::Unauthorized;
+
+	/// Extracts the value from the session, or [None] if the session doesn't
+	/// hold one.
+	fn from_session(session: &T) -> Option<Self>;
+}
+
+/// A request guard extracting a `U` from the session value of type `T`, see
+/// the [module](self) documentation.
+///
+/// It fails with [FromSession::MISSING] when there is nothing to extract, and
+/// with [Status::InternalServerError] when the store can't be reached.
+pub struct SessionExtract<U, T> {
+	value: U,
+	session: PhantomData<fn() -> T>,
+}
+
+impl<U, T> SessionExtract<U, T> {
+	/// Get back the extracted value.
+	pub fn into_inner(self) -> U {
+		self.value
+	}
+}
+
+impl<U, T> Deref for SessionExtract<U, T> {
+	type Target = U;
+
+	fn deref(&self) -> &Self::Target {
+		&self.value
+	}
+}
+
+#[rocket::async_trait]
+impl<'r, U, T> FromRequest<'r> for SessionExtract<U, T>
+where
+	U: FromSession<T>,
+	T: Send + Sync + Clone + 'static,
+{
+	type Error = ();
+
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let session = Session::<T>::of(request)
+			.await
+			.expect("Session store must be set in fairing");
+		match session.get_opt().await {
+			Ok(value) => match value.as_ref().and_then(U::from_session) {
+				Some(value) => Outcome::Success(Self {
+					value,
+					session: PhantomData,
+				}),
+				None => Outcome::Error((U::MISSING, ())),
+			},
+			Err(e) => {
+				log::warn!("could not read the session to extract from it: {}", e);
+				Outcome::Error((Status::InternalServerError, ()))
+			}
+		}
+	}
+}
@@ -0,0 +1,204 @@
+//! A health check route for the session store.
+//!
+//! This module provides [HealthFairing], which mounts a route reporting
+//! whether the session store can be reached, for load balancers and
+//! orchestration platforms. It is created with
+//! [SessionStore::health_fairing](crate::SessionStore::health_fairing) and
+//! attached next to the main fairing.
+//!
+//! [route] provides the same check as a route to mount on any path, such as
+//! the path of a readiness probe, for the store managed by the main fairing.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{memory::MemoryStore, SessionStore};
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.health_fairing().with_path("/health/session"))
+//!     .attach(store.fairing());
+//! ```
+
+use std::{
+	marker::PhantomData,
+	sync::Arc,
+	time::Duration,
+};
+
+use rocket::{
+	fairing::{
+		Fairing,
+		Info,
+		Kind,
+	},
+	http::{
+		ContentType,
+		Method,
+		Status,
+	},
+	route::{
+		Handler,
+		Outcome,
+	},
+	tokio::time::timeout,
+	Build,
+	Data,
+	Request,
+	Rocket,
+	Route,
+	State,
+};
+use serde_json::json;
+
+use crate::{
+	SessionError,
+	SessionResult,
+	SessionStore,
+	Store,
+};
+
+/// How long a store has to answer a ping before it is deemed unhealthy.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The fairing mounting the health check route.
+///
+/// The route answers `GET` requests with `{"status": "ok"}` and a 200 status
+/// when [Store::ping] succeeds within two seconds, and with
+/// `{"status": "error", "reason": "..."}` and a 503 status otherwise. It is
+/// mounted on `/healthz/session` unless another path is given.
+pub struct HealthFairing<T> {
+	store: Arc<dyn Store<Value = T>>,
+	path: String,
+}
+
+impl<T> HealthFairing<T> {
+	pub(crate) fn new(store: Arc<dyn Store<Value = T>>) -> Self {
+		Self {
+			store,
+			path: "/healthz/session".into(),
+		}
+	}
+
+	/// Sets the path of the health check route.
+	pub fn with_path(mut self, path: impl Into<String>) -> Self {
+		self.path = path.into();
+		self
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Fairing for HealthFairing<T>
+where
+	T: Send + Sync + 'static,
+{
+	fn info(&self) -> Info {
+		Info {
+			name: "Session Store Health Check",
+			kind: Kind::Ignite,
+		}
+	}
+
+	async fn on_ignite(&self, rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+		let handler = HealthHandler {
+			store: self.store.clone(),
+		};
+		let route = Route::new(Method::Get, &self.path, handler);
+		Ok(rocket.mount("/", vec![route]))
+	}
+}
+
+struct HealthHandler<T> {
+	store: Arc<dyn Store<Value = T>>,
+}
+
+impl<T> Clone for HealthHandler<T> {
+	fn clone(&self) -> Self {
+		Self {
+			store: self.store.clone(),
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Handler for HealthHandler<T>
+where
+	T: Send + Sync + 'static,
+{
+	async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+		respond(request, ping(&*self.store).await)
+	}
+}
+
+/// Get a readiness route answering like the route of [HealthFairing], for
+/// the store managed by the main fairing.
+///
+/// The route is mounted on the path given to
+/// [Rocket::mount](rocket::Rocket::mount), so it can be reached wherever a
+/// readiness probe expects it. It answers 503 until the main fairing is
+/// attached, and whenever [SessionStore::healthy] fails.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use rocket_session_store::{health, memory::MemoryStore, SessionStore};
+/// let store: SessionStore<String> =
+///     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+/// let rocket = rocket::build()
+///     .attach(store.fairing())
+///     .mount("/readyz", vec![health::route::<String>()]);
+/// ```
+pub fn route<T>() -> Route
+where
+	T: Send + Sync + 'static,
+{
+	let handler = ReadinessHandler::<T>(PhantomData);
+	Route::new(Method::Get, "/", handler)
+}
+
+struct ReadinessHandler<T>(PhantomData<fn() -> T>);
+
+impl<T> Clone for ReadinessHandler<T> {
+	fn clone(&self) -> Self {
+		Self(PhantomData)
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Handler for ReadinessHandler<T>
+where
+	T: Send + Sync + 'static,
+{
+	async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+		let result = match State::<SessionStore<T>>::get(request.rocket()) {
+			Some(store) => store.healthy().await,
+			None => {
+				log::error!("the readiness route is mounted without the session store fairing");
+				Err(SessionError::Other)
+			}
+		};
+		respond(request, result)
+	}
+}
+
+/// Pings a store, failing if it doesn't answer within [PING_TIMEOUT].
+pub(crate) async fn ping<T>(store: &dyn Store<Value = T>) -> SessionResult<()> {
+	match timeout(PING_TIMEOUT, store.ping()).await {
+		Ok(result) => result,
+		Err(_) => {
+			log::warn!("the session store didn't answer within {:?}", PING_TIMEOUT);
+			Err(SessionError::Timeout)
+		}
+	}
+}
+
+fn respond<'r>(request: &'r Request<'_>, result: SessionResult<()>) -> Outcome<'r> {
+	let (status, body) = match result {
+		Ok(()) => (Status::Ok, json!({ "status": "ok" })),
+		Err(e) => (
+			Status::ServiceUnavailable,
+			json!({ "status": "error", "reason": e.to_string() }),
+		),
+	};
+	Outcome::from(request, (status, (ContentType::JSON, body.to_string())))
+}
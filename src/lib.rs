@@ -1,192 +1,3010 @@
 #![doc = include_str!("../README.md")]
 #![warn(missing_docs)]
 
+// Lets the code generated by the derives refer to this crate from its own
+// tests.
+#[cfg(all(test, feature = "macros"))]
+extern crate self as rocket_session_store;
+
 #[cfg(test)]
 mod test;
 
-pub mod memory;
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod any;
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod bearer;
+pub mod bound;
+pub mod codec;
+#[cfg(feature = "cookie")]
+pub mod cookie;
+#[cfg(feature = "couchbase")]
+pub mod couchbase;
+#[cfg(feature = "csrf")]
+pub mod csrf;
+pub mod debounce;
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+pub mod events;
+pub mod extract;
+pub mod health;
+pub mod lock;
+mod measure;
+pub mod memory;
+pub mod migration;
+#[cfg(feature = "moka")]
+pub mod moka;
+pub mod multiplex;
+#[cfg(feature = "okapi")]
+mod okapi;
+pub mod owned;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod replicated;
+pub mod scope;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod trace;
+pub mod transaction;
+pub mod validator;
+
+#[cfg(feature = "redis")]
+pub mod redis;
+
+/// Checks at compile time that a string literal is a valid cookie name.
+///
+/// It expands to the literal, so it can be given as the name of the
+/// session cookie. Enabled with the `macros` feature.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rocket_session_store::{memory::MemoryStore, session_name, SessionStore};
+/// let store: SessionStore<String> = SessionStore::new(
+///     MemoryStore::new(),
+///     session_name!("session"),
+///     Duration::from_secs(3600),
+/// );
+/// ```
+///
+/// ```compile_fail
+/// # use rocket_session_store::session_name;
+/// let name = session_name!("my session");
+/// ```
+#[cfg(feature = "macros")]
+pub use rocket_session_store_macros::session_name;
+/// Derives what a session value needs: `Clone`, `Serialize`, `Deserialize`
+/// and [SessionValue].
+///
+/// A derive can't add other derives, so this is an attribute. Traits
+/// already derived on the type are left out, and serde is used through
+/// rocket, so the application doesn't need to depend on it. Enabled with
+/// the `macros` feature.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rocket_session_store::{memory::MemoryStore, session_value, SessionStore};
+/// #[session_value]
+/// #[derive(Debug)]
+/// struct User {
+///     name: String,
+///     roles: Vec<String>,
+/// }
+///
+/// let store: SessionStore<User> =
+///     SessionStore::new(MemoryStore::new(), "session", Duration::from_secs(3600));
+/// ```
+#[cfg(feature = "macros")]
+pub use rocket_session_store_macros::session_value;
+/// Implements [SessionValueMarker], checking that the type can be stored in
+/// a session.
+///
+/// Every field has to implement `Clone`, and a field that doesn't is
+/// reported on its own. Enabled with the `macros` feature.
+///
+/// ```compile_fail
+/// # use rocket_session_store::session_value;
+/// struct Connection;
+///
+/// #[session_value]
+/// struct User {
+///     name: String,
+///     connection: Connection,
+/// }
+/// ```
+#[cfg(feature = "macros")]
+pub use rocket_session_store_macros::SessionValue;
+
+use std::{
+	any::Any,
+	collections::hash_map::DefaultHasher,
+	fmt,
+	future::Future,
+	hash::{Hash, Hasher},
+	panic::{catch_unwind, AssertUnwindSafe},
+	str::FromStr,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex as SyncMutex,
+	},
+	time::{Duration, Instant, SystemTime},
+};
+
+use rand::{rngs::OsRng, Rng};
+use rocket::{
+	fairing::{Fairing, Info, Kind},
+	http::{Cookie, SameSite, Status, StatusClass},
+	request::{FromRequest, Outcome},
+	response::{Redirect, Responder},
+	tokio::sync::Mutex,
+	Build, Data, Orbit, Request, Response, Rocket, State,
+};
+use serde::{
+	de::{self, DeserializeOwned},
+	Deserialize, Deserializer, Serialize, Serializer,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+	events::{SecurityObserver, SessionAuditRecord, SessionEvents, TokenStatus},
+	debounce::TouchDebounce,
+	health::HealthFairing,
+	measure::Meter,
+	owned::OwnedSession,
+	validator::SessionValidator,
+};
+
+fn new_id(length: usize, charset: &[u8]) -> String {
+	(0..length)
+		.map(|_| char::from(charset[OsRng.gen_range(0..charset.len())]))
+		.collect()
+}
+
+/// Whether a byte is a cookie-octet from RFC 6265: printable ASCII except
+/// whitespace, double quotes, commas, semicolons and backslashes.
+fn is_cookie_octet(byte: u8) -> bool {
+	byte.is_ascii_graphic() && !matches!(byte, b'"' | b',' | b';' | b'\\')
+}
+
+/// Letters and digits, the default characters of session ids, carrying
+/// about 5.95 bits each.
+pub const ID_CHARSET_ALPHANUMERIC: &[u8] =
+	b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+/// The URL-safe base64 alphabet, carrying 6 bits per character.
+pub const ID_CHARSET_BASE64URL: &[u8] =
+	b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+/// Lowercase hexadecimal digits, carrying 4 bits per character.
+pub const ID_CHARSET_HEX: &[u8] = b"0123456789abcdef";
+
+const ID_LENGTH: usize = 24;
+const DEFAULT_MAX_TOKEN_LENGTH: usize = 512;
+/// The shortest duration the jitter can give a session, as some stores only
+/// keep whole seconds.
+const MIN_JITTERED_DURATION: Duration = Duration::from_secs(1);
+/// Number of expired sessions cleared at once by the cleanup task.
+const CLEANUP_BATCH: usize = 1000;
+/// Number of tokens generated before giving up when they are all in use, with
+/// [SessionStore::collision_detection].
+const COLLISION_ATTEMPTS: usize = 3;
+/// Number of times [Session::update] computes the value again when another
+/// request changed it first, before giving up.
+const UPDATE_ATTEMPTS: usize = 10;
+
+/// The duration of sessions that never expire.
+///
+/// Storing or touching a session with it removes its expiry, so that it lives
+/// until it is removed. [Store::ttl] reports it for such sessions, which
+/// tells them apart from missing ones.
+pub const NO_EXPIRY: Duration = Duration::MAX;
+
+/// Access to a value as [Any], to recover its concrete type.
+///
+/// This is implemented for every `'static` type, and is a supertrait of
+/// [Store] so that [SessionStore::downcast_store] can recover the concrete
+/// store.
+pub trait AsAny {
+	/// Get the value as [Any].
+	fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
+/// A generic store in which to write and retrive sessions either
+/// trough an in memory hashmap or a database connection.
+#[rocket::async_trait]
+pub trait Store: AsAny + Send + Sync {
+	/// Type that is associated with sessions.
+	///
+	/// The store will store and retrieve values of this type.
+	type Value;
+	/// Get the value from the store
+	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>>;
+	/// Set the value from the store
+	///
+	/// The session expires after `duration`, or never with [NO_EXPIRY].
+	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()>;
+	/// Touch the value, refreshing its expiry time.
+	///
+	/// Sessions that never expire are left as they are, unless `duration` is
+	/// [NO_EXPIRY]. Returns the remaining time to live of the session, as
+	/// [Store::ttl] would, or [None] if there is no session under the id.
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>>;
+	/// Remove the value from the store.
+	///
+	/// Returns whether a live session was removed, as opposed to a session
+	/// that didn't exist or had already expired.
+	async fn remove(&self, id: &str) -> SessionResult<bool>;
+	/// Whether there is a live session under the id.
+	///
+	/// The default implementation reads the value with [Store::get]. Stores
+	/// that can check for an id without reading its value should override it.
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		Ok(self.get(id).await?.is_some())
+	}
+	/// Get the remaining time to live of a session.
+	///
+	/// Returns [None] if there is no session under the id, and [NO_EXPIRY]
+	/// if the session never expires.
+	/// Stores that can't report it return an error.
+	async fn ttl(&self, _id: &str) -> SessionResult<Option<Duration>> {
+		Err(SessionError::Other)
+	}
+	/// Get the value along with the remaining time to live of its session.
+	///
+	/// Stores that can should do this in a single operation. The default
+	/// implementation calls [Store::ttl] and then [Store::get], so it returns
+	/// an error for stores that can't report the time to live.
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Self::Value, Duration)>> {
+		let Some(ttl) = self.ttl(id).await? else {
+			return Ok(None);
+		};
+		Ok(self.get(id).await?.map(|value| (value, ttl)))
+	}
+	/// Get the time at which a value was first stored under the id.
+	///
+	/// Overwriting an existing session keeps its creation time, while
+	/// storing a value under a new id, such as when regenerating the token,
+	/// starts a new one. Returns [None] if there is no session under the id
+	/// or if the store doesn't keep track of creation times, which is what
+	/// the default implementation does.
+	async fn created_at(&self, _id: &str) -> SessionResult<Option<SystemTime>> {
+		Ok(None)
+	}
+	/// Get the hash stored next to the value of a session, without
+	/// deserializing the value.
+	///
+	/// [Session::value_hash] reads it for values written by other requests,
+	/// with [SessionStore::with_value_hash]. Returns [None] if there is no
+	/// session under the id. Stores that don't keep hashes return an error,
+	/// which is what the default implementation does. A [codec::CodecStore]
+	/// keeps them with a [codec::HashedCodec].
+	async fn value_hash(&self, _id: &str) -> SessionResult<Option<u64>> {
+		Err(SessionError::Other)
+	}
+	/// Encode a value into the token of its session.
+	///
+	/// Stores that keep the value on the client, such as
+	/// [cookie::CookieStore], return the token that carries the value, which
+	/// then replaces the token of the session. The value is [None] when the
+	/// session is removed or has no value. Server side stores keep the
+	/// default implementation, which returns [None] and leaves tokens as is.
+	fn encode_token(
+		&self,
+		_value: Option<&Self::Value>,
+		_duration: Duration,
+	) -> SessionResult<Option<String>> {
+		Ok(None)
+	}
+	/// Associate a session with a user, recording when it was associated.
+	///
+	/// This builds a secondary index that allows listing all the sessions
+	/// of a user. Stores that don't support it return an error.
+	async fn associate_with(&self, _user_key: &str, _id: &str) -> SessionResult<()> {
+		Err(SessionError::Other)
+	}
+	/// List the sessions associated with a user, oldest first.
+	async fn list_sessions_for(&self, _user_key: &str) -> SessionResult<Vec<String>> {
+		Err(SessionError::Other)
+	}
+	/// Remove the association between a session and a user.
+	async fn dissociate(&self, _user_key: &str, _id: &str) -> SessionResult<()> {
+		Err(SessionError::Other)
+	}
+	/// Get the values of several sessions, in the order of the ids, with
+	/// [None] for sessions that don't exist.
+	///
+	/// The default implementation gets them one by one. Stores that can
+	/// fetch them in one round trip should override it.
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Self::Value>>>
+	where
+		Self::Value: Send,
+	{
+		let mut values = Vec::with_capacity(ids.len());
+		for id in ids {
+			values.push(self.get(id).await?);
+		}
+		Ok(values)
+	}
+	/// Set the value of a session, returning the value it replaces, or
+	/// [None] if there was no session under the id.
+	///
+	/// The default implementation calls [Store::get] and then [Store::set],
+	/// so a value set in between by another request is lost without being
+	/// returned. Stores that can swap the value atomically should override
+	/// it.
+	async fn replace(
+		&self,
+		id: &str,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<Option<Self::Value>>
+	where
+		Self::Value: Send,
+	{
+		let previous = self.get(id).await?;
+		self.set(id, value, duration).await?;
+		Ok(previous)
+	}
+	/// Remove several sessions.
+	///
+	/// The default implementation removes them one by one. Stores that can
+	/// remove them in one round trip should override it.
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		for id in ids {
+			self.remove(id).await?;
+		}
+		Ok(())
+	}
+	/// Move a session to a new id, renewing its expiry to `duration`.
+	///
+	/// The session gets a new creation time, as when a value is stored
+	/// under a new id. Returns whether there was a live session to move.
+	///
+	/// The default implementation calls [Store::get], [Store::set] and then
+	/// [Store::remove], so the session briefly exists under both ids, and a
+	/// value set in between under the old id is lost. Stores that can move
+	/// the session atomically should override it.
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool>
+	where
+		Self::Value: Send,
+	{
+		let Some(value) = self.get(old_id).await? else {
+			return Ok(false);
+		};
+		self.set(new_id, value, duration).await?;
+		self.remove(old_id).await?;
+		Ok(true)
+	}
+	/// Store a value under a new id and remove the session under the old
+	/// id, as when the token is regenerated along with a new value.
+	///
+	/// The value gets a new creation time, as when a value is stored under a
+	/// new id. The default implementation calls [Store::set] and then
+	/// [Store::remove], so a failure removing the old session leaves the
+	/// value under both ids. Stores that can do both in a single atomic
+	/// operation should override it.
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<()>
+	where
+		Self::Value: Send,
+	{
+		self.set(new_id, value, duration).await?;
+		self.remove(old_id).await?;
+		Ok(())
+	}
+	/// Set the value of a session, if it still holds `expected`, or if
+	/// there is no session under the id when `expected` is [None].
+	///
+	/// Returns whether the value was set. The check and the write are a
+	/// single atomic operation, so that [Session::update] doesn't lose the
+	/// writes of concurrent requests. Stores that can't compare values
+	/// atomically return an error.
+	async fn compare_and_swap(
+		&self,
+		_id: &str,
+		_expected: Option<Self::Value>,
+		_value: Self::Value,
+		_duration: Duration,
+	) -> SessionResult<bool>
+	where
+		Self::Value: Send,
+	{
+		Err(SessionError::Other)
+	}
+	/// Take the advisory lock of a session for `holder`, unless another
+	/// holder has it.
+	///
+	/// Returns whether the lock was taken, which it is again when `holder`
+	/// already has it, renewing it. The lock is released after `ttl` even if
+	/// its holder never releases it, so that a holder that crashed doesn't
+	/// block the session. Locks are kept apart from the values: a session
+	/// needn't exist to be locked, and writes aren't stopped by a lock.
+	/// Stores that don't support locks return an error.
+	async fn acquire_lock(&self, _id: &str, _holder: &str, _ttl: Duration) -> SessionResult<bool> {
+		Err(SessionError::Other)
+	}
+	/// Release the lock of a session, if `holder` still has it.
+	///
+	/// Returns whether the lock was released, which it isn't when it expired
+	/// and was taken by another holder since.
+	async fn release_lock(&self, _id: &str, _holder: &str) -> SessionResult<bool> {
+		Err(SessionError::Other)
+	}
+	/// List the ids of live sessions, sorted, skipping the first `offset`
+	/// and returning at most `limit` of them.
+	///
+	/// This is meant for administration and may be slow on large stores.
+	/// Stores that can't enumerate their sessions return an error.
+	async fn list_ids(&self, _offset: usize, _limit: usize) -> SessionResult<Vec<String>> {
+		Err(SessionError::Other)
+	}
+	/// Count the live sessions.
+	///
+	/// Stores that can't enumerate their sessions return an error.
+	async fn count(&self) -> SessionResult<usize> {
+		Err(SessionError::Other)
+	}
+	/// Wait for pending writes and flush any buffered state to the backend.
+	///
+	/// This is called when the rocket shuts down, so that a store buffering
+	/// writes doesn't lose them. The default implementation does nothing, as
+	/// the stores of this crate write through.
+	async fn drain(&self) -> SessionResult<()> {
+		Ok(())
+	}
+	/// Short name of the kind of store, used in traces.
+	///
+	/// Defaults to the name of the type.
+	fn backend(&self) -> &'static str {
+		std::any::type_name::<Self>()
+	}
+	/// Check that the store can be reached.
+	///
+	/// The default implementation looks up a session that doesn't exist.
+	async fn ping(&self) -> SessionResult<()> {
+		self.get("ping").await.map(|_| ())
+	}
+	/// Get the store as [ExpiredCleanup], if it has to remove expired
+	/// sessions itself.
+	///
+	/// The fairing uses it to clear expired sessions every
+	/// [SessionStore::cleanup_interval]. Defaults to [None], for stores whose
+	/// backend reaps expired sessions, like redis.
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		None
+	}
+}
+
+/// Removal of expired sessions, for stores whose backend doesn't reap them.
+///
+/// Stores implementing it return themselves from [Store::expired_cleanup].
+#[rocket::async_trait]
+pub trait ExpiredCleanup: Send + Sync {
+	/// Remove at most `limit` expired sessions, returning how many were
+	/// removed.
+	///
+	/// Bounding each call lets a large backlog be cleared a batch at a time.
+	async fn clear_expired(&self, limit: usize) -> SessionResult<usize>;
+}
+
+/// A type meant to be stored in sessions.
+///
+/// It gathers the bounds the stores of this crate put on values, so that a
+/// type missing one of them is reported where it is declared rather than
+/// where it is first stored. It is implemented with the `SessionValue`
+/// derive of the `macros` feature, or by hand.
+pub trait SessionValueMarker: Clone + Serialize + DeserializeOwned + Send + Sync + 'static {}
+
+/// Implements [Store] for pointers to a store, forwarding every method to it.
+///
+/// This allows sharing a store between a [SessionStore] and the rest of an
+/// application, for instance by giving a clone of an [Arc] to the former.
+macro_rules! forward_store {
+	($($pointer:ty),*) => {$(
+		#[rocket::async_trait]
+		impl<S> Store for $pointer
+		where
+			S: Store + ?Sized + 'static,
+			S::Value: Send,
+		{
+			type Value = S::Value;
+
+			async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
+				(**self).get(id).await
+			}
+
+			async fn set(
+				&self,
+				id: &str,
+				value: Self::Value,
+				duration: Duration,
+			) -> SessionResult<()> {
+				(**self).set(id, value, duration).await
+			}
+
+			async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+				(**self).touch(id, duration).await
+			}
+
+			async fn remove(&self, id: &str) -> SessionResult<bool> {
+				(**self).remove(id).await
+			}
+
+			async fn contains(&self, id: &str) -> SessionResult<bool> {
+				(**self).contains(id).await
+			}
+
+			async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+				(**self).ttl(id).await
+			}
+
+			async fn get_with_expiry(
+				&self,
+				id: &str,
+			) -> SessionResult<Option<(Self::Value, Duration)>> {
+				(**self).get_with_expiry(id).await
+			}
+
+			async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+				(**self).created_at(id).await
+			}
+
+			async fn value_hash(&self, id: &str) -> SessionResult<Option<u64>> {
+				(**self).value_hash(id).await
+			}
+
+			fn encode_token(
+				&self,
+				value: Option<&Self::Value>,
+				duration: Duration,
+			) -> SessionResult<Option<String>> {
+				(**self).encode_token(value, duration)
+			}
+
+			async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+				(**self).associate_with(user_key, id).await
+			}
+
+			async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+				(**self).list_sessions_for(user_key).await
+			}
+
+			async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+				(**self).dissociate(user_key, id).await
+			}
+
+			async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Self::Value>>> {
+				(**self).get_many(ids).await
+			}
+
+			async fn replace(
+				&self,
+				id: &str,
+				value: Self::Value,
+				duration: Duration,
+			) -> SessionResult<Option<Self::Value>> {
+				(**self).replace(id, value, duration).await
+			}
+
+			async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+				(**self).remove_many(ids).await
+			}
+
+			async fn rename(
+				&self,
+				old_id: &str,
+				new_id: &str,
+				duration: Duration,
+			) -> SessionResult<bool> {
+				(**self).rename(old_id, new_id, duration).await
+			}
+
+			async fn rename_with(
+				&self,
+				old_id: &str,
+				new_id: &str,
+				value: Self::Value,
+				duration: Duration,
+			) -> SessionResult<()> {
+				(**self).rename_with(old_id, new_id, value, duration).await
+			}
+
+			async fn compare_and_swap(
+				&self,
+				id: &str,
+				expected: Option<Self::Value>,
+				value: Self::Value,
+				duration: Duration,
+			) -> SessionResult<bool> {
+				(**self).compare_and_swap(id, expected, value, duration).await
+			}
+
+			async fn acquire_lock(
+				&self,
+				id: &str,
+				holder: &str,
+				ttl: Duration,
+			) -> SessionResult<bool> {
+				(**self).acquire_lock(id, holder, ttl).await
+			}
+
+			async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+				(**self).release_lock(id, holder).await
+			}
+
+			async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+				(**self).list_ids(offset, limit).await
+			}
+
+			async fn count(&self) -> SessionResult<usize> {
+				(**self).count().await
+			}
+
+			async fn drain(&self) -> SessionResult<()> {
+				(**self).drain().await
+			}
+
+			fn backend(&self) -> &'static str {
+				(**self).backend()
+			}
+
+			async fn ping(&self) -> SessionResult<()> {
+				(**self).ping().await
+			}
+
+			fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+				(**self).expired_cleanup()
+			}
+		}
+	)*};
+}
+
+forward_store!(Arc<S>, Box<S>, &'static S);
+
+/// How session tokens are generated and mapped to keys in the store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenScheme {
+	/// The token is a random string, used as is as the key in the store.
+	#[default]
+	Plain,
+	/// The token is `selector.verifier`, made of two random strings, and the
+	/// key in the store is the selector followed by a SHA-256 hash of the
+	/// verifier.
+	///
+	/// The store never sees the verifier, so a leaked dump of the store can't
+	/// be replayed as cookies. A token with a wrong verifier maps to a key
+	/// that doesn't exist and is treated as a session without a value. Since
+	/// the key is derived from the hash, there is no stored hash to compare
+	/// against and nothing to learn from timing the lookup.
+	///
+	/// This scheme only applies to tokens generated by the session store, so
+	/// it shouldn't be combined with stores that keep the value in the token.
+	SplitVerifier,
+}
+
+impl TokenScheme {
+	fn generate(self, charset: &[u8]) -> SessionID {
+		let new_id = || new_id(ID_LENGTH, charset);
+		match self {
+			Self::Plain => SessionID::plain(new_id()),
+			Self::SplitVerifier => SessionID::split(&new_id(), &new_id()),
+		}
+	}
+
+	/// Parses a token sent by the client, rejecting malformed ones.
+	fn parse(self, token: String) -> Option<SessionID> {
+		match self {
+			Self::Plain => Some(SessionID::plain(token)),
+			Self::SplitVerifier => {
+				let (selector, verifier) = token.split_once('.')?;
+				if selector.is_empty() || verifier.is_empty() {
+					return None;
+				}
+				Some(SessionID::split(selector, verifier))
+			}
+		}
+	}
+}
+
+/// The token identifying a session, as sent to the client.
+///
+/// It serializes as a plain string and is parsed with [FromStr], which
+/// checks that the token is at least as long as generated tokens and only
+/// made of characters allowed in a cookie value by RFC 6265.
+///
+/// Ids parsed or deserialized this way use the token as the key, as with
+/// [TokenScheme::Plain]. Use [SessionStore::parse_id] to get the key the
+/// store would use for a token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionID {
+	/// The token sent to the client.
+	token: String,
+	/// The key of the session in the store.
+	key: String,
+}
+
+impl SessionID {
+	fn plain(token: String) -> Self {
+		Self {
+			key: token.clone(),
+			token,
+		}
+	}
+
+	fn split(selector: &str, verifier: &str) -> Self {
+		let hash: String = Sha256::digest(verifier.as_bytes())
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect();
+		Self {
+			token: format!("{}.{}", selector, verifier),
+			key: format!("{}.{}", selector, hash),
+		}
+	}
+
+	fn token(&self) -> &str {
+		&self.token
+	}
+
+	fn with_key(mut self, key: String) -> Self {
+		self.key = key;
+		self
+	}
+
+	/// The key of the session in the store.
+	///
+	/// This is the token itself, unless the token was generated with
+	/// [TokenScheme::SplitVerifier] or the store has a
+	/// [key mapper](SessionStore::key_mapper). Ids parsed with [FromStr] always
+	/// use the token as the key.
+	pub fn key(&self) -> &str {
+		&self.key
+	}
+
+	/// The token as a string.
+	pub fn as_str(&self) -> &str {
+		&self.token
+	}
+}
+
+impl AsRef<str> for SessionID {
+	fn as_ref(&self) -> &str {
+		&self.token
+	}
+}
+
+impl fmt::Display for SessionID {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.token)
+	}
+}
+
+/// Error returned when parsing an invalid [SessionID].
+#[derive(Error, Debug)]
+#[error("invalid session id")]
+pub struct InvalidSessionID;
+
+/// Whether the name is a token as defined by RFC 6265.
+fn is_cookie_name(name: &str) -> bool {
+	let is_token_char = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+	!name.is_empty() && name.chars().all(is_token_char)
+}
+
+impl FromStr for SessionID {
+	type Err = InvalidSessionID;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.len() < ID_LENGTH || !s.bytes().all(is_cookie_octet) {
+			return Err(InvalidSessionID);
+		}
+		Ok(Self::plain(s.to_owned()))
+	}
+}
+
+impl Serialize for SessionID {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.token)
+	}
+}
+
+impl<'de> Deserialize<'de> for SessionID {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		s.parse().map_err(de::Error::custom)
+	}
+}
+
+/// Token state of a request, shared by every [Session] guard of that request.
+///
+/// This lives in the request-local cache so that the fairing can issue
+/// the cookie for a token that was regenerated while handling the request.
+#[derive(Debug)]
+struct SessionState {
+	token: SyncMutex<SessionID>,
+	/// Whether the token differs from the one sent by the client.
+	changed: AtomicBool,
+	/// Whether the session was removed while handling the request.
+	destroyed: AtomicBool,
+	/// Whether the value was set while handling the request.
+	dirty: AtomicBool,
+	/// Whether the token was generated for the request but not yet checked
+	/// for collisions.
+	unchecked: AtomicBool,
+	/// Whether a value is stored under the token, when the request knows it,
+	/// so that writes can tell whether they create the session.
+	stored: SyncMutex<Option<bool>>,
+	/// Whether the cookie is removed from the client with the response,
+	/// until the session is written again.
+	cookie_cleared: AtomicBool,
+	/// Status of the token sent by the client, when a security observer
+	/// needs it.
+	status: Option<TokenStatus>,
+	/// The `Option<T>` read by [Session::get], until the session changes.
+	value: SyncMutex<Option<Box<dyn Any + Send + Sync>>>,
+	/// The hash of the value, with [SessionStore::value_hash], from when it
+	/// was last read or written, until the session changes.
+	hash: SyncMutex<Option<u64>>,
+	/// The writes buffered by [transactions](transaction), until they are
+	/// committed.
+	pending: SyncMutex<Option<transaction::PendingWrites>>,
+	/// Where the token was read from, and so where it is sent back to.
+	source: TokenSource,
+}
+
+/// Where the token of a request is read from, set by the first session
+/// guard of the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TokenSource {
+	/// The session cookie, sent back with `Set-Cookie`.
+	Cookie,
+	/// The `Authorization: Bearer` header, falling back to the cookie, sent
+	/// back with the `X-Session-Token` header.
+	Bearer,
+}
+
+impl SessionState {
+	fn new(token: SessionID, changed: bool) -> Self {
+		Self {
+			token: SyncMutex::new(token),
+			changed: AtomicBool::new(changed),
+			destroyed: AtomicBool::new(false),
+			dirty: AtomicBool::new(false),
+			unchecked: AtomicBool::new(false),
+			stored: SyncMutex::new(None),
+			cookie_cleared: AtomicBool::new(false),
+			status: None,
+			value: SyncMutex::new(None),
+			hash: SyncMutex::new(None),
+			pending: SyncMutex::new(None),
+			source: TokenSource::Cookie,
+		}
+	}
+
+	fn changed(&self) -> bool {
+		self.changed.load(Ordering::Relaxed)
+	}
+
+	fn destroyed(&self) -> bool {
+		self.destroyed.load(Ordering::Relaxed)
+	}
+
+	fn dirty(&self) -> bool {
+		self.dirty.load(Ordering::Relaxed)
+	}
+
+	fn mark_dirty(&self) {
+		self.dirty.store(true, Ordering::Relaxed);
+		self.set_stored(true);
+		self.cookie_cleared.store(false, Ordering::Relaxed);
+	}
+
+	fn stored(&self) -> Option<bool> {
+		*self.stored.lock().expect("session state poisoned")
+	}
+
+	fn set_stored(&self, stored: bool) {
+		*self.stored.lock().expect("session state poisoned") = Some(stored);
+	}
+
+	fn cookie_cleared(&self) -> bool {
+		self.cookie_cleared.load(Ordering::Relaxed)
+	}
+
+	#[cfg(feature = "auth")]
+	fn clear_cookie(&self) {
+		self.cookie_cleared.store(true, Ordering::Relaxed);
+	}
+
+	fn token(&self) -> SessionID {
+		self.token.lock().expect("session state poisoned").clone()
+	}
+
+	fn set_token(&self, token: SessionID) {
+		*self.token.lock().expect("session state poisoned") = token;
+		self.changed.store(true, Ordering::Relaxed);
+		self.invalidate();
+	}
+
+	/// The value cached by [Session::get], if it was read since the session
+	/// last changed.
+	fn cached<T: Clone + 'static>(&self) -> Option<Option<T>> {
+		let value = self.value.lock().expect("session state poisoned");
+		value.as_ref()?.downcast_ref::<Option<T>>().cloned()
+	}
+
+	/// Runs a function on the value cached by [Session::get], without
+	/// cloning it, if it was read since the session last changed.
+	fn inspect_cached<T: 'static, R>(&self, f: impl FnOnce(Option<&T>) -> R) -> Option<R> {
+		let value = self.value.lock().expect("session state poisoned");
+		let value = value.as_ref()?.downcast_ref::<Option<T>>()?;
+		Some(f(value.as_ref()))
+	}
+
+	fn cache<T: Send + Sync + 'static>(&self, value: Option<T>) {
+		self.set_stored(value.is_some());
+		*self.value.lock().expect("session state poisoned") = Some(Box::new(value));
+	}
+
+	fn invalidate(&self) {
+		*self.value.lock().expect("session state poisoned") = None;
+		*self.hash.lock().expect("session state poisoned") = None;
+	}
+
+	/// The hash kept by [SessionState::set_hash], if the session didn't
+	/// change since.
+	fn hash(&self) -> Option<u64> {
+		*self.hash.lock().expect("session state poisoned")
+	}
+
+	fn set_hash(&self, hash: Option<u64>) {
+		*self.hash.lock().expect("session state poisoned") = hash;
+	}
+}
+
+/// A [KeyMapper] applied for a given request.
+type RequestKeyMapper<'r> = dyn Fn(&str) -> String + Send + Sync + 'r;
+
+/// A request guard implementing [FromRequest] to retrive the session
+/// based on the cookie from the user.
+pub struct Session<'s, T: 'static> {
+	store: &'s State<SessionStore<T>>,
+	state: Arc<SessionState>,
+	/// The key mapper of the store, applied for the request.
+	key_mapper: Option<Box<RequestKeyMapper<'s>>>,
+}
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone,
+{
+	/// Get the session value from the store.
+	///
+	/// Fails with [SessionError::NotFound] if there is no initialized session
+	/// value or if the value has expired. When the store has a
+	/// [validator](SessionStore::with_validator) rejecting the value, the
+	/// session is removed and [SessionError::NotFound] is returned. With
+	/// [SessionStore::auto_create], a default value is stored and returned
+	/// instead.
+	///
+	/// The value is kept for the rest of the request, shared by every
+	/// [Session] of the request, so further calls don't reach the store until
+	/// the session is set, removed or has its token regenerated. Changes made
+	/// to the store by other means during the request aren't seen.
+	///
+	/// A missing session and a store that can't be reached are told apart by
+	/// the error, so routes can send visitors without a session to a login
+	/// page while responding with the error status when the store is down:
+	///
+	/// ```no_run
+	/// # use rocket::{get, response::Redirect};
+	/// # use rocket_session_store::{Session, SessionError, SessionResult};
+	/// #[get("/account")]
+	/// async fn account(session: Session<'_, String>) -> SessionResult<Result<String, Redirect>> {
+	///     match session.get().await {
+	///         Ok(user) => Ok(Ok(format!("Signed in as {}", user))),
+	///         Err(SessionError::NotFound) => Ok(Err(Redirect::to("/login"))),
+	///         Err(e) => Err(e),
+	///     }
+	/// }
+	/// ```
+	pub async fn get(&self) -> SessionResult<T> {
+		self.get_opt().await?.ok_or(SessionError::NotFound)
+	}
+
+	/// Get the session value from the store, or [None] if there is none.
+	///
+	/// This works like [Session::get], returning `Ok(None)` rather than
+	/// [SessionError::NotFound], as [Session::get] did before the error had
+	/// variants.
+	pub async fn get_opt(&self) -> SessionResult<Option<T>> {
+		if let Some(value) = self.state.cached() {
+			return Ok(value);
+		}
+		let result = self
+			.instrumented("get", async {
+				let value = self.store.store.get(self.state.token().key()).await?;
+				trace::record_hit(value.is_some());
+				Ok(value)
+			})
+			.await;
+		self.store.meter().read(&result);
+		let value = result?;
+		let value = match (value, &self.store.validator) {
+			(Some(value), Some(validator)) if !validator.validate(&value).await => {
+				log::debug!("Session value rejected by the validator, removing the session.");
+				self.remove().await?;
+				None
+			}
+			(value, _) => value,
+		};
+		let value = match (value, &self.store.auto_create) {
+			(None, Some(create)) => {
+				let value = create();
+				self.set(value.clone()).await?;
+				Some(value)
+			}
+			(value, _) => value,
+		};
+		self.state
+			.set_hash(value.as_ref().and_then(|value| self.store.hash(value)));
+		self.state.cache(value.clone());
+		Ok(value)
+	}
+
+	/// Get the token of the session.
+	///
+	/// This is the token sent to the client, which changes when the token is
+	/// regenerated.
+	pub fn id(&self) -> SessionID {
+		self.state.token()
+	}
+
+	/// Get an owned handle to the session, which can outlive the request.
+	///
+	/// See [OwnedSession].
+	pub fn to_owned(&self) -> OwnedSession<T> {
+		OwnedSession::new(self.store, self.state.clone())
+	}
+
+	/// Whether the value of the session was set while handling the request.
+	///
+	/// Guards of the same request share this, so a session only read by the
+	/// handler isn't dirty, even if another guard read it too.
+	pub fn is_dirty(&self) -> bool {
+		self.state.dirty()
+	}
+
+	/// Get the session of a request outside of a route, such as in a catcher.
+	///
+	/// Catchers only receive the request, so they can't use the request
+	/// guard. The session returned here is the one the handler saw: it shares
+	/// the state of the request, including a token regenerated by the
+	/// handler, and changes made through it are sent to the client by the
+	/// fairing like any other.
+	///
+	/// Returns [None] if there is no [SessionStore] for this value type.
+	///
+	/// ```no_run
+	/// # use rocket::{catch, Request};
+	/// # use rocket_session_store::Session;
+	/// #[catch(401)]
+	/// async fn unauthorized(request: &Request<'_>) -> &'static str {
+	///     if let Some(session) = Session::<String>::of(request).await {
+	///         let _ = session.remove().await;
+	///     }
+	///     "You have been logged out."
+	/// }
+	/// ```
+	pub async fn of(request: &'s Request<'_>) -> Option<Session<'s, T>> {
+		Self::from_source(request, TokenSource::Cookie).await
+	}
+
+	/// Get the session of a request, reading the token from the given source
+	/// unless a guard of the request already read it.
+	pub(crate) async fn from_source(
+		request: &'s Request<'_>,
+		source: TokenSource,
+	) -> Option<Session<'s, T>> {
+		let store = State::<SessionStore<T>>::get(request.rocket())?;
+		let state = store.request_state(request, source).await;
+		let key_mapper = store.key_mapper.clone().map(|mapper| {
+			Box::new(move |key: &str| mapper(request, key)) as Box<RequestKeyMapper<'_>>
+		});
+		Some(Session {
+			store,
+			state,
+			key_mapper,
+		})
+	}
+
+	/// Get the session value, or a redirect to the given url if there is none.
+	///
+	/// This is meant for routes that require a session, such as redirecting
+	/// to a login page. Failing to access the store also redirects, see
+	/// [Session::get] to respond with an error instead.
+	pub async fn get_or_redirect(&self, url: &str) -> Result<T, Redirect> {
+		match self.get_opt().await {
+			Ok(Some(value)) => Ok(value),
+			Ok(None) => {
+				log::debug!("No session value, redirecting to {}.", url);
+				Err(Redirect::to(url.to_owned()))
+			}
+			Err(e) => {
+				log::warn!("could not read the session, redirecting to {}: {}", url, e);
+				Err(Redirect::to(url.to_owned()))
+			}
+		}
+	}
+
+	/// Sets the session value from the store.
+	///
+	/// This will refresh the expiration timer. Values over the
+	/// [maximum size](SessionStore::max_value_size) are rejected.
+	pub async fn set(&self, value: T) -> SessionResult<()> {
+		self.store.check_value_size(&value)?;
+		let duration = self.store.jittered_duration(Some(&value));
+		self.set_for(value, duration).await
+	}
+
+	/// Sets the session value without checking its
+	/// [size](SessionStore::max_value_size).
+	///
+	/// This works like [Session::set], for the rare value which is meant to
+	/// be larger than the limit.
+	pub async fn set_unchecked(&self, value: T) -> SessionResult<()> {
+		let duration = self.store.jittered_duration(Some(&value));
+		self.set_for(value, duration).await
+	}
+
+	/// Sets the session value, storing the session without an expiry.
+	///
+	/// The session lives until it is removed, and [Session::touch] leaves it
+	/// as it is. Setting the value again with [Session::set] or regenerating
+	/// its token gives it the duration of the store back. The cookie is issued
+	/// as for other sessions.
+	pub async fn set_permanent(&self, value: T) -> SessionResult<()> {
+		self.store.check_value_size(&value)?;
+		self.set_for(value, NO_EXPIRY).await
+	}
+
+	async fn set_for(&self, value: T, duration: Duration) -> SessionResult<()> {
+		self.state.invalidate();
+		self.instrumented("set", async {
+			self.check_new_token().await?;
+			let store = &self.store.store;
+			let token = self.store.token_for(&self.state, Some(&value), duration)?;
+			let created_value = self
+				.store
+				.events
+				.as_ref()
+				.filter(|_| self.state.stored() != Some(true))
+				.map(|_| value.clone());
+			let hash = self.store.hash(&value);
+			// Whether the write creates the session. When the request doesn't
+			// know, it is only learnt from the value replaced when the events
+			// or the metrics need it.
+			let created = match self.state.stored() {
+				None if self.store.events.is_some() || self.store.meter().counts_sessions() => {
+					store.replace(token.key(), value, duration).await?.is_none()
+				}
+				stored => {
+					store.set(token.key(), value, duration).await?;
+					stored == Some(false)
+				}
+			};
+			self.state.mark_dirty();
+			self.state.set_hash(hash);
+			self.store.renewed(token.key(), duration);
+			if created {
+				self.store.meter().active_sessions(1.0);
+				if let (Some(events), Some(value)) = (&self.store.events, created_value) {
+					log_event("created", events.created(token.key(), &value).await);
+				}
+			}
+			Ok(())
+		})
+		.await
+	}
+
+	/// Sets the session value, returning the value it replaces.
+	///
+	/// This works like [Session::set], refreshing the expiry of the session,
+	/// and exchanges the value in a single store operation with
+	/// [Store::replace], which is atomic when the store overrides it. This
+	/// suits switching accounts and merging the cart of the previous one,
+	/// rather than a [Session::get] followed by a [Session::set]. Swapping
+	/// the value of an empty session returns [None] and creates it, as
+	/// [Session::set] does, firing the created event.
+	///
+	/// Along with [Session::update], which computes the new value from the
+	/// current one, this reads and modifies the session in one step.
+	pub async fn swap(&self, value: T) -> SessionResult<Option<T>> {
+		self.store.check_value_size(&value)?;
+		self.state.invalidate();
+		self.instrumented("swap", async {
+			self.check_new_token().await?;
+			let duration = self.store.jittered_duration(Some(&value));
+			let token = self.store.token_for(&self.state, Some(&value), duration)?;
+			let created_value = self.store.events.is_some().then(|| value.clone());
+			let hash = self.store.hash(&value);
+			let previous = self
+				.store
+				.store
+				.replace(token.key(), value, duration)
+				.await?;
+			self.state.mark_dirty();
+			self.state.set_hash(hash);
+			self.store.renewed(token.key(), duration);
+			if previous.is_none() {
+				self.store.meter().active_sessions(1.0);
+				if let (Some(events), Some(value)) = (&self.store.events, created_value) {
+					log_event("created", events.created(token.key(), &value).await);
+				}
+			}
+			Ok(previous)
+		})
+		.await
+	}
+
+	/// Sets the session value, returning the value it replaces.
+	///
+	/// This is the same as [Session::swap].
+	pub async fn replace(&self, value: T) -> SessionResult<Option<T>> {
+		self.swap(value).await
+	}
+
+	/// Sets the session value computed from the current one, without losing
+	/// the updates of concurrent requests.
+	///
+	/// The value is read from the store, bypassing the value cached for the
+	/// request, and written back with [Store::compare_and_swap] only if no
+	/// other request changed it in between. Otherwise the function runs
+	/// again on the new value after a short random wait, up to 10 times
+	/// before failing with [SessionError]. The function may thus run more
+	/// than once, and shouldn't have side effects. Returns the value set.
+	///
+	/// This needs a store supporting [Store::compare_and_swap], such as
+	/// [RedisStore](crate::redis::RedisStore) or
+	/// [SerializingMemoryStore](memory::SerializingMemoryStore).
+	///
+	/// ```no_run
+	/// # use rocket::post;
+	/// # use rocket_session_store::{Session, SessionResult};
+	/// #[post("/visit")]
+	/// async fn visit(session: Session<'_, u64>) -> SessionResult<String> {
+	///     let visits = session.update(|visits| visits.unwrap_or(0) + 1).await?;
+	///     Ok(visits.to_string())
+	/// }
+	/// ```
+	pub async fn update(&self, f: impl Fn(Option<T>) -> T + Send + Sync) -> SessionResult<T> {
+		self.state.invalidate();
+		self.instrumented("update", async {
+			self.check_new_token().await?;
+			let token = self.state.token();
+			let store = &self.store.store;
+			for attempt in 0..UPDATE_ATTEMPTS {
+				if attempt > 0 {
+					// Spread the retries of concurrent updates.
+					let wait = OsRng.gen_range(0..=attempt as u64);
+					rocket::tokio::time::sleep(Duration::from_millis(wait)).await;
+				}
+				let current = store.get(token.key()).await?;
+				let created = current.is_none();
+				let value = f(current.clone());
+				self.store.check_value_size(&value)?;
+				let duration = self.store.jittered_duration(Some(&value));
+				if !store
+					.compare_and_swap(token.key(), current, value.clone(), duration)
+					.await?
+				{
+					continue;
+				}
+				self.state.mark_dirty();
+				self.store.renewed(token.key(), duration);
+				if created {
+					self.store.meter().active_sessions(1.0);
+					if let Some(ref events) = self.store.events {
+						log_event("created", events.created(token.key(), &value).await);
+					}
+				}
+				self.state.set_hash(self.store.hash(&value));
+				self.state.cache(Some(value.clone()));
+				return Ok(value);
+			}
+			log::warn!(
+				"the session kept changing, giving up updating it after {} attempts",
+				UPDATE_ATTEMPTS
+			);
+			Err(SessionError::Other)
+		})
+		.await
+	}
+
+	/// Refreshes the expiration timer on the sesion in the store.
+	///
+	/// Returns the remaining time to live of the session, which is
+	/// [NO_EXPIRY] for sessions that never expire, or [None] if there is no
+	/// session to touch. When the session was renewed within the
+	/// [debounce window](SessionStore::touch_debounce), the store isn't
+	/// reached and the time to live left since then is returned.
+	pub async fn touch(&self) -> SessionResult<Option<Duration>> {
+		let token = self.state.token();
+		let debounce = self.store.touch_debounce.as_ref();
+		if let Some(remaining) = debounce.and_then(|debounce| debounce.remaining(token.key())) {
+			return Ok(Some(remaining));
+		}
+		let duration = self.store.session_duration(&self.state).await?;
+		let jittered = self.store.jittered(duration);
+		let ttl = self
+			.instrumented("touch", self.store.store.touch(token.key(), jittered))
+			.await?;
+		if let Some(debounce) = debounce {
+			match ttl {
+				Some(ttl) if ttl != NO_EXPIRY => debounce.renewed(token.key(), jittered),
+				_ => debounce.forget(token.key()),
+			}
+		}
+		Ok(ttl)
+	}
+
+	/// Removes the session from the store.
+	///
+	/// Returns whether there was a live session to remove. The cookie is
+	/// removed either way.
+	pub async fn remove(&self) -> SessionResult<bool> {
+		self.state.invalidate();
+		self.instrumented("remove", async {
+			let token = self.state.token();
+			let removed = self.store.store.remove(token.key()).await?;
+			if let Some(ref debounce) = self.store.touch_debounce {
+				debounce.forget(token.key());
+			}
+			self.store.token_for(&self.state, None, self.store.duration)?;
+			self.state.destroyed.store(true, Ordering::Relaxed);
+			self.state.set_stored(false);
+			if removed {
+				self.store.meter().active_sessions(-1.0);
+				if let Some(ref events) = self.store.events {
+					log_event("destroyed", events.destroyed(token.key()).await);
+				}
+			}
+			Ok(removed)
+		})
+		.await
+	}
+
+	/// Associates the session with a user.
+	///
+	/// Associations belong to the current token, so a session should be
+	/// associated again after regenerating its token.
+	pub async fn associate_with(&self, user_key: &str) -> SessionResult<()> {
+		self.store
+			.store
+			.associate_with(user_key, self.state.token().key())
+			.await
+	}
+
+	/// Sets the session value and associates the session with a user, then
+	/// removes the oldest sessions of that user so that at most `max_sessions`
+	/// remain.
+	///
+	/// The current session is never removed, even if `max_sessions` is zero.
+	pub async fn set_for_user_limited(
+		&self,
+		user_key: &str,
+		value: T,
+		max_sessions: usize,
+	) -> SessionResult<()> {
+		self.set(value).await?;
+		self.associate_with(user_key).await?;
+		let token = self.state.token();
+		let store = &self.store.store;
+		let sessions = store.list_sessions_for(user_key).await?;
+		let excess = sessions.len().saturating_sub(max_sessions);
+		for id in sessions.iter().take(excess) {
+			if id == token.key() {
+				continue;
+			}
+			let removed = store.remove(id).await?;
+			store.dissociate(user_key, id).await?;
+			if !removed {
+				continue;
+			}
+			if let Some(ref events) = self.store.events {
+				log_event("destroyed", events.destroyed(id).await);
+			}
+		}
+		Ok(())
+	}
+
+	/// Moves the session to a newly generated token.
+	///
+	/// The session is moved to the new token with [Store::rename], in a
+	/// single operation for stores that support it. The new token is sent to
+	/// the client when the response is issued. Regenerating the token after a
+	/// privilege change, such as logging in, protects against session
+	/// fixation.
+	pub async fn regenerate_token(&self) -> SessionResult<()> {
+		self.instrumented(
+			"regenerate_token",
+			self.store.regenerate(&self.state, |id| self.map_key(id)),
+		)
+		.await
+	}
+
+	/// Moves the session to a newly generated token, transforming its value.
+	///
+	/// This works like [Session::regenerate_token], except that the current
+	/// value is passed through `migrate` before being stored under the new
+	/// token, for instance to turn an anonymous cart into the cart of the
+	/// user logging in. If `migrate` returns [None], the new token has no
+	/// value. A migrated value over the
+	/// [maximum size](SessionStore::max_value_size) is rejected, leaving the
+	/// session under its current token.
+	pub async fn regenerate_token_with_migration<F>(&self, migrate: F) -> SessionResult<()>
+	where
+		F: FnOnce(Option<T>) -> Option<T> + Send,
+	{
+		let migrate = |value| {
+			let value = migrate(value);
+			if let Some(ref value) = value {
+				self.store.check_value_size(value)?;
+			}
+			Ok(value)
+		};
+		self.instrumented(
+			"regenerate_token",
+			self.store
+				.regenerate_with(&self.state, migrate, |id| self.map_key(id)),
+		)
+		.await
+	}
+
+	/// Moves the session to a newly generated token holding `value`, writing
+	/// the value and removing the previous session in a single store
+	/// operation for stores that support it, see [Store::rename_with].
+	pub(crate) async fn regenerate_token_to(&self, value: T) -> SessionResult<()> {
+		self.store.check_value_size(&value)?;
+		self.instrumented(
+			"regenerate_token",
+			self.store
+				.regenerate_to(&self.state, Some(value), |id| self.map_key(id)),
+		)
+		.await?;
+		self.state.mark_dirty();
+		Ok(())
+	}
+
+	/// Moves a session created by the request to an unused token before it
+	/// is first written, with [SessionStore::collision_detection].
+	async fn check_new_token(&self) -> SessionResult<()> {
+		if !self.state.unchecked.load(Ordering::Relaxed) {
+			return Ok(());
+		}
+		let token = self.state.token();
+		let unused = self
+			.store
+			.unused_token(token.clone(), |id| self.map_key(id))
+			.await?;
+		if unused != token {
+			self.state.set_token(unused);
+		}
+		self.state.unchecked.store(false, Ordering::Relaxed);
+		Ok(())
+	}
+
+	/// Maps the key of a new token with the key mapper of the store.
+	fn map_key(&self, id: SessionID) -> SessionID {
+		match self.key_mapper {
+			Some(ref mapper) => {
+				let key = mapper(id.key());
+				id.with_key(key)
+			}
+			None => id,
+		}
+	}
+
+	/// Runs an operation on the session, within a span when the `tracing`
+	/// feature is enabled and measured when the `metrics` feature is.
+	async fn instrumented<R>(
+		&self,
+		op: &'static str,
+		operation: impl Future<Output = SessionResult<R>>,
+	) -> SessionResult<R> {
+		let operation = trace::traced(op, &*self.store.store, &self.state, operation);
+		self.store.meter().measured(op, operation).await
+	}
+}
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone + Serialize,
+{
+	/// Get the session value deserialized into another type.
+	///
+	/// This is useful when a loosely typed value, such as a
+	/// [serde_json::Value], holds different shapes at different points.
+	///
+	/// Returns [None] if there is no session value or if the value
+	/// can't be deserialized into `U`.
+	pub async fn get_typed<U: DeserializeOwned>(&self) -> SessionResult<Option<U>> {
+		Ok(self
+			.get_opt()
+			.await?
+			.and_then(|value| serde_json::to_value(value).ok())
+			.and_then(|value| serde_json::from_value(value).ok()))
+	}
+}
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone + Hash,
+{
+	/// Get a hash of the session value.
+	///
+	/// This allows telling whether the value changed between requests, for
+	/// instance to only re-issue a token derived from it when needed, by
+	/// keeping the hash instead of the whole value. Returns [None] if there is
+	/// no session value.
+	///
+	/// With [SessionStore::with_value_hash], the hash is computed when the
+	/// value is written, and the store keeps it next to the value, so other
+	/// requests read it with [Store::value_hash] without deserializing the
+	/// value. Otherwise the value is read from the store once and hashed on
+	/// every call. It uses [DefaultHasher], which may change between Rust
+	/// releases, so hashes should only be compared within the same build of
+	/// the application.
+	pub async fn value_hash(&self) -> SessionResult<Option<u64>> {
+		if let Some(hash) = self.state.hash() {
+			return Ok(Some(hash));
+		}
+		if let Some(hash) = self
+			.state
+			.inspect_cached(|value: Option<&T>| value.map(hash_value))
+		{
+			return Ok(hash);
+		}
+		if self.store.value_hash.is_some() {
+			let hash = self
+				.instrumented("value_hash", async {
+					self.store.store.value_hash(self.state.token().key()).await
+				})
+				.await?;
+			self.state.set_hash(hash);
+			return Ok(hash);
+		}
+		let Some(value) = self.get_opt().await? else {
+			return Ok(None);
+		};
+		let hash = self.state.hash().unwrap_or_else(|| hash_value(&value));
+		Ok(Some(hash))
+	}
+}
+
+fn log_event(event: &str, result: SessionResult<()>) {
+	if let Err(e) = result {
+		log::warn!("session event `{}` failed: {}", event, e);
+	}
+}
+
+#[rocket::async_trait]
+impl<T, 'r, 's> FromRequest<'r> for Session<'s, T>
+where
+	T: Send + Sync + 'static + Clone,
+	'r: 's,
+{
+	type Error = ();
+	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+		let session = Session::of(request)
+			.await
+			.expect("Session store must be set in fairing");
+		Outcome::Success(session)
+	}
+}
+
+/// The cookie options for the session cookie. Currently only a small subset of
+/// available options via the CookieBuilder are supported.
+#[derive(Clone)]
+pub struct CookieConfig {
+	/// A string indicating the path of the cookie.
+	///
+	/// Defaults to the [base path](SessionStore::base_path) of the store if
+	/// not specified, or else to the mount point shared by every route of
+	/// the rocket when it ignites, such as `/app` when the whole application
+	/// is mounted there, falling back to `/`.
+	pub path: Option<String>,
+	/// The same site policy of the cookie.
+	///
+	/// Defaults to `Lax` if not specified.
+	pub same_site: Option<SameSite>,
+	/// Whether the cookie is only to be sent over HTTPS
+	///
+	/// Defaults to whether Rocket is configured with TLS if not specified.
+	pub secure: Option<bool>,
+	/// Whether the cookie is only to be sent over HTTP(S), and not made available to client JavaScript
+	pub http_only: bool,
+	/// Whether the cookie is partitioned by the top-level site, as proposed
+	/// by CHIPS, for applications embedded in other sites.
+	///
+	/// Partitioned cookies must be secure, so this makes the cookie secure
+	/// unless `secure` is set, and the rocket fails to ignite if `secure` is
+	/// set to `false`.
+	pub partitioned: bool,
+}
+
+impl Default for CookieConfig {
+	/// Create default cookie config.
+	fn default() -> Self {
+		Self {
+			path: None,
+			same_site: None,
+			secure: None,
+			http_only: true,
+			partitioned: false,
+		}
+	}
+}
+
+/// A cookie the session was stored under before being renamed, which the
+/// fairing removes from browsers, see [SessionStore::legacy_cookies].
+#[derive(Clone, Debug)]
+pub struct LegacyCookie {
+	/// The name of the old cookie.
+	pub name: String,
+	/// The path the old cookie was set on.
+	///
+	/// Defaults to the path of the session cookie if not specified.
+	pub path: Option<String>,
+	/// The domain the old cookie was set on, if it was set on one.
+	pub domain: Option<String>,
+}
+
+impl LegacyCookie {
+	/// Create a legacy cookie with the given name, set on the path of the
+	/// session cookie and without a domain.
+	pub fn new(name: impl Into<String>) -> Self {
+		Self {
+			name: name.into(),
+			path: None,
+			domain: None,
+		}
+	}
+
+	/// Sets the path the old cookie was set on.
+	pub fn with_path(mut self, path: impl Into<String>) -> Self {
+		self.path = Some(path.into());
+		self
+	}
+
+	/// Sets the domain the old cookie was set on.
+	pub fn with_domain(mut self, domain: impl Into<String>) -> Self {
+		self.domain = Some(domain.into());
+		self
+	}
+}
+
+/// Store that keeps tracks of sessions
+pub struct SessionStore<T> {
+	/// The store that will keep track of sessions.
+	///
+	/// It is shared, so the application can reach the same store through
+	/// [SessionStore::store].
+	pub store: Arc<dyn Store<Value = T>>,
+	/// The name of the cookie to be used for sessions.
+	///
+	/// This will be the name the cookie will be stored under in the browser.
+	/// It must be a token as defined by RFC 6265, which is checked when the
+	/// rocket ignites, or at compile time with the `session_name!` macro of
+	/// the `macros` feature.
+	pub name: String,
+	/// The duration of the session.
+	///
+	/// When so much time passes after storing or touching a session, it expires
+	/// and won't be accesible. Sessions whose value is given another duration
+	/// by [SessionStore::duration_for] use that one instead.
+	pub duration: Duration,
+	/// Picks the duration of a session from its value.
+	///
+	/// This gives sessions of different kinds different lifetimes, such as
+	/// 30 minutes for anonymous visitors and 14 days for logged in users. It
+	/// is used when the value is set, when the session is touched and when
+	/// the token is regenerated, as well as for the `Max-Age` of the cookie
+	/// with a [renewal threshold](SessionStore::renew_cookie_threshold).
+	/// Sessions read by the handler are measured from the value read,
+	/// others cost one [Store::get]. A session setting a new value, such as
+	/// on login, takes the duration of the new value, and its cookie is
+	/// re-issued with it.
+	pub duration_for: Option<Arc<DurationFor<T>>>,
+	/// The cookie options.
+	///
+	/// This will be used in the fairing to build the cookie
+	pub cookie: CookieConfig,
+	/// Callbacks invoked when sessions are created, destroyed or regenerated.
+	pub events: Option<Arc<dyn SessionEvents<T>>>,
+	/// Age after which the token of a session is regenerated.
+	///
+	/// When a request uses a session whose token is older than this, the
+	/// token is regenerated while issuing the response, exactly as if the
+	/// handler had called [Session::regenerate_token]. This happens at most
+	/// once per request and is skipped when the handler already regenerated
+	/// the token or removed the session. The age is taken from
+	/// [Store::created_at], so stores that don't keep track of creation
+	/// times are never regenerated automatically.
+	pub auto_regenerate_after: Option<Duration>,
+	/// Fraction of the duration under which the cookie is re-issued.
+	///
+	/// When set, the cookie carries a `Max-Age` and is only sent when the
+	/// token changes or when the remaining time to live of the session drops
+	/// below `threshold * duration`. This costs one [Store::ttl] call
+	/// per response. When unset, the cookie is sent on every response
+	/// without a `Max-Age`.
+	pub renew_cookie_threshold: Option<f64>,
+	/// Maximum length in bytes of a token accepted from the client.
+	///
+	/// Longer cookies are ignored, as if the client hadn't sent any, so that
+	/// clients can't make the store use arbitrarily large keys. Defaults to
+	/// 512 bytes, which stores keeping the value in the token, such as
+	/// [cookie::CookieStore], may need to raise.
+	pub max_token_length: usize,
+	/// How tokens are generated and mapped to keys in the store.
+	///
+	/// Defaults to [TokenScheme::Plain].
+	pub token_scheme: TokenScheme,
+	/// Characters the random parts of tokens are made of.
+	///
+	/// Defaults to [ID_CHARSET_ALPHANUMERIC]. Tokens keep their length, so a
+	/// larger charset makes them harder to guess. Use
+	/// [SessionStore::with_id_charset] to have it checked.
+	pub id_charset: &'static [u8],
+	/// Callback receiving security relevant facts about every request.
+	///
+	/// It is invoked once per request while issuing the response, including
+	/// requests that don't use a [Session]. Checking whether the token sent
+	/// by the client is valid costs one [Store::get] per request.
+	pub security_observer: Option<Arc<SecurityObserver>>,
+	/// Name of the store in metrics, recorded with the `metrics` feature.
+	///
+	/// This distinguishes the series of applications using several stores.
+	/// Defaults to [Store::backend].
+	pub metrics_name: Option<String>,
+	/// Check run on every value read through [Session::get].
+	///
+	/// Sessions whose value is rejected are removed.
+	pub validator: Option<Arc<dyn SessionValidator<T>>>,
+	/// Derives the key of a session in the store from the request and the
+	/// key of its token.
+	///
+	/// This namespaces sessions by something in the request, such as the
+	/// tenant named by the `Host` header, so the sessions of a tenant can be
+	/// purged on their own. The cookie still carries the token, and the
+	/// mapped key is what the store sees, before any prefix of the store
+	/// itself. Sessions seeded with [testing::seed_session] and tokens
+	/// encoding their value, as with [Store::encode_token], aren't mapped.
+	pub key_mapper: Option<Arc<KeyMapper>>,
+	/// Interval at which expired sessions are cleared.
+	///
+	/// When set, a task started at liftoff clears the expired sessions of
+	/// stores implementing [ExpiredCleanup] at this interval, until the
+	/// rocket shuts down. Other stores are left alone.
+	pub cleanup_interval: Option<Duration>,
+	/// Time after renewing a session during which touching it again doesn't
+	/// reach the store.
+	///
+	/// Handlers touching the session on every request would otherwise renew
+	/// it many times a minute. When the session was set or touched within
+	/// this window, [Session::touch] is skipped, so the session is left with
+	/// at least `duration - debounce` to live. When it was renewed is kept in
+	/// memory, per instance of the application, and shared by the clones of
+	/// the [TouchDebounce].
+	pub touch_debounce: Option<TouchDebounce>,
+	/// Spread applied to the duration of sessions.
+	///
+	/// When set, sessions are stored and touched with a duration picked at
+	/// random within `duration ± jitter`, so that sessions created together,
+	/// such as by a bulk import, don't all expire at once. The duration never
+	/// goes below one second, or below `duration` when it is shorter than
+	/// that. Sessions without an expiry are left alone.
+	pub ttl_jitter: Option<Duration>,
+	/// Status of the responses made from a [SessionError].
+	///
+	/// Defaults to 500 when unset. Applications can return 503 or 504
+	/// instead, to tell clients that the store being down is transient and
+	/// the request can be retried. The status is shared by every store of
+	/// the rocket, so when several stores set one, the first one attached
+	/// wins.
+	pub error_status: Option<Status>,
+	/// Whether newly generated tokens are checked against the store.
+	///
+	/// Collisions between random tokens are already vanishingly unlikely.
+	/// When enabled, a token is only used once [Store::contains] tells that no
+	/// live session has it, costing one more call when a new session is first
+	/// written and when a token is regenerated. After 3 tokens in a row are
+	/// found in use, the operation fails with [SessionError].
+	pub collision_detection: bool,
+	/// Whether tokens sent by the client are only used when the store holds
+	/// a session under them.
+	///
+	/// Otherwise, a token planted in the browser of a victim, such as by a
+	/// sibling subdomain setting the cookie, becomes the token of the session
+	/// the victim creates next, which the attacker then knows. When enabled,
+	/// an unknown or expired token is replaced by a new one, as if the client
+	/// hadn't sent any, so sessions are only ever stored under tokens the
+	/// server generated. This costs one [Store::contains] per request using a
+	/// [Session]. When the store can't be reached, the token is kept.
+	pub strict_tokens: bool,
+	/// Callback modifying the response of requests using a session, based
+	/// on its value.
+	///
+	/// It runs while issuing the response, after the handler and any
+	/// [automatic regeneration](SessionStore::auto_regenerate_after), for
+	/// requests that used a [Session] and whose session has a value, such as
+	/// to add a header telling the role of the user. The value read by the
+	/// handler is reused, otherwise it costs one [Store::get]. Sessions
+	/// removed by the handler are skipped.
+	pub response_hook: Option<Arc<ResponseHook<T>>>,
+	/// Largest size of a value written through a [Session], in bytes.
+	///
+	/// Values measured above it with [SessionStore::value_size] are rejected
+	/// with [SessionError::ValueTooLarge] before the store is reached, so a
+	/// handler storing a large result by mistake fails instead of filling the
+	/// store. [Session::set_unchecked] writes a value without checking it.
+	/// Without a [value size](SessionStore::value_size), values aren't
+	/// checked.
+	pub max_value_size: Option<usize>,
+	/// Measures the size of values for [SessionStore::max_value_size].
+	///
+	/// [SessionStore::with_max_value_size] measures values serialized to
+	/// JSON unless another size is set, such as an estimate for values which
+	/// aren't serialized by the store. The size is measured apart from the
+	/// store, so it is an approximation of what the store writes: a store
+	/// with another [codec](codec::SessionCodec), compressing or encrypting
+	/// values writes a different number of bytes.
+	pub value_size: Option<Arc<ValueSize<T>>>,
+	/// Hashes values as they are written or read, for [Session::value_hash].
+	///
+	/// The hash is kept for the rest of the request, so that hashing the
+	/// value doesn't need it read back from the store, and other requests
+	/// read the hash the store keeps next to the value. This costs a hash on
+	/// every write, so it is only set by [SessionStore::with_value_hash].
+	pub value_hash: Option<Arc<ValueHash<T>>>,
+	/// Creates the value of sessions read without one.
+	///
+	/// When set, [Session::get] stores a value made by this function instead
+	/// of returning [None], and the cookie is issued with the response, so
+	/// handlers can count on every visitor having a session, such as to hold
+	/// their locale or A/B test bucket. The value is made when the session
+	/// is first read in a request, including through guards reading it such
+	/// as [SessionExtract](extract::SessionExtract), so requests that don't
+	/// read the session don't write it either.
+	pub auto_create: Option<Arc<AutoCreate<T>>>,
+	/// How long the [advisory lock](Session::lock) of a session is held at
+	/// most.
+	///
+	/// A lock still held after this long, such as by a request that crashed,
+	/// is released so that other requests can take it. Defaults to
+	/// [lock::DEFAULT_LOCK_TTL], 30 seconds.
+	pub lock_ttl: Duration,
+	/// The path the application is served under, used as the path of the
+	/// cookie unless [CookieConfig::path] is set.
+	///
+	/// Without it, the path of the cookie is the mount point shared by the
+	/// routes of the rocket. This sets it when the application is served
+	/// under a path its routes don't know about, such as behind a reverse
+	/// proxy forwarding `/app` to `/`.
+	pub base_path: Option<String>,
+	/// Cookies the session was stored under before the cookie was renamed.
+	///
+	/// Browsers keep sending an old cookie until it expires. Whenever the
+	/// session cookie is issued, the fairing expires each of these with a
+	/// removal cookie on their path and domain. Only cookies with exactly
+	/// these names are removed. The names must be valid cookie names other
+	/// than the name of the session cookie, which is checked when the rocket
+	/// ignites.
+	pub legacy_cookies: Vec<LegacyCookie>,
+	/// End of the window during which the
+	/// [legacy cookies](SessionStore::legacy_cookies) are removed on every
+	/// response, rather than only along with the session cookie.
+	///
+	/// This also reaches visitors whose requests don't use the session, at
+	/// the cost of a header per legacy cookie on every response.
+	pub legacy_cleanup_until: Option<SystemTime>,
+	/// Options of the companion CSRF cookie, issued along with the session
+	/// cookie when set.
+	///
+	/// See the [csrf] module. Enabled with the `csrf` feature.
+	#[cfg(feature = "csrf")]
+	pub csrf: Option<csrf::CsrfConfig>,
+	#[cfg(feature = "prometheus")]
+	prometheus: Arc<prometheus::SessionMetrics>,
+}
+
+/// Clones share the underlying store, callbacks and debounce window, so
+/// sessions reached through either behave the same.
+impl<T> Clone for SessionStore<T> {
+	fn clone(&self) -> Self {
+		Self {
+			store: self.store.clone(),
+			name: self.name.clone(),
+			duration: self.duration,
+			duration_for: self.duration_for.clone(),
+			cookie: self.cookie.clone(),
+			events: self.events.clone(),
+			auto_regenerate_after: self.auto_regenerate_after,
+			renew_cookie_threshold: self.renew_cookie_threshold,
+			max_token_length: self.max_token_length,
+			token_scheme: self.token_scheme,
+			id_charset: self.id_charset,
+			security_observer: self.security_observer.clone(),
+			metrics_name: self.metrics_name.clone(),
+			validator: self.validator.clone(),
+			key_mapper: self.key_mapper.clone(),
+			cleanup_interval: self.cleanup_interval,
+			touch_debounce: self.touch_debounce.clone(),
+			ttl_jitter: self.ttl_jitter,
+			error_status: self.error_status,
+			collision_detection: self.collision_detection,
+			strict_tokens: self.strict_tokens,
+			response_hook: self.response_hook.clone(),
+			max_value_size: self.max_value_size,
+			value_size: self.value_size.clone(),
+			value_hash: self.value_hash.clone(),
+			auto_create: self.auto_create.clone(),
+			lock_ttl: self.lock_ttl,
+			base_path: self.base_path.clone(),
+			legacy_cookies: self.legacy_cookies.clone(),
+			legacy_cleanup_until: self.legacy_cleanup_until,
+			#[cfg(feature = "csrf")]
+			csrf: self.csrf.clone(),
+			#[cfg(feature = "prometheus")]
+			prometheus: self.prometheus.clone(),
+		}
+	}
+}
+
+impl<T> SessionStore<T> {
+	/// Creates a session store with the default cookie options.
+	pub fn new(
+		store: impl Store<Value = T> + 'static,
+		name: impl Into<String>,
+		duration: Duration,
+	) -> Self {
+		Self {
+			store: Arc::new(store),
+			name: name.into(),
+			duration,
+			duration_for: None,
+			cookie: CookieConfig::default(),
+			events: None,
+			auto_regenerate_after: None,
+			renew_cookie_threshold: None,
+			max_token_length: DEFAULT_MAX_TOKEN_LENGTH,
+			token_scheme: TokenScheme::Plain,
+			id_charset: ID_CHARSET_ALPHANUMERIC,
+			security_observer: None,
+			metrics_name: None,
+			validator: None,
+			key_mapper: None,
+			cleanup_interval: None,
+			touch_debounce: None,
+			ttl_jitter: None,
+			error_status: None,
+			collision_detection: false,
+			strict_tokens: false,
+			response_hook: None,
+			max_value_size: None,
+			value_size: None,
+			value_hash: None,
+			auto_create: None,
+			lock_ttl: lock::DEFAULT_LOCK_TTL,
+			base_path: None,
+			legacy_cookies: Vec::new(),
+			legacy_cleanup_until: None,
+			#[cfg(feature = "csrf")]
+			csrf: None,
+			#[cfg(feature = "prometheus")]
+			prometheus: Arc::new(prometheus::SessionMetrics::new()),
+		}
+	}
+
+	/// Get a handle to the underlying store.
+	///
+	/// This allows reaching the sessions from outside of a [Session], for
+	/// instance from admin routes through `&State<SessionStore<T>>`.
+	///
+	/// ```no_run
+	/// # use rocket::{post, State};
+	/// # use rocket_session_store::{SessionResult, SessionStore};
+	/// #[post("/admin/logout/<id>")]
+	/// async fn force_logout(
+	///     id: &str,
+	///     sessions: &State<SessionStore<String>>,
+	/// ) -> SessionResult<Option<()>> {
+	///     Ok(sessions.store().remove(id).await?.then_some(()))
+	/// }
+	/// ```
+	pub fn store(&self) -> Arc<dyn Store<Value = T>> {
+		self.store.clone()
+	}
+
+	/// Get the underlying store as its concrete type, if it is one.
+	///
+	/// This allows handlers to use operations specific to a store. The type
+	/// must be the one given to [SessionStore::new]: a store wrapping another
+	/// one doesn't downcast to the inner store, so wrappers should provide an
+	/// `inner()` method to reach it.
+	///
+	/// ```no_run
+	/// # use rocket::{get, State};
+	/// # use rocket_session_store::{memory::MemoryStore, SessionStore};
+	/// #[get("/admin/sessions")]
+	/// async fn session_count(sessions: &State<SessionStore<String>>) -> Option<String> {
+	///     let store = sessions.downcast_store::<MemoryStore<String>>()?;
+	///     Some(store.session_count().await.to_string())
+	/// }
+	/// ```
+	pub fn downcast_store<S: Store<Value = T> + 'static>(&self) -> Option<&S> {
+		(*self.store).as_any().downcast_ref()
+	}
+
+	/// Sets the cookie options.
+	pub fn with_cookie(mut self, cookie: CookieConfig) -> Self {
+		self.cookie = cookie;
+		self
+	}
+
+	/// Sets whether the cookie is partitioned by the top-level site.
+	///
+	/// See [CookieConfig::partitioned].
+	pub fn with_partitioned_cookie(mut self, partitioned: bool) -> Self {
+		self.cookie.partitioned = partitioned;
+		self
+	}
+
+	/// Sets the callbacks invoked on the lifecycle of sessions.
+	pub fn with_events(mut self, events: Arc<dyn SessionEvents<T>>) -> Self {
+		self.events = Some(events);
+		self
+	}
+
+	/// Sets the check run on every value read through [Session::get].
+	///
+	/// See [SessionStore::validator].
+	pub fn with_validator(mut self, validator: Arc<dyn SessionValidator<T>>) -> Self {
+		self.validator = Some(validator);
+		self
+	}
+
+	/// Sets the function deriving the key of a session in the store from
+	/// the request.
+	///
+	/// See [SessionStore::key_mapper].
+	pub fn with_key_mapper(
+		mut self,
+		mapper: impl Fn(&Request<'_>, &str) -> String + Send + Sync + 'static,
+	) -> Self {
+		self.key_mapper = Some(Arc::new(mapper));
+		self
+	}
+
+	/// Sets the callback modifying the response of requests using a session.
+	///
+	/// See [SessionStore::response_hook].
+	///
+	/// ```no_run
+	/// # use std::time::Duration;
+	/// # use rocket::http::Header;
+	/// # use rocket_session_store::{memory::MemoryStore, SessionStore};
+	/// let store: SessionStore<String> =
+	///     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+	///         .with_response_hook(|role: &String, response| {
+	///             response.set_header(Header::new("X-User-Role", role.clone()));
+	///         });
+	/// ```
+	pub fn with_response_hook(
+		mut self,
+		hook: impl Fn(&T, &mut Response<'_>) + Send + Sync + 'static,
+	) -> Self {
+		self.response_hook = Some(Arc::new(hook));
+		self
+	}
+
+	/// Sets how the size of values is measured for
+	/// [SessionStore::max_value_size].
+	///
+	/// See [SessionStore::value_size].
+	pub fn with_value_size(mut self, size: impl Fn(&T) -> usize + Send + Sync + 'static) -> Self {
+		self.value_size = Some(Arc::new(size));
+		self
+	}
+
+	/// Sets the function picking the duration of a session from its value.
+	///
+	/// See [SessionStore::duration_for].
+	///
+	/// ```no_run
+	/// # use std::time::Duration;
+	/// # use rocket_session_store::{memory::MemoryStore, SessionStore};
+	/// let store: SessionStore<Option<u64>> =
+	///     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(30 * 60))
+	///         .with_duration_for(|user_id: &Option<u64>| match user_id {
+	///             Some(_) => Duration::from_secs(14 * 24 * 3600),
+	///             None => Duration::from_secs(30 * 60),
+	///         });
+	/// ```
+	pub fn with_duration_for(
+		mut self,
+		duration_for: impl Fn(&T) -> Duration + Send + Sync + 'static,
+	) -> Self {
+		self.duration_for = Some(Arc::new(duration_for));
+		self
+	}
+
+	/// Creates the value of sessions read without one with the given
+	/// function.
+	///
+	/// See [SessionStore::auto_create]. Values implementing [Default] can be
+	/// created with `with_auto_create(T::default)`.
+	pub fn with_auto_create(mut self, create: impl Fn() -> T + Send + Sync + 'static) -> Self {
+		self.auto_create = Some(Arc::new(create));
+		self
+	}
+
+	/// Sets how long the advisory lock of a session is held at most.
+	///
+	/// See [SessionStore::lock_ttl].
+	pub fn with_lock_ttl(mut self, ttl: Duration) -> Self {
+		self.lock_ttl = ttl;
+		self
+	}
+
+	/// Clears expired sessions at the given interval.
+	///
+	/// See [SessionStore::cleanup_interval].
+	pub fn with_cleanup_interval(mut self, interval: Duration) -> Self {
+		self.cleanup_interval = Some(interval);
+		self
+	}
+
+	/// Skips touching sessions renewed within the given window.
+	///
+	/// See [SessionStore::touch_debounce].
+	pub fn with_touch_debounce(mut self, debounce: Duration) -> Self {
+		self.touch_debounce = Some(TouchDebounce::new(debounce));
+		self
+	}
+
+	/// Checks that newly generated tokens aren't in use.
+	///
+	/// See [SessionStore::collision_detection].
+	pub fn with_collision_detection(mut self, enabled: bool) -> Self {
+		self.collision_detection = enabled;
+		self
+	}
+
+	/// Replaces tokens sent by the client which have no session.
+	///
+	/// See [SessionStore::strict_tokens].
+	pub fn with_strict_tokens(mut self, enabled: bool) -> Self {
+		self.strict_tokens = enabled;
+		self
+	}
+
+	/// Sets the path the application is served under.
+	///
+	/// See [SessionStore::base_path].
+	pub fn with_base_path(mut self, path: impl Into<String>) -> Self {
+		self.base_path = Some(path.into());
+		self
+	}
+
+	/// Removes a cookie the session was stored under before the cookie was
+	/// renamed.
+	///
+	/// See [SessionStore::legacy_cookies].
+	pub fn with_legacy_cookie(mut self, cookie: LegacyCookie) -> Self {
+		self.legacy_cookies.push(cookie);
+		self
+	}
+
+	/// Removes the legacy cookies on every response until the given time.
+	///
+	/// See [SessionStore::legacy_cleanup_until].
+	pub fn with_legacy_cleanup_until(mut self, until: SystemTime) -> Self {
+		self.legacy_cleanup_until = Some(until);
+		self
+	}
+
+	/// Issues a companion CSRF cookie along with the session cookie.
+	///
+	/// See [SessionStore::csrf].
+	#[cfg(feature = "csrf")]
+	pub fn with_csrf(mut self, config: csrf::CsrfConfig) -> Self {
+		self.csrf = Some(config);
+		self
+	}
+
+	/// Spreads the duration of sessions within `duration ± jitter`.
+	///
+	/// See [SessionStore::ttl_jitter].
+	pub fn with_ttl_jitter(mut self, jitter: Duration) -> Self {
+		self.ttl_jitter = Some(jitter);
+		self
+	}
+
+	/// Sets the status of the responses made from a [SessionError].
+	///
+	/// See [SessionStore::error_status].
+	///
+	/// # Panics
+	///
+	/// Panics if the status isn't a client or server error status.
+	pub fn with_error_status(mut self, status: Status) -> Self {
+		assert!(
+			matches!(
+				status.class(),
+				StatusClass::ClientError | StatusClass::ServerError
+			),
+			"the session error status must be an error status, not {}",
+			status
+		);
+		self.error_status = Some(status);
+		self
+	}
+
+	/// Get the duration of a session with the given value, or of the store
+	/// without a value.
+	fn duration_of(&self, value: Option<&T>) -> Duration {
+		match (&self.duration_for, value) {
+			(Some(duration_for), Some(value)) => duration_for(value),
+			_ => self.duration,
+		}
+	}
+
+	/// Get the duration to store or touch a session with, spread by the
+	/// [jitter](SessionStore::ttl_jitter), if any.
+	fn jittered_duration(&self, value: Option<&T>) -> Duration {
+		self.jittered(self.duration_of(value))
+	}
+
+	/// Spreads a duration by the [jitter](SessionStore::ttl_jitter), if any.
+	fn jittered(&self, duration: Duration) -> Duration {
+		let Some(jitter) = self.ttl_jitter else {
+			return duration;
+		};
+		if duration == NO_EXPIRY {
+			return duration;
+		}
+		let min = duration
+			.saturating_sub(jitter)
+			.max(MIN_JITTERED_DURATION)
+			.min(duration);
+		let max = duration.checked_add(jitter).unwrap_or(duration);
+		OsRng.gen_range(min..=max)
+	}
+
+	/// Regenerates the token of sessions older than the given age.
+	///
+	/// See [SessionStore::auto_regenerate_after].
+	pub fn with_auto_regenerate_after(mut self, age: Duration) -> Self {
+		self.auto_regenerate_after = Some(age);
+		self
+	}
+
+	/// Re-issues the cookie only when the session is close to expiring.
+	///
+	/// See [SessionStore::renew_cookie_threshold].
+	///
+	/// # Panics
+	///
+	/// Panics if the threshold is not between 0 and 1.
+	pub fn with_renew_cookie_threshold(mut self, threshold: f64) -> Self {
+		assert!(
+			(0.0..=1.0).contains(&threshold),
+			"the cookie renewal threshold must be between 0 and 1"
+		);
+		self.renew_cookie_threshold = Some(threshold);
+		self
+	}
+
+	/// Sets the maximum length of a token accepted from the client.
+	///
+	/// See [SessionStore::max_token_length].
+	pub fn with_max_token_length(mut self, length: usize) -> Self {
+		self.max_token_length = length;
+		self
+	}
 
-#[cfg(feature = "redis")]
-pub mod redis;
+	/// Sets how tokens are generated and mapped to keys in the store.
+	///
+	/// See [TokenScheme].
+	pub fn with_token_scheme(mut self, token_scheme: TokenScheme) -> Self {
+		self.token_scheme = token_scheme;
+		self
+	}
 
-use std::time::Duration;
+	/// Sets the characters the random parts of tokens are made of.
+	///
+	/// See [SessionStore::id_charset].
+	///
+	/// # Panics
+	///
+	/// Panics if the charset has fewer than two characters, repeats a
+	/// character, which would make it more likely than the others, or holds
+	/// a character that isn't allowed in cookies or the `.` separating the
+	/// parts of [TokenScheme::SplitVerifier] tokens.
+	pub fn with_id_charset(mut self, charset: &'static [u8]) -> Self {
+		assert!(
+			charset.len() >= 2,
+			"the id charset must have at least two characters"
+		);
+		for (i, &byte) in charset.iter().enumerate() {
+			assert!(
+				is_cookie_octet(byte) && byte != b'.',
+				"the id charset holds {:?}, which isn't allowed in tokens",
+				char::from(byte)
+			);
+			assert!(
+				!charset[..i].contains(&byte),
+				"the id charset repeats {:?}",
+				char::from(byte)
+			);
+		}
+		self.id_charset = charset;
+		self
+	}
 
-use rand::{rngs::OsRng, Rng};
-use rocket::{
-	fairing::{Fairing, Info, Kind},
-	http::{Cookie, SameSite, Status},
-	request::{FromRequest, Outcome},
-	response::Responder,
-	tokio::sync::Mutex,
-	Build, Request, Response, Rocket, State,
-};
-use thiserror::Error;
+	/// Get the entropy in bits of the random parts of new tokens.
+	///
+	/// Each character of the [charset](SessionStore::id_charset) carries
+	/// `log2(charset length)` bits. With [TokenScheme::SplitVerifier], this
+	/// is the entropy of the verifier alone.
+	pub fn id_entropy_bits(&self) -> f64 {
+		ID_LENGTH as f64 * (self.id_charset.len() as f64).log2()
+	}
 
-fn new_id(length: usize) -> String {
-	OsRng
-		.sample_iter(&rand::distributions::Alphanumeric)
-		.take(length)
-		.map(char::from)
-		.collect()
-}
+	/// Parses a token as the session store would parse it from a request,
+	/// following its [token scheme](SessionStore::token_scheme).
+	///
+	/// The [key mapper](SessionStore::key_mapper) isn't applied, as it needs
+	/// the request.
+	pub fn parse_id(&self, token: &str) -> Result<SessionID, InvalidSessionID> {
+		let id: SessionID = token.parse()?;
+		if token.len() > self.max_token_length {
+			return Err(InvalidSessionID);
+		}
+		self.token_scheme.parse(id.token).ok_or(InvalidSessionID)
+	}
 
-const ID_LENGTH: usize = 24;
+	/// Generates a new token with the scheme and charset of the store.
+	fn generate_token(&self) -> SessionID {
+		self.token_scheme.generate(self.id_charset)
+	}
 
-/// A generic store in which to write and retrive sessions either
-/// trough an in memory hashmap or a database connection.
-#[rocket::async_trait]
-pub trait Store: Send + Sync {
-	/// Type that is associated with sessions.
+	/// Sets the callback receiving security relevant facts about every
+	/// request.
 	///
-	/// The store will store and retrieve values of this type.
-	type Value;
-	/// Get the value from the store
-	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>>;
-	/// Set the value from the store
-	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()>;
-	/// Touch the value, refreshing its expiry time.
-	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<()>;
-	/// Remove the value from the store.
-	async fn remove(&self, id: &str) -> SessionResult<()>;
-}
-
-/// String representing the ID.
-#[derive(Debug, Clone)]
-struct SessionID(String);
+	/// See [SessionStore::security_observer]. Panics in the callback are
+	/// caught and logged, so it can never fail a request.
+	pub fn with_security_observer(
+		mut self,
+		observer: impl Fn(&SessionAuditRecord) + Send + Sync + 'static,
+	) -> Self {
+		self.security_observer = Some(Arc::new(observer));
+		self
+	}
 
-impl AsRef<str> for SessionID {
-	fn as_ref(&self) -> &str {
-		&self.0
+	/// Sets the name of the store in metrics.
+	///
+	/// See [SessionStore::metrics_name].
+	pub fn with_metrics_name(mut self, name: impl Into<String>) -> Self {
+		self.metrics_name = Some(name.into());
+		self
 	}
-}
 
-/// A request guard implementing [FromRequest] to retrive the session
-/// based on the cookie from the user.
-pub struct Session<'s, T: 'static> {
-	store: &'s State<SessionStore<T>>,
-	pub(crate) token: SessionID,
-}
+	/// Get the registry holding the Prometheus metrics of the store.
+	///
+	/// See [prometheus].
+	#[cfg(feature = "prometheus")]
+	pub fn prometheus_registry(&self) -> &::prometheus::Registry {
+		self.prometheus.registry()
+	}
 
-impl<'s, T> Session<'s, T> {
-	/// Get the session value from the store.
+	/// Creates a fairing mounting a route serving the Prometheus metrics of
+	/// the store on the given path.
 	///
-	/// Returns [None] if there is no initialized session value
-	/// or if the value has expired.
-	pub async fn get(&self) -> SessionResult<Option<T>> {
-		self.store.store.get(self.token.as_ref()).await
+	/// See [prometheus]. It is independent from the main fairing, so it
+	/// needs to be attached as well.
+	#[cfg(feature = "prometheus")]
+	pub fn metrics_fairing(&self, path: &'static str) -> prometheus::MetricsFairing {
+		prometheus::MetricsFairing::new(self.prometheus.clone(), path)
 	}
 
-	/// Sets the session value from the store.
+	/// Checks that the store can be reached, with [Store::ping].
 	///
-	/// This will refresh the expiration timer.
-	pub async fn set(&self, value: T) -> SessionResult<()> {
-		self.store
-			.store
-			.set(self.token.as_ref(), value, self.store.duration)
-			.await
+	/// It fails if the store doesn't answer within two seconds, so it can
+	/// back a readiness probe, as with [health::route].
+	pub async fn healthy(&self) -> SessionResult<()> {
+		health::ping(&*self.store).await
 	}
 
-	/// Refreshes the expiration timer on the sesion in the store.
-	pub async fn touch(&self) -> SessionResult<()> {
-		self.store
-			.store
-			.touch(self.token.as_ref(), self.store.duration)
-			.await
+	/// Waits for the pending writes of the store and flushes its buffered
+	/// state, with [Store::drain].
+	///
+	/// The fairing does this when the rocket shuts down, so it only needs to
+	/// be called when shutting down otherwise.
+	pub async fn drain(&self) -> SessionResult<()> {
+		self.store.drain().await
 	}
 
-	/// Removes the session from the store.
-	pub async fn remove(&self) -> SessionResult<()> {
-		self.store.store.remove(self.token.as_ref()).await
+	/// Creates a fairing mounting a health check route for the store.
+	///
+	/// See [HealthFairing]. It is independent from the main fairing, so it
+	/// has to be created before turning the store into it.
+	pub fn health_fairing(&self) -> HealthFairing<T> {
+		HealthFairing::new(self.store())
 	}
 }
 
-#[rocket::async_trait]
-impl<T, 'r, 's> FromRequest<'r> for Session<'s, T>
+impl<T> SessionStore<T>
 where
-	T: Send + Sync + 'static + Clone,
-	'r: 's,
+	T: Send + Sync + 'static,
 {
-	type Error = ();
-	async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-		let store: &State<SessionStore<T>> = request
-			.guard()
-			.await
-			.expect("Session store must be set in fairing");
-		let token: SessionID = request
+	/// A function to turn the store into a [Fairing] to attach on a rocket.
+	///
+	/// With the `okapi` feature, this also records the names of the cookie
+	/// and CSRF header to document for the guards of the store.
+	pub fn fairing(self) -> SessionStoreFairing<T> {
+		#[cfg(feature = "okapi")]
+		okapi::register(&self);
+		SessionStoreFairing {
+			store: Mutex::new(Some(self)),
+		}
+	}
+
+	/// Get the ids and values of all the live sessions associated with a
+	/// user, such as to show the active sessions of a user on an admin page.
+	///
+	/// The ids are the keys of the sessions in the store, as listed by
+	/// [Store::list_sessions_for], and the values are fetched with
+	/// [Store::get_many]. Sessions expiring between the two calls are left
+	/// out.
+	pub async fn get_all_for_user(&self, user_key: &str) -> SessionResult<Vec<(String, T)>> {
+		let ids = self.store.list_sessions_for(user_key).await?;
+		let values = self.store.get_many(&ids).await?;
+		Ok(ids
+			.into_iter()
+			.zip(values)
+			.filter_map(|(id, value)| Some((id, value?)))
+			.collect())
+	}
+
+	/// Hashes a value with [SessionStore::value_hash], if set.
+	fn hash(&self, value: &T) -> Option<u64> {
+		self.value_hash.as_ref().map(|hash| hash(value))
+	}
+
+	/// Checks a value against [SessionStore::max_value_size].
+	fn check_value_size(&self, value: &T) -> SessionResult<()> {
+		let (Some(limit), Some(size)) = (self.max_value_size, &self.value_size) else {
+			return Ok(());
+		};
+		let size = size(value);
+		if size > limit {
+			log::warn!(
+				"Session value of {} bytes is over the limit of {} bytes, not storing it.",
+				size,
+				limit
+			);
+			return Err(SessionError::ValueTooLarge { size, limit });
+		}
+		Ok(())
+	}
+
+	fn meter(&self) -> Meter<'_> {
+		Meter {
+			store: self
+				.metrics_name
+				.as_deref()
+				.unwrap_or_else(|| self.store.backend()),
+			#[cfg(feature = "prometheus")]
+			prometheus: &self.prometheus,
+		}
+	}
+
+	/// Records that a session was stored for the given duration, for the
+	/// [touch debounce](SessionStore::touch_debounce).
+	fn renewed(&self, key: &str, duration: Duration) {
+		let Some(ref debounce) = self.touch_debounce else {
+			return;
+		};
+		match duration {
+			NO_EXPIRY => debounce.forget(key),
+			_ => debounce.renewed(key, duration),
+		}
+	}
+
+	/// Get the duration of the session of a request, from the value read by
+	/// the handler or from the store when it is picked by
+	/// [SessionStore::duration_for].
+	async fn session_duration(&self, state: &SessionState) -> SessionResult<Duration>
+	where
+		T: 'static,
+	{
+		if self.duration_for.is_none() {
+			return Ok(self.duration);
+		}
+		if let Some(duration) = state.inspect_cached(|value: Option<&T>| self.duration_of(value)) {
+			return Ok(duration);
+		}
+		let value = self.store.get(state.token().key()).await?;
+		Ok(self.duration_of(value.as_ref()))
+	}
+
+	/// Get the path of the session cookie.
+	///
+	/// The fairing sets the path of the cookie when the rocket ignites, so
+	/// this only falls back to `/` for a store which wasn't attached.
+	fn cookie_path(&self) -> &str {
+		self.cookie.path.as_deref().unwrap_or("/")
+	}
+
+	/// Whether the legacy cookies are removed on every response.
+	fn legacy_cleanup(&self) -> bool {
+		self.legacy_cleanup_until
+			.is_some_and(|until| SystemTime::now() < until)
+	}
+
+	/// Adds a removal cookie for each of the legacy cookies to a response.
+	fn remove_legacy_cookies(&self, response: &mut Response<'_>) {
+		for legacy in &self.legacy_cookies {
+			let path = legacy.path.as_deref().unwrap_or(self.cookie_path());
+			let mut removal = Cookie::build(legacy.name.as_str()).path(path);
+			if let Some(ref domain) = legacy.domain {
+				removal = removal.domain(domain.as_str());
+			}
+			let mut removal = removal.build();
+			removal.make_removal();
+			response.adjoin_header(removal);
+		}
+	}
+
+	/// Get the session state of the request, reading the token sent by the
+	/// client on first use.
+	async fn request_state(&self, request: &Request<'_>, source: TokenSource) -> Arc<SessionState> {
+		request
 			.local_cache_async(async {
-				let cookies = request.cookies();
-				let token = cookies.get(store.name.as_str()).map_or_else(
-					|| SessionID(new_id(ID_LENGTH)),
-					|c| SessionID(String::from(c.value())),
-				);
-				token
+				let token = self.client_token(request, source);
+				// The status is checked before the handler can change the session.
+				let status = match self.security_observer {
+					Some(_) => Some(self.token_status(&token).await),
+					None => None,
+				};
+				let token = match token {
+					Ok(token) if self.strict_tokens && !self.is_known(&token).await => {
+						log::debug!("Replacing a `{}` session token unknown to the store.", self.name);
+						Err(TokenStatus::Expired)
+					}
+					token => token,
+				};
+				let mut state = match token {
+					Ok(token) => SessionState::new(token, false),
+					Err(_) => self.new_state(self.map_key(request, self.generate_token())),
+				};
+				state.status = status;
+				state.source = source;
+				Some(Arc::new(state))
 			})
 			.await
-			.clone();
+			.clone()
+			.expect("session state is set by the guard before any response")
+	}
 
-		let session = Session { store, token };
-		Outcome::Success(session)
+	/// Get the state of a session under a newly generated token.
+	fn new_state(&self, token: SessionID) -> SessionState {
+		let state = SessionState::new(token, true);
+		state
+			.unchecked
+			.store(self.collision_detection, Ordering::Relaxed);
+		state.set_stored(false);
+		state
 	}
-}
 
-/// The cookie options for the session cookie. Currently only a small subset of
-/// available options via the CookieBuilder are supported.
-#[derive(Clone)]
-pub struct CookieConfig {
-	/// A string indicating the path of the cookie.
+	/// Reads the token sent by the client, if it is acceptable.
 	///
-	/// Defaults to the request path if not specified.
-	pub path: Option<String>,
-	/// The same site policy of the cookie.
+	/// With [TokenSource::Bearer], a bearer token takes precedence over the
+	/// cookie. Fails with [TokenStatus::New] when there is no token and with
+	/// [TokenStatus::InvalidFormat] when it can't be used.
+	fn client_token(
+		&self,
+		request: &Request<'_>,
+		source: TokenSource,
+	) -> Result<SessionID, TokenStatus> {
+		let token = match (source, bearer::bearer_token(request)) {
+			(TokenSource::Bearer, Some(token)) => {
+				if self.cookie_token(request).is_some() {
+					log::warn!(
+						"Both a bearer token and a `{}` session cookie were sent, using the bearer token.",
+						self.name
+					);
+				}
+				token.to_owned()
+			}
+			_ => self.cookie_token(request).ok_or(TokenStatus::New)?,
+		};
+		self.parse_token(token)
+			.map(|token| self.map_key(request, token))
+			.ok_or(TokenStatus::InvalidFormat)
+	}
+
+	/// Parses a token sent by the client, or [None] if it is too long or
+	/// doesn't follow the token scheme.
+	fn parse_token(&self, token: String) -> Option<SessionID> {
+		if token.len() > self.max_token_length {
+			log::warn!(
+				"Ignoring a `{}` session token of {} bytes.",
+				self.name,
+				token.len()
+			);
+			return None;
+		}
+		self.token_scheme.parse(token)
+	}
+
+	/// Reads the session cookie sent by the client.
 	///
-	/// Defaults to `Lax` if not specified.
-	pub same_site: Option<SameSite>,
-	/// Whether the cookie is only to be sent over HTTPS
-	pub secure: bool,
-	/// Whether the cookie is only to be sent over HTTP(S), and not made available to client JavaScript
-	pub http_only: bool,
-}
+	/// When the client sends several cookies with the session name, for
+	/// instance set on different paths, the first one is used.
+	fn cookie_token(&self, request: &Request<'_>) -> Option<String> {
+		let mut tokens = request
+			.headers()
+			.get("Cookie")
+			.flat_map(|header| header.split(';'))
+			.filter_map(|cookie| Cookie::parse_encoded(cookie.trim()).ok())
+			.filter(|cookie| cookie.name() == self.name);
+		match tokens.next() {
+			Some(cookie) => {
+				if tokens.next().is_some() {
+					log::warn!(
+						"Multiple `{}` session cookies were sent, using the first one.",
+						self.name
+					);
+				}
+				Some(cookie.value().to_owned())
+			}
+			None => request
+				.cookies()
+				.get(&self.name)
+				.map(|cookie| cookie.value().to_owned()),
+		}
+	}
 
-impl Default for CookieConfig {
-	/// Create default cookie config.
-	fn default() -> Self {
-		Self {
-			path: None,
-			same_site: None,
-			secure: false,
-			http_only: true,
+	/// Maps the key of a token with the key mapper, if any.
+	fn map_key(&self, request: &Request<'_>, id: SessionID) -> SessionID {
+		match self.key_mapper {
+			Some(ref mapper) => {
+				let key = mapper(request, id.key());
+				id.with_key(key)
+			}
+			None => id,
+		}
+	}
+
+	/// Checks whether the token sent by the client refers to a session.
+	async fn token_status(&self, token: &Result<SessionID, TokenStatus>) -> TokenStatus {
+		match token {
+			Ok(token) => match self.store.get(token.key()).await {
+				Ok(Some(_)) => TokenStatus::Valid,
+				Ok(None) => TokenStatus::Expired,
+				Err(e) => {
+					log::warn!("could not check the session token: {}", e);
+					TokenStatus::Expired
+				}
+			},
+			Err(status) => *status,
+		}
+	}
+
+	/// Whether the store holds a session under a token sent by the client,
+	/// for [SessionStore::strict_tokens].
+	async fn is_known(&self, token: &SessionID) -> bool {
+		self.store.contains(token.key()).await.unwrap_or_else(|e| {
+			log::warn!("could not check the session token, keeping it: {}", e);
+			true
+		})
+	}
+
+	/// Passes the audit record of a request to the security observer.
+	async fn observe(&self, request: &Request<'_>, state: Option<&SessionState>) {
+		let observer = match self.security_observer {
+			Some(ref observer) => observer,
+			None => return,
+		};
+		let status = match state.and_then(|state| state.status) {
+			Some(status) => status,
+			None => {
+				let token = self.client_token(request, TokenSource::Cookie);
+				self.token_status(&token).await
+			}
+		};
+		let RequestStart(start) = request.local_cache(|| RequestStart(Instant::now()));
+		let record = SessionAuditRecord {
+			status,
+			path: request.uri().path().to_string(),
+			client_ip: request.client_ip(),
+			elapsed: start.elapsed(),
+		};
+		if catch_unwind(AssertUnwindSafe(|| observer(&record))).is_err() {
+			log::warn!("the session security observer panicked");
+		}
+	}
+
+	/// Moves the session to the token the store encodes its value in, if any.
+	fn token_for(
+		&self,
+		state: &SessionState,
+		value: Option<&T>,
+		duration: Duration,
+	) -> SessionResult<SessionID> {
+		match self.store.encode_token(value, duration)? {
+			Some(token) => {
+				let token = SessionID::plain(token);
+				state.set_token(token.clone());
+				Ok(token)
+			}
+			None => Ok(state.token()),
+		}
+	}
+
+	/// Moves the session to a new token, whose key is mapped with `map_key`.
+	async fn regenerate(
+		&self,
+		state: &SessionState,
+		map_key: impl Fn(SessionID) -> SessionID + Send + Sync,
+	) -> SessionResult<()> {
+		// Stores keeping the value in the token need it to make the new one.
+		if self.store.encode_token(None, self.duration)?.is_some() {
+			return self.regenerate_with(state, Ok, map_key).await;
+		}
+		let old = state.token();
+		let duration = self.jittered(self.session_duration(state).await?);
+		let new = self.new_token(map_key).await?;
+		self.store.rename(old.key(), new.key(), duration).await?;
+		self.regenerated(state, old, new).await;
+		Ok(())
+	}
+
+	async fn regenerate_with<F>(
+		&self,
+		state: &SessionState,
+		migrate: F,
+		map_key: impl Fn(SessionID) -> SessionID + Send + Sync,
+	) -> SessionResult<()>
+	where
+		F: FnOnce(Option<T>) -> SessionResult<Option<T>> + Send,
+	{
+		let value = migrate(self.store.get(state.token().key()).await?)?;
+		self.regenerate_to(state, value, map_key).await
+	}
+
+	/// Moves the session to a newly generated token holding `value`, or
+	/// without a value if it is [None], with [Store::rename_with].
+	async fn regenerate_to(
+		&self,
+		state: &SessionState,
+		value: Option<T>,
+		map_key: impl Fn(SessionID) -> SessionID + Send + Sync,
+	) -> SessionResult<()> {
+		let old = state.token();
+		let duration = self.jittered_duration(value.as_ref());
+		let new = match self.store.encode_token(value.as_ref(), duration)? {
+			Some(token) => SessionID::plain(token),
+			None => self.new_token(map_key).await?,
+		};
+		let stored = value.is_some();
+		match value {
+			Some(value) => {
+				self.store
+					.rename_with(old.key(), new.key(), value, duration)
+					.await?
+			}
+			None => drop(self.store.remove(old.key()).await?),
+		}
+		self.regenerated(state, old, new).await;
+		state.set_stored(stored);
+		Ok(())
+	}
+
+	/// Generates a new token, whose key is mapped with `map_key`, checking that
+	/// it isn't in use with [SessionStore::collision_detection].
+	async fn new_token(
+		&self,
+		map_key: impl Fn(SessionID) -> SessionID + Send + Sync,
+	) -> SessionResult<SessionID> {
+		let token = map_key(self.generate_token());
+		match self.collision_detection {
+			true => self.unused_token(token, map_key).await,
+			false => Ok(token),
+		}
+	}
+
+	/// Get the first token not used by a live session, starting with the
+	/// given one and generating others with `map_key`.
+	async fn unused_token(
+		&self,
+		mut token: SessionID,
+		map_key: impl Fn(SessionID) -> SessionID + Send + Sync,
+	) -> SessionResult<SessionID> {
+		let mut attempts = 1;
+		while self.store.contains(token.key()).await? {
+			if attempts == COLLISION_ATTEMPTS {
+				log::warn!(
+					"could not generate an unused session token in {} attempts",
+					COLLISION_ATTEMPTS
+				);
+				return Err(SessionError::Other);
+			}
+			log::warn!("a generated session token is already in use, generating another one");
+			attempts += 1;
+			token = map_key(self.generate_token());
+		}
+		Ok(token)
+	}
+
+	/// Switches the session to its new token and emits the event.
+	async fn regenerated(&self, state: &SessionState, old: SessionID, new: SessionID) {
+		state.set_token(new.clone());
+		if let Some(ref events) = self.events {
+			log_event(
+				"regenerated",
+				events.regenerated(old.key(), new.key()).await,
+			);
+		}
+	}
+
+	/// Regenerates the token if it's older than [SessionStore::auto_regenerate_after].
+	async fn auto_regenerate(&self, request: &Request<'_>, state: &SessionState) {
+		let Some(max_age) = self.auto_regenerate_after else {
+			return;
+		};
+		if state.changed() || state.destroyed() {
+			return;
+		}
+		let result = match self.store.created_at(state.token().key()).await {
+			Ok(Some(created)) if created.elapsed().unwrap_or_default() > max_age => {
+				self.regenerate(state, |id| self.map_key(request, id)).await
+			}
+			Ok(_) => Ok(()),
+			Err(e) => Err(e),
+		};
+		if let Err(e) = result {
+			log::warn!("could not regenerate the session token: {}", e);
+		}
+	}
+
+	/// Runs the [response hook](SessionStore::response_hook) on the value of
+	/// the session, if it has one.
+	async fn run_response_hook(&self, state: &SessionState, response: &mut Response<'_>) {
+		let Some(ref hook) = self.response_hook else {
+			return;
+		};
+		if state.destroyed() || state.cookie_cleared() {
+			return;
+		}
+		let cached = state.inspect_cached::<T, _>(|value| {
+			if let Some(value) = value {
+				hook(value, response);
+			}
+		});
+		if cached.is_some() {
+			return;
+		}
+		match self.store.get(state.token().key()).await {
+			Ok(Some(value)) => hook(&value, response),
+			Ok(None) => {}
+			Err(e) => log::warn!("could not read the session for the response hook: {}", e),
 		}
 	}
 }
 
-/// Store that keeps tracks of sessions
-pub struct SessionStore<T> {
-	/// The store that will keep track of sessions.
-	pub store: Box<dyn Store<Value = T>>,
-	/// The name of the cookie to be used for sessions.
+impl<T> SessionStore<T>
+where
+	T: Serialize + Send + Sync + 'static,
+{
+	/// Rejects values larger than the given number of bytes.
 	///
-	/// This will be the name the cookie will be stored under in the browser.
-	pub name: String,
-	/// The duration of the session.
+	/// See [SessionStore::max_value_size]. Values are measured serialized to
+	/// JSON, without keeping the JSON, unless
+	/// [another size](SessionStore::with_value_size) is set. This costs a
+	/// serialization on every write on top of the one of the store, and the
+	/// JSON size only approximates the bytes written by stores using another
+	/// [codec](codec::SessionCodec).
+	pub fn with_max_value_size(mut self, bytes: usize) -> Self {
+		self.max_value_size = Some(bytes);
+		if self.value_size.is_none() {
+			self.value_size = Some(Arc::new(json_size::<T>));
+		}
+		self
+	}
+
+	/// Exports a session as JSON, for a data export such as a subject
+	/// access request.
 	///
-	/// When so much time passes after storing or touching a session, it expires
-	/// and won't be accesible.
-	pub duration: Duration,
-	/// The cookie options.
+	/// The id is the key of the session in the store, as listed by
+	/// [Store::list_sessions_for]. The export is an object with the `id`, the
+	/// `value`, the `created_at` time in seconds since the Unix epoch, and the
+	/// remaining time to live in `expires_in_secs`. The times are `null`
+	/// when the store doesn't track them. Reading the session doesn't extend
+	/// its time to live. Returns [None] when there is no such session.
+	pub async fn export(&self, id: &str) -> SessionResult<Option<serde_json::Value>> {
+		let (value, ttl) = match self.store.get_with_expiry(id).await {
+			Ok(Some((value, ttl))) => (value, Some(ttl)),
+			Ok(None) => return Ok(None),
+			// Not every store knows the time to live of a session.
+			Err(_) => match self.store.get(id).await? {
+				Some(value) => (value, None),
+				None => return Ok(None),
+			},
+		};
+		let created_at = self.store.created_at(id).await.ok().flatten();
+		let value = serde_json::to_value(value).map_err(|e| {
+			log::warn!("could not serialize the session value: {}", e);
+			SessionError::Other
+		})?;
+		Ok(Some(serde_json::json!({
+			"id": id,
+			"value": value,
+			"created_at": created_at.and_then(|time| {
+				time.duration_since(SystemTime::UNIX_EPOCH).ok().map(|since| since.as_secs())
+			}),
+			"expires_in_secs": ttl.map(|ttl| ttl.as_secs()),
+		})))
+	}
+
+	/// Exports all the live sessions associated with a user, as with
+	/// [SessionStore::export].
 	///
-	/// This will be used in the fairing to build the cookie
-	pub cookie: CookieConfig,
+	/// Sessions that have expired since being associated are left out.
+	pub async fn export_all_for(&self, user_key: &str) -> SessionResult<Vec<serde_json::Value>> {
+		let mut exports = Vec::new();
+		for id in self.store.list_sessions_for(user_key).await? {
+			if let Some(export) = self.export(&id).await? {
+				exports.push(export);
+			}
+		}
+		Ok(exports)
+	}
 }
 
-impl<T> SessionStore<T> {
-	/// A function to turn the store into a [Fairing] to attach on a rocket.
-	pub fn fairing(self) -> SessionStoreFairing<T> {
-		SessionStoreFairing {
-			store: Mutex::new(Some(self)),
-		}
+impl<T> SessionStore<T>
+where
+	T: Hash + Send + Sync + 'static,
+{
+	/// Hashes values as they are written or read, so that
+	/// [Session::value_hash] doesn't read the value back.
+	///
+	/// Values written by other requests aren't deserialized either: their
+	/// hash is read with [Store::value_hash], so the store must keep hashes,
+	/// such as a [codec::CodecStore] with a [codec::HashedCodec] hashing the
+	/// same way. See [SessionStore::value_hash].
+	pub fn with_value_hash(mut self) -> Self {
+		self.value_hash = Some(Arc::new(hash_value::<T>));
+		self
 	}
 }
 
+/// When the request was received, cached by the fairing.
+struct RequestStart(Instant);
+
 /// The fairing for the session store.
 ///
 /// This shouldn't be created directly and you should
@@ -198,44 +3016,310 @@ pub struct SessionStoreFairing<T> {
 #[rocket::async_trait]
 impl<T> Fairing for SessionStoreFairing<T>
 where
-	T: 'static,
+	T: Send + Sync + 'static,
 {
 	fn info(&self) -> rocket::fairing::Info {
 		Info {
 			name: "Session Store",
-			kind: Kind::Ignite | Kind::Response | Kind::Singleton,
+			kind: Kind::Ignite
+				| Kind::Liftoff
+				| Kind::Request
+				| Kind::Response
+				| Kind::Shutdown
+				| Kind::Singleton,
 		}
 	}
 
-	async fn on_ignite(&self, rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+	async fn on_ignite(&self, mut rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
 		let mut lock = self.store.lock().await;
-		let store = lock.take().expect("Expected store");
+		let mut store = lock.take().expect("Expected store");
+		if !is_cookie_name(&store.name) {
+			log::error!("`{}` is not a valid session cookie name.", store.name);
+			return Err(rocket);
+		}
+		#[cfg(feature = "csrf")]
+		if let Some(ref csrf) = store.csrf {
+			if !is_cookie_name(&csrf.cookie_name) || csrf.cookie_name == store.name {
+				log::error!("`{}` is not a valid CSRF cookie name.", csrf.cookie_name);
+				return Err(rocket);
+			}
+		}
+		for legacy in &store.legacy_cookies {
+			if !is_cookie_name(&legacy.name) || legacy.name == store.name {
+				log::error!("`{}` is not a valid legacy session cookie name.", legacy.name);
+				return Err(rocket);
+			}
+		}
+		if store.cookie.partitioned && store.cookie.secure == Some(false) {
+			log::error!("The partitioned session cookie must be secure.");
+			return Err(rocket);
+		}
+		if store.cookie.path.is_none() {
+			let path = match store.base_path {
+				Some(ref path) => path.clone(),
+				None => mount_point(&rocket),
+			};
+			log::debug!("Setting the `{}` session cookie path to `{}`.", store.name, path);
+			store.cookie.path = Some(path);
+		}
+		if store.cookie.secure.is_none() {
+			let config = rocket.figment().extract::<rocket::Config>();
+			let tls = config.is_ok_and(|config| config.tls_enabled());
+			store.cookie.secure = Some(tls || store.cookie.partitioned);
+		}
+		if let Some(status) = store.error_status {
+			match rocket.state::<ErrorStatus>() {
+				Some(ErrorStatus(other)) if *other != status => log::warn!(
+					"Session errors already respond with {}, ignoring {}.",
+					other,
+					status
+				),
+				Some(_) => {}
+				None => rocket = rocket.manage(ErrorStatus(status)),
+			}
+		}
 		let rocket = rocket.manage(store);
 		Ok(rocket)
 	}
 
+	async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+		let Some(store) = rocket.state::<SessionStore<T>>() else {
+			return;
+		};
+		let Some(interval) = store.cleanup_interval else {
+			return;
+		};
+		if store.store.expired_cleanup().is_none() {
+			log::warn!("the session store doesn't clear expired sessions, not starting the cleanup task");
+			return;
+		}
+		let sessions = store.store.clone();
+		let shutdown = rocket.shutdown();
+		rocket::tokio::spawn(async move {
+			loop {
+				rocket::tokio::select! {
+					_ = shutdown.clone() => break,
+					_ = rocket::tokio::time::sleep(interval) => clear_expired(&*sessions).await,
+				}
+			}
+		});
+	}
+
+	async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+		request.local_cache(|| RequestStart(Instant::now()));
+	}
+
 	async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
-		let session: &SessionID = request.local_cache(|| SessionID("".into()));
-		if !session.0.is_empty() {
-			let store: &State<SessionStore<T>> = request.guard().await.expect("");
+		// The state is only cached by the session guards, so requests that
+		// didn't use the session are left alone.
+		let state: &Option<Arc<SessionState>> = request.local_cache(|| None);
+		let Some(store) = State::<SessionStore<T>>::get(request.rocket()) else {
+			return;
+		};
+		if let Some(state) = state {
+			let commit = state
+				.pending
+				.lock()
+				.expect("session state poisoned")
+				.as_ref()
+				.map(transaction::PendingWrites::commit);
+			if let Some(commit) = commit {
+				commit(request).await;
+			}
+		}
+		store.observe(request, state.as_deref()).await;
+		store.meter().request(state.is_some());
+		let legacy_cleanup = store.legacy_cleanup();
+		if legacy_cleanup {
+			store.remove_legacy_cookies(response);
+		}
+		if let Some(state) = state {
+			store.auto_regenerate(request, state).await;
+			store.run_response_hook(state, response).await;
+			if state.cookie_cleared() {
+				if state.source == TokenSource::Cookie {
+					let mut removal = Cookie::build(store.name.as_str())
+						.path(store.cookie_path())
+						.build();
+					removal.make_removal();
+					#[cfg(feature = "csrf")]
+					if let Some(ref csrf) = store.csrf {
+						let mut companion = removal.clone();
+						companion.set_name(csrf.cookie_name.as_str());
+						response.adjoin_header(companion);
+					}
+					response.adjoin_header(removal);
+				}
+				return;
+			}
+			let token = state.token();
+			let duration = match store.renew_cookie_threshold {
+				None => store.duration,
+				Some(_) => match store.session_duration(state).await {
+					Ok(duration) => duration,
+					Err(e) => {
+						log::warn!("could not get the session duration: {}", e);
+						return;
+					}
+				},
+			};
+			let max_age = match store.renew_cookie_threshold {
+				None => None,
+				// The duration may have changed with the value.
+				Some(_) if state.changed() || (state.dirty() && store.duration_for.is_some()) => {
+					Some(duration)
+				}
+				Some(threshold) => match store.store.ttl(token.key()).await {
+					Ok(Some(ttl)) if ttl < duration.mul_f64(threshold) => Some(ttl),
+					Ok(_) => return,
+					Err(e) => {
+						log::warn!("could not get the session time to live: {}", e);
+						return;
+					}
+				},
+			};
+			if state.source == TokenSource::Bearer {
+				response.set_raw_header(bearer::TOKEN_HEADER, token.token().to_owned());
+				return;
+			}
 			let name = store.name.as_str();
 			let cookie = &store.cookie;
-			response.adjoin_header::<Cookie>(
-				Cookie::build((name, session.0.as_str()))
-					.http_only(cookie.http_only)
-					.path(
-						cookie
-							.path
-							.as_ref()
-							.unwrap_or(&request.uri().path().to_string())
-							.as_str(),
-					)
-					.same_site(cookie.same_site.unwrap_or(SameSite::Lax))
-					.secure(cookie.secure)
-					.into(),
-			)
+			let mut builder = Cookie::build((name, token.token()));
+			if let Some(max_age) = max_age {
+				// Round up so that a session about to expire isn't deleted early.
+				let secs = (max_age.as_millis() as i64 + 999) / 1000;
+				builder = builder.max_age(rocket::time::Duration::seconds(secs));
+			}
+			store.meter().cookie_issued();
+			if !legacy_cleanup {
+				store.remove_legacy_cookies(response);
+			}
+			let session_cookie: Cookie = builder
+				.http_only(cookie.http_only)
+				.path(store.cookie_path())
+				.same_site(cookie.same_site.unwrap_or(SameSite::Lax))
+				.secure(cookie.secure.unwrap_or(false))
+				.partitioned(cookie.partitioned)
+				.into();
+			#[cfg(feature = "csrf")]
+			if let Some(ref csrf) = store.csrf {
+				// Scripts read the companion cookie to echo it.
+				let mut companion = session_cookie.clone();
+				companion.set_name(csrf.cookie_name.as_str());
+				companion.set_value(csrf.token_for(token.token()));
+				companion.set_http_only(false);
+				response.adjoin_header(companion);
+			}
+			response.adjoin_header(session_cookie)
+		}
+	}
+
+	async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+		let Some(store) = rocket.state::<SessionStore<T>>() else {
+			return;
+		};
+		if let Err(e) = store.drain().await {
+			log::error!("could not drain the session store on shutdown: {}", e);
+		}
+	}
+}
+
+/// Get the longest path shared by the mount points of the routes of a
+/// rocket, or `/` when they have none in common.
+fn mount_point(rocket: &Rocket<Build>) -> String {
+	let mut bases = rocket.routes().map(|route| {
+		route
+			.uri
+			.base()
+			.split('/')
+			.filter(|segment| !segment.is_empty())
+			.collect::<Vec<_>>()
+	});
+	let Some(mut common) = bases.next() else {
+		return "/".to_owned();
+	};
+	for base in bases {
+		let shared = common
+			.iter()
+			.zip(&base)
+			.take_while(|(a, b)| a == b)
+			.count();
+		common.truncate(shared);
+	}
+	format!("/{}", common.join("/"))
+}
+
+/// Clears the expired sessions of a store, a batch at a time.
+async fn clear_expired<T>(store: &dyn Store<Value = T>) {
+	let Some(cleanup) = store.expired_cleanup() else {
+		return;
+	};
+	loop {
+		match cleanup.clear_expired(CLEANUP_BATCH).await {
+			Ok(CLEANUP_BATCH) => continue,
+			Ok(removed) => {
+				log::debug!("cleared {} expired sessions", removed);
+				break;
+			}
+			Err(e) => {
+				log::warn!("could not clear the expired sessions: {}", e);
+				break;
+			}
+		}
+	}
+}
+
+/// A function deriving the key of a session in the store from the request
+/// and the key of its token, see [SessionStore::key_mapper].
+pub type KeyMapper = dyn Fn(&Request<'_>, &str) -> String + Send + Sync;
+
+/// A callback modifying the response of a request based on the value of its
+/// session, see [SessionStore::response_hook].
+pub type ResponseHook<T> = dyn Fn(&T, &mut Response<'_>) + Send + Sync;
+
+/// A function measuring the size of a session value in bytes, see
+/// [SessionStore::value_size].
+pub type ValueSize<T> = dyn Fn(&T) -> usize + Send + Sync;
+
+/// A function hashing a session value, see [SessionStore::value_hash].
+pub type ValueHash<T> = dyn Fn(&T) -> u64 + Send + Sync;
+
+/// A function making the value of a new session, see
+/// [SessionStore::auto_create].
+pub type AutoCreate<T> = dyn Fn() -> T + Send + Sync;
+
+/// A function picking the duration of a session from its value, see
+/// [SessionStore::duration_for].
+pub type DurationFor<T> = dyn Fn(&T) -> Duration + Send + Sync;
+
+/// Get the length of a value serialized to JSON, or 0 if it can't be
+/// serialized, leaving the store to fail.
+fn json_size<T: Serialize>(value: &T) -> usize {
+	struct Counter(usize);
+
+	impl std::io::Write for Counter {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0 += buf.len();
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
 		}
 	}
+
+	let mut counter = Counter(0);
+	match serde_json::to_writer(&mut counter, value) {
+		Ok(()) => counter.0,
+		Err(_) => 0,
+	}
+}
+
+/// Hash a value with [DefaultHasher], for [Session::value_hash].
+pub(crate) fn hash_value<T: Hash>(value: &T) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	value.hash(&mut hasher);
+	hasher.finish()
 }
 
 /// A result wrapper around [SessionError], allowing you to wrap the Result
@@ -243,14 +3327,47 @@ pub type SessionResult<T> = Result<T, SessionError>;
 
 /// Errors produced when accessing the session store.
 ///
-/// These can be problems like a database connection drop.
-/// It implements [Responder], returning a 500 status error.
-#[derive(Error, Debug)]
-#[error("could not access the session store")]
-pub struct SessionError;
+/// It implements [Responder], returning a 500 status error, or the
+/// [error status](SessionStore::error_status) of the store.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+	/// There is no session value, or it has expired.
+	///
+	/// This is returned by [Session::get], where other reads return
+	/// `Ok(None)`, so that handlers can send visitors without a session to a
+	/// login page.
+	#[error("no session value")]
+	NotFound,
+	/// The store couldn't be reached, such as when a database connection
+	/// is refused or drops.
+	#[error("could not access the session store")]
+	StoreUnavailable,
+	/// The store didn't answer in time, such as when a network partition
+	/// leaves a database connection without a reply.
+	#[error("the session store didn't answer in time")]
+	Timeout,
+	/// The value is over the [maximum size](SessionStore::max_value_size) of
+	/// the store, and wasn't stored.
+	#[error("the session value of {size} bytes is over the limit of {limit} bytes")]
+	ValueTooLarge {
+		/// The size of the value, in bytes.
+		size: usize,
+		/// The largest size allowed, in bytes.
+		limit: usize,
+	},
+	/// Any other failure, such as a value that can't be serialized or an
+	/// operation the store doesn't support.
+	#[error("the session operation failed")]
+	Other,
+}
+
+/// The status of the responses made from a [SessionError], managed by the
+/// fairing when a store sets one.
+struct ErrorStatus(Status);
 
 impl<'r, 'o: 'r> Responder<'r, 'o> for SessionError {
-	fn respond_to(self, _request: &'r Request<'_>) -> rocket::response::Result<'o> {
-		Err(Status::InternalServerError)
+	fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'o> {
+		let status = request.rocket().state::<ErrorStatus>();
+		Err(status.map_or(Status::InternalServerError, |status| status.0))
 	}
 }
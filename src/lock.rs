@@ -0,0 +1,156 @@
+//! Advisory locks serializing the requests of a session.
+//!
+//! Two requests of the same client, such as concurrent AJAX calls, may both
+//! read the session, change different parts of it and write it back, the
+//! second write erasing the change of the first. Taking the lock of the
+//! session with [Session::lock] around such a read and write makes the
+//! other request wait until the lock is released.
+//!
+//! The lock is advisory: it only holds back requests taking it too, and
+//! writes made without it go through. It is held until the
+//! [guard](SessionLockGuard) is released or dropped, or until the
+//! [lock TTL](crate::SessionStore::lock_ttl) of the store passes, so a
+//! request that crashed while holding it doesn't block the session for
+//! long. Stores support locks through [Store::acquire_lock] and
+//! [Store::release_lock].
+//!
+//! The lock belongs to the current token, so regenerating the token while
+//! holding it leaves the new token unlocked.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::post;
+//! # use rocket_session_store::{Session, SessionResult};
+//! #[post("/cart/<item>")]
+//! async fn add_to_cart(item: String, session: Session<'_, Vec<String>>) -> SessionResult<()> {
+//!     let lock = session.lock(Duration::from_secs(5)).await?;
+//!     let mut cart = session.get_opt().await?.unwrap_or_default();
+//!     cart.push(item);
+//!     session.set(cart).await?;
+//!     lock.release().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::{
+	sync::Arc,
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+use rand::{
+	distributions::Alphanumeric,
+	rngs::OsRng,
+	Rng,
+};
+use rocket::tokio::{
+	runtime::Handle,
+	time::sleep,
+};
+
+use crate::{
+	Session,
+	SessionError,
+	SessionResult,
+	Store,
+};
+
+/// How long a session is locked unless the store sets another duration.
+pub const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(30);
+
+/// The first wait before trying to take a lock again, doubled on every
+/// attempt.
+const RETRY_MIN: Duration = Duration::from_millis(5);
+
+/// The longest wait between two attempts to take a lock.
+const RETRY_MAX: Duration = Duration::from_millis(100);
+
+/// The length of the random identifier of a lock holder.
+const HOLDER_LENGTH: usize = 24;
+
+/// The advisory lock of a session, held until it is released or dropped, see
+/// the [module](self) documentation.
+///
+/// Dropping the guard releases the lock from a spawned task, as releasing it
+/// reaches the store. [SessionLockGuard::release] releases it before going
+/// on, and reports whether it was still held.
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct SessionLockGuard<T: 'static> {
+	store: Arc<dyn Store<Value = T>>,
+	id: String,
+	holder: String,
+	released: bool,
+}
+
+impl<T: 'static> SessionLockGuard<T> {
+	/// Releases the lock.
+	///
+	/// Returns whether the lock was still held, which it isn't when it
+	/// outlived the lock TTL and another request took it.
+	pub async fn release(mut self) -> SessionResult<bool> {
+		self.released = true;
+		self.store.release_lock(&self.id, &self.holder).await
+	}
+}
+
+impl<T: 'static> Drop for SessionLockGuard<T> {
+	fn drop(&mut self) {
+		if self.released {
+			return;
+		}
+		let Ok(handle) = Handle::try_current() else {
+			log::debug!("no runtime to release the session lock, leaving it to expire");
+			return;
+		};
+		let store = self.store.clone();
+		let id = std::mem::take(&mut self.id);
+		let holder = std::mem::take(&mut self.holder);
+		handle.spawn(async move {
+			if let Err(e) = store.release_lock(&id, &holder).await {
+				log::warn!("could not release the session lock: {}", e);
+			}
+		});
+	}
+}
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone,
+{
+	/// Takes the advisory lock of the session, waiting at most `timeout` for
+	/// another request to release it.
+	///
+	/// Fails with [SessionError] when the lock is still held after the
+	/// timeout, or when the store doesn't support locks. See the
+	/// [module](crate::lock) documentation.
+	pub async fn lock(&self, timeout: Duration) -> SessionResult<SessionLockGuard<T>> {
+		let id = self.state.token().key().to_owned();
+		let holder: String = OsRng
+			.sample_iter(&Alphanumeric)
+			.take(HOLDER_LENGTH)
+			.map(char::from)
+			.collect();
+		let store = &self.store.store;
+		let start = Instant::now();
+		let mut wait = RETRY_MIN;
+		while !store.acquire_lock(&id, &holder, self.store.lock_ttl).await? {
+			let Some(left) = timeout.checked_sub(start.elapsed()).filter(|left| !left.is_zero())
+			else {
+				log::warn!("timed out waiting {:?} for the session lock", timeout);
+				return Err(SessionError::Other);
+			};
+			sleep(wait.min(left)).await;
+			wait = (wait * 2).min(RETRY_MAX);
+		}
+		Ok(SessionLockGuard {
+			store: store.clone(),
+			id,
+			holder,
+			released: false,
+		})
+	}
+}
@@ -0,0 +1,110 @@
+//! Metrics about sessions, recorded with the `metrics` and `prometheus`
+//! features.
+//!
+//! With the `metrics` feature, metrics go through the [metrics](::metrics)
+//! facade, so they can be exported with any recorder. Every series is
+//! labelled with the name of the store, see
+//! [SessionStore::metrics_name](crate::SessionStore::metrics_name):
+//!
+//! - `session_store_operations_total{store, op, outcome}` counts operations
+//!   on sessions, with an outcome of `ok` or `error`.
+//! - `session_store_operation_duration_seconds{store, op}` is a histogram of
+//!   their latency.
+//! - `session_active_estimate{store}` goes up when a session is created and
+//!   down when one is removed. Sessions that expire aren't accounted for, so
+//!   it is an upper estimate.
+//! - `session_requests_total{store, session}` counts the requests that used
+//!   a session guard (`with`) or not (`without`).
+//! - `session_cookies_issued_total{store}` counts the session cookies sent.
+//!
+//! With the `prometheus` feature, the metrics of
+//! [prometheus](crate::prometheus) are recorded as well. Without either
+//! feature, a [Meter] only runs the operations.
+
+use std::future::Future;
+
+use crate::SessionResult;
+
+/// Records the metrics of a session store.
+pub(crate) struct Meter<'a> {
+	/// Name of the store in the `metrics` series.
+	#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+	pub(crate) store: &'a str,
+	#[cfg(feature = "prometheus")]
+	pub(crate) prometheus: &'a crate::prometheus::SessionMetrics,
+}
+
+impl Meter<'_> {
+	/// Runs an operation on a session, counting and timing it.
+	pub(crate) async fn measured<R>(
+		&self,
+		op: &'static str,
+		operation: impl Future<Output = SessionResult<R>>,
+	) -> SessionResult<R> {
+		#[cfg(any(feature = "metrics", feature = "prometheus"))]
+		let start = std::time::Instant::now();
+		let result = operation.await;
+		#[cfg(feature = "metrics")]
+		{
+			let outcome = if result.is_ok() { "ok" } else { "error" };
+			::metrics::counter!(
+				"session_store_operations_total",
+				"store" => self.store.to_owned(),
+				"op" => op,
+				"outcome" => outcome,
+			)
+			.increment(1);
+			::metrics::histogram!(
+				"session_store_operation_duration_seconds",
+				"store" => self.store.to_owned(),
+				"op" => op,
+			)
+			.record(start.elapsed().as_secs_f64());
+		}
+		#[cfg(feature = "prometheus")]
+		self.prometheus.operation(op, start.elapsed());
+		#[cfg(not(any(feature = "metrics", feature = "prometheus")))]
+		let _ = op;
+		result
+	}
+
+	/// Records the outcome of reading a session.
+	pub(crate) fn read<T>(&self, _result: &SessionResult<Option<T>>) {
+		#[cfg(feature = "prometheus")]
+		self.prometheus.read(_result);
+	}
+
+	/// Whether sessions created and removed are counted, so that writes
+	/// need to know whether they create the session.
+	pub(crate) fn counts_sessions(&self) -> bool {
+		cfg!(any(feature = "metrics", feature = "prometheus"))
+	}
+
+	/// Records that a session was created, or removed when `delta` is
+	/// negative.
+	pub(crate) fn active_sessions(&self, _delta: f64) {
+		#[cfg(feature = "metrics")]
+		::metrics::gauge!("session_active_estimate", "store" => self.store.to_owned())
+			.increment(_delta);
+		#[cfg(feature = "prometheus")]
+		self.prometheus.active_sessions(_delta);
+	}
+
+	/// Records a request, and whether it used a session guard.
+	pub(crate) fn request(&self, _with_session: bool) {
+		#[cfg(feature = "metrics")]
+		::metrics::counter!(
+			"session_requests_total",
+			"store" => self.store.to_owned(),
+			"session" => if _with_session { "with" } else { "without" },
+		)
+		.increment(1);
+	}
+
+	/// Records that a session cookie was sent.
+	pub(crate) fn cookie_issued(&self) {
+		#[cfg(feature = "metrics")]
+		::metrics::counter!("session_cookies_issued_total", "store" => self.store.to_owned())
+			.increment(1);
+	}
+}
@@ -3,23 +3,46 @@
 //! This module provides [MemoryStore], an implementation of [Store]
 //! to be used for testing and development. It is not optimized for production
 //! and thus you should use another store to use it in the real world.
+//!
+//! It also provides [MemoryRawStore], the same store keeping values as bytes,
+//! to be used with a [CodecStore](crate::codec::CodecStore), and
+//! [SerializingMemoryStore], keeping values as JSON so that its snapshots can
+//! be restored whatever the value type.
 
 use std::{
-	collections::HashMap,
+	collections::{
+		hash_map::Entry,
+		HashMap,
+	},
+	fmt,
+	marker::PhantomData,
 	time::{
 		Duration,
 		Instant,
+		SystemTime,
+		UNIX_EPOCH,
 	},
 };
 
-use rocket::tokio::sync::{
-	Mutex,
-	RwLock,
+use rocket::{
+	serde::{
+		Deserialize,
+		Serialize,
+	},
+	tokio::sync::{
+		Mutex,
+		RwLock,
+	},
 };
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::{
+	ExpiredCleanup,
+	SessionError,
 	SessionResult,
 	Store,
+	NO_EXPIRY,
 };
 
 /// An in memory implementation of a session store using hashmaps.
@@ -27,11 +50,40 @@ use crate::{
 /// and should not be used in any real world application.
 pub struct MemoryStore<T> {
 	map: RwLock<HashMap<String, Mutex<MemoryStoreFrame<T>>>>,
+	/// Sessions of each user, in the order they were associated.
+	users: Mutex<HashMap<String, Vec<String>>>,
+	/// Holder and expiry of the advisory lock of each session.
+	locks: Mutex<HashMap<String, (String, Instant)>>,
 }
 
+/// An in-memory [RawStore](crate::codec::RawStore), keeping values as bytes.
+pub type MemoryRawStore = MemoryStore<Vec<u8>>;
+
 struct MemoryStoreFrame<T> {
 	value: T,
-	expiry: Instant,
+	/// When the session expires, if it ever does.
+	expiry: Option<Instant>,
+	created: SystemTime,
+}
+
+impl<T> MemoryStoreFrame<T> {
+	fn expiry_after(now: Instant, duration: Duration) -> Option<Instant> {
+		if duration == NO_EXPIRY {
+			return None;
+		}
+		now.checked_add(duration)
+	}
+
+	fn is_live(&self, now: Instant) -> bool {
+		self.expiry.is_none_or(|expiry| expiry > now)
+	}
+
+	fn ttl(&self, now: Instant) -> Option<Duration> {
+		match self.expiry {
+			Some(expiry) => expiry.checked_duration_since(now),
+			None => Some(NO_EXPIRY),
+		}
+	}
 }
 
 impl<T> Default for MemoryStore<T> {
@@ -45,56 +97,771 @@ impl<T> MemoryStore<T> {
 	pub fn new() -> Self {
 		Self {
 			map: RwLock::default(),
+			users: Mutex::default(),
+			locks: Mutex::default(),
+		}
+	}
+
+	/// Count the sessions that haven't expired.
+	pub async fn session_count(&self) -> usize {
+		let lock = self.map.read().await;
+		let now = Instant::now();
+		let mut count = 0;
+		for frame in lock.values() {
+			if frame.lock().await.is_live(now) {
+				count += 1;
+			}
+		}
+		count
+	}
+
+	/// Sets the value of a session if the current value, or [None] without
+	/// a live session, passes the check, under the lock of the store.
+	///
+	/// Returns whether the value was set.
+	async fn swap_if(
+		&self,
+		id: &str,
+		check: impl FnOnce(Option<&T>) -> bool,
+		value: T,
+		expiry: Duration,
+	) -> bool {
+		let mut lock = self.map.write().await;
+		let now = Instant::now();
+		let current = lock
+			.get_mut(id)
+			.map(Mutex::get_mut)
+			.filter(|frame| frame.is_live(now));
+		if !check(current.as_ref().map(|frame| &frame.value)) {
+			return false;
+		}
+		let frame = MemoryStoreFrame {
+			value,
+			expiry: MemoryStoreFrame::<T>::expiry_after(now, expiry),
+			created: current.map_or_else(SystemTime::now, |frame| frame.created),
+		};
+		lock.insert(id.into(), Mutex::new(frame));
+		true
+	}
+}
+
+/// Shows the number of sessions, how many of them have expired, and an
+/// estimate of the memory held by the live ones, from the size of `T`
+/// alone.
+///
+/// It never waits for the store, so it can be used while debugging a stuck
+/// application: when the store is in use, the counts are left out, and
+/// sessions in use are counted as live.
+impl<T> fmt::Debug for MemoryStore<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("MemoryStore");
+		let Ok(map) = self.map.try_read() else {
+			return debug.finish_non_exhaustive();
+		};
+		let now = Instant::now();
+		let expired = map
+			.values()
+			.filter(|frame| frame.try_lock().is_ok_and(|frame| !frame.is_live(now)))
+			.count();
+		let live = map.len() - expired;
+		debug
+			.field("sessions", &map.len())
+			.field("expired", &expired)
+			.field("estimated_size", &(live * std::mem::size_of::<T>()))
+			.finish()
+	}
+}
+
+/// The live sessions of a [MemoryStore], as serialized by
+/// [MemoryStore::snapshot].
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Snapshot<T> {
+	sessions: HashMap<String, SnapshotFrame<T>>,
+	users: HashMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotFrame<T> {
+	value: T,
+	/// The remaining time to live in milliseconds, or `null` for sessions
+	/// that never expire.
+	ttl_ms: Option<u64>,
+	/// The creation time in milliseconds since the Unix epoch.
+	created_at_ms: u64,
+}
+
+impl<T> MemoryStore<T>
+where
+	T: Serialize + DeserializeOwned,
+{
+	/// Serializes the live sessions of the store to JSON, along with their
+	/// remaining time to live, creation time and associated users.
+	///
+	/// The snapshot can be saved and restored with
+	/// [MemoryStore::from_snapshot], for instance across a restart of a
+	/// development server. Sessions keep counting down from the time the
+	/// snapshot was taken, so the time spent between the two isn't counted.
+	pub async fn snapshot(&self) -> SessionResult<Value> {
+		let lock = self.map.read().await;
+		let now = Instant::now();
+		let mut sessions = HashMap::with_capacity(lock.len());
+		for (id, frame) in lock.iter() {
+			let frame = frame.lock().await;
+			let Some(ttl) = frame.ttl(now) else {
+				continue;
+			};
+			let created_at = frame.created.duration_since(UNIX_EPOCH).unwrap_or_default();
+			sessions.insert(
+				id.clone(),
+				SnapshotFrame {
+					value: to_json(&frame.value)?,
+					ttl_ms: (ttl != NO_EXPIRY).then_some(ttl.as_millis() as u64),
+					created_at_ms: created_at.as_millis() as u64,
+				},
+			);
+		}
+		let users = self.users.lock().await.clone();
+		to_json(&Snapshot { sessions, users })
+	}
+
+	/// Creates a store holding the sessions of a snapshot taken with
+	/// [MemoryStore::snapshot].
+	///
+	/// The values only need to deserialize into `T`, so a snapshot taken
+	/// from a store of another type, such as [SerializingMemoryStore], can
+	/// be restored.
+	pub fn from_snapshot(snapshot: Value) -> SessionResult<Self> {
+		let snapshot: Snapshot<T> = from_json(snapshot)?;
+		let now = Instant::now();
+		let map = snapshot
+			.sessions
+			.into_iter()
+			.map(|(id, frame)| {
+				let ttl = frame.ttl_ms.map_or(NO_EXPIRY, Duration::from_millis);
+				let frame = MemoryStoreFrame {
+					value: frame.value,
+					expiry: MemoryStoreFrame::<T>::expiry_after(now, ttl),
+					created: UNIX_EPOCH + Duration::from_millis(frame.created_at_ms),
+				};
+				(id, Mutex::new(frame))
+			})
+			.collect();
+		Ok(Self {
+			map: RwLock::new(map),
+			users: Mutex::new(snapshot.users),
+			locks: Mutex::default(),
+		})
+	}
+}
+
+fn to_json<T: Serialize>(value: &T) -> SessionResult<Value> {
+	serde_json::to_value(value).map_err(|e| {
+		log::warn!("could not serialize the session value: {}", e);
+		SessionError::Other
+	})
+}
+
+fn from_json<T: DeserializeOwned>(value: Value) -> SessionResult<T> {
+	serde_json::from_value(value).map_err(|e| {
+		log::warn!("could not deserialize the session value: {}", e);
+		SessionError::Other
+	})
+}
+
+/// An in-memory store keeping values as JSON.
+///
+/// Values are serialized when they are set and deserialized when they are
+/// read, as a store backed by a database would. This catches values that
+/// don't round-trip through serde while developing with a memory store, and
+/// makes [snapshots](SerializingMemoryStore::snapshot) independent of the
+/// value type, so they can be restored into a store of another type.
+pub struct SerializingMemoryStore<T> {
+	inner: MemoryStore<Value>,
+	value: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for SerializingMemoryStore<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> SerializingMemoryStore<T> {
+	/// Create a new in-memory store
+	pub fn new() -> Self {
+		Self::from_inner(MemoryStore::new())
+	}
+
+	/// Creates a store holding the sessions of a snapshot.
+	///
+	/// See [MemoryStore::from_snapshot].
+	pub fn from_snapshot(snapshot: Value) -> SessionResult<Self> {
+		MemoryStore::from_snapshot(snapshot).map(Self::from_inner)
+	}
+
+	/// Serializes the live sessions of the store to JSON.
+	///
+	/// See [MemoryStore::snapshot].
+	pub async fn snapshot(&self) -> SessionResult<Value> {
+		self.inner.snapshot().await
+	}
+
+	/// Get the store of JSON values keeping the sessions.
+	pub fn inner(&self) -> &MemoryStore<Value> {
+		&self.inner
+	}
+
+	fn from_inner(inner: MemoryStore<Value>) -> Self {
+		Self {
+			inner,
+			value: PhantomData,
 		}
 	}
 }
 
+#[rocket::async_trait]
+impl<T> Store for SerializingMemoryStore<T>
+where
+	T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		self.inner.get(id).await?.map(from_json).transpose()
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		self.inner.set(id, to_json(&value)?, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.inner.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		match self.inner.get_with_expiry(id).await? {
+			Some((value, ttl)) => Ok(Some((from_json(value)?, ttl))),
+			None => Ok(None),
+		}
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.inner.created_at(id).await
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<T>>> {
+		let values = self.inner.get_many(ids).await?;
+		values
+			.into_iter()
+			.map(|value| value.map(from_json).transpose())
+			.collect()
+	}
+
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		let value = to_json(&value)?;
+		let previous = self.inner.replace(id, value, duration).await?;
+		previous.map(from_json).transpose()
+	}
+
+	/// Compares the values as JSON, so values equal once serialized match.
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		let expected = expected.as_ref().map(to_json).transpose()?;
+		let value = to_json(&value)?;
+		let check = |current: Option<&Value>| current == expected.as_ref();
+		Ok(self.inner.swap_if(id, check, value, duration).await)
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		self.inner.remove_many(ids).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.inner.rename(old_id, new_id, duration).await
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let value = to_json(&value)?;
+		self.inner
+			.rename_with(old_id, new_id, value, duration)
+			.await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.inner.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.inner.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.inner.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.inner.count().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.inner.expired_cleanup()
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.inner.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner.dissociate(user_key, id).await
+	}
+}
+
 #[rocket::async_trait]
 impl<T> Store for MemoryStore<T>
 where
-	T: Send + Sync + Clone,
+	T: Send + Sync + Clone + 'static,
 {
 	type Value = T;
 
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
 	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
 		let lock = self.map.read().await;
 		if let Some(frame) = lock.get(id) {
 			let frame_lock = frame.lock().await;
-			if frame_lock
-				.expiry
-				.checked_duration_since(Instant::now())
-				.is_some()
-			{
+			if frame_lock.is_live(Instant::now()) {
 				return Ok(Some(frame_lock.value.clone()));
 			};
 		};
 		Ok(None)
 	}
 
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
 	async fn set(&self, id: &str, value: Self::Value, expiry: Duration) -> SessionResult<()> {
 		let mut lock = self.map.write().await;
+		let now = Instant::now();
+		let created = match lock.get_mut(id).map(Mutex::get_mut) {
+			Some(frame) if frame.is_live(now) => frame.created,
+			_ => SystemTime::now(),
+		};
 		let frame = MemoryStoreFrame {
 			value,
-			expiry: Instant::now() + expiry,
+			expiry: MemoryStoreFrame::<T>::expiry_after(now, expiry),
+			created,
 		};
 		lock.insert(id.into(), Mutex::new(frame));
 
 		Ok(())
 	}
 
-	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<()> {
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
 		let lock = self.map.read().await;
+		let now = Instant::now();
 		if let Some(frame) = lock.get(id) {
 			let mut frame_lock = frame.lock().await;
-			frame_lock.expiry = Instant::now() + duration;
+			if !frame_lock.is_live(now) {
+				return Ok(None);
+			}
+			if frame_lock.expiry.is_some() {
+				frame_lock.expiry = MemoryStoreFrame::<T>::expiry_after(now, duration);
+			}
+			return Ok(frame_lock.ttl(now));
+		};
+		Ok(None)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let mut lock = self.map.write().await;
+		let removed = lock.remove(id);
+
+		Ok(removed.is_some_and(|frame| frame.into_inner().is_live(Instant::now())))
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		let lock = self.map.read().await;
+		match lock.get(id) {
+			Some(frame) => Ok(frame.lock().await.is_live(Instant::now())),
+			None => Ok(false),
+		}
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		let lock = self.map.read().await;
+		if let Some(frame) = lock.get(id) {
+			let frame_lock = frame.lock().await;
+			return Ok(frame_lock.ttl(Instant::now()));
 		};
+		Ok(None)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		let lock = self.map.read().await;
+		if let Some(frame) = lock.get(id) {
+			let frame_lock = frame.lock().await;
+			if let Some(ttl) = frame_lock.ttl(Instant::now()) {
+				return Ok(Some((frame_lock.value.clone(), ttl)));
+			}
+		};
+		Ok(None)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		let lock = self.map.read().await;
+		if let Some(frame) = lock.get(id) {
+			let frame_lock = frame.lock().await;
+			if frame_lock.is_live(Instant::now()) {
+				return Ok(Some(frame_lock.created));
+			}
+		};
+		Ok(None)
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<T>>> {
+		let lock = self.map.read().await;
+		let now = Instant::now();
+		let mut values = Vec::with_capacity(ids.len());
+		for id in ids {
+			let value = match lock.get(id) {
+				Some(frame) => {
+					let frame_lock = frame.lock().await;
+					frame_lock.is_live(now).then(|| frame_lock.value.clone())
+				}
+				None => None,
+			};
+			values.push(value);
+		}
+		Ok(values)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn replace(&self, id: &str, value: T, expiry: Duration) -> SessionResult<Option<T>> {
+		let mut lock = self.map.write().await;
+		let now = Instant::now();
+		let frame = MemoryStoreFrame {
+			value,
+			expiry: MemoryStoreFrame::<T>::expiry_after(now, expiry),
+			created: SystemTime::now(),
+		};
+		match lock.entry(id.into()) {
+			Entry::Occupied(mut entry) => {
+				let previous = entry.get_mut().get_mut();
+				if !previous.is_live(now) {
+					entry.insert(Mutex::new(frame));
+					return Ok(None);
+				}
+				let frame = MemoryStoreFrame {
+					created: previous.created,
+					..frame
+				};
+				let previous = std::mem::replace(previous, frame);
+				Ok(Some(previous.value))
+			}
+			Entry::Vacant(entry) => {
+				entry.insert(Mutex::new(frame));
+				Ok(None)
+			}
+		}
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(old_id))
+		)
+	)]
+	async fn rename(&self, old_id: &str, new_id: &str, expiry: Duration) -> SessionResult<bool> {
+		let mut lock = self.map.write().await;
+		let now = Instant::now();
+		let Some(frame) = lock.remove(old_id).map(Mutex::into_inner) else {
+			return Ok(false);
+		};
+		if !frame.is_live(now) {
+			return Ok(false);
+		}
+		let frame = MemoryStoreFrame {
+			value: frame.value,
+			expiry: MemoryStoreFrame::<T>::expiry_after(now, expiry),
+			created: SystemTime::now(),
+		};
+		lock.insert(new_id.into(), Mutex::new(frame));
+
+		Ok(true)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(old_id))
+		)
+	)]
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		expiry: Duration,
+	) -> SessionResult<()> {
+		let mut lock = self.map.write().await;
+		let frame = MemoryStoreFrame {
+			value,
+			expiry: MemoryStoreFrame::<T>::expiry_after(Instant::now(), expiry),
+			created: SystemTime::now(),
+		};
+		lock.remove(old_id);
+		lock.insert(new_id.into(), Mutex::new(frame));
+
 		Ok(())
 	}
 
-	async fn remove(&self, id: &str) -> SessionResult<()> {
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		let mut locks = self.locks.lock().await;
+		let now = Instant::now();
+		if let Some((other, expiry)) = locks.get(id) {
+			if other != holder && *expiry > now {
+				return Ok(false);
+			}
+		}
+		let expiry = now.checked_add(ttl).ok_or(SessionError::Other)?;
+		locks.insert(id.into(), (holder.into(), expiry));
+
+		Ok(true)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		let mut locks = self.locks.lock().await;
+		match locks.get(id) {
+			Some((other, expiry)) if other == holder && *expiry > Instant::now() => {
+				locks.remove(id);
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
 		let mut lock = self.map.write().await;
-		lock.remove(id);
+		for id in ids {
+			lock.remove(id);
+		}
 
 		Ok(())
 	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		let lock = self.map.read().await;
+		let now = Instant::now();
+		let mut ids = Vec::new();
+		for (id, frame) in lock.iter() {
+			if frame.lock().await.is_live(now) {
+				ids.push(id.clone());
+			}
+		}
+		ids.sort_unstable();
+		Ok(ids.into_iter().skip(offset).take(limit).collect())
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		Ok(self.session_count().await)
+	}
+
+	fn backend(&self) -> &'static str {
+		"memory"
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		Some(self)
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let mut users = self.users.lock().await;
+		let sessions = users.entry(user_key.into()).or_default();
+		sessions.retain(|session| session != id);
+		sessions.push(id.into());
+
+		Ok(())
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		let mut users = self.users.lock().await;
+		let Some(sessions) = users.get_mut(user_key) else {
+			return Ok(Vec::new());
+		};
+		// Drop the sessions that were removed or have expired since.
+		let map = self.map.read().await;
+		let now = Instant::now();
+		let mut live = Vec::with_capacity(sessions.len());
+		for id in sessions.iter() {
+			if let Some(frame) = map.get(id) {
+				if frame.lock().await.is_live(now) {
+					live.push(id.clone());
+				}
+			}
+		}
+		if live.is_empty() {
+			users.remove(user_key);
+		} else {
+			*sessions = live.clone();
+		}
+
+		Ok(live)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "memory", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let mut users = self.users.lock().await;
+		if let Some(sessions) = users.get_mut(user_key) {
+			sessions.retain(|session| session != id);
+		}
+
+		Ok(())
+	}
+}
+
+#[rocket::async_trait]
+impl<T> ExpiredCleanup for MemoryStore<T>
+where
+	T: Send + Sync,
+{
+	async fn clear_expired(&self, limit: usize) -> SessionResult<usize> {
+		let mut lock = self.map.write().await;
+		let now = Instant::now();
+		let mut removed = 0;
+		lock.retain(|_, frame| {
+			if removed < limit && !frame.get_mut().is_live(now) {
+				removed += 1;
+				return false;
+			}
+			true
+		});
+
+		Ok(removed)
+	}
 }
@@ -0,0 +1,47 @@
+//! Moving sessions from one store to another.
+//!
+//! [export_to] copies the live sessions of a store into another, such as
+//! from a [MemoryStore](crate::memory::MemoryStore) used in development to a
+//! redis store used in production, or between two redis instances.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{memory::MemoryStore, migration::export_to};
+//! # rocket::async_test(async {
+//! let old: MemoryStore<String> = MemoryStore::new();
+//! let new: MemoryStore<String> = MemoryStore::new();
+//! let migrated = export_to(&old, &new).await.expect("sessions migrated");
+//! println!("migrated {} sessions", migrated);
+//! # });
+//! ```
+
+use crate::{
+	SessionResult,
+	Store,
+};
+
+/// Copies every live session of `src` into `dst`, and returns how many
+/// sessions were copied.
+///
+/// The source has to support [Store::list_ids]. Each session keeps its id
+/// and its remaining time to live, while its creation time is reset by the
+/// destination. Sessions that expire during the migration are skipped, and
+/// the migration stops at the first error of either store. Associations
+/// between users and sessions aren't copied.
+pub async fn export_to<T>(
+	src: &(impl Store<Value = T> + ?Sized),
+	dst: &(impl Store<Value = T> + ?Sized),
+) -> SessionResult<usize> {
+	// All the ids are listed first, as sessions expiring while they are
+	// copied would shift the following pages.
+	let mut migrated = 0;
+	for id in src.list_ids(0, usize::MAX).await? {
+		if let Some((value, ttl)) = src.get_with_expiry(&id).await? {
+			dst.set(&id, value, ttl).await?;
+			migrated += 1;
+		}
+	}
+	Ok(migrated)
+}
@@ -0,0 +1,234 @@
+//! An in-memory session store built on the [moka] cache, enabled with the
+//! `moka` feature.
+//!
+//! This module provides [MokaStore], which keeps sessions in a bounded
+//! concurrent cache evicting expired sessions by itself. Prefer it over
+//! [MemoryStore](crate::memory::MemoryStore) when sessions are kept in memory
+//! in production, such as for a single instance application: the number of
+//! sessions is bounded, expired sessions are evicted without a cleanup task
+//! and reads don't contend on a single lock. [MemoryStore] is still the
+//! better fit for tests, as it can snapshot its sessions and keeps the
+//! sessions of users.
+//!
+//! [MemoryStore]: crate::memory::MemoryStore
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use rocket_session_store::{moka::MokaStore, SessionStore};
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MokaStore::new(10_000), "token", Duration::from_secs(3600));
+//! ```
+
+use std::time::{
+	Duration,
+	Instant,
+	SystemTime,
+};
+
+use moka::{
+	future::Cache,
+	ops::compute::Op,
+	Expiry,
+};
+
+use crate::{
+	SessionResult,
+	Store,
+	NO_EXPIRY,
+};
+
+/// An in-memory implementation for [Store] on top of a [moka] cache.
+///
+/// Values are cloned when they are read. Once the cache holds
+/// `max_capacity` sessions, the least used ones are evicted to make room for
+/// new ones, and expired sessions are evicted as the cache is used.
+pub struct MokaStore<T> {
+	cache: Cache<String, MokaEntry<T>>,
+}
+
+#[derive(Clone)]
+struct MokaEntry<T> {
+	value: T,
+	/// When the session expires, if it ever does.
+	expiry: Option<Instant>,
+	created: SystemTime,
+}
+
+impl<T> MokaEntry<T> {
+	fn new(value: T, now: Instant, duration: Duration, created: SystemTime) -> Self {
+		let expiry = match duration {
+			NO_EXPIRY => None,
+			duration => now.checked_add(duration),
+		};
+		Self {
+			value,
+			expiry,
+			created,
+		}
+	}
+
+	fn is_live(&self, now: Instant) -> bool {
+		self.expiry.is_none_or(|expiry| expiry > now)
+	}
+
+	fn ttl(&self, now: Instant) -> Option<Duration> {
+		match self.expiry {
+			Some(expiry) => expiry.checked_duration_since(now),
+			None => Some(NO_EXPIRY),
+		}
+	}
+}
+
+/// Lets the cache evict each session when it expires.
+struct SessionExpiry;
+
+impl<T> Expiry<String, MokaEntry<T>> for SessionExpiry {
+	fn expire_after_create(
+		&self,
+		_key: &String,
+		entry: &MokaEntry<T>,
+		created_at: Instant,
+	) -> Option<Duration> {
+		entry.ttl(created_at).filter(|ttl| *ttl != NO_EXPIRY)
+	}
+
+	fn expire_after_update(
+		&self,
+		_key: &String,
+		entry: &MokaEntry<T>,
+		updated_at: Instant,
+		_duration_until_expiry: Option<Duration>,
+	) -> Option<Duration> {
+		entry.ttl(updated_at).filter(|ttl| *ttl != NO_EXPIRY)
+	}
+}
+
+impl<T> MokaStore<T>
+where
+	T: Clone + Send + Sync + 'static,
+{
+	/// Creates a new store holding at most `max_capacity` sessions.
+	pub fn new(max_capacity: u64) -> Self {
+		Self {
+			cache: Cache::builder()
+				.max_capacity(max_capacity)
+				.expire_after(SessionExpiry)
+				.build(),
+		}
+	}
+
+	/// Get the session under the id, if it hasn't expired.
+	async fn live_entry(&self, id: &str) -> Option<MokaEntry<T>> {
+		let entry = self.cache.get(id).await?;
+		entry.is_live(Instant::now()).then_some(entry)
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Store for MokaStore<T>
+where
+	T: Clone + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		Ok(self.live_entry(id).await.map(|entry| entry.value))
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		self.replace(id, value, duration).await?;
+		Ok(())
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let now = Instant::now();
+		let mut ttl = None;
+		self.cache
+			.entry_by_ref(id)
+			.and_compute_with(|entry| {
+				let op = match entry.map(|entry| entry.into_value()) {
+					Some(entry) if entry.is_live(now) => {
+						let entry = match entry.expiry {
+							None if duration != NO_EXPIRY => entry,
+							_ => MokaEntry::new(entry.value, now, duration, entry.created),
+						};
+						ttl = entry.ttl(now);
+						Op::Put(entry)
+					}
+					_ => Op::Nop,
+				};
+				std::future::ready(op)
+			})
+			.await;
+		Ok(ttl)
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let removed = self.cache.remove(id).await;
+		Ok(removed.is_some_and(|entry| entry.is_live(Instant::now())))
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		let entry = self.live_entry(id).await;
+		Ok(entry.and_then(|entry| entry.ttl(Instant::now())))
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		let Some(entry) = self.live_entry(id).await else {
+			return Ok(None);
+		};
+		Ok(entry.ttl(Instant::now()).map(|ttl| (entry.value, ttl)))
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		Ok(self.live_entry(id).await.map(|entry| entry.created))
+	}
+
+	/// Swaps the value under a lock on the id, keeping the creation time of
+	/// a live session.
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		let now = Instant::now();
+		let mut previous = None;
+		self.cache
+			.entry_by_ref(id)
+			.and_compute_with(|entry| {
+				let created = match entry.map(|entry| entry.into_value()) {
+					Some(entry) if entry.is_live(now) => {
+						previous = Some(entry.value);
+						entry.created
+					}
+					_ => SystemTime::now(),
+				};
+				std::future::ready(Op::Put(MokaEntry::new(value, now, duration, created)))
+			})
+			.await;
+		Ok(previous)
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		let now = Instant::now();
+		let mut ids: Vec<String> = self
+			.cache
+			.iter()
+			.filter(|(_, entry)| entry.is_live(now))
+			.map(|(id, _)| id.as_ref().clone())
+			.collect();
+		ids.sort_unstable();
+		Ok(ids.into_iter().skip(offset).take(limit).collect())
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		let now = Instant::now();
+		Ok(self
+			.cache
+			.iter()
+			.filter(|(_, entry)| entry.is_live(now))
+			.count())
+	}
+
+	fn backend(&self) -> &'static str {
+		"moka"
+	}
+}
@@ -0,0 +1,345 @@
+//! Writing sessions to two independent stores, surviving the loss of either.
+//!
+//! A [MultiplexStore] writes every session to two stores at once, and reads
+//! from the first one, falling back to the second when the first has no
+//! such session or fails. Neither store is needed for sessions to work:
+//! writes go through as long as one store takes them, so writing sessions to
+//! two independent backends, such as two redis instances, keeps them alive
+//! through the loss of either. Unlike a
+//! [ReplicatedStore](crate::replicated::ReplicatedStore), no store is the
+//! primary one.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{
+//! #     memory::MemoryStore,
+//! #     multiplex::MultiplexStore,
+//! #     SessionStore,
+//! # };
+//! let a: MemoryStore<String> = MemoryStore::new();
+//! let b: MemoryStore<String> = MemoryStore::new();
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MultiplexStore::new(a, b), "token", Duration::from_secs(3600));
+//! ```
+
+use std::time::{
+	Duration,
+	SystemTime,
+};
+
+use rocket::tokio::join;
+
+use crate::{
+	ExpiredCleanup,
+	SessionResult,
+	Store,
+};
+
+/// A store writing sessions to two stores, see the [module](self)
+/// documentation.
+///
+/// Writes are sent to both stores concurrently, and succeed if they succeed
+/// on either store, the failures of the other one being logged. Reads go to
+/// the first store, and to the second one when the first has no such session
+/// or fails. Operations that can't be run on both stores at once, such as
+/// [locks](Store::acquire_lock) and
+/// [compare-and-swap](Store::compare_and_swap), run on the first store and
+/// on the second one when the first fails. A store coming back online
+/// misses the writes made while it was offline, so reads may get an older
+/// value from the first store until the session is written again.
+pub struct MultiplexStore<A, B> {
+	a: A,
+	b: B,
+}
+
+impl<A, B> MultiplexStore<A, B> {
+	/// Creates a store writing to both stores, reading from `a` first.
+	pub fn new(a: A, b: B) -> Self {
+		Self { a, b }
+	}
+
+	/// Get the store read first.
+	pub fn first(&self) -> &A {
+		&self.a
+	}
+
+	/// Get the store read when the first one fails.
+	pub fn second(&self) -> &B {
+		&self.b
+	}
+
+	/// Combines the results of a write on both stores, failing only if both
+	/// failed, and returning the results of the stores that succeeded.
+	fn multiplexed<R>(
+		&self,
+		op: &str,
+		a: SessionResult<R>,
+		b: SessionResult<R>,
+	) -> SessionResult<(Option<R>, Option<R>)> {
+		match (a, b) {
+			(Ok(a), Ok(b)) => Ok((Some(a), Some(b))),
+			(Ok(a), Err(e)) => {
+				log::warn!("multiplexed {} failed on the second store: {}", op, e);
+				Ok((Some(a), None))
+			}
+			(Err(e), Ok(b)) => {
+				log::warn!("multiplexed {} failed on the first store: {}", op, e);
+				Ok((None, Some(b)))
+			}
+			(Err(e), Err(_)) => Err(e),
+		}
+	}
+}
+
+/// Reads from the first store, falling back to the second one when the
+/// first has nothing or fails.
+macro_rules! read_through {
+	($self:ident, $op:literal, $call:ident($($arg:expr),*)) => {
+		match $self.a.$call($($arg),*).await {
+			Ok(Some(found)) => Ok(Some(found)),
+			Ok(None) => $self.b.$call($($arg),*).await,
+			Err(e) => {
+				log::warn!("multiplexed {} failed on the first store: {}", $op, e);
+				$self.b.$call($($arg),*).await
+			}
+		}
+	};
+}
+
+/// Runs an operation on the first store, falling back to the second one
+/// when the first fails.
+macro_rules! fail_over {
+	($self:ident, $op:literal, $call:ident($($arg:expr),*)) => {
+		match $self.a.$call($($arg),*).await {
+			Err(e) => {
+				log::warn!("multiplexed {} failed on the first store: {}", $op, e);
+				$self.b.$call($($arg),*).await
+			}
+			result => result,
+		}
+	};
+}
+
+#[rocket::async_trait]
+impl<A, B, T> Store for MultiplexStore<A, B>
+where
+	A: Store<Value = T> + 'static,
+	B: Store<Value = T> + 'static,
+	T: Clone + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		read_through!(self, "get", get(id))
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		let (a, b) = join!(
+			self.a.set(id, value.clone(), duration),
+			self.b.set(id, value, duration),
+		);
+		self.multiplexed("set", a, b)?;
+		Ok(())
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let (a, b) = join!(self.a.touch(id, duration), self.b.touch(id, duration));
+		let (a, b) = self.multiplexed("touch", a, b)?;
+		Ok(a.flatten().or(b.flatten()))
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let (a, b) = join!(self.a.remove(id), self.b.remove(id));
+		let (a, b) = self.multiplexed("remove", a, b)?;
+		Ok(a.unwrap_or_default() || b.unwrap_or_default())
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		match self.a.contains(id).await {
+			Ok(true) => Ok(true),
+			Ok(false) => self.b.contains(id).await,
+			Err(e) => {
+				log::warn!("multiplexed contains failed on the first store: {}", e);
+				self.b.contains(id).await
+			}
+		}
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		read_through!(self, "ttl", ttl(id))
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		read_through!(self, "get_with_expiry", get_with_expiry(id))
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		read_through!(self, "created_at", created_at(id))
+	}
+
+	async fn value_hash(&self, id: &str) -> SessionResult<Option<u64>> {
+		read_through!(self, "value_hash", value_hash(id))
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let (a, b) = join!(
+			self.a.associate_with(user_key, id),
+			self.b.associate_with(user_key, id),
+		);
+		self.multiplexed("associate_with", a, b)?;
+		Ok(())
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		fail_over!(self, "list_sessions_for", list_sessions_for(user_key))
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let (a, b) = join!(
+			self.a.dissociate(user_key, id),
+			self.b.dissociate(user_key, id),
+		);
+		self.multiplexed("dissociate", a, b)?;
+		Ok(())
+	}
+
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		let (a, b) = join!(
+			self.a.replace(id, value.clone(), duration),
+			self.b.replace(id, value, duration),
+		);
+		let (a, b) = self.multiplexed("replace", a, b)?;
+		Ok(a.flatten().or(b.flatten()))
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		let (a, b) = join!(self.a.remove_many(ids), self.b.remove_many(ids));
+		self.multiplexed("remove_many", a, b)?;
+		Ok(())
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		let (a, b) = join!(
+			self.a.rename(old_id, new_id, duration),
+			self.b.rename(old_id, new_id, duration),
+		);
+		let (a, b) = self.multiplexed("rename", a, b)?;
+		Ok(a.unwrap_or_default() || b.unwrap_or_default())
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let (a, b) = join!(
+			self.a.rename_with(old_id, new_id, value.clone(), duration),
+			self.b.rename_with(old_id, new_id, value, duration),
+		);
+		self.multiplexed("rename_with", a, b)?;
+		Ok(())
+	}
+
+	/// The value is compared on the first store, and copied to the second
+	/// one once it is set. It is compared on the second store when the first
+	/// fails.
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		match self
+			.a
+			.compare_and_swap(id, expected.clone(), value.clone(), duration)
+			.await
+		{
+			Ok(true) => {
+				let b = self.b.set(id, value, duration).await;
+				self.multiplexed("compare_and_swap", Ok(()), b)?;
+				Ok(true)
+			}
+			Ok(false) => Ok(false),
+			Err(e) => {
+				log::warn!(
+					"multiplexed compare_and_swap failed on the first store: {}",
+					e
+				);
+				self.b.compare_and_swap(id, expected, value, duration).await
+			}
+		}
+	}
+
+	/// Locks are taken on one store only, as a lock taken on one store but
+	/// not the other would be held by two requests at once: the first store,
+	/// or the second one while the first fails.
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		fail_over!(self, "acquire_lock", acquire_lock(id, holder, ttl))
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		fail_over!(self, "release_lock", release_lock(id, holder))
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		fail_over!(self, "list_ids", list_ids(offset, limit))
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		fail_over!(self, "count", count())
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		let (a, b) = join!(self.a.drain(), self.b.drain());
+		self.multiplexed("drain", a, b)?;
+		Ok(())
+	}
+
+	fn backend(&self) -> &'static str {
+		"multiplex"
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		let (a, b) = join!(self.a.ping(), self.b.ping());
+		self.multiplexed("ping", a, b)?;
+		Ok(())
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		let a = self.a.expired_cleanup().is_some();
+		let b = self.b.expired_cleanup().is_some();
+		(a || b).then_some(self)
+	}
+}
+
+#[rocket::async_trait]
+impl<A, B, T> ExpiredCleanup for MultiplexStore<A, B>
+where
+	A: Store<Value = T> + 'static,
+	B: Store<Value = T> + 'static,
+	T: Clone + Send + Sync + 'static,
+{
+	/// Clears both stores, returning the larger number of sessions removed
+	/// from either, so that batches go on until both are cleared.
+	async fn clear_expired(&self, limit: usize) -> SessionResult<usize> {
+		let (a, b) = join!(
+			clear(self.a.expired_cleanup(), limit),
+			clear(self.b.expired_cleanup(), limit),
+		);
+		let (a, b) = self.multiplexed("clear_expired", a, b)?;
+		Ok(a.unwrap_or_default().max(b.unwrap_or_default()))
+	}
+}
+
+/// Clears the expired sessions of a store, if it has to.
+async fn clear(store: Option<&dyn ExpiredCleanup>, limit: usize) -> SessionResult<usize> {
+	match store {
+		Some(store) => store.clear_expired(limit).await,
+		None => Ok(0),
+	}
+}
@@ -0,0 +1,209 @@
+//! OpenAPI documentation of the session guards, for `rocket_okapi`.
+//!
+//! This allows routes taking a [Session], [BoundSession], [SessionExtract]
+//! or [BearerSession] to be documented with the `#[openapi]` attribute. The
+//! session cookie is declared as an API key security scheme named after the
+//! cookie, and bearer sessions as an HTTP bearer scheme named `bearer`.
+//! [CsrfCheck] declares the header echoing the companion cookie as another
+//! API key scheme.
+//!
+//! The guards only know the type of the session values, so the names are
+//! taken from the [SessionStore] of that type once it was turned into a
+//! fairing, which has to happen before the routes are documented. Until
+//! then, the cookie is named `session` and the CSRF header
+//! [DEFAULT_CSRF_HEADER](crate::csrf::DEFAULT_CSRF_HEADER).
+//!
+//! [SessionError] is documented as a response with a 500 status.
+
+use std::{
+	any::TypeId,
+	collections::BTreeMap,
+	sync::Mutex,
+};
+
+use rocket_okapi::{
+	gen::OpenApiGenerator,
+	okapi::openapi3::{
+		Object,
+		Responses,
+		SecurityRequirement,
+		SecurityScheme,
+		SecuritySchemeData,
+	},
+	request::{
+		OpenApiFromRequest,
+		RequestHeaderInput,
+	},
+	response::OpenApiResponderInner,
+	util::ensure_status_code_exists,
+};
+
+#[cfg(feature = "csrf")]
+use crate::csrf::CsrfCheck;
+use crate::{
+	bearer::BearerSession,
+	bound::{
+		Bound,
+		BoundSession,
+	},
+	extract::{
+		FromSession,
+		SessionExtract,
+	},
+	Session,
+	SessionError,
+	SessionStore,
+};
+
+/// The cookie name documented for stores that weren't turned into a fairing.
+const DEFAULT_COOKIE: &str = "session";
+
+const BEARER_SCHEME: &str = "bearer";
+
+/// The names documented for the store of each type of session values.
+struct Names {
+	cookie: String,
+	#[cfg(feature = "csrf")]
+	csrf_header: Option<String>,
+}
+
+static NAMES: Mutex<BTreeMap<TypeId, Names>> = Mutex::new(BTreeMap::new());
+
+/// Records the names of a store turned into a fairing, for the guards of its
+/// values to document.
+pub(crate) fn register<T: 'static>(store: &SessionStore<T>) {
+	let names = Names {
+		cookie: store.name.clone(),
+		#[cfg(feature = "csrf")]
+		csrf_header: store.csrf.as_ref().map(|csrf| csrf.header_name.clone()),
+	};
+	NAMES
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.insert(TypeId::of::<T>(), names);
+}
+
+fn with_names<T: 'static, R>(f: impl FnOnce(Option<&Names>) -> R) -> R {
+	let names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+	f(names.get(&TypeId::of::<T>()))
+}
+
+/// An API key security scheme, named after the key it documents.
+fn api_key(description: &str, name: String, location: &str) -> RequestHeaderInput {
+	let scheme = SecurityScheme {
+		description: Some(description.into()),
+		data: SecuritySchemeData::ApiKey {
+			name: name.clone(),
+			location: location.into(),
+		},
+		extensions: Object::default(),
+	};
+	let mut requirement = SecurityRequirement::new();
+	requirement.insert(name.clone(), Vec::new());
+	RequestHeaderInput::Security(name, scheme, requirement)
+}
+
+/// The session cookie of the store of values of type `T`.
+fn session_cookie<T: 'static>() -> RequestHeaderInput {
+	let cookie = with_names::<T, _>(|names| {
+		names.map_or_else(|| DEFAULT_COOKIE.to_owned(), |names| names.cookie.clone())
+	});
+	api_key("The session cookie.", cookie, "cookie")
+}
+
+impl<'r, 's, T> OpenApiFromRequest<'r> for Session<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+	'r: 's,
+{
+	fn from_request_input(
+		_gen: &mut OpenApiGenerator,
+		_name: String,
+		_required: bool,
+	) -> rocket_okapi::Result<RequestHeaderInput> {
+		Ok(session_cookie::<T>())
+	}
+}
+
+impl<'r, 's, T> OpenApiFromRequest<'r> for BoundSession<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+	'r: 's,
+{
+	fn from_request_input(
+		_gen: &mut OpenApiGenerator,
+		_name: String,
+		_required: bool,
+	) -> rocket_okapi::Result<RequestHeaderInput> {
+		Ok(session_cookie::<Bound<T>>())
+	}
+}
+
+impl<'r, U, T> OpenApiFromRequest<'r> for SessionExtract<U, T>
+where
+	U: FromSession<T>,
+	T: Send + Sync + Clone + 'static,
+{
+	fn from_request_input(
+		_gen: &mut OpenApiGenerator,
+		_name: String,
+		_required: bool,
+	) -> rocket_okapi::Result<RequestHeaderInput> {
+		Ok(session_cookie::<T>())
+	}
+}
+
+impl<'r, 's, T> OpenApiFromRequest<'r> for BearerSession<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+	'r: 's,
+{
+	fn from_request_input(
+		_gen: &mut OpenApiGenerator,
+		_name: String,
+		_required: bool,
+	) -> rocket_okapi::Result<RequestHeaderInput> {
+		let scheme = SecurityScheme {
+			description: Some("The session token.".into()),
+			data: SecuritySchemeData::Http {
+				scheme: "bearer".into(),
+				bearer_format: None,
+			},
+			extensions: Object::default(),
+		};
+		let mut requirement = SecurityRequirement::new();
+		requirement.insert(BEARER_SCHEME.into(), Vec::new());
+		Ok(RequestHeaderInput::Security(
+			BEARER_SCHEME.into(),
+			scheme,
+			requirement,
+		))
+	}
+}
+
+#[cfg(feature = "csrf")]
+impl<'r, T> OpenApiFromRequest<'r> for CsrfCheck<T>
+where
+	T: Send + Sync + 'static,
+{
+	fn from_request_input(
+		_gen: &mut OpenApiGenerator,
+		_name: String,
+		_required: bool,
+	) -> rocket_okapi::Result<RequestHeaderInput> {
+		let header = with_names::<T, _>(|names| {
+			names
+				.and_then(|names| names.csrf_header.clone())
+				.unwrap_or_else(|| crate::csrf::DEFAULT_CSRF_HEADER.to_owned())
+		});
+		Ok(api_key("The value of the CSRF cookie.", header, "header"))
+	}
+}
+
+impl OpenApiResponderInner for SessionError {
+	fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+		let mut responses = Responses::default();
+		ensure_status_code_exists(&mut responses, 500);
+		Ok(responses)
+	}
+}
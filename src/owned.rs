@@ -0,0 +1,183 @@
+//! Sessions outliving their request.
+//!
+//! A [Session] borrows the [SessionStore] managed by the rocket for the
+//! lifetime of the request, so it can't be moved into a spawned task. An
+//! [OwnedSession], obtained with [Session::to_owned], holds its own handle to
+//! the store instead, so background work started by a handler can still read
+//! and update the session after the response is sent.
+//!
+//! Changes made once the response is sent reach the store, but not the
+//! client: a token regenerated or a session removed from a background task
+//! isn't reflected in the cookie.
+//!
+//! [Session::prefetch] makes an owned session from the value of the session
+//! cookie alone, reading its value right away, so that server side rendering
+//! can start loading the session as soon as the request headers are parsed.
+//! Its reads are then answered from the value read, until it is changed
+//! through it.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use rocket::{post, tokio};
+//! # use rocket_session_store::Session;
+//! #[post("/import")]
+//! async fn import(session: Session<'_, String>) -> &'static str {
+//!     let session = session.to_owned();
+//!     tokio::spawn(async move {
+//!         // A long running import, then
+//!         let _ = session.set("imported".into()).await;
+//!     });
+//!     "Import started"
+//! }
+//! ```
+
+use std::{
+	future::Future,
+	sync::Arc,
+	time::Duration,
+};
+
+use rocket::State;
+
+use crate::{
+	Session,
+	SessionError,
+	SessionID,
+	SessionResult,
+	SessionState,
+	SessionStore,
+};
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	/// Reads the session of the given session cookie value ahead of the
+	/// request, returning an owned session holding the value read.
+	///
+	/// Tokens the store rejects, such as tokens which are too long, get a
+	/// new session instead, as they would in a request, as do unknown
+	/// tokens with [strict tokens](SessionStore::strict_tokens). The
+	/// session isn't tied to a request, so such a new token isn't sent to
+	/// the client. The returned future doesn't borrow the store, so it can
+	/// be spawned. It fails when the store has a
+	/// [key mapper](SessionStore::key_mapper), as there is no request to map
+	/// the token with.
+	pub fn prefetch(
+		store: &SessionStore<T>,
+		cookie_value: &str,
+	) -> impl Future<Output = SessionResult<OwnedSession<T>>> + Send + 'static {
+		// Owned up front, so the read can be spawned.
+		let store = Arc::new(store.clone());
+		let cookie_value = cookie_value.to_owned();
+		async move {
+			if store.key_mapper.is_some() {
+				log::warn!("can't prefetch a session with a key mapper");
+				return Err(SessionError::Other);
+			}
+			let token = match store.parse_token(cookie_value) {
+				Some(token) if store.strict_tokens && !store.is_known(&token).await => None,
+				token => token,
+			};
+			let state = match token {
+				Some(token) => SessionState::new(token, false),
+				None => store.new_state(store.generate_token()),
+			};
+			let session = OwnedSession {
+				store,
+				state: Arc::new(state),
+			};
+			// Caches the value for the reads of the session.
+			session.get().await?;
+			Ok(session)
+		}
+	}
+}
+
+/// A handle to a session which isn't tied to a request, see the
+/// [module](self) documentation.
+///
+/// It shares the state of the request it was made from, so changes made
+/// through it while the request is handled are seen by the [Session] guards
+/// of the request and sent to the client as usual. It is cheap to clone.
+pub struct OwnedSession<T: 'static> {
+	store: Arc<SessionStore<T>>,
+	state: Arc<SessionState>,
+}
+
+impl<T: 'static> Clone for OwnedSession<T> {
+	fn clone(&self) -> Self {
+		Self {
+			store: self.store.clone(),
+			state: self.state.clone(),
+		}
+	}
+}
+
+impl<T> OwnedSession<T>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	pub(crate) fn new(store: &SessionStore<T>, state: Arc<SessionState>) -> Self {
+		Self {
+			store: Arc::new(store.clone()),
+			state,
+		}
+	}
+
+	/// Borrows the session as a [Session], to run its operations.
+	fn session(&self) -> Session<'_, T> {
+		Session {
+			store: State::from(&*self.store),
+			state: self.state.clone(),
+			key_mapper: None,
+		}
+	}
+
+	/// Get the token of the session.
+	pub fn id(&self) -> SessionID {
+		self.state.token()
+	}
+
+	/// Get the session value from the store.
+	///
+	/// See [Session::get].
+	pub async fn get(&self) -> SessionResult<Option<T>> {
+		self.session().get_opt().await
+	}
+
+	/// Sets the session value.
+	///
+	/// See [Session::set].
+	pub async fn set(&self, value: T) -> SessionResult<()> {
+		self.session().set(value).await
+	}
+
+	/// Refreshes the expiration timer of the session.
+	///
+	/// See [Session::touch].
+	pub async fn touch(&self) -> SessionResult<Option<Duration>> {
+		self.session().touch().await
+	}
+
+	/// Removes the session from the store.
+	///
+	/// See [Session::remove].
+	pub async fn remove(&self) -> SessionResult<bool> {
+		self.session().remove().await
+	}
+
+	/// Moves the session to a newly generated token.
+	///
+	/// See [Session::regenerate_token]. This fails when the store has a
+	/// [key mapper](SessionStore::key_mapper), as the request it needs to map
+	/// the new token is gone.
+	pub async fn regenerate_token(&self) -> SessionResult<()> {
+		if self.store.key_mapper.is_some() {
+			log::warn!("can't regenerate the token of an owned session with a key mapper");
+			return Err(SessionError::Other);
+		}
+		self.session().regenerate_token().await
+	}
+}
@@ -0,0 +1,206 @@
+//! Prometheus metrics of a session store, enabled with the `prometheus`
+//! feature.
+//!
+//! Every [SessionStore](crate::SessionStore) records its metrics in its own
+//! [Registry], available from
+//! [SessionStore::prometheus_registry](crate::SessionStore::prometheus_registry)
+//! to be gathered with the rest of the application, or served on its own by
+//! the fairing created with
+//! [SessionStore::metrics_fairing](crate::SessionStore::metrics_fairing).
+//!
+//! - `session_store_get_total{result}` counts reads, with a result of `hit`,
+//!   `miss` or `error`.
+//! - `session_store_set_total` and `session_store_remove_total` count writes
+//!   and removals.
+//! - `session_store_active_sessions` goes up when a session is created and
+//!   down when one is removed. Sessions that expire aren't accounted for, so
+//!   it is an upper estimate.
+//! - `session_store_operation_duration_seconds{op}` is a histogram of the
+//!   latency of operations.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{memory::MemoryStore, SessionStore};
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build()
+//!     .attach(store.metrics_fairing("/metrics"))
+//!     .attach(store.fairing());
+//! ```
+
+use std::{
+	sync::Arc,
+	time::Duration,
+};
+
+use ::prometheus::{
+	Encoder,
+	Gauge,
+	HistogramOpts,
+	HistogramVec,
+	IntCounter,
+	IntCounterVec,
+	Opts,
+	Registry,
+	TextEncoder,
+};
+use rocket::{
+	fairing::{
+		Fairing,
+		Info,
+		Kind,
+	},
+	http::{
+		ContentType,
+		Method,
+		Status,
+	},
+	route::{
+		Handler,
+		Outcome,
+	},
+	Build,
+	Data,
+	Request,
+	Rocket,
+	Route,
+};
+
+use crate::SessionResult;
+
+/// The metrics of a session store and the registry holding them.
+pub struct SessionMetrics {
+	registry: Registry,
+	get_total: IntCounterVec,
+	set_total: IntCounter,
+	remove_total: IntCounter,
+	active_sessions: Gauge,
+	duration: HistogramVec,
+}
+
+impl SessionMetrics {
+	pub(crate) fn new() -> Self {
+		let registry = Registry::new();
+		let get_total = IntCounterVec::new(
+			Opts::new("session_store_get_total", "Reads of session values."),
+			&["result"],
+		)
+		.expect("valid metric");
+		let set_total = IntCounter::new("session_store_set_total", "Writes of session values.")
+			.expect("valid metric");
+		let remove_total = IntCounter::new("session_store_remove_total", "Removed sessions.")
+			.expect("valid metric");
+		let active_sessions = Gauge::new(
+			"session_store_active_sessions",
+			"Sessions created and not removed, including expired ones.",
+		)
+		.expect("valid metric");
+		let duration = HistogramVec::new(
+			HistogramOpts::new(
+				"session_store_operation_duration_seconds",
+				"Latency of session operations.",
+			),
+			&["op"],
+		)
+		.expect("valid metric");
+		registry
+			.register(Box::new(get_total.clone()))
+			.and_then(|_| registry.register(Box::new(set_total.clone())))
+			.and_then(|_| registry.register(Box::new(remove_total.clone())))
+			.and_then(|_| registry.register(Box::new(active_sessions.clone())))
+			.and_then(|_| registry.register(Box::new(duration.clone())))
+			.expect("metrics are registered once in a new registry");
+		Self {
+			registry,
+			get_total,
+			set_total,
+			remove_total,
+			active_sessions,
+			duration,
+		}
+	}
+
+	/// Get the registry holding the metrics.
+	pub fn registry(&self) -> &Registry {
+		&self.registry
+	}
+
+	pub(crate) fn operation(&self, op: &str, elapsed: Duration) {
+		self.duration
+			.with_label_values(&[op])
+			.observe(elapsed.as_secs_f64());
+		match op {
+			"set" | "swap" => self.set_total.inc(),
+			"remove" => self.remove_total.inc(),
+			_ => {}
+		}
+	}
+
+	pub(crate) fn read<T>(&self, result: &SessionResult<Option<T>>) {
+		let result = match result {
+			Ok(Some(_)) => "hit",
+			Ok(None) => "miss",
+			Err(_) => "error",
+		};
+		self.get_total.with_label_values(&[result]).inc();
+	}
+
+	pub(crate) fn active_sessions(&self, delta: f64) {
+		self.active_sessions.add(delta);
+	}
+}
+
+/// The fairing mounting the scrape route of the metrics.
+///
+/// The route answers `GET` requests with the metrics of the store in the
+/// Prometheus text format.
+pub struct MetricsFairing {
+	metrics: Arc<SessionMetrics>,
+	path: &'static str,
+}
+
+impl MetricsFairing {
+	pub(crate) fn new(metrics: Arc<SessionMetrics>, path: &'static str) -> Self {
+		Self { metrics, path }
+	}
+}
+
+#[rocket::async_trait]
+impl Fairing for MetricsFairing {
+	fn info(&self) -> Info {
+		Info {
+			name: "Session Store Metrics",
+			kind: Kind::Ignite,
+		}
+	}
+
+	async fn on_ignite(&self, rocket: Rocket<Build>) -> Result<Rocket<Build>, Rocket<Build>> {
+		let handler = MetricsHandler {
+			metrics: self.metrics.clone(),
+		};
+		let route = Route::new(Method::Get, self.path, handler);
+		Ok(rocket.mount("/", vec![route]))
+	}
+}
+
+#[derive(Clone)]
+struct MetricsHandler {
+	metrics: Arc<SessionMetrics>,
+}
+
+#[rocket::async_trait]
+impl Handler for MetricsHandler {
+	async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+		let encoder = TextEncoder::new();
+		let mut body = Vec::new();
+		if let Err(e) = encoder.encode(&self.metrics.registry.gather(), &mut body) {
+			log::warn!("could not encode the session metrics: {}", e);
+			return Outcome::from(request, Status::InternalServerError);
+		}
+		let content_type =
+			ContentType::parse_flexible(encoder.format_type()).unwrap_or(ContentType::Plain);
+		Outcome::from(request, (content_type, body))
+	}
+}
@@ -1,154 +1,1639 @@
 //! A redis implementation of a session store.
-//! 
+//!
 //! This module provides [RedisStore], which is a
-//! session store that uses redis.
-//! 
+//! session store that uses redis, and [RedisRawStore], the same store keeping
+//! values as bytes, to be used with another
+//! [SessionCodec](crate::codec::SessionCodec).
+//!
 //! ## Example
-//! 
+//!
 //! ```no_run
 //! # use std::time::Duration;
 //! # use redis::Client;
 //! # use rocket_session_store::{SessionStore, CookieConfig, redis::RedisStore};
 //! let client: Client = Client::open("redis://127.0.0.1")
-//! 	.expect("Failed to connect to redis");
+//!     .expect("Failed to connect to redis");
 //! let redis_store: RedisStore<String> = RedisStore::new(client);
-//! let store: SessionStore<String> = SessionStore {
-//! 	store: Box::new(redis_store),
-//! 	name: "token".into(),
-//! 	duration: Duration::from_secs(3600),
-//! 	cookie: CookieConfig::default(),
-//! };
+//! let store: SessionStore<String> =
+//!     SessionStore::new(redis_store, "token", Duration::from_secs(3600))
+//!         .with_cookie(CookieConfig::default());
+//! ```
+//!
+//! ## Connection pools
+//!
+//! With the `redis-pool` feature, [RedisStore::from_pool] takes connections
+//! from a `deadpool-redis` 0.10 pool instead of opening one per operation.
+//! This release uses the same version of redis as the store. It is the pool
+//! type of the `deadpool_redis` driver of `rocket_db_pools` 0.1.0-rc.2, so an
+//! application already holding a redis database can share its pool with the
+//! session store, taking the pool out of the database in an ignite fairing
+//! before attaching the store:
+//!
+//! ```no_run
+//! # #[cfg(feature = "redis-pool")] {
+//! # use std::time::Duration;
+//! # use rocket_session_store::{SessionStore, redis::RedisStore};
+//! // With rocket_db_pools, this is `&*Sessions::fetch(&rocket)?` for a
+//! // `#[database("sessions")] struct Sessions(deadpool_redis::Pool)`.
+//! let pool = deadpool_redis::Config::from_url("redis://127.0.0.1")
+//!     .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+//!     .expect("Failed to create the redis pool");
+//! let store: SessionStore<String> =
+//!     SessionStore::new(RedisStore::from_pool(&pool), "token", Duration::from_secs(3600));
+//! # }
+//! ```
+//!
+//! ## Creation times
+//!
+//! The time a session was created is kept under a companion key expiring
+//! along with it, so that [Store::created_at] can tell the age of the
+//! session, such as for
+//! [SessionStore::auto_regenerate_after](crate::SessionStore::auto_regenerate_after).
+//! Sessions stored by earlier versions of the store have none until their
+//! token is regenerated.
+//!
+//! ## Read replicas
+//!
+//! Reads can be sent to replicas of the master with
+//! [RedisStore::with_replica], taking load off the master. Replicas are
+//! updated asynchronously, so a session written by one request may not be
+//! on the replica yet when the next request reads it, for a few
+//! milliseconds at most on a healthy deployment. A session may then briefly
+//! look missing or stale right after being created or changed.
+//!
+//! ```no_run
+//! # use redis::Client;
+//! # use rocket_session_store::redis::RedisStore;
+//! let master = Client::open("redis://10.0.0.1").expect("valid redis URL");
+//! let replica = Client::open("redis://10.0.0.2").expect("valid redis URL");
+//! let store: RedisStore<String> = RedisStore::new(master).with_replica(replica);
 //! ```
 
 use std::{
-	marker::PhantomData,
-	time::Duration,
+	fmt,
+	future::Future,
+	sync::{
+		atomic::{
+			AtomicUsize,
+			Ordering,
+		},
+		Arc,
+		OnceLock,
+		RwLock,
+	},
+	time::{
+		Duration,
+		SystemTime,
+		UNIX_EPOCH,
+	},
 };
 
 use redis::{
+	aio::Connection,
 	Client,
-	ConnectionLike,
+	Cmd,
+	FromRedisValue,
+	Pipeline,
+	RedisError,
+	RedisResult,
+	RedisWrite,
+	Script,
+	ScriptInvocation,
+	ToRedisArgs,
+	Value,
 };
-use rocket::serde::DeserializeOwned;
-use serde::Serialize;
-use serde_json::{
-	from_slice,
-	to_string,
+use rocket::{
+	serde::DeserializeOwned,
+	tokio::time,
 };
+use serde::Serialize;
 
 use crate::{
+	codec::{
+		CodecStore,
+		JsonCodec,
+	},
+	ExpiredCleanup,
 	SessionError,
 	SessionResult,
 	Store,
+	NO_EXPIRY,
 };
 
+/// How long connecting to redis may take unless another timeout is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The highest database number of a default redis configuration.
+const MAX_DATABASE: u8 = 15;
+
+/// Approximate number of entries kept in the audit stream.
+const AUDIT_STREAM_MAXLEN: usize = 1000;
+
+/// The namespace of the keys holding the sessions of a user.
+const USER_NAMESPACE: &str = "user-sessions:";
+
+/// The namespace of the keys holding the advisory locks of sessions.
+const LOCK_NAMESPACE: &str = "lock:";
+
+/// The namespace of the keys holding when sessions were created, in
+/// milliseconds since the unix epoch, expiring along with the sessions.
+const CREATED_NAMESPACE: &str = "created:";
+
+/// A Lua script, sent by its hash with `EVALSHA` and loaded into redis
+/// the first time the server doesn't know it.
+struct LuaScript {
+	code: &'static str,
+	script: OnceLock<Script>,
+}
+
+impl LuaScript {
+	const fn new(code: &'static str) -> Self {
+		Self {
+			code,
+			script: OnceLock::new(),
+		}
+	}
+
+	/// Get the script, hashing it on first use.
+	fn get(&self) -> &Script {
+		self.script.get_or_init(|| Script::new(self.code))
+	}
+}
+
+/// Sets a session if it holds the expected value, or if it doesn't exist
+/// when none is expected, returning 1 when it was set.
+const COMPARE_AND_SWAP_SCRIPT: &str = "
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == 'missing' then
+	if current then return 0 end
+elseif current ~= ARGV[2] then
+	return 0
+end
+redis.call('SET', KEYS[2], ARGV[5], 'NX')
+if ARGV[4] == 'persist' then
+	redis.call('SET', KEYS[1], ARGV[3])
+	redis.call('PERSIST', KEYS[2])
+else
+	redis.call('SET', KEYS[1], ARGV[3], 'EX', ARGV[4])
+	redis.call('EXPIRE', KEYS[2], ARGV[4])
+end
+return 1";
+
+/// Takes a lock with `SET NX PX`, or renews it when the holder already has
+/// it, returning 1 when the lock is held.
+const ACQUIRE_LOCK_SCRIPT: &str = "
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then return 1 end
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+	redis.call('PEXPIRE', KEYS[1], ARGV[2])
+	return 1
+end
+return 0";
+
+/// Deletes a lock if it is still held by the holder, returning 1 when it was
+/// deleted.
+const RELEASE_LOCK_SCRIPT: &str = "
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+	return redis.call('DEL', KEYS[1])
+end
+return 0";
+
+/// Sets the expiry of a session and of its creation time in seconds, or
+/// removes it with `persist`, and replies like `PTTL`. Sessions without an
+/// expiry keep it unless persisted.
+const TOUCH_SCRIPT: &str = "
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl == -2 then return -2 end
+if ARGV[1] == 'persist' then
+	redis.call('PERSIST', KEYS[1])
+	redis.call('PERSIST', KEYS[2])
+	return -1
+end
+if ttl == -1 then return -1 end
+redis.call('EXPIRE', KEYS[1], ARGV[1])
+redis.call('EXPIRE', KEYS[2], ARGV[1])
+return tonumber(ARGV[1]) * 1000";
+
+/// Moves a session with `RENAME` and renews its expiry, returning 0 when
+/// there is no session to move. The session under the new id is created
+/// now, so its creation time is replaced.
+const RENAME_SCRIPT: &str = "
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+redis.call('RENAME', KEYS[1], KEYS[2])
+redis.call('DEL', KEYS[3])
+if ARGV[1] == 'persist' then
+	redis.call('PERSIST', KEYS[2])
+	redis.call('SET', KEYS[4], ARGV[2])
+else
+	redis.call('EXPIRE', KEYS[2], ARGV[1])
+	redis.call('SET', KEYS[4], ARGV[2], 'EX', ARGV[1])
+end
+return 1";
+
+static COMPARE_AND_SWAP: LuaScript = LuaScript::new(COMPARE_AND_SWAP_SCRIPT);
+static ACQUIRE_LOCK: LuaScript = LuaScript::new(ACQUIRE_LOCK_SCRIPT);
+static RELEASE_LOCK: LuaScript = LuaScript::new(RELEASE_LOCK_SCRIPT);
+static RENAME: LuaScript = LuaScript::new(RENAME_SCRIPT);
+static TOUCH: LuaScript = LuaScript::new(TOUCH_SCRIPT);
+
+/// A redis store encoding values with bincode, enabled with the `bincode`
+/// feature.
+///
+/// Values are much smaller than with [RedisStore], see
+/// [BincodeCodec](crate::codec::BincodeCodec). It is created with
+/// [CodecStore::new]:
+///
+/// ```no_run
+/// # use redis::Client;
+/// # use rocket_session_store::{
+/// #     codec::{BincodeCodec, CodecStore},
+/// #     redis::{BincodeRedisStore, RedisRawStore},
+/// # };
+/// let client = Client::open("redis://127.0.0.1").expect("valid redis URL");
+/// let store: BincodeRedisStore<(u64, u8)> =
+///     CodecStore::new(BincodeCodec, RedisRawStore::new(client));
+/// ```
+#[cfg(feature = "bincode")]
+pub type BincodeRedisStore<T> = CodecStore<crate::codec::BincodeCodec, RedisRawStore, T>;
+
+/// A redis store serializing values to MessagePack, enabled with the
+/// `redis-msgpack` feature.
+///
+/// It is a drop-in replacement for [RedisStore] storing smaller values, see
+/// [MessagePackCodec](crate::codec::MessagePackCodec). It is created with
+/// [CodecStore::new]:
+///
+/// ```no_run
+/// # use redis::Client;
+/// # use rocket_session_store::{
+/// #     codec::{CodecStore, MessagePackCodec},
+/// #     redis::{MessagePackRedisStore, RedisRawStore},
+/// # };
+/// let client = Client::open("redis://127.0.0.1").expect("valid redis URL");
+/// let store: MessagePackRedisStore<String> =
+///     CodecStore::new(MessagePackCodec, RedisRawStore::new(client));
+/// ```
+#[cfg(feature = "redis-msgpack")]
+pub type MessagePackRedisStore<T> = CodecStore<crate::codec::MessagePackCodec, RedisRawStore, T>;
+
 /// A redis implementation for [Store].
+///
+/// Values are serialized to JSON. This is a [CodecStore] running a
+/// [JsonCodec] over a [RedisRawStore].
 pub struct RedisStore<T> {
-	client: Client,
-	prefix: Option<String>,
-	postfix: Option<String>,
-	_marker: PhantomData<T>,
+	inner: CodecStore<JsonCodec, RedisRawStore, T>,
 }
 
 impl<T> RedisStore<T> {
+	/// Creates a new store from a redis client.
+	pub fn new(client: Client) -> Self {
+		Self::from_raw(RedisRawStore::new(client))
+	}
+
+	/// Creates a new store connecting to the redis URL held by an environment
+	/// variable, such as `REDIS_URL`.
+	///
+	/// See [RedisRawStore::from_env].
+	pub fn from_env(var: &str) -> SessionResult<Self> {
+		RedisRawStore::from_env(var).map(Self::from_raw)
+	}
+
+	/// Creates a new store connecting to the redis URL held by an environment
+	/// variable, or to the default URL when the variable isn't set.
+	///
+	/// See [RedisRawStore::from_env_or].
+	pub fn from_env_or(var: &str, default: &str) -> SessionResult<Self> {
+		RedisRawStore::from_env_or(var, default).map(Self::from_raw)
+	}
+
+	/// Creates a new store taking its connections from a `deadpool-redis`
+	/// pool, enabled with the `redis-pool` feature.
+	///
+	/// See [RedisRawStore::from_pool].
+	#[cfg(feature = "redis-pool")]
+	pub fn from_pool(pool: &deadpool_redis::Pool) -> Self {
+		Self::from_raw(RedisRawStore::from_pool(pool))
+	}
+
+	/// Creates a new store keeping values in the given raw store.
+	pub fn from_raw(raw: RedisRawStore) -> Self {
+		Self {
+			inner: CodecStore::new(JsonCodec, raw),
+		}
+	}
+
+	/// Adds a prefix to the key when storing it to the redis database.
+	///
+	/// See [RedisRawStore::prefix].
+	pub fn prefix(self, prefix: String) -> Self {
+		Self::from_raw(self.into_raw().prefix(prefix))
+	}
+
+	/// Adds a postfix to the key when storing it to the redis database.
+	///
+	/// See [RedisRawStore::postfix].
+	pub fn postfix(self, postfix: String) -> Self {
+		Self::from_raw(self.into_raw().postfix(postfix))
+	}
+
+	/// Sets how long connecting to redis may take before failing.
+	///
+	/// See [RedisRawStore::with_connection_timeout].
+	pub fn with_connection_timeout(self, timeout: Duration) -> Self {
+		Self::from_raw(self.into_raw().with_connection_timeout(timeout))
+	}
+
+	/// Selects the redis database the sessions are kept in.
+	///
+	/// See [RedisRawStore::with_database].
+	pub fn with_database(self, db: u8) -> SessionResult<Self> {
+		self.into_raw().with_database(db).map(Self::from_raw)
+	}
+
+	/// Sends reads to a replica of the master.
+	///
+	/// See [RedisRawStore::with_replica].
+	pub fn with_replica(self, replica: Client) -> Self {
+		Self::from_raw(self.into_raw().with_replica(replica))
+	}
+
+	/// Appends the operations on sessions to a redis stream.
+	///
+	/// See [RedisRawStore::with_audit_stream].
+	pub fn with_audit_stream(self, stream: String) -> Self {
+		Self::from_raw(self.into_raw().with_audit_stream(stream))
+	}
+
+	/// Get the audited operations on a session, oldest first.
+	///
+	/// See [RedisRawStore::read_audit].
+	pub async fn read_audit(&self, session_id: &str) -> SessionResult<Vec<AuditEntry>> {
+		self.inner().read_audit(session_id).await
+	}
+
+	/// Changes the prefix of the keys of the store while it is in use.
+	///
+	/// See [RedisRawStore::set_prefix].
+	pub fn set_prefix(&self, prefix: impl Into<String>) {
+		self.inner().set_prefix(prefix)
+	}
+
+	/// Makes every existing session unreachable by bumping the generation
+	/// number in the prefix.
+	///
+	/// See [RedisRawStore::bump_generation].
+	pub fn bump_generation(&self) -> u64 {
+		self.inner().bump_generation()
+	}
+
+	/// Get the time since a session was last accessed.
+	///
+	/// See [RedisRawStore::idle_time].
+	pub async fn idle_time(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner().idle_time(id).await
+	}
+
+	/// Get the raw store keeping the serialized values.
+	pub fn inner(&self) -> &RedisRawStore {
+		self.inner.inner()
+	}
+
+	fn into_raw(self) -> RedisRawStore {
+		self.inner.into_inner()
+	}
+}
+
+/// A redis implementation for [RawStore](crate::codec::RawStore), keeping
+/// values as bytes.
+pub struct RedisRawStore {
+	connector: Connector,
+	prefix: RwLock<Option<Arc<str>>>,
+	postfix: Option<String>,
+	timeout: Duration,
+	audit_stream: Option<String>,
+	replicas: Vec<Client>,
+	/// The replica the next read is sent to, modulo the number of replicas.
+	next_replica: AtomicUsize,
+}
+
+/// Where a [RedisRawStore] gets its connections from.
+enum Connector {
+	Client(Client),
+	#[cfg(feature = "redis-pool")]
+	Pool(deadpool_redis::Pool),
+}
+
+/// A connection made for a single operation, or taken from a pool.
+enum RedisConnection {
+	/// A connection of the client, whose replies are awaited for at most the
+	/// [connection timeout](RedisRawStore::with_connection_timeout).
+	Direct(Connection, Duration),
+	#[cfg(feature = "redis-pool")]
+	Pooled(deadpool_redis::Connection),
+}
+
+impl RedisConnection {
+	async fn query<T: FromRedisValue>(&mut self, cmd: &Cmd) -> SessionResult<T> {
+		match self {
+			RedisConnection::Direct(con, timeout) => within(*timeout, cmd.query_async(con)).await,
+			#[cfg(feature = "redis-pool")]
+			RedisConnection::Pooled(con) => cmd.query_async(con).await.map_err(command_error),
+		}
+	}
+
+	async fn query_pipe<T: FromRedisValue>(&mut self, pipe: &Pipeline) -> SessionResult<T> {
+		match self {
+			RedisConnection::Direct(con, timeout) => within(*timeout, pipe.query_async(con)).await,
+			#[cfg(feature = "redis-pool")]
+			RedisConnection::Pooled(con) => pipe.query_async(con).await.map_err(command_error),
+		}
+	}
+
+	async fn invoke<T: FromRedisValue>(
+		&mut self,
+		script: &ScriptInvocation<'_>,
+	) -> SessionResult<T> {
+		match self {
+			RedisConnection::Direct(con, timeout) => {
+				within(*timeout, script.invoke_async(con)).await
+			}
+			#[cfg(feature = "redis-pool")]
+			RedisConnection::Pooled(con) => script.invoke_async(con).await.map_err(command_error),
+		}
+	}
+}
+
+/// An operation on a session, as recorded in the audit stream, see
+/// [RedisRawStore::with_audit_stream].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOp {
+	/// The session was read with [Store::get].
+	Get,
+	/// The session was written with [Store::set].
+	Set,
+	/// The expiry of the session was renewed with [Store::touch].
+	Touch,
+	/// The session was removed with [Store::remove].
+	Remove,
+}
+
+impl AuditOp {
+	/// Get the name of the operation, as written in the stream.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			AuditOp::Get => "get",
+			AuditOp::Set => "set",
+			AuditOp::Touch => "touch",
+			AuditOp::Remove => "remove",
+		}
+	}
 
+	fn parse(op: &str) -> Option<Self> {
+		match op {
+			"get" => Some(AuditOp::Get),
+			"set" => Some(AuditOp::Set),
+			"touch" => Some(AuditOp::Touch),
+			"remove" => Some(AuditOp::Remove),
+			_ => None,
+		}
+	}
+}
+
+/// An entry of the audit stream, see [RedisRawStore::read_audit].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+	/// The id of the entry in the stream.
+	pub entry_id: String,
+	/// When the operation happened, as recorded by redis.
+	pub at: SystemTime,
+	/// The operation on the session.
+	pub op: AuditOp,
+	/// The id of the session.
+	pub session_id: String,
+}
+
+impl RedisRawStore {
 	/// Creates a new store from a redis client.
 	pub fn new(client: Client) -> Self {
+		Self::with_connector(Connector::Client(client))
+	}
+
+	/// Creates a new store taking its connections from a `deadpool-redis`
+	/// pool, enabled with the `redis-pool` feature.
+	///
+	/// The pool is shared rather than taken, so the pool of a
+	/// `rocket_db_pools` database can be used by the store as well. Its
+	/// connections are bounded by the timeouts of the pool rather than by
+	/// [RedisRawStore::with_connection_timeout].
+	#[cfg(feature = "redis-pool")]
+	pub fn from_pool(pool: &deadpool_redis::Pool) -> Self {
+		Self::with_connector(Connector::Pool(pool.clone()))
+	}
+
+	fn with_connector(connector: Connector) -> Self {
 		Self {
-			client,
-			prefix: None,
+			connector,
+			prefix: RwLock::new(None),
 			postfix: None,
-			_marker: PhantomData::default(),
+			timeout: DEFAULT_TIMEOUT,
+			audit_stream: None,
+			replicas: Vec::new(),
+			next_replica: AtomicUsize::new(0),
+		}
+	}
+
+	/// Creates a new store connecting to the redis URL held by an environment
+	/// variable, such as the `REDIS_URL` set by many hosting platforms.
+	///
+	/// No connection is made yet. It fails, logging why, when the variable
+	/// isn't set or doesn't hold a valid redis URL.
+	pub fn from_env(var: &str) -> SessionResult<Self> {
+		match std::env::var(var) {
+			Ok(url) => Self::from_url(&format!("${}", var), &url),
+			Err(e) => {
+				log::warn!("could not read the redis URL from ${}: {}", var, e);
+				Err(SessionError::Other)
+			}
+		}
+	}
+
+	/// Creates a new store connecting to the redis URL held by an environment
+	/// variable, or to the default URL when the variable isn't set.
+	///
+	/// This is handy for local development, with
+	/// `from_env_or("REDIS_URL", "redis://127.0.0.1")`. It fails, logging
+	/// why, when the URL in use isn't a valid redis URL.
+	pub fn from_env_or(var: &str, default: &str) -> SessionResult<Self> {
+		match std::env::var(var) {
+			Ok(url) => Self::from_url(&format!("${}", var), &url),
+			Err(_) => Self::from_url("the default URL", default),
+		}
+	}
+
+	/// Opens a client for a URL, without logging the URL as it may hold a
+	/// password.
+	fn from_url(source: &str, url: &str) -> SessionResult<Self> {
+		let client = Client::open(url).map_err(|e| {
+			log::warn!("invalid redis URL in {}: {}", source, e);
+			SessionError::Other
+		})?;
+		Ok(Self::new(client))
+	}
+
+	/// Get the redis client of the store, or [None] if it uses a pool.
+	pub fn client(&self) -> Option<&Client> {
+		match self.connector {
+			Connector::Client(ref client) => Some(client),
+			#[cfg(feature = "redis-pool")]
+			Connector::Pool(_) => None,
 		}
 	}
 
 	/// Adds a prefix to the key when storing it to the redis database.
-	/// 
+	///
 	/// For example, if a session had the cookie "1234", giving it the
 	/// prefix "user:" will store the session under the key "user:1234".
-	pub fn prefix(mut self, prefix: String) -> Self{
-		self.prefix = Some(prefix);
+	pub fn prefix(mut self, prefix: String) -> Self {
+		self.prefix = RwLock::new(Some(prefix.into()));
 		self
 	}
 
+	/// Changes the prefix of the keys of the store while it is in use.
+	///
+	/// The sessions stored under the previous prefix are left in the
+	/// database until they expire, but can't be reached anymore.
+	pub fn set_prefix(&self, prefix: impl Into<String>) {
+		let prefix: String = prefix.into();
+		*self.prefix.write().expect("redis prefix poisoned") = Some(prefix.into());
+	}
+
+	/// Get the current prefix of the keys of the store.
+	pub fn current_prefix(&self) -> Option<String> {
+		self.keys().prefix.as_deref().map(str::to_owned)
+	}
+
+	/// Makes every existing session unreachable by bumping the generation
+	/// number in the prefix, and returns the new generation.
+	///
+	/// The generation is the number ending the prefix, before a trailing
+	/// `:`. A prefix without one gets the generation 1, so the prefix
+	/// "user:" becomes "user:1:", then "user:2:", and no prefix becomes "1:". This is cheaper than
+	/// removing every session, for instance after a security incident, as
+	/// the old sessions simply expire. The generation isn't persisted, so it
+	/// has to be part of the configured prefix to survive a restart.
+	pub fn bump_generation(&self) -> u64 {
+		let mut prefix = self.prefix.write().expect("redis prefix poisoned");
+		let current = prefix.take().unwrap_or_default();
+		let body = current.strip_suffix(':').unwrap_or(&current);
+		let base = body.trim_end_matches(|c: char| c.is_ascii_digit());
+		let (base, generation) = match body[base.len()..].parse::<u64>() {
+			Ok(generation) => (base.to_owned(), generation.saturating_add(1)),
+			Err(_) if body.is_empty() => (String::new(), 1),
+			Err(_) => (format!("{}:", body), 1),
+		};
+		*prefix = Some(format!("{}{}:", base, generation).into());
+		generation
+	}
+
 	/// Adds a postfix to the key when storing it to the redis database.
-	/// 
+	///
 	/// For example, if a session had the cookie "1234", giving it the
 	/// postfix ":id" will store the session under the key "1234:id"
-	pub fn postfix(mut self, postfix: String) -> Self{
+	pub fn postfix(mut self, postfix: String) -> Self {
 		self.postfix = Some(postfix);
 		self
 	}
 
-	fn to_key(&self, id: &str) -> String {
-		let n = id.len()
-			+ self.prefix.as_ref().map_or(0, |s| s.len())
-			+ self.postfix.as_ref().map_or(0, |s| s.len());
-		let mut key = String::with_capacity(n);
-		if let Some(ref prefix) = self.prefix {
-			key.push_str(prefix);
+	/// Sets how long connecting to redis, and then sending a command and
+	/// reading its reply, may take before failing with
+	/// [SessionError::Timeout]. It is 5 seconds by default.
+	///
+	/// Without a timeout, a network partition would leave requests waiting
+	/// for redis indefinitely. Connecting includes the `AUTH` and `SELECT`
+	/// commands sent for a database with a password or a number.
+	pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Selects the redis database the sessions are kept in, from 0 to 15,
+	/// replacing the one of the client's URL.
+	///
+	/// Stores using different databases of the same server don't see each
+	/// other's sessions, which isolates applications or session types
+	/// without a [prefix](RedisRawStore::prefix). The database is selected
+	/// with `SELECT` on every new connection.
+	///
+	/// It fails, logging why, if the database is above 15, or if the store
+	/// uses a [pool](RedisRawStore::from_pool), whose database is set in the
+	/// URL of the pool as its connections are shared.
+	pub fn with_database(mut self, db: u8) -> SessionResult<Self> {
+		if db > MAX_DATABASE {
+			log::warn!("the redis database must be between 0 and 15, not {}", db);
+			return Err(SessionError::Other);
 		}
-		key.push_str(id);
-		if let Some(ref postfix) = self.postfix {
-			key.push_str(postfix);
+		let Some(client) = self.client() else {
+			log::warn!("the database of a redis pool is set in its URL");
+			return Err(SessionError::Other);
+		};
+		let mut info = client.get_connection_info().clone();
+		info.redis.db = db.into();
+		let client = Client::open(info).map_err(|e| {
+			log::warn!("could not select the redis database {}: {}", db, e);
+			SessionError::Other
+		})?;
+		self.connector = Connector::Client(client);
+		Ok(self)
+	}
+
+	/// Sends [Store::get], [Store::ttl], [Store::get_with_expiry] and
+	/// [Store::get_many] to a replica of the master, see the
+	/// [module](self) documentation.
+	///
+	/// Calling it again adds more replicas, which take the reads in turn.
+	/// Every other operation, including [Store::contains] which guards
+	/// against token collisions, goes to the master. Replicas are connected
+	/// to like the master, with the
+	/// [connection timeout](RedisRawStore::with_connection_timeout), and
+	/// their database is the one of their own URL.
+	pub fn with_replica(mut self, replica: Client) -> Self {
+		self.replicas.push(replica);
+		self
+	}
+
+	/// Appends every [Store::get], [Store::set], [Store::touch] and
+	/// [Store::remove] to a redis stream, for auditing.
+	///
+	/// Each entry holds the name of the operation in `op` and the session id
+	/// in `id`, so the stream should be kept as safe as the sessions unless
+	/// a [key mapper](crate::SessionStore::key_mapper) hashes them. The
+	/// stream is trimmed to about 1000 entries. The entry is appended after
+	/// the operation, on the same connection, and failing to append it fails
+	/// the operation. Reads sent to a [replica](RedisRawStore::with_replica)
+	/// are appended on a connection to the master.
+	pub fn with_audit_stream(mut self, stream: String) -> Self {
+		self.audit_stream = Some(stream);
+		self
+	}
+
+	/// Get the entries of the audit stream for a session, oldest first.
+	///
+	/// This reads the whole stream, which is kept short. Returns an error
+	/// without an [audit stream](RedisRawStore::with_audit_stream).
+	pub async fn read_audit(&self, session_id: &str) -> SessionResult<Vec<AuditEntry>> {
+		let Some(ref stream) = self.audit_stream else {
+			return Err(SessionError::Other);
+		};
+		let mut cmd = redis::cmd("XRANGE");
+		cmd.arg(stream).arg("-").arg("+");
+		let mut con = self.connection().await?;
+		let entries: Vec<(String, Vec<String>)> = con.query(&cmd).await?;
+		Ok(entries
+			.into_iter()
+			.filter_map(|(entry_id, fields)| {
+				let field = |name: &str| {
+					fields
+						.chunks_exact(2)
+						.find(|pair| pair[0] == name)
+						.map(|pair| pair[1].as_str())
+				};
+				if field("id")? != session_id {
+					return None;
+				}
+				let op = AuditOp::parse(field("op")?)?;
+				let (millis, _) = entry_id.split_once('-')?;
+				let at = UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?);
+				Some(AuditEntry {
+					entry_id,
+					at,
+					op,
+					session_id: session_id.to_owned(),
+				})
+			})
+			.collect())
+	}
+
+	/// Appends an operation to the audit stream, if there is one.
+	async fn audit(&self, con: &mut RedisConnection, op: AuditOp, id: &str) -> SessionResult<()> {
+		let Some(ref stream) = self.audit_stream else {
+			return Ok(());
+		};
+		let mut cmd = redis::cmd("XADD");
+		cmd.arg(stream)
+			.arg("MAXLEN")
+			.arg("~")
+			.arg(AUDIT_STREAM_MAXLEN)
+			.arg("*")
+			.arg("op")
+			.arg(op.as_str())
+			.arg("id")
+			.arg(id);
+		con.query::<()>(&cmd).await.inspect_err(|_| {
+			log::warn!("could not append to the redis audit stream");
+		})?;
+
+		Ok(())
+	}
+
+	/// Connects to the next replica in turn, or to the master without
+	/// replicas.
+	async fn read_connection(&self) -> SessionResult<RedisConnection> {
+		if self.replicas.is_empty() {
+			return self.connection().await;
+		}
+		let next = self.next_replica.fetch_add(1, Ordering::Relaxed);
+		connect_client(&self.replicas[next % self.replicas.len()], self.timeout).await
+	}
+
+	/// Connects to redis, or takes a connection from the pool, which has
+	/// its own timeouts.
+	async fn connection(&self) -> SessionResult<RedisConnection> {
+		match self.connector {
+			Connector::Client(ref client) => connect_client(client, self.timeout).await,
+			#[cfg(feature = "redis-pool")]
+			Connector::Pool(ref pool) => match pool.get().await {
+				Ok(con) => Ok(RedisConnection::Pooled(con)),
+				Err(e) => {
+					log::warn!("could not get a redis connection from the pool: {}", e);
+					Err(SessionError::StoreUnavailable)
+				}
+			},
 		}
-		key
+	}
+
+	/// Get the keys of the store, with its current prefix.
+	///
+	/// The prefix is read once, so that the keys of an operation agree even
+	/// if [RedisRawStore::set_prefix] changes it meanwhile, and writing them
+	/// into a command doesn't lock it again.
+	pub fn keys(&self) -> Keys<'_> {
+		Keys {
+			prefix: self.prefix.read().expect("redis prefix poisoned").clone(),
+			postfix: self.postfix.as_deref().unwrap_or_default(),
+		}
+	}
+
+	/// Get the ids of all the sessions with `SCAN`, leaving out the sessions
+	/// of users and the locks.
+	async fn scan_ids(&self) -> SessionResult<Vec<String>> {
+		let keys = self.keys();
+		let prefix = keys.prefix.as_deref().unwrap_or_default();
+		let postfix = keys.postfix;
+		let pattern = format!("{}*{}", escape_pattern(prefix), escape_pattern(postfix));
+		let users = keys.user("").to_string();
+		let users = users.strip_suffix(postfix).unwrap_or(&users);
+		let locks = keys.lock("").to_string();
+		let locks = locks.strip_suffix(postfix).unwrap_or(&locks);
+		let created = keys.created("").to_string();
+		let created = created.strip_suffix(postfix).unwrap_or(&created);
+		let mut con = self.connection().await?;
+		let mut keys = Vec::new();
+		let mut cursor = 0;
+		loop {
+			let mut cmd = redis::cmd("SCAN");
+			cmd.arg(cursor).arg("MATCH").arg(&pattern);
+			let (next, batch): (u64, Vec<String>) = con.query(&cmd).await?;
+			keys.extend(batch);
+			cursor = next;
+			if cursor == 0 {
+				break;
+			}
+		}
+		Ok(keys
+			.into_iter()
+			.filter(|key| {
+				!key.starts_with(users) && !key.starts_with(locks) && !key.starts_with(created)
+			})
+			.filter_map(|key| {
+				let id = key.strip_prefix(prefix)?.strip_suffix(postfix)?;
+				Some(id.to_owned())
+			})
+			.collect())
+	}
+
+	/// Adds the commands recording when a session was created, unless it
+	/// already was, and giving the record the duration of the session.
+	fn record_creation(
+		keys: &Keys<'_>,
+		pipe: &mut Pipeline,
+		id: &str,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let key = keys.created(id);
+		pipe.cmd("SET").arg(&key).arg(now_millis()?).arg("NX").ignore();
+		match duration {
+			NO_EXPIRY => pipe.cmd("PERSIST").arg(&key).ignore(),
+			duration => pipe.cmd("EXPIRE").arg(&key).arg(duration.as_secs()).ignore(),
+		};
+		Ok(())
+	}
+
+	/// Drops the sessions that were removed or have expired from the index of
+	/// a user, returning the ones left, oldest first.
+	///
+	/// The session being associated is kept, as it may not be stored yet.
+	async fn prune_user_index(
+		con: &mut RedisConnection,
+		keys: &Keys<'_>,
+		key: &Key<'_>,
+		keep: Option<&str>,
+	) -> SessionResult<Vec<String>> {
+		let mut cmd = redis::cmd("ZRANGE");
+		cmd.arg(key);
+		cmd.arg(0);
+		cmd.arg(-1);
+		let ids: Vec<String> = con.query(&cmd).await?;
+		if ids.is_empty() {
+			return Ok(ids);
+		}
+
+		let mut pipe = redis::pipe();
+		for id in &ids {
+			pipe.cmd("EXISTS").arg(keys.session(id));
+		}
+		let exists: Vec<bool> = con.query_pipe(&pipe).await?;
+		let (live, dead): (Vec<_>, Vec<_>) = ids
+			.into_iter()
+			.zip(exists)
+			.partition(|(id, exists)| *exists || keep == Some(id.as_str()));
+		if !dead.is_empty() {
+			let mut cmd = redis::cmd("ZREM");
+			cmd.arg(key);
+			for (id, _) in dead {
+				cmd.arg(id);
+			}
+			con.query::<()>(&cmd).await?;
+		}
+
+		Ok(live.into_iter().map(|(id, _)| id).collect())
+	}
+
+	/// Get the time since a session was last accessed, using `OBJECT IDLETIME`.
+	///
+	/// This allows enforcing an idle timeout without storing the time of the
+	/// last access along with the value. Redis reports it in whole seconds,
+	/// and can't report it when `maxmemory-policy` is set to an LFU policy, in
+	/// which case an error is returned. Returns [None] if there is no session
+	/// under the id.
+	pub async fn idle_time(&self, id: &str) -> SessionResult<Option<Duration>> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut cmd = redis::cmd("OBJECT");
+		cmd.arg("IDLETIME").arg(key);
+		let mut con = self.connection().await?;
+		let secs: Option<u64> = con.query(&cmd).await?;
+		Ok(secs.map(Duration::from_secs))
 	}
 }
 
 #[rocket::async_trait]
-impl<T> Store for RedisStore<T>
-where
-	T: Serialize + DeserializeOwned + Send + Sync,
-{
-	type Value = T;
+impl Store for RedisRawStore {
+	type Value = Vec<u8>;
 
-	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
-		let key = self.to_key(id);
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn get(&self, id: &str) -> SessionResult<Option<Vec<u8>>> {
+		let keys = self.keys();
+		let key = keys.session(id);
 		let mut cmd = redis::cmd("GET");
 		cmd.arg(key);
-		let mut con = self.client.get_connection().map_err(|_| SessionError)?;
-		let val = con.req_command(&cmd).map_err(|_| SessionError)?;
+		let mut con = self.read_connection().await?;
+		let val: Value = con.query(&cmd).await?;
+		if self.audit_stream.is_some() && !self.replicas.is_empty() {
+			con = self.connection().await?;
+		}
+		self.audit(&mut con, AuditOp::Get, id).await?;
 		use redis::Value::*;
 		Ok(match val {
 			Nil => None,
-			Data(ref bytes) => Some(from_slice(bytes).expect("Failed to deserialize")),
+			Data(bytes) => Some(bytes),
 			_ => None,
 		})
 	}
 
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
 	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()> {
-		let key = self.to_key(id);
-		let mut cmd = redis::cmd("SET");
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut pipe = redis::pipe();
+		let cmd = pipe.cmd("SET").arg(key).arg(value);
+		if duration != NO_EXPIRY {
+			cmd.arg("EX").arg(duration.as_secs());
+		}
+		cmd.ignore();
+		Self::record_creation(&keys, &mut pipe, id, duration)?;
+		let mut con = self.connection().await?;
+		con.query_pipe::<()>(&pipe).await?;
+		self.audit(&mut con, AuditOp::Set, id).await?;
+
+		Ok(())
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let expiry = match duration {
+			NO_EXPIRY => "persist".to_owned(),
+			duration => duration.as_secs().to_string(),
+		};
+		let mut script = TOUCH.get().prepare_invoke();
+		script.key(key).key(keys.created(id)).arg(expiry);
+		let mut con = self.connection().await?;
+		let millis: i64 = con.invoke(&script).await?;
+		self.audit(&mut con, AuditOp::Touch, id).await?;
+
+		Ok(ttl_from_millis(millis))
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut pipe = redis::pipe();
+		pipe.cmd("DEL").arg(key);
+		pipe.cmd("DEL").arg(keys.created(id)).ignore();
+		let mut con = self.connection().await?;
+		let (removed,): (usize,) = con.query_pipe(&pipe).await?;
+		self.audit(&mut con, AuditOp::Remove, id).await?;
+
+		Ok(removed > 0)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut cmd = redis::cmd("EXISTS");
+		cmd.arg(key);
+		let mut con = self.connection().await?;
+		let exists: usize = con.query(&cmd).await?;
+		Ok(exists > 0)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut cmd = redis::cmd("PTTL");
+		cmd.arg(key);
+		let mut con = self.read_connection().await?;
+		let millis: i64 = con.query(&cmd).await?;
+		Ok(ttl_from_millis(millis))
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Vec<u8>, Duration)>> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut pipe = redis::pipe();
+		pipe.cmd("GET").arg(&key).cmd("PTTL").arg(&key);
+		let mut con = self.read_connection().await?;
+		let (value, millis): (Option<Vec<u8>>, i64) = con.query_pipe(&pipe).await?;
+		Ok(value.zip(ttl_from_millis(millis)))
+	}
+
+	/// Reads the creation time kept under a companion key of the session,
+	/// which sessions stored by earlier versions of the store don't have.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		let keys = self.keys();
+		let key = keys.created(id);
+		let mut cmd = redis::cmd("GET");
 		cmd.arg(key);
-		let serialized = to_string(&value).expect("Failed to serialize");
-		cmd.arg(serialized);
-		cmd.arg("EX");
-		cmd.arg(duration.as_secs());
-		let mut con = self.client.get_connection().map_err(|_| SessionError)?;
-		con.req_command(&cmd).map_err(|_| SessionError)?;
+		let mut con = self.read_connection().await?;
+		let millis: Option<u64> = con.query(&cmd).await?;
+		Ok(millis.map(|millis| UNIX_EPOCH + Duration::from_millis(millis)))
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let keys = self.keys();
+		let key = keys.user(user_key);
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_err(|_| SessionError::Other)?;
+		let mut cmd = redis::cmd("ZADD");
+		cmd.arg(&key);
+		cmd.arg(timestamp.as_micros() as u64);
+		cmd.arg(id);
+		let mut con = self.connection().await?;
+		con.query::<()>(&cmd).await?;
+		// Sessions of a user that keeps logging in would otherwise pile up.
+		Self::prune_user_index(&mut con, &keys, &key, Some(id)).await?;
 
 		Ok(())
 	}
 
-	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<()> {
-		let key = self.to_key(id);
-		let mut cmd = redis::cmd("EXPIRE");
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		let keys = self.keys();
+		let key = keys.user(user_key);
+		let mut con = self.connection().await?;
+		Self::prune_user_index(&mut con, &keys, &key, None).await
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let keys = self.keys();
+		let key = keys.user(user_key);
+		let mut cmd = redis::cmd("ZREM");
 		cmd.arg(key);
-		cmd.arg(duration.as_secs());
-		let mut con = self.client.get_connection().map_err(|_| SessionError)?;
-		con.req_command(&cmd).map_err(|_| SessionError)?;
+		cmd.arg(id);
+		let mut con = self.connection().await?;
+		con.query::<()>(&cmd).await?;
 
 		Ok(())
 	}
 
-	async fn remove(&self, id: &str) -> SessionResult<()> {
-		let key = self.to_key(id);
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Vec<u8>>>> {
+		let keys = self.keys();
+		if ids.is_empty() {
+			return Ok(Vec::new());
+		}
+		let mut cmd = redis::cmd("MGET");
+		for id in ids {
+			cmd.arg(keys.session(id));
+		}
+		let mut con = self.read_connection().await?;
+		con.query(&cmd).await
+	}
+
+	/// Uses `SET ... GET`, which needs redis 6.2 or later.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn replace(
+		&self,
+		id: &str,
+		value: Vec<u8>,
+		duration: Duration,
+	) -> SessionResult<Option<Vec<u8>>> {
+		let keys = self.keys();
+		let key = keys.session(id);
+		let mut pipe = redis::pipe();
+		let cmd = pipe.cmd("SET").arg(key).arg(value);
+		if duration != NO_EXPIRY {
+			cmd.arg("EX").arg(duration.as_secs());
+		}
+		cmd.arg("GET");
+		Self::record_creation(&keys, &mut pipe, id, duration)?;
+		let mut con = self.connection().await?;
+		let (previous,): (Option<Vec<u8>>,) = con.query_pipe(&pipe).await?;
+		Ok(previous)
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		let keys = self.keys();
+		if ids.is_empty() {
+			return Ok(());
+		}
 		let mut cmd = redis::cmd("DEL");
-		cmd.arg(key);
-		let mut con = self.client.get_connection().map_err(|_| SessionError)?;
-		con.req_command(&cmd).map_err(|_| SessionError)?;
+		for id in ids {
+			cmd.arg(keys.session(id)).arg(keys.created(id));
+		}
+		let mut con = self.connection().await?;
+		con.query::<()>(&cmd).await?;
 
 		Ok(())
 	}
+
+	/// Runs `RENAME` and `EXPIRE` in a script, so that the session is
+	/// always under one of the ids.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(old_id))
+		)
+	)]
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		let keys = self.keys();
+		let expiry = match duration {
+			NO_EXPIRY => "persist".to_owned(),
+			duration => duration.as_secs().to_string(),
+		};
+		let mut script = RENAME.get().prepare_invoke();
+		script
+			.key(keys.session(old_id))
+			.key(keys.session(new_id))
+			.key(keys.created(old_id))
+			.key(keys.created(new_id))
+			.arg(expiry)
+			.arg(now_millis()?);
+		let mut con = self.connection().await?;
+		let renamed: bool = con.invoke(&script).await?;
+
+		Ok(renamed)
+	}
+
+	/// Sets the value and removes the old session in a `MULTI` transaction.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(old_id))
+		)
+	)]
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: Vec<u8>,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let keys = self.keys();
+		let mut pipe = redis::pipe();
+		pipe.atomic();
+		let cmd = pipe.cmd("SET").arg(keys.session(new_id)).arg(value);
+		if duration != NO_EXPIRY {
+			cmd.arg("EX").arg(duration.as_secs());
+		}
+		cmd.ignore();
+		pipe.cmd("DEL")
+			.arg(keys.session(old_id))
+			.arg(keys.created(old_id))
+			.ignore();
+		Self::record_creation(&keys, &mut pipe, new_id, duration)?;
+		let mut con = self.connection().await?;
+		con.query_pipe::<()>(&pipe).await?;
+
+		Ok(())
+	}
+
+	/// Compares and sets the value in a script, audited as a [Store::set]
+	/// when the value is set.
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<Vec<u8>>,
+		value: Vec<u8>,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		let keys = self.keys();
+		let expiry = match duration {
+			NO_EXPIRY => "persist".to_owned(),
+			duration => duration.as_secs().to_string(),
+		};
+		let mut script = COMPARE_AND_SWAP.get().prepare_invoke();
+		script.key(keys.session(id)).key(keys.created(id));
+		match expected {
+			Some(expected) => script.arg("expected").arg(expected),
+			None => script.arg("missing").arg(""),
+		};
+		script.arg(value).arg(expiry).arg(now_millis()?);
+		let mut con = self.connection().await?;
+		let swapped: bool = con.invoke(&script).await?;
+		if swapped {
+			self.audit(&mut con, AuditOp::Set, id).await?;
+		}
+		Ok(swapped)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		let keys = self.keys();
+		let mut script = ACQUIRE_LOCK.get().prepare_invoke();
+		script
+			.key(keys.lock(id))
+			.arg(holder)
+			.arg(ttl.as_millis().max(1) as u64);
+		let mut con = self.connection().await?;
+		let acquired: i64 = con.invoke(&script).await?;
+
+		Ok(acquired == 1)
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(
+			level = "debug",
+			skip_all,
+			fields(store = "redis", session.token = %crate::trace::digest(id))
+		)
+	)]
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		let keys = self.keys();
+		let mut script = RELEASE_LOCK.get().prepare_invoke();
+		script.key(keys.lock(id)).arg(holder);
+		let mut con = self.connection().await?;
+		let released: i64 = con.invoke(&script).await?;
+
+		Ok(released == 1)
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		let mut ids = self.scan_ids().await?;
+		ids.sort_unstable();
+		Ok(ids.into_iter().skip(offset).take(limit).collect())
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		Ok(self.scan_ids().await?.len())
+	}
+
+	fn backend(&self) -> &'static str {
+		"redis"
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		let cmd = redis::cmd("PING");
+		let mut con = self.connection().await?;
+		con.query::<()>(&cmd).await?;
+
+		Ok(())
+	}
+}
+
+#[rocket::async_trait]
+impl<T> Store for RedisStore<T>
+where
+	T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.inner.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		self.inner.get_with_expiry(id).await
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.inner.created_at(id).await
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.inner.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner.dissociate(user_key, id).await
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<T>>> {
+		self.inner.get_many(ids).await
+	}
+
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		self.inner.replace(id, value, duration).await
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		self.inner.remove_many(ids).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.inner.rename(old_id, new_id, duration).await
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<()> {
+		self.inner
+			.rename_with(old_id, new_id, value, duration)
+			.await
+	}
+
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		self.inner
+			.compare_and_swap(id, expected, value, duration)
+			.await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.inner.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.inner.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.inner.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.inner.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		self.inner.drain().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.inner.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.inner.expired_cleanup()
+	}
+}
+
+/// The keys of a store, with the prefix it had when they were taken, see
+/// [RedisRawStore::keys].
+pub struct Keys<'a> {
+	prefix: Option<Arc<str>>,
+	postfix: &'a str,
+}
+
+impl Keys<'_> {
+	/// Get the key of a session, written straight into the redis command.
+	pub fn session<'a>(&'a self, id: &'a str) -> Key<'a> {
+		self.key("", id)
+	}
+
+	fn user<'a>(&'a self, user_key: &'a str) -> Key<'a> {
+		self.key(USER_NAMESPACE, user_key)
+	}
+
+	fn lock<'a>(&'a self, id: &'a str) -> Key<'a> {
+		self.key(LOCK_NAMESPACE, id)
+	}
+
+	fn created<'a>(&'a self, id: &'a str) -> Key<'a> {
+		self.key(CREATED_NAMESPACE, id)
+	}
+
+	fn key<'a>(&'a self, namespace: &'static str, id: &'a str) -> Key<'a> {
+		Key {
+			parts: [
+				self.prefix.as_deref().unwrap_or_default(),
+				namespace,
+				id,
+				self.postfix,
+			],
+		}
+	}
+}
+
+/// The key of a session or of the sessions of a user, made of the prefix,
+/// the id and the postfix of the store.
+///
+/// It is written as a single redis argument through a buffer on the stack
+/// rather than an intermediate [String], as it is on the path of every
+/// request.
+pub struct Key<'a> {
+	parts: [&'a str; 4],
+}
+
+/// The longest key written through the stack buffer, which holds typical
+/// session ids along with a prefix and a postfix.
+const KEY_BUFFER: usize = 128;
+
+impl fmt::Display for Key<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.parts.iter().try_for_each(|part| f.write_str(part))
+	}
+}
+
+impl ToRedisArgs for Key<'_> {
+	fn write_redis_args<W>(&self, out: &mut W)
+	where
+		W: ?Sized + RedisWrite,
+	{
+		let len = self.parts.iter().map(|part| part.len()).sum();
+		if len > KEY_BUFFER {
+			return out.write_arg(self.parts.concat().as_bytes());
+		}
+		let mut buf = [0; KEY_BUFFER];
+		let mut end = 0;
+		for part in self.parts {
+			buf[end..end + part.len()].copy_from_slice(part.as_bytes());
+			end += part.len();
+		}
+		out.write_arg(&buf[..len]);
+	}
+}
+
+/// Get the current time in milliseconds since the unix epoch.
+fn now_millis() -> SessionResult<u64> {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(|_| SessionError::Other)?;
+	Ok(now.as_millis() as u64)
+}
+
+/// Connects to redis, giving up with [SessionError::Timeout] after the
+/// timeout.
+async fn connect_client(client: &Client, timeout: Duration) -> SessionResult<RedisConnection> {
+	match time::timeout(timeout, client.get_async_connection()).await {
+		Ok(Ok(con)) => Ok(RedisConnection::Direct(con, timeout)),
+		Ok(Err(e)) => {
+			log::warn!("could not connect to redis: {}", e);
+			Err(SessionError::StoreUnavailable)
+		}
+		Err(_) => {
+			log::warn!("timed out connecting to redis after {:?}", timeout);
+			Err(SessionError::Timeout)
+		}
+	}
+}
+
+/// Waits for the reply to a command, giving up with [SessionError::Timeout]
+/// after the timeout.
+async fn within<T>(
+	timeout: Duration,
+	reply: impl Future<Output = RedisResult<T>>,
+) -> SessionResult<T> {
+	match time::timeout(timeout, reply).await {
+		Ok(reply) => reply.map_err(command_error),
+		Err(_) => {
+			log::warn!("timed out waiting for redis to reply after {:?}", timeout);
+			Err(SessionError::Timeout)
+		}
+	}
+}
+
+/// Tells the failures to reach redis apart from the other errors of a
+/// command, such as a reply of an unexpected type.
+fn command_error(e: RedisError) -> SessionError {
+	if e.is_timeout() {
+		SessionError::Timeout
+	} else if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() {
+		SessionError::StoreUnavailable
+	} else {
+		SessionError::Other
+	}
+}
+
+/// Escapes the characters with a meaning in redis glob patterns.
+fn escape_pattern(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		if matches!(c, '*' | '?' | '[' | ']' | '\\' | '^' | '-') {
+			escaped.push('\\');
+		}
+		escaped.push(c);
+	}
+	escaped
+}
+
+/// Converts a `PTTL` reply, which is -1 for keys without an expiry and -2
+/// for missing keys.
+fn ttl_from_millis(millis: i64) -> Option<Duration> {
+	match millis {
+		-1 => Some(NO_EXPIRY),
+		millis => u64::try_from(millis).ok().map(Duration::from_millis),
+	}
 }
@@ -0,0 +1,335 @@
+//! Mirroring sessions to two stores.
+//!
+//! A [ReplicatedStore] writes every session to a primary and a secondary
+//! store at once, and reads from the primary, falling back to the secondary.
+//! This keeps a second backend up to date while migrating to it, so reads
+//! can be moved over without losing sessions, or keeps a warm standby in
+//! another region. Unlike [export_to](crate::migration::export_to), which
+//! copies the sessions once, the stores are kept in sync as sessions change.
+//! To keep sessions alive through the loss of either of two backends, see
+//! [MultiplexStore](crate::multiplex::MultiplexStore) instead.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket_session_store::{
+//! #     memory::MemoryStore,
+//! #     replicated::{ReplicatedStore, ReplicationPolicy},
+//! #     SessionStore,
+//! # };
+//! let old: MemoryStore<String> = MemoryStore::new();
+//! let new: MemoryStore<String> = MemoryStore::new();
+//! let replicated =
+//!     ReplicatedStore::new(old, new).with_policy(ReplicationPolicy::RequirePrimary);
+//! let store: SessionStore<String> =
+//!     SessionStore::new(replicated, "token", Duration::from_secs(3600));
+//! ```
+
+use std::time::{
+	Duration,
+	SystemTime,
+};
+
+use rocket::tokio::join;
+
+use crate::{
+	ExpiredCleanup,
+	SessionResult,
+	Store,
+};
+
+/// How a [ReplicatedStore] handles a write failing on one of its stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplicationPolicy {
+	/// Writes fail if they fail on either store.
+	#[default]
+	RequireBoth,
+	/// Writes succeed if they succeed on the primary store, and failures of
+	/// the secondary store are logged.
+	RequirePrimary,
+}
+
+/// A store writing sessions to two stores, see the [module](self)
+/// documentation.
+///
+/// Writes are sent to both stores concurrently. Reads go to the primary
+/// store, and to the secondary store when the primary has no such session
+/// or fails. Writes failing on the primary store always fail, as do writes
+/// failing on the secondary store under [ReplicationPolicy::RequireBoth],
+/// the default. A failed write may still have been applied to the other
+/// store.
+pub struct ReplicatedStore<A, B> {
+	primary: A,
+	secondary: B,
+	policy: ReplicationPolicy,
+}
+
+impl<A, B> ReplicatedStore<A, B> {
+	/// Creates a store writing to both stores.
+	pub fn new(primary: A, secondary: B) -> Self {
+		Self {
+			primary,
+			secondary,
+			policy: ReplicationPolicy::default(),
+		}
+	}
+
+	/// Sets how writes failing on one of the stores are handled.
+	pub fn with_policy(mut self, policy: ReplicationPolicy) -> Self {
+		self.policy = policy;
+		self
+	}
+
+	/// Get the primary store.
+	pub fn primary(&self) -> &A {
+		&self.primary
+	}
+
+	/// Get the secondary store.
+	pub fn secondary(&self) -> &B {
+		&self.secondary
+	}
+
+	/// Combines the results of a write on both stores according to the
+	/// policy, returning the result of the secondary store if it succeeded.
+	fn replicated<R>(
+		&self,
+		op: &str,
+		primary: SessionResult<R>,
+		secondary: SessionResult<R>,
+	) -> SessionResult<(R, Option<R>)> {
+		match (primary, secondary) {
+			(Ok(primary), Ok(secondary)) => Ok((primary, Some(secondary))),
+			(Ok(primary), Err(e)) => {
+				log::warn!("replicated {} failed on the secondary store: {}", op, e);
+				match self.policy {
+					ReplicationPolicy::RequireBoth => Err(e),
+					ReplicationPolicy::RequirePrimary => Ok((primary, None)),
+				}
+			}
+			(Err(e), _) => Err(e),
+		}
+	}
+}
+
+/// Reads from the primary store, falling back to the secondary one when the
+/// primary has nothing or fails.
+macro_rules! read_through {
+	($self:ident, $op:literal, $call:ident($($arg:expr),*)) => {
+		match $self.primary.$call($($arg),*).await {
+			Ok(Some(found)) => Ok(Some(found)),
+			Ok(None) => $self.secondary.$call($($arg),*).await,
+			Err(e) => {
+				log::warn!("replicated {} failed on the primary store: {}", $op, e);
+				$self.secondary.$call($($arg),*).await
+			}
+		}
+	};
+}
+
+#[rocket::async_trait]
+impl<A, B, T> Store for ReplicatedStore<A, B>
+where
+	A: Store<Value = T> + 'static,
+	B: Store<Value = T> + 'static,
+	T: Clone + Send + Sync + 'static,
+{
+	type Value = T;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<T>> {
+		read_through!(self, "get", get(id))
+	}
+
+	async fn set(&self, id: &str, value: T, duration: Duration) -> SessionResult<()> {
+		let (primary, secondary) = join!(
+			self.primary.set(id, value.clone(), duration),
+			self.secondary.set(id, value, duration),
+		);
+		self.replicated("set", primary, secondary)?;
+		Ok(())
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let (primary, secondary) = join!(
+			self.primary.touch(id, duration),
+			self.secondary.touch(id, duration),
+		);
+		let (primary, secondary) = self.replicated("touch", primary, secondary)?;
+		Ok(primary.or(secondary.flatten()))
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let (primary, secondary) = join!(self.primary.remove(id), self.secondary.remove(id));
+		let (primary, secondary) = self.replicated("remove", primary, secondary)?;
+		Ok(primary || secondary.unwrap_or_default())
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		read_through!(self, "ttl", ttl(id))
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(T, Duration)>> {
+		read_through!(self, "get_with_expiry", get_with_expiry(id))
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		read_through!(self, "created_at", created_at(id))
+	}
+
+	async fn value_hash(&self, id: &str) -> SessionResult<Option<u64>> {
+		read_through!(self, "value_hash", value_hash(id))
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let (primary, secondary) = join!(
+			self.primary.associate_with(user_key, id),
+			self.secondary.associate_with(user_key, id),
+		);
+		self.replicated("associate_with", primary, secondary)?;
+		Ok(())
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.primary.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let (primary, secondary) = join!(
+			self.primary.dissociate(user_key, id),
+			self.secondary.dissociate(user_key, id),
+		);
+		self.replicated("dissociate", primary, secondary)?;
+		Ok(())
+	}
+
+	async fn replace(&self, id: &str, value: T, duration: Duration) -> SessionResult<Option<T>> {
+		let (primary, secondary) = join!(
+			self.primary.replace(id, value.clone(), duration),
+			self.secondary.replace(id, value, duration),
+		);
+		let (primary, secondary) = self.replicated("replace", primary, secondary)?;
+		Ok(primary.or(secondary.flatten()))
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		let (primary, secondary) = join!(
+			self.primary.remove_many(ids),
+			self.secondary.remove_many(ids),
+		);
+		self.replicated("remove_many", primary, secondary)?;
+		Ok(())
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		let (primary, secondary) = join!(
+			self.primary.rename(old_id, new_id, duration),
+			self.secondary.rename(old_id, new_id, duration),
+		);
+		let (primary, secondary) = self.replicated("rename", primary, secondary)?;
+		Ok(primary || secondary.unwrap_or_default())
+	}
+
+	async fn rename_with(
+		&self,
+		old_id: &str,
+		new_id: &str,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<()> {
+		let (primary, secondary) = join!(
+			self.primary
+				.rename_with(old_id, new_id, value.clone(), duration),
+			self.secondary.rename_with(old_id, new_id, value, duration),
+		);
+		self.replicated("rename_with", primary, secondary)?;
+		Ok(())
+	}
+
+	/// The value is compared on the primary store, and copied to the
+	/// secondary one once it is set.
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<T>,
+		value: T,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		let swapped = self
+			.primary
+			.compare_and_swap(id, expected, value.clone(), duration)
+			.await?;
+		if swapped {
+			let secondary = self.secondary.set(id, value, duration).await;
+			self.replicated("compare_and_swap", Ok(()), secondary)?;
+		}
+		Ok(swapped)
+	}
+
+	/// Locks are only taken on the primary store, as a lock taken on one
+	/// store but not the other would be held by two requests at once.
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.primary.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.primary.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.primary.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.primary.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		let (primary, secondary) = join!(self.primary.drain(), self.secondary.drain());
+		self.replicated("drain", primary, secondary)?;
+		Ok(())
+	}
+
+	fn backend(&self) -> &'static str {
+		"replicated"
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		let (primary, secondary) = join!(self.primary.ping(), self.secondary.ping());
+		self.replicated("ping", primary, secondary)?;
+		Ok(())
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		let primary = self.primary.expired_cleanup().is_some();
+		let secondary = self.secondary.expired_cleanup().is_some();
+		(primary || secondary).then_some(self)
+	}
+}
+
+#[rocket::async_trait]
+impl<A, B, T> ExpiredCleanup for ReplicatedStore<A, B>
+where
+	A: Store<Value = T> + 'static,
+	B: Store<Value = T> + 'static,
+	T: Clone + Send + Sync + 'static,
+{
+	/// Clears both stores, returning the larger number of sessions removed
+	/// from either, so that batches go on until both are cleared.
+	async fn clear_expired(&self, limit: usize) -> SessionResult<usize> {
+		let (primary, secondary) = join!(
+			clear(self.primary.expired_cleanup(), limit),
+			clear(self.secondary.expired_cleanup(), limit),
+		);
+		let (primary, secondary) = self.replicated("clear_expired", primary, secondary)?;
+		Ok(primary.max(secondary.unwrap_or_default()))
+	}
+}
+
+/// Clears the expired sessions of a store, if it has to.
+async fn clear(store: Option<&dyn ExpiredCleanup>, limit: usize) -> SessionResult<usize> {
+	match store {
+		Some(store) => store.clear_expired(limit).await,
+		None => Ok(0),
+	}
+}
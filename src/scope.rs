@@ -0,0 +1,127 @@
+//! Named slices of a session.
+//!
+//! Modules of an application sharing a session would otherwise agree on a
+//! single value type holding the state of each of them. A session holding a
+//! [HashMap] from names to values can instead be split in scopes with
+//! [Session::scope], each reading and writing its own entry of the map
+//! through a [ScopedSession], without touching the others.
+//!
+//! The scopes live in the session, so removing the session removes every
+//! scope, while removing a scope leaves the others as they were.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use rocket::post;
+//! # use rocket_session_store::{Session, SessionResult};
+//! # use serde_json::{json, Value};
+//! #[post("/cart/<item>")]
+//! async fn add_to_cart(
+//!     item: String,
+//!     session: Session<'_, HashMap<String, Value>>,
+//! ) -> SessionResult<()> {
+//!     let cart = session.scope("cart");
+//!     let mut items = cart.get().await?.unwrap_or_else(|| json!([]));
+//!     items.as_array_mut().unwrap().push(item.into());
+//!     cart.set(items).await
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{
+	Session,
+	SessionResult,
+};
+
+/// A scope of a session, see the [module](self) documentation.
+pub struct ScopedSession<'a, V: 'static> {
+	session: &'a Session<'a, HashMap<String, V>>,
+	name: String,
+}
+
+/// Whether a name can be given to a scope.
+///
+/// Names are kept to ASCII letters, digits, `-` and `_`, so they can't
+/// collide with a separator of stores flattening them, and read well in
+/// logs.
+fn is_valid_scope(name: &str) -> bool {
+	!name.is_empty()
+		&& name
+			.bytes()
+			.all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+}
+
+impl<'s, V> Session<'s, HashMap<String, V>>
+where
+	V: Send + Sync + Clone,
+{
+	/// Get the scope of the session with the given name.
+	///
+	/// # Panics
+	///
+	/// Panics if the name is empty or holds characters other than ASCII
+	/// letters, digits, `-` and `_`.
+	pub fn scope(&self, name: &str) -> ScopedSession<'_, V> {
+		assert!(
+			is_valid_scope(name),
+			"invalid session scope name `{}`, only ASCII letters, digits, `-` and `_` are allowed",
+			name
+		);
+		ScopedSession {
+			session: self,
+			name: name.to_owned(),
+		}
+	}
+}
+
+impl<V> ScopedSession<'_, V>
+where
+	V: Send + Sync + Clone,
+{
+	/// Get the name of the scope.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Get the value of the scope.
+	///
+	/// Returns [None] if there is no session or the scope has no value.
+	pub async fn get(&self) -> SessionResult<Option<V>> {
+		let map = self.session.get_opt().await?;
+		Ok(map.and_then(|mut map| map.remove(&self.name)))
+	}
+
+	/// Whether the scope has a value.
+	pub async fn exists(&self) -> SessionResult<bool> {
+		let map = self.session.get_opt().await?;
+		Ok(map.is_some_and(|map| map.contains_key(&self.name)))
+	}
+
+	/// Sets the value of the scope, keeping the other scopes and creating
+	/// the session if needed.
+	///
+	/// The session is read and written back, so concurrent requests setting
+	/// different scopes of the same session may overwrite each other.
+	pub async fn set(&self, value: V) -> SessionResult<()> {
+		let mut map = self.session.get_opt().await?.unwrap_or_default();
+		map.insert(self.name.clone(), value);
+		self.session.set(map).await
+	}
+
+	/// Removes the value of the scope, keeping the other scopes.
+	///
+	/// Returns whether the scope had a value. The session is kept, even when
+	/// no scope is left.
+	pub async fn remove(&self) -> SessionResult<bool> {
+		let Some(mut map) = self.session.get_opt().await? else {
+			return Ok(false);
+		};
+		if map.remove(&self.name).is_none() {
+			return Ok(false);
+		}
+		self.session.set(map).await?;
+		Ok(true)
+	}
+}
@@ -1,230 +1,4674 @@
 use std::{
+	sync::{
+		atomic::{
+			AtomicBool,
+			AtomicUsize,
+			Ordering,
+		},
+		Arc,
+		Mutex,
+		OnceLock,
+	},
 	thread::sleep,
-	time::Duration,
+	time::{
+		Duration,
+		SystemTime,
+		UNIX_EPOCH,
+	},
 };
 
 #[cfg(feature = "redis")]
 use ::redis::Client as RedisClient;
 use rocket::{
+	catch,
+	catchers,
 	get,
 	http::{
-		SameSite,
+		Cookie,
+		Header,
 		Status,
 	},
 	local::blocking::Client,
 	post,
+	response::Redirect,
 	routes,
+	serde::{
+		Deserialize,
+		Serialize,
+	},
 	Build,
+	Request,
 	Rocket,
+	State,
+};
+use serde_json::{
+	json,
+	Value,
 };
 
 #[cfg(feature = "redis")]
-use crate::redis::RedisStore;
+use crate::redis::{
+	RedisRawStore,
+	RedisStore,
+};
 use crate::{
-	memory::MemoryStore,
+	any::{
+		register_session_type,
+		AnySession,
+		AnySessionStore,
+		TypeMapCodec,
+	},
+	bearer::{
+		BearerSession,
+		TOKEN_HEADER,
+	},
+	bound::{
+		BoundSession,
+		BoundSessionStore,
+	},
+	codec::{
+		CodecStore,
+		HashedCodec,
+		JsonCodec,
+		RawStore,
+		SessionCodec,
+		VersionedCodec,
+	},
+	events::{
+		SessionEvents,
+		TokenStatus,
+	},
+	extract::{
+		FromSession,
+		SessionExtract,
+	},
+	memory::{
+		MemoryRawStore,
+		MemoryStore,
+		SerializingMemoryStore,
+	},
+	multiplex::MultiplexStore,
+	replicated::{
+		ReplicatedStore,
+		ReplicationPolicy,
+	},
+	testing::{
+		conformance,
+		seed_session,
+		import_and_replay,
+		ChaosConfig,
+		ChaosStore,
+		RecordedOp,
+		RecordingStore,
+		IsolatedMemoryStore,
+	},
+	validator::SessionValidator,
 	CookieConfig,
+	ExpiredCleanup,
 	Session,
+	SessionError,
+	SessionID,
 	SessionResult,
 	SessionStore,
 	Store,
+	TokenScheme,
+	ID_CHARSET_HEX,
+	NO_EXPIRY,
 };
 
-#[post("/set_name/<name>")]
-async fn set_name(name: String, session: Session<'_, String>) -> SessionResult<()> {
-	session.set(name).await
+#[post("/promote/<user>")]
+async fn promote(user: &str, session: Session<'_, String>) -> SessionResult<()> {
+	session
+		.regenerate_token_with_migration(|name| {
+			name.filter(|name| name != "anonymous")
+				.map(|name| format!("{}@{}", name, user))
+		})
+		.await
+}
+
+#[post("/regenerate_shared")]
+async fn regenerate_shared(
+	first: Session<'_, String>,
+	second: Session<'_, String>,
+) -> SessionResult<Option<String>> {
+	first.regenerate_token().await?;
+	second.get_opt().await
+}
+
+#[post("/rename/<name>")]
+async fn rename(
+	name: String,
+	session: Session<'_, String>,
+	session_store: &State<SessionStore<String>>,
+) -> SessionResult<Option<String>> {
+	let store = session_store.store();
+	store
+		.set(session.id().key(), name, Duration::from_secs(3600))
+		.await?;
+	session.get_opt().await
+}
+
+#[get("/profile")]
+async fn profile(session: Session<'_, String>) -> Result<String, Redirect> {
+	session.get_or_redirect("/login").await
+}
+
+#[get("/account")]
+async fn account(session: Session<'_, String>) -> String {
+	match session.get().await {
+		Ok(name) => name,
+		Err(SessionError::NotFound) => "not found".into(),
+		Err(SessionError::StoreUnavailable) => "unavailable".into(),
+		Err(SessionError::Other) => "other".into(),
+		Err(e) => e.to_string(),
+	}
 }
 
-#[get("/get_name")]
-async fn get_name(session: Session<'_, String>) -> SessionResult<Option<String>> {
-	let name = session.get().await;
-	name
+#[post("/swap_name/<name>")]
+async fn swap_name(name: String, session: Session<'_, String>) -> SessionResult<String> {
+	let previous = session.swap(name).await?;
+	Ok(previous.unwrap_or_else(|| "empty".into()))
 }
 
-#[post("/remove_name")]
-async fn remove_name(session: Session<'_, String>) -> SessionResult<()> {
-	session.remove().await
+#[get("/required_name")]
+async fn required_name(session: Session<'_, String>) -> SessionResult<String> {
+	session.get().await
 }
 
-#[post("/refresh")]
-async fn refresh(session: Session<'_, String>) -> SessionResult<()> {
-	session.touch().await
+/// Seeds a session in the store of the client.
+fn seed(client: &Client, name: &str) -> Cookie<'static> {
+	let store = client.rocket().state::<SessionStore<String>>().unwrap();
+	rocket::execute(seed_session(store, name.into()))
 }
 
-fn example_rocket<T: 'static>(store: SessionStore<T>) -> Rocket<Build> {
+fn example_rocket<T: Send + Sync + 'static>(store: SessionStore<T>) -> Rocket<Build> {
 	rocket::build()
 		.attach(store.fairing())
-		.mount("/", routes![set_name, get_name, remove_name, refresh])
+		.mount("/", conformance::routes())
+		.mount(
+			"/",
+			routes![
+				profile,
+				account,
+				required_name,
+				swap_name,
+				regenerate_shared,
+				rename,
+				promote
+			],
+		)
+}
+
+crate::test_store!(in_memory, MemoryStore::<String>::new());
+
+crate::test_store!(codec, CodecStore::new(JsonCodec, MemoryRawStore::new()));
+
+crate::test_store!(
+	hashed_codec,
+	CodecStore::new(HashedCodec::new(JsonCodec), MemoryRawStore::new())
+);
+
+#[cfg(feature = "rmp-serde")]
+crate::test_store!(
+	msgpack_codec,
+	CodecStore::new(crate::codec::MessagePackCodec, MemoryRawStore::new())
+);
+
+#[cfg(feature = "bincode")]
+crate::test_store!(
+	bincode_codec,
+	CodecStore::new(crate::codec::BincodeCodec, MemoryRawStore::new())
+);
+
+crate::test_store!(serializing_memory, SerializingMemoryStore::<String>::new());
+
+#[cfg(feature = "encryption")]
+crate::test_store!(
+	encrypted,
+	CodecStore::new(
+		JsonCodec,
+		crate::encrypted::EncryptedStore::new(
+			MemoryRawStore::new(),
+			vec![crate::encrypted::EncryptionKey::new(1, [1; 32])]
+		)
+	)
+);
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_key_rotation_test() {
+	use crate::encrypted::{
+		EncryptedStore,
+		EncryptionKey,
+	};
+
+	let raw = Arc::new(MemoryRawStore::new());
+	let key_a = EncryptionKey::new(1, [1; 32]);
+	let key_b = EncryptionKey::new(2, [2; 32]);
+	let old = EncryptedStore::new(raw.clone(), vec![key_a.clone()]);
+	let new = EncryptedStore::new(raw.clone(), vec![key_b.clone(), key_a])
+		.with_reencrypt_on_read(true);
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		old.set("session", b"Alice".to_vec(), hour).await.unwrap();
+		old.set("other", b"Bob".to_vec(), hour).await.unwrap();
+		let stored = raw.get("session").await.unwrap().unwrap();
+		assert_eq!(stored[0], 1);
+		assert!(!stored.windows(5).any(|bytes| bytes == b"Alice"));
+
+		assert_eq!(new.get("session").await.unwrap(), Some(b"Alice".to_vec()));
+		let stored = raw.get("session").await.unwrap().unwrap();
+		assert_eq!(stored[0], 2, "the value was re-encrypted under the new key");
+		assert!(raw.ttl("session").await.unwrap().unwrap() > hour / 2);
+
+		assert!(new.rotate("other").await.unwrap());
+		assert!(!new.rotate("other").await.unwrap());
+		assert_eq!(raw.get("other").await.unwrap().unwrap()[0], 2);
+
+		// Values no key decrypts are treated as missing.
+		let dropped = EncryptedStore::new(raw.clone(), vec![key_b]);
+		old.set("stale", b"Carol".to_vec(), hour).await.unwrap();
+		assert_eq!(dropped.get("stale").await.unwrap(), None);
+		assert_eq!(old.get("stale").await.unwrap(), Some(b"Carol".to_vec()));
+	});
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_transplant_test() {
+	use crate::encrypted::{
+		EncryptedStore,
+		EncryptionKey,
+	};
+
+	let raw = Arc::new(MemoryRawStore::new());
+	let store = EncryptedStore::new(raw.clone(), vec![EncryptionKey::new(1, [1; 32])]);
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		store.set("alice", b"Alice".to_vec(), hour).await.unwrap();
+		store.set("mallory", b"Mallory".to_vec(), hour).await.unwrap();
+
+		// A value copied under the id of another session doesn't decrypt.
+		let stolen = raw.get("alice").await.unwrap().unwrap();
+		raw.set("mallory", stolen, hour).await.unwrap();
+		assert_eq!(store.get("mallory").await.unwrap(), None);
+		assert_eq!(store.get("alice").await.unwrap(), Some(b"Alice".to_vec()));
+
+		// Renaming a session encrypts its value for the new id.
+		assert!(store.rename("alice", "bob", hour).await.unwrap());
+		assert_eq!(store.get("bob").await.unwrap(), Some(b"Alice".to_vec()));
+		assert_eq!(store.get("alice").await.unwrap(), None);
+	});
+}
+
+#[cfg(feature = "compression")]
+crate::test_store!(
+	compressed,
+	CodecStore::new(
+		crate::codec::CompressedCodec::new(JsonCodec, 16),
+		MemoryRawStore::new()
+	)
+);
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_codec_threshold_test() {
+	use crate::codec::CompressedCodec;
+
+	let raw = Arc::new(MemoryRawStore::new());
+	let store = CodecStore::new(CompressedCodec::new(JsonCodec, 512), raw.clone());
+	let hour = Duration::from_secs(3600);
+	let small = "Alice".to_owned();
+	let large = "Alice".repeat(200);
+
+	rocket::execute(async {
+		store.set("small", small.clone(), hour).await.unwrap();
+		let stored = raw.get("small").await.unwrap().unwrap();
+		assert_eq!(stored[0], 0);
+		assert_eq!(&stored[1..], b"\"Alice\"");
+		assert_eq!(store.get("small").await.unwrap(), Some(small.clone()));
+
+		store.set("large", large.clone(), hour).await.unwrap();
+		let stored = raw.get("large").await.unwrap().unwrap();
+		assert_eq!(stored[0], 1);
+		assert!(stored.len() < large.len() / 10);
+		assert_eq!(store.get("large").await.unwrap(), Some(large.clone()));
+
+		// Values are decoded whatever the threshold they were stored with.
+		let eager = CodecStore::new(CompressedCodec::new(JsonCodec, 0), raw.clone());
+		assert_eq!(eager.get("small").await.unwrap(), Some(small));
+		assert_eq!(eager.get("large").await.unwrap(), Some(large));
+
+		raw.set("garbage", vec![7, 1, 2], hour).await.unwrap();
+		assert!(store.get("garbage").await.is_err());
+	});
+}
+
+crate::test_store!(
+	replicated,
+	ReplicatedStore::new(MemoryStore::<String>::new(), MemoryStore::new())
+);
+
+crate::test_store!(
+	multiplex,
+	MultiplexStore::new(MemoryStore::<String>::new(), MemoryStore::new())
+);
+
+#[cfg(feature = "moka")]
+crate::test_store!(
+	moka,
+	crate::moka::MokaStore::<String>::new(1000),
+	conformance::Options {
+		user_sessions: false,
+		..Default::default()
+	}
+);
+
+/// Runs the operations of the couchbase store against the cluster named by
+/// `COUCHBASE_CONNECTION_STRING`, and passes without checking anything when
+/// it isn't set.
+#[cfg(feature = "couchbase")]
+#[test]
+fn couchbase_test() {
+	use ::couchbase::{
+		authenticator::PasswordAuthenticator,
+		cluster::Cluster,
+		options::cluster_options::ClusterOptions,
+	};
+
+	use crate::couchbase::CouchbaseStore;
+
+	let Ok(url) = std::env::var("COUCHBASE_CONNECTION_STRING") else {
+		return;
+	};
+	let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.into());
+	let authenticator = PasswordAuthenticator::new(
+		var("COUCHBASE_USERNAME", "Administrator"),
+		var("COUCHBASE_PASSWORD", "password"),
+	);
+	rocket::async_test(async {
+		let options = ClusterOptions::new(authenticator.into());
+		let cluster = Cluster::connect(url, options).await.unwrap();
+		let bucket = cluster.bucket(var("COUCHBASE_BUCKET", "sessions"));
+		let store: CouchbaseStore<String> = CouchbaseStore::new(bucket.default_collection());
+		let hour = Duration::from_secs(3600);
+		let id = format!("couchbase-test-{}", crate::new_id(8, crate::ID_CHARSET_HEX));
+
+		assert_eq!(store.get(&id).await.unwrap(), None);
+		store.set(&id, "TestingName".into(), hour).await.unwrap();
+		assert_eq!(store.get(&id).await.unwrap(), Some("TestingName".into()));
+		let (value, ttl) = store.get_with_expiry(&id).await.unwrap().unwrap();
+		assert_eq!(value, "TestingName");
+		assert!(ttl <= hour && ttl > hour - Duration::from_secs(60));
+
+		let ttl = store.touch(&id, 2 * hour).await.unwrap().unwrap();
+		assert!(ttl > hour);
+		store.set(&id, "Permanent".into(), NO_EXPIRY).await.unwrap();
+		assert_eq!(store.touch(&id, hour).await.unwrap(), Some(NO_EXPIRY));
+		assert_eq!(store.ttl(&id).await.unwrap(), Some(NO_EXPIRY));
+
+		assert!(store.remove(&id).await.unwrap());
+		assert!(!store.remove(&id).await.unwrap());
+		assert_eq!(store.touch(&id, hour).await.unwrap(), None);
+
+		store.set(&id, "Expiring".into(), Duration::from_secs(1)).await.unwrap();
+		sleep(Duration::from_secs(3));
+		assert_eq!(store.get(&id).await.unwrap(), None);
+	});
+}
+
+/// A memory store shared by every test using it.
+fn shared_memory_store() -> Arc<MemoryStore<String>> {
+	static STORE: OnceLock<Arc<MemoryStore<String>>> = OnceLock::new();
+	STORE.get_or_init(Default::default).clone()
+}
+
+crate::test_store!(
+	isolated_memory,
+	shared_memory_store(),
+	conformance::Options {
+		isolated: true,
+		..Default::default()
+	}
+);
+
+#[cfg(feature = "redis")]
+crate::test_store!(
+	redis,
+	{
+		let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+		RedisStore::new(client).prefix("user:".to_owned())
+	},
+	conformance::Options {
+		isolated: true,
+		..Default::default()
+	}
+);
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_idle_time_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client).prefix("user:".to_owned());
+	let idle_time = |id| rocket::execute(store.idle_time(id)).unwrap();
+
+	rocket::execute(store.set("idle", "TestingName".into(), Duration::from_secs(60))).unwrap();
+	sleep(Duration::from_millis(1_100));
+	let first = idle_time("idle").unwrap();
+	sleep(Duration::from_millis(1_100));
+	let second = idle_time("idle").unwrap();
+	assert!(second > first);
+
+	// Reading the session resets its idle time.
+	rocket::execute(store.get("idle")).unwrap();
+	assert!(idle_time("idle").unwrap() < second);
+	assert_eq!(idle_time("missing"), None);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_created_at_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client).prefix("creation:".to_owned());
+	let id = format!("session{}", rand::random::<u64>());
+	let renamed = format!("{}renamed", id);
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		assert_eq!(store.created_at(&id).await.unwrap(), None);
+		store.set(&id, "Alice".into(), hour).await.unwrap();
+		let created = store.created_at(&id).await.unwrap().unwrap();
+		assert!(created.elapsed().unwrap() < Duration::from_secs(5));
+
+		// Overwriting the session keeps its creation time.
+		sleep(Duration::from_millis(20));
+		store.set(&id, "Bob".into(), hour).await.unwrap();
+		store.replace(&id, "Carol".into(), hour).await.unwrap();
+		assert_eq!(store.created_at(&id).await.unwrap(), Some(created));
+		assert!(store.list_ids(0, usize::MAX).await.unwrap().contains(&id));
+		assert!(!store
+			.list_ids(0, usize::MAX)
+			.await
+			.unwrap()
+			.iter()
+			.any(|listed| listed.starts_with("created:")));
+
+		// Moving it to a new id starts a new one.
+		assert!(store.rename(&id, &renamed, hour).await.unwrap());
+		assert_eq!(store.created_at(&id).await.unwrap(), None);
+		let moved = store.created_at(&renamed).await.unwrap().unwrap();
+		assert!(moved > created);
+
+		store.remove(&renamed).await.unwrap();
+		assert_eq!(store.created_at(&renamed).await.unwrap(), None);
+	});
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_user_index_prune_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client.clone()).prefix("prune:".to_owned());
+	let user = format!("user{}", rand::random::<u64>());
+	let indexed = || -> usize {
+		let mut con = client.get_connection().unwrap();
+		::redis::cmd("ZCARD")
+			.arg(format!("prune:user-sessions:{}", user))
+			.query(&mut con)
+			.unwrap()
+	};
+
+	rocket::execute(async {
+		for id in ["first", "second", "third"] {
+			store.associate_with(&user, id).await.unwrap();
+		}
+		store.set("live", "TestingName".into(), Duration::from_secs(60)).await.unwrap();
+		store.associate_with(&user, "live").await.unwrap();
+	});
+	// The sessions gone from the store are dropped from the index as others
+	// are added, without waiting for the sessions to be listed.
+	assert_eq!(indexed(), 1);
+
+	// The session being associated is kept before it is stored.
+	rocket::execute(store.associate_with(&user, "pending")).unwrap();
+	assert_eq!(indexed(), 2);
+	rocket::execute(store.remove("live")).unwrap();
+}
+
+/// A store which can't be reached.
+struct FailingStore;
+
+#[rocket::async_trait]
+impl Store for FailingStore {
+	type Value = String;
+
+	async fn get(&self, _id: &str) -> SessionResult<Option<String>> {
+		Err(SessionError::StoreUnavailable)
+	}
+
+	async fn set(&self, _id: &str, _value: String, _duration: Duration) -> SessionResult<()> {
+		Err(SessionError::StoreUnavailable)
+	}
+
+	async fn touch(&self, _id: &str, _duration: Duration) -> SessionResult<Option<Duration>> {
+		Err(SessionError::StoreUnavailable)
+	}
+
+	async fn remove(&self, _id: &str) -> SessionResult<bool> {
+		Err(SessionError::StoreUnavailable)
+	}
+}
+
+#[test]
+fn replicated_store_test() {
+	let primary = Arc::new(MemoryStore::<String>::new());
+	let secondary = Arc::new(MemoryStore::<String>::new());
+	let store = ReplicatedStore::new(primary.clone(), secondary.clone());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		store.set("first", "Alice".into(), hour).await.unwrap();
+		store.set("second", "Bob".into(), hour).await.unwrap();
+		store.touch("first", hour * 2).await.unwrap();
+		assert!(store.remove("second").await.unwrap());
+		let replaced = store.replace("first", "Carol".into(), hour).await.unwrap();
+		assert_eq!(replaced.as_deref(), Some("Alice"));
+		assert!(store.rename("first", "third", hour).await.unwrap());
+		store.set("fourth", "Dave".into(), hour).await.unwrap();
+
+		let ids = primary.list_ids(0, usize::MAX).await.unwrap();
+		assert_eq!(ids, ["fourth", "third"]);
+		assert_eq!(secondary.list_ids(0, usize::MAX).await.unwrap(), ids);
+		for id in &ids {
+			assert_eq!(
+				primary.get(id).await.unwrap(),
+				secondary.get(id).await.unwrap()
+			);
+		}
+
+		// Reads fall back to the secondary store.
+		secondary.set("standby", "Erin".into(), hour).await.unwrap();
+		assert_eq!(store.get("standby").await.unwrap().as_deref(), Some("Erin"));
+	});
+}
+
+#[test]
+fn replicated_store_policy_test() {
+	let hour = Duration::from_secs(3600);
+	rocket::execute(async {
+		let store = ReplicatedStore::new(MemoryStore::<String>::new(), FailingStore);
+		assert!(store.set("session", "Alice".into(), hour).await.is_err());
+		let store = store.with_policy(ReplicationPolicy::RequirePrimary);
+		store.set("session", "Alice".into(), hour).await.unwrap();
+		assert_eq!(store.get("session").await.unwrap().as_deref(), Some("Alice"));
+		assert!(store.remove("session").await.unwrap());
+
+		// A failing primary fails writes whatever the policy, but reads fall
+		// back to the secondary store.
+		let store = ReplicatedStore::new(FailingStore, MemoryStore::<String>::new())
+			.with_policy(ReplicationPolicy::RequirePrimary);
+		assert!(store.set("session", "Alice".into(), hour).await.is_err());
+		assert_eq!(store.get("session").await.unwrap().as_deref(), Some("Alice"));
+	});
+}
+
+/// A memory store which can be taken offline, failing every operation.
+#[derive(Default)]
+struct OfflineStore {
+	inner: MemoryStore<String>,
+	offline: AtomicBool,
+}
+
+impl OfflineStore {
+	fn set_offline(&self, offline: bool) {
+		self.offline.store(offline, Ordering::SeqCst);
+	}
+
+	fn check(&self) -> SessionResult<()> {
+		match self.offline.load(Ordering::SeqCst) {
+			true => Err(SessionError::StoreUnavailable),
+			false => Ok(()),
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl Store for OfflineStore {
+	type Value = String;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<String>> {
+		self.check()?;
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: String, duration: Duration) -> SessionResult<()> {
+		self.check()?;
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.check()?;
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.check()?;
+		self.inner.remove(id).await
+	}
+}
+
+#[test]
+fn multiplex_store_test() {
+	let a = Arc::new(OfflineStore::default());
+	let b = Arc::new(OfflineStore::default());
+	let store = MultiplexStore::new(a.clone(), b.clone());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		// Written to both, a session outlives either store going offline.
+		store.set("session", "Alice".into(), hour).await.unwrap();
+		a.set_offline(true);
+		assert_eq!(store.get("session").await.unwrap().as_deref(), Some("Alice"));
+		a.set_offline(false);
+		b.set_offline(true);
+		assert_eq!(store.get("session").await.unwrap().as_deref(), Some("Alice"));
+
+		// Writes go through while one store is offline.
+		store.set("session", "Bob".into(), hour).await.unwrap();
+		b.set_offline(false);
+		a.set_offline(true);
+		store.set("other", "Carol".into(), hour).await.unwrap();
+		a.set_offline(false);
+		assert_eq!(a.inner.get("session").await.unwrap().as_deref(), Some("Bob"));
+		assert_eq!(b.inner.get("other").await.unwrap().as_deref(), Some("Carol"));
+
+		// Both stores are cleared on removal.
+		assert!(store.remove("session").await.unwrap());
+		assert_eq!(a.inner.get("session").await.unwrap(), None);
+		assert_eq!(b.inner.get("session").await.unwrap(), None);
+
+		a.set_offline(true);
+		b.set_offline(true);
+		assert!(store.set("session", "Dave".into(), hour).await.is_err());
+		assert!(store.get("other").await.is_err());
+
+		// Compare-and-swap runs on the second store while the first fails.
+		let store = MultiplexStore::new(FailingStore, SerializingMemoryStore::<String>::new());
+		store.set("session", "Alice".into(), hour).await.unwrap();
+		let swapped = store.compare_and_swap("session", Some("Alice".into()), "Bob".into(), hour);
+		assert!(swapped.await.unwrap());
+		assert_eq!(store.get("session").await.unwrap().as_deref(), Some("Bob"));
+	});
+}
+
+#[derive(Default)]
+struct RecordingEvents(Mutex<Vec<String>>);
+
+#[rocket::async_trait]
+impl SessionEvents<String> for RecordingEvents {
+	async fn created(&self, id: &str, value: &String) -> SessionResult<()> {
+		self.0
+			.lock()
+			.unwrap()
+			.push(format!("created {} {}", id, value));
+		Ok(())
+	}
+
+	async fn destroyed(&self, id: &str) -> SessionResult<()> {
+		self.0.lock().unwrap().push(format!("destroyed {}", id));
+		Ok(())
+	}
+
+	async fn regenerated(&self, old_id: &str, new_id: &str) -> SessionResult<()> {
+		self.0
+			.lock()
+			.unwrap()
+			.push(format!("regenerated {} {}", old_id, new_id));
+		Ok(())
+	}
 }
 
-fn generic_basic_test(store: impl Store<Value = String> + 'static) {
+#[test]
+fn events_test() {
+	let events = Arc::new(RecordingEvents::default());
 	let client: Client = {
-		let session_store: SessionStore<String> = SessionStore {
-			store: Box::new(store),
-			name: "token".into(),
-			duration: Duration::from_secs(3600),
-			cookie: CookieConfig::default(),
-		};
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_events(events.clone());
 		let rocket = example_rocket(session_store);
 		Client::tracked(rocket).expect("Expected to build client")
 	};
+	let token = |client: &Client| client.cookies().get("token").unwrap().value().to_owned();
 
-	assert_eq!(client.cookies().get("token"), None);
-
-	let res1 = client.get("/get_name").dispatch();
-	assert_eq!(res1.status(), Status::NotFound);
+	let res1 = client.post("/set_name/Alice").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let login_token = token(&client);
 
-	let res2 = client.post("/set_name/TestingName").dispatch();
+	// Overwriting an existing session is not a creation.
+	let res2 = client.post("/set_name/Bob").dispatch();
 	assert_eq!(res2.status(), Status::Ok);
-	assert!(client.cookies().get("token").is_some());
 
-	let res3 = client.get("/get_name").dispatch();
+	let res3 = client.post("/regenerate").dispatch();
 	assert_eq!(res3.status(), Status::Ok);
-	assert_eq!(res3.into_string(), Some("TestingName".into()))
+	let regenerated_token = token(&client);
+
+	let res4 = client.post("/remove_name").dispatch();
+	assert_eq!(res4.status(), Status::Ok);
+
+	assert_eq!(
+		*events.0.lock().unwrap(),
+		vec![
+			format!("created {} Alice", login_token),
+			format!("regenerated {} {}", login_token, regenerated_token),
+			format!("destroyed {}", regenerated_token),
+		]
+	);
 }
 
-fn generic_expiration_test(store: impl Store<Value = String> + 'static) {
+#[test]
+fn swap_test() {
+	let events = Arc::new(RecordingEvents::default());
+	let session_store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+			.with_events(events.clone());
+	let client = Client::tracked(example_rocket(session_store)).expect("Expected to build client");
+
+	// Swapping into an empty session creates it, as setting it would.
+	let res = client.post("/swap_name/Alice").dispatch();
+	assert_eq!(res.into_string(), Some("empty".into()));
+	let token = client.cookies().get("token").unwrap().value().to_owned();
+	assert_eq!(
+		*events.0.lock().unwrap(),
+		vec![format!("created {} Alice", token)]
+	);
+
+	let res = client.post("/swap_name/Bob").dispatch();
+	assert_eq!(res.into_string(), Some("Alice".into()));
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string(), Some("Bob".into()));
+	assert_eq!(events.0.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn regenerate_with_migration_test() {
 	let client: Client = {
-		let session_store: SessionStore<String> = SessionStore {
-			store: Box::new(store),
-			name: "token".into(),
-			duration: Duration::from_secs(1),
-			cookie: CookieConfig::default(),
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+	let promote = |name: &str| {
+		let old_cookie = seed(&client, name);
+		let res1 = client
+			.post("/promote/alice")
+			.cookie(old_cookie.clone())
+			.dispatch();
+		assert_eq!(res1.status(), Status::Ok);
+		let new_cookie = res1.cookies().get("token").unwrap().clone();
+		assert_ne!(old_cookie.value(), new_cookie.value());
+		let get_name = |cookie: Cookie<'static>| {
+			let res = client.get("/get_name").cookie(cookie).dispatch();
+			if res.status() != Status::Ok {
+				return None;
+			}
+			res.into_string()
 		};
+		assert_eq!(get_name(old_cookie), None);
+		get_name(new_cookie)
+	};
+
+	assert_eq!(promote("Cart"), Some("Cart@alice".into()));
+	assert_eq!(promote("anonymous"), None);
+}
+
+#[test]
+fn shared_store_test() {
+	let shared = Arc::new(MemoryStore::<String>::new());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(shared.clone(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+	let value = rocket::execute(shared.get(&token)).unwrap();
+	assert_eq!(value, Some("TestingName".into()));
+
+	rocket::execute(shared.set(&token, "OtherName".into(), Duration::from_secs(3600))).unwrap();
+	let res2 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token))
+		.dispatch();
+	assert_eq!(res2.into_string(), Some("OtherName".into()));
+}
+
+#[test]
+fn store_handle_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
 		let rocket = example_rocket(session_store);
 		Client::tracked(rocket).expect("Expected to build client")
 	};
 
 	let res1 = client.post("/set_name/TestingName").dispatch();
 	assert_eq!(res1.status(), Status::Ok);
-	let res2 = client.get("/get_name").dispatch();
+
+	let res2 = client.post("/rename/OtherName").dispatch();
 	assert_eq!(res2.status(), Status::Ok);
-	sleep(Duration::from_secs(2));
+	assert_eq!(res2.into_string(), Some("OtherName".into()));
+
 	let res3 = client.get("/get_name").dispatch();
-	assert_eq!(res3.status(), Status::NotFound);
+	assert_eq!(res3.into_string(), Some("OtherName".into()));
+}
+
+struct RejectBanned;
+
+#[rocket::async_trait]
+impl SessionValidator<String> for RejectBanned {
+	async fn validate(&self, value: &String) -> bool {
+		value != "Banned"
+	}
 }
 
-fn generic_remove_test(store: impl Store<Value = String> + 'static) {
+#[test]
+fn validator_test() {
+	let memory_store = Arc::new(MemoryStore::<String>::new());
 	let client: Client = {
-		let session_store: SessionStore<String> = SessionStore {
-			store: Box::new(store),
-			name: "token".into(),
-			duration: Duration::from_secs(3600),
-			cookie: CookieConfig::default(),
-		};
+		let session_store: SessionStore<String> =
+			SessionStore::new(memory_store.clone(), "token", Duration::from_secs(3600))
+				.with_validator(Arc::new(RejectBanned));
 		let rocket = example_rocket(session_store);
 		Client::tracked(rocket).expect("Expected to build client")
 	};
 
-	let res1 = client.post("/set_name/TestingName").dispatch();
+	client.post("/set_name/TestingName").dispatch();
+	let res1 = client.get("/get_name").dispatch();
+	assert_eq!(res1.into_string(), Some("TestingName".into()));
+
+	client.post("/set_name/Banned").dispatch();
+	let res2 = client.get("/get_name").dispatch();
+	assert_eq!(res2.status(), Status::NotFound);
+	assert_eq!(
+		rocket::async_test(memory_store.session_count()),
+		0,
+		"rejected sessions are removed"
+	);
+}
+
+#[test]
+fn downcast_store_test() {
+	let session_store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+	let memory_store = session_store
+		.downcast_store::<MemoryStore<String>>()
+		.unwrap();
+	rocket::execute(memory_store.set("id", "TestingName".into(), Duration::from_secs(3600)))
+		.unwrap();
+	assert_eq!(rocket::execute(memory_store.session_count()), 1);
+
+	// Wrappers don't downcast to the store they wrap.
+	let session_store: SessionStore<String> = SessionStore::new(
+		Arc::new(MemoryStore::new()),
+		"token",
+		Duration::from_secs(3600),
+	);
+	assert!(session_store
+		.downcast_store::<MemoryStore<String>>()
+		.is_none());
+	assert!(session_store
+		.downcast_store::<Arc<MemoryStore<String>>>()
+		.is_some());
+}
+
+#[test]
+fn shared_guard_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+
+	let old_cookie = seed(&client, "TestingName");
 
+	// The second guard sees the token regenerated through the first one.
+	let res1 = client
+		.post("/regenerate_shared")
+		.cookie(old_cookie.clone())
+		.dispatch();
 	assert_eq!(res1.status(), Status::Ok);
-	assert!(client.cookies().get("token").is_some());
+	assert_eq!(res1.cookies().iter().count(), 1);
+	let new_cookie = res1.cookies().get("token").unwrap().clone();
+	assert_ne!(old_cookie.value(), new_cookie.value());
+	assert_eq!(res1.into_string(), Some("TestingName".into()));
 
-	let res2 = client.get("/get_name").dispatch();
+	let res2 = client.get("/get_name").cookie(new_cookie).dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+}
+
+#[test]
+fn get_or_redirect_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
 
+	let res1 = client.get("/profile").dispatch();
+	assert_eq!(res1.status(), Status::SeeOther);
+	assert_eq!(res1.headers().get_one("Location"), Some("/login"));
+
+	let res2 = client.post("/set_name/TestingName").dispatch();
 	assert_eq!(res2.status(), Status::Ok);
 
-	let res3 = client.post("/remove_name").dispatch();
+	let res3 = client.get("/profile").dispatch();
 	assert_eq!(res3.status(), Status::Ok);
+	assert_eq!(res3.into_string(), Some("TestingName".into()));
+}
 
-	let res4 = client.get("/get_name").dispatch();
-	assert_eq!(res4.status(), Status::NotFound);
+#[test]
+fn session_error_variants_test() {
+	let client = Client::tracked(example_rocket(SessionStore::<String>::new(
+		MemoryStore::new(),
+		"token",
+		Duration::from_secs(3600),
+	)))
+	.expect("Expected to build client");
+	let res = client.get("/account").dispatch();
+	assert_eq!(res.into_string(), Some("not found".into()));
+	assert_eq!(
+		client.get("/required_name").dispatch().status(),
+		Status::InternalServerError
+	);
+	client.post("/set_name/TestingName").dispatch();
+	let res = client.get("/account").dispatch();
+	assert_eq!(res.into_string(), Some("TestingName".into()));
+
+	let client = Client::tracked(example_rocket(SessionStore::new(
+		FailingStore,
+		"token",
+		Duration::from_secs(3600),
+	)))
+	.expect("Expected to build client");
+	let res = client.get("/account").dispatch();
+	assert_eq!(res.into_string(), Some("unavailable".into()));
+	assert_eq!(
+		client.get("/required_name").dispatch().status(),
+		Status::InternalServerError
+	);
+
+	// A value that can't be decoded is neither missing nor unreachable.
+	let raw = Arc::new(MemoryRawStore::new());
+	let store: SessionStore<String> = SessionStore::new(
+		CodecStore::new(JsonCodec, raw.clone()),
+		"token",
+		Duration::from_secs(3600),
+	);
+	let client = Client::untracked(example_rocket(store)).expect("Expected to build client");
+	let cookie = client
+		.get("/account")
+		.dispatch()
+		.cookies()
+		.get("token")
+		.unwrap()
+		.clone();
+	let garbage = b"not json".to_vec();
+	rocket::execute(raw.set(cookie.value(), garbage, Duration::from_secs(3600))).unwrap();
+	let res = client.get("/account").cookie(cookie).dispatch();
+	assert_eq!(res.into_string(), Some("other".into()));
 }
 
-fn generic_refresh_test(store: impl Store<Value = String> + 'static) {
+#[test]
+fn oversized_cookie_test() {
 	let client: Client = {
-		let session_store: SessionStore<String> = SessionStore {
-			store: Box::new(store),
-			name: "token".into(),
-			duration: Duration::from_secs(2),
-			cookie: CookieConfig::default(),
-		};
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_max_token_length(64);
 		let rocket = example_rocket(session_store);
-		Client::tracked(rocket).expect("Expected to build client")
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+
+	let oversized = "a".repeat(65);
+	let res1 = client
+		.post("/set_name/TestingName")
+		.cookie(Cookie::new("token", oversized.clone()))
+		.dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+	assert_ne!(token, oversized);
+
+	let res2 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", oversized))
+		.dispatch();
+	assert_eq!(res2.status(), Status::NotFound);
+
+	let res3 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token))
+		.dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+}
+
+#[test]
+fn duplicate_cookie_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
 	};
 
 	let res1 = client.post("/set_name/TestingName").dispatch();
 	assert_eq!(res1.status(), Status::Ok);
-	sleep(Duration::from_millis(1_500));
-	let res2 = client.post("/refresh").dispatch();
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+
+	let res2 = client
+		.get("/get_name")
+		.header(Header::new(
+			"Cookie",
+			format!("token={}; other=1; token=unknown", token),
+		))
+		.dispatch();
 	assert_eq!(res2.status(), Status::Ok);
-	sleep(Duration::from_millis(1_500));
-	let res3 = client.get("/get_name").dispatch();
-	assert_eq!(res3.status(), Status::Ok);
+	assert_eq!(res2.into_string(), Some("TestingName".into()));
+
+	let res3 = client
+		.get("/get_name")
+		.header(Header::new(
+			"Cookie",
+			format!("token=unknown; token={}", token),
+		))
+		.dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
 }
 
-fn cookie_config_test(store: impl Store<Value = String> + 'static) {
+#[test]
+fn split_verifier_test() {
+	let events = Arc::new(RecordingEvents::default());
 	let client: Client = {
-		let session_store: SessionStore<String> = SessionStore {
-			store: Box::new(store),
-			name: "token".into(),
-			duration: Duration::from_secs(2),
-			cookie: CookieConfig {
-				path: Some("/".into()),
-				same_site: Some(SameSite::Lax),
-				secure: true,
-				http_only: true,
-			},
-		};
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_token_scheme(TokenScheme::SplitVerifier)
+				.with_events(events.clone());
 		let rocket = example_rocket(session_store);
-		Client::tracked(rocket).expect("Expected to build client")
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+	let get_name = |token: &str| {
+		client
+			.get("/get_name")
+			.cookie(Cookie::new("token", token.to_owned()))
+			.dispatch()
+			.status()
+	};
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+	let (selector, verifier) = token.split_once('.').unwrap();
+	assert_eq!(get_name(&token), Status::Ok);
+
+	// The store only ever sees the selector and a hash of the verifier.
+	let created = events.0.lock().unwrap()[0].clone();
+	assert!(created.starts_with(&format!("created {}.", selector)));
+	assert!(!created.contains(verifier));
+
+	assert_eq!(get_name(&format!("{}.wrong", selector)), Status::NotFound);
+	assert_eq!(get_name(selector), Status::NotFound);
+
+	let res2 = client
+		.post("/regenerate")
+		.cookie(Cookie::new("token", token.clone()))
+		.dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	let new_token = res2.cookies().get("token").unwrap().value().to_owned();
+	assert_ne!(new_token, token);
+	assert!(new_token.contains('.'));
+	assert_eq!(get_name(&new_token), Status::Ok);
+	assert_eq!(get_name(&token), Status::NotFound);
+}
+
+#[test]
+fn security_observer_test() {
+	let records = Arc::new(Mutex::new(Vec::new()));
+	let client: Client = {
+		let records = records.clone();
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_token_scheme(TokenScheme::SplitVerifier)
+				.with_security_observer(move |record| {
+					records
+						.lock()
+						.unwrap()
+						.push((record.status, record.path.clone()));
+				});
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
 	};
 
-	// make a request to set a cookie
 	let res1 = client.post("/set_name/TestingName").dispatch();
 	assert_eq!(res1.status(), Status::Ok);
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+
+	let res2 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token.clone()))
+		.dispatch();
+	assert_eq!(res2.status(), Status::Ok);
 
-	let cookie_jar = client.cookies();
-	let cookie = cookie_jar.get("token");
+	let res3 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", "garbage"))
+		.dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
 
-	assert!(cookie.is_some());
+	// Removing the session doesn't change the status of the incoming token.
+	let res4 = client
+		.post("/remove_name")
+		.cookie(Cookie::new("token", token.clone()))
+		.dispatch();
+	assert_eq!(res4.status(), Status::Ok);
 
-	let cookie = cookie.unwrap();
+	let res5 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token))
+		.dispatch();
+	assert_eq!(res5.status(), Status::NotFound);
 
-	assert_eq!(cookie.path(), Some("/"));
-	assert_eq!(cookie.same_site(), Some(SameSite::Lax));
-	assert_eq!(cookie.secure(), Some(true));
-	assert_eq!(cookie.http_only(), Some(true));
+	assert_eq!(
+		*records.lock().unwrap(),
+		vec![
+			(TokenStatus::New, "/set_name/TestingName".to_owned()),
+			(TokenStatus::Valid, "/get_name".to_owned()),
+			(TokenStatus::InvalidFormat, "/get_name".to_owned()),
+			(TokenStatus::Valid, "/remove_name".to_owned()),
+			(TokenStatus::Expired, "/get_name".to_owned()),
+		]
+	);
 }
 
-macro_rules! test_store {
-	($name:ident, $store:expr) => {
-		mod $name {
-			use super::*;
+#[test]
+fn session_id_serde_test() {
+	let id: SessionID = "0123456789abcdefghijABCD.selector".parse().unwrap();
+	let json = serde_json::to_string(&id).unwrap();
+	assert_eq!(json, "\"0123456789abcdefghijABCD.selector\"");
+	assert_eq!(serde_json::from_str::<SessionID>(&json).unwrap(), id);
 
-			#[test]
-			fn basic_test() {
-				generic_basic_test($store);
-			}
+	assert!(serde_json::from_str::<SessionID>("\"tooshort\"").is_err());
+	assert!(serde_json::from_str::<SessionID>("\"0123456789 abcdefghij;ABCD\"").is_err());
+	assert!("0123456789abcdefghij\\ABCD".parse::<SessionID>().is_err());
+}
 
-			#[test]
-			fn expiration_test() {
-				generic_expiration_test($store);
-			}
+#[test]
+fn session_store_parse_id_test() {
+	let store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(60))
+			.with_token_scheme(TokenScheme::SplitVerifier);
+	let token = "0123456789abcdefghijABCD.selector";
+	let id = store.parse_id(token).unwrap();
+	assert_eq!(id.as_str(), token);
+	assert_ne!(id.key(), token);
+	assert!(id.key().starts_with("0123456789abcdefghijABCD."));
+	assert_eq!(token.parse::<SessionID>().unwrap().key(), token);
 
-			#[test]
-			fn remove_test() {
-				generic_remove_test($store);
-			}
+	assert!(store.parse_id("0123456789abcdefghijABCDselector").is_err());
+	assert!(store.parse_id("tooshort").is_err());
+}
 
-			#[test]
-			fn refresh_test() {
-				generic_refresh_test($store);
-			}
+#[test]
+fn health_fairing_test() {
+	let health = |store: SessionStore<String>, path: &str| {
+		let rocket = rocket::build()
+			.attach(store.health_fairing().with_path("/health"))
+			.attach(store.fairing());
+		let client = Client::untracked(rocket).expect("Expected to build client");
+		let res = client.get(path).dispatch();
+		let status = res.status();
+		let body = res
+			.into_string()
+			.and_then(|body| serde_json::from_str::<Value>(&body).ok());
+		(status, body)
+	};
+	let duration = Duration::from_secs(3600);
 
-			#[test]
-			fn cookie_test() {
-				cookie_config_test($store);
-			}
-		}
+	let (status, body) = health(
+		SessionStore::new(MemoryStore::new(), "token", duration),
+		"/health",
+	);
+	assert_eq!(status, Status::Ok);
+	assert_eq!(body, Some(json!({ "status": "ok" })));
+
+	let (status, body) = health(
+		SessionStore::new(FailingStore, "token", duration),
+		"/health",
+	);
+	assert_eq!(status, Status::ServiceUnavailable);
+	assert_eq!(
+		body,
+		Some(json!({ "status": "error", "reason": "could not access the session store" }))
+	);
+
+	let (status, _) = health(
+		SessionStore::new(MemoryStore::new(), "token", duration),
+		"/healthz/session",
+	);
+	assert_eq!(status, Status::NotFound);
+}
+
+#[test]
+fn readiness_route_test() {
+	let ready = |store: SessionStore<String>| {
+		let rocket = rocket::build()
+			.attach(store.fairing())
+			.mount("/readyz", vec![crate::health::route::<String>()]);
+		let client = Client::untracked(rocket).expect("Expected to build client");
+		let status = client.get("/readyz").dispatch().status();
+		status
 	};
+	let duration = Duration::from_secs(3600);
+
+	let store = SessionStore::new(MemoryStore::new(), "token", duration);
+	assert!(rocket::execute(store.healthy()).is_ok());
+	assert_eq!(ready(store), Status::Ok);
+	let store = SessionStore::new(FailingStore, "token", duration);
+	assert!(rocket::execute(store.healthy()).is_err());
+	assert_eq!(ready(store), Status::ServiceUnavailable);
+
+	#[cfg(feature = "redis")]
+	{
+		let client = RedisClient::open("redis://127.0.0.1:1/").unwrap();
+		let store = SessionStore::new(RedisStore::new(client), "token", duration);
+		assert_eq!(ready(store), Status::ServiceUnavailable);
+	}
 }
 
-test_store!(in_memory, MemoryStore::<String>::new());
+#[test]
+fn error_status_test() {
+	let status = |store: SessionStore<String>| {
+		let client = Client::untracked(example_rocket(store)).expect("Expected to build client");
+		let status = client.get("/get_name").dispatch().status();
+		status
+	};
+	let duration = Duration::from_secs(3600);
 
-#[cfg(feature = "redis")]
-test_store!(redis, {
-	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
-	let store = RedisStore::new(client).prefix("user:".to_owned());
-	store
-});
+	let store = SessionStore::new(FailingStore, "token", duration);
+	assert_eq!(status(store), Status::InternalServerError);
+	let store = SessionStore::new(FailingStore, "token", duration)
+		.with_error_status(Status::ServiceUnavailable);
+	assert_eq!(status(store), Status::ServiceUnavailable);
+	// Working stores are unaffected.
+	let store = SessionStore::new(MemoryStore::new(), "token", duration)
+		.with_error_status(Status::ServiceUnavailable);
+	assert_eq!(status(store), Status::NotFound);
+}
+
+#[derive(Clone)]
+struct Counter(u32);
+
+#[derive(Clone)]
+struct Nickname(String);
+
+#[post("/nickname/<name>")]
+async fn set_nickname(name: String, session: AnySession<'_>) -> SessionResult<()> {
+	session.set_value(Nickname(name)).await
+}
+
+#[get("/nickname")]
+async fn get_nickname(session: AnySession<'_>) -> SessionResult<Option<String>> {
+	Ok(session
+		.get_value::<Nickname>()
+		.await?
+		.map(|nickname| nickname.0))
+}
+
+#[post("/forget_nickname")]
+async fn forget_nickname(session: AnySession<'_>) -> SessionResult<()> {
+	session.remove_value::<Nickname>().await
+}
+
+#[post("/count")]
+async fn count(session: AnySession<'_>) -> SessionResult<String> {
+	let Counter(count) = session.get_value().await?.unwrap_or(Counter(0));
+	session.set_value(Counter(count + 1)).await?;
+	Ok((count + 1).to_string())
+}
+
+#[test]
+fn any_session_test() {
+	let client: Client = {
+		let session_store: AnySessionStore =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build().attach(session_store.fairing()).mount(
+			"/",
+			routes![set_nickname, get_nickname, forget_nickname, count],
+		);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	assert_eq!(
+		client.post("/count").dispatch().into_string(),
+		Some("1".into())
+	);
+	assert_eq!(
+		client.get("/nickname").dispatch().status(),
+		Status::NotFound
+	);
+
+	let res1 = client.post("/nickname/Alice").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	assert_eq!(
+		client.post("/count").dispatch().into_string(),
+		Some("2".into())
+	);
+	assert_eq!(
+		client.get("/nickname").dispatch().into_string(),
+		Some("Alice".into())
+	);
+
+	let res2 = client.post("/forget_nickname").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert_eq!(
+		client.get("/nickname").dispatch().status(),
+		Status::NotFound
+	);
+	assert_eq!(
+		client.post("/count").dispatch().into_string(),
+		Some("3".into())
+	);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Theme(String);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Visits(u32);
+
+#[post("/theme/<name>")]
+async fn set_theme(name: String, session: AnySession<'_>) -> SessionResult<()> {
+	session.set_value(Theme(name)).await
+}
+
+#[post("/visit")]
+async fn visit(session: AnySession<'_>) -> SessionResult<String> {
+	let Visits(visits) = session.get_value().await?.unwrap_or(Visits(0));
+	session.set_value(Visits(visits + 1)).await?;
+	let theme = session.get_value::<Theme>().await?;
+	Ok(format!(
+		"{} {}",
+		visits + 1,
+		theme.map(|theme| theme.0).unwrap_or_default()
+	))
+}
+
+fn check_registered_types(raw: impl RawStore + 'static) {
+	register_session_type::<Theme>();
+	register_session_type::<Visits>();
+	let session_store: AnySessionStore = SessionStore::new(
+		CodecStore::new(TypeMapCodec, raw),
+		"token",
+		Duration::from_secs(3600),
+	);
+	let rocket = rocket::build()
+		.attach(session_store.fairing())
+		.mount("/", routes![set_theme, visit, count]);
+	let client = Client::tracked(rocket).expect("Expected to build client");
+
+	assert_eq!(
+		client.post("/visit").dispatch().into_string(),
+		Some("1 ".into())
+	);
+	let res = client.post("/theme/dark").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	assert_eq!(
+		client.post("/visit").dispatch().into_string(),
+		Some("2 dark".into())
+	);
+	// Sessions holding a type that isn't registered can't be stored.
+	assert_eq!(
+		client.post("/count").dispatch().status(),
+		Status::InternalServerError
+	);
+	assert_eq!(
+		client.post("/visit").dispatch().into_string(),
+		Some("3 dark".into())
+	);
+}
+
+#[test]
+fn registered_types_test() {
+	check_registered_types(MemoryRawStore::new());
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_registered_types_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	check_registered_types(RedisRawStore::new(client).prefix("any:".to_owned()));
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Cart {
+	items: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct User {
+	name: String,
+}
+
+#[post("/cart/<item>")]
+async fn set_cart(item: String, session: Session<'_, Value>) -> SessionResult<()> {
+	session.set(json!({ "items": [item] })).await
+}
+
+#[get("/cart")]
+async fn get_cart(session: Session<'_, Value>) -> SessionResult<Option<String>> {
+	Ok(session
+		.get_typed::<Cart>()
+		.await?
+		.map(|cart| cart.items.join(",")))
+}
+
+#[get("/user")]
+async fn get_user(session: Session<'_, Value>) -> SessionResult<Option<String>> {
+	Ok(session.get_typed::<User>().await?.map(|user| user.name))
+}
+
+#[test]
+fn get_typed_test() {
+	let client: Client = {
+		let session_store: SessionStore<Value> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build()
+			.attach(session_store.fairing())
+			.mount("/", routes![set_cart, get_cart, get_user]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	let res1 = client.post("/cart/apple").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+
+	let res2 = client.get("/cart").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert_eq!(res2.into_string(), Some("apple".into()));
+
+	// The stored value doesn't have the shape of a user.
+	let res3 = client.get("/user").dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
+}
+
+#[test]
+fn auto_regenerate_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_auto_regenerate_after(Duration::from_millis(100));
+		let rocket = example_rocket(session_store);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+	let token = |res: &rocket::local::blocking::LocalResponse<'_>| {
+		res.cookies().get("token").unwrap().value().to_owned()
+	};
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let first = token(&res1);
+
+	let res2 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", first.clone()))
+		.dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert_eq!(token(&res2), first);
+
+	sleep(Duration::from_millis(200));
+	let res3 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", first.clone()))
+		.dispatch();
+	let second = token(&res3);
+	assert_ne!(second, first);
+	assert_eq!(res3.into_string(), Some("TestingName".into()));
+
+	let res4 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", second.clone()))
+		.dispatch();
+	assert_eq!(token(&res4), second);
+	assert_eq!(res4.into_string(), Some("TestingName".into()));
+
+	// Requests that destroy the session keep their token.
+	sleep(Duration::from_millis(200));
+	let res5 = client
+		.post("/remove_name")
+		.cookie(Cookie::new("token", second.clone()))
+		.dispatch();
+	assert_eq!(res5.status(), Status::Ok);
+	assert_eq!(token(&res5), second);
+}
+
+#[cfg(feature = "cookie")]
+#[test]
+fn cookie_store_test() {
+	use crate::cookie::CookieStore;
+
+	let build_client = |key: &[u8]| {
+		let session_store: SessionStore<String> =
+			SessionStore::new(CookieStore::new(key), "token", Duration::from_secs(3600));
+		Client::untracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+	let client = build_client(b"a secret key of at least 32 bytes!");
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let token = res1.cookies().get("token").unwrap().value().to_owned();
+
+	let res2 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token.clone()))
+		.dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert_eq!(res2.into_string(), Some("TestingName".into()));
+
+	// Changing a single character of the payload breaks the signature.
+	let mut tampered = token.clone().into_bytes();
+	tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+	let res3 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", String::from_utf8(tampered).unwrap()))
+		.dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
+
+	let other_client = build_client(b"another secret key of 32 bytes!!");
+	let res4 = other_client
+		.get("/get_name")
+		.cookie(Cookie::new("token", token.clone()))
+		.dispatch();
+	assert_eq!(res4.status(), Status::NotFound);
+
+	// Removing the session replaces the cookie with one carrying no value.
+	let res5 = client
+		.post("/remove_name")
+		.cookie(Cookie::new("token", token))
+		.dispatch();
+	assert_eq!(res5.status(), Status::Ok);
+	let removed_token = res5.cookies().get("token").unwrap().value().to_owned();
+	let res6 = client
+		.get("/get_name")
+		.cookie(Cookie::new("token", removed_token))
+		.dispatch();
+	assert_eq!(res6.status(), Status::NotFound);
+
+	// A token encoded without an expiry never expires.
+	let store: CookieStore<String> = CookieStore::new(b"a secret key of at least 32 bytes!");
+	let permanent = store
+		.encode_token(Some(&"TestingName".into()), NO_EXPIRY)
+		.unwrap()
+		.unwrap();
+	assert_eq!(
+		rocket::execute(store.ttl(&permanent)).unwrap(),
+		Some(NO_EXPIRY)
+	);
+}
+
+#[test]
+fn id_charset_test() {
+	let new_store = || -> SessionStore<String> {
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+	};
+	assert!((new_store().id_entropy_bits() - 142.9).abs() < 0.1);
+	let session_store = new_store().with_id_charset(ID_CHARSET_HEX);
+	assert_eq!(session_store.id_entropy_bits(), 96.0);
+
+	let client = Client::tracked(example_rocket(session_store)).expect("Expected to build client");
+	let res = client.post("/set_name/TestingName").dispatch();
+	let token = res.cookies().get("token").unwrap().value().to_owned();
+	assert_eq!(token.len(), 24);
+	assert!(token.bytes().all(|byte| ID_CHARSET_HEX.contains(&byte)));
+	assert_eq!(
+		client.get("/get_name").dispatch().into_string(),
+		Some("TestingName".into())
+	);
+
+	for charset in [&b"a"[..], b"abca", b"ab;", b"ab ", b"ab."] {
+		let rejected = std::panic::catch_unwind(|| new_store().with_id_charset(charset));
+		assert!(rejected.is_err(), "{:?} was accepted", charset);
+	}
+}
+
+#[test]
+fn tls_secure_cookie_test() {
+	use rocket::config::{
+		Config,
+		TlsConfig,
+	};
+
+	let secure_cookie = |tls: Option<TlsConfig>, cookie: CookieConfig| {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_cookie(cookie);
+		let config = Config {
+			tls,
+			..Config::debug_default()
+		};
+		let rocket = example_rocket(session_store).configure(config);
+		let client = Client::untracked(rocket).expect("Expected to build client");
+		let res = client.post("/set_name/TestingName").dispatch();
+		assert_eq!(res.status(), Status::Ok);
+		let secure = res.cookies().get("token").unwrap().secure();
+		secure == Some(true)
+	};
+	let tls = || Some(TlsConfig::from_bytes(b"certificate", b"private key"));
+
+	assert!(!secure_cookie(None, CookieConfig::default()));
+	assert!(secure_cookie(tls(), CookieConfig::default()));
+
+	// An explicit setting wins over the TLS configuration.
+	let insecure = CookieConfig {
+		secure: Some(false),
+		..CookieConfig::default()
+	};
+	assert!(!secure_cookie(tls(), insecure));
+}
+
+#[test]
+fn mount_point_cookie_path_test() {
+	let client = |session_store: SessionStore<String>| {
+		let rocket = rocket::build()
+			.attach(session_store.fairing())
+			.mount("/app", conformance::routes())
+			.mount("/app/account", routes![profile, promote]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let new_store = || -> SessionStore<String> {
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+	};
+	let cookie_path = |client: &Client| {
+		let res = client.post("/app/set_name/Alice").dispatch();
+		let path = res.cookies().get("token").unwrap().path().map(str::to_owned);
+		path
+	};
+
+	// The cookie set on one route is sent to the others.
+	let app = client(new_store());
+	assert_eq!(cookie_path(&app).as_deref(), Some("/app"));
+	let res = app.get("/app/get_name").dispatch();
+	assert_eq!(res.into_string(), Some("Alice".into()));
+	let res = app.get("/app/account/profile").dispatch();
+	assert_eq!(res.into_string(), Some("Alice".into()));
+
+	let proxied = client(new_store().with_base_path("/proxy/app"));
+	assert_eq!(cookie_path(&proxied).as_deref(), Some("/proxy/app"));
+
+	// An explicit path wins.
+	let explicit = client(
+		new_store()
+			.with_base_path("/proxy/app")
+			.with_cookie(CookieConfig {
+				path: Some("/".into()),
+				..CookieConfig::default()
+			}),
+	);
+	assert_eq!(cookie_path(&explicit).as_deref(), Some("/"));
+
+	// Routes without a shared mount point fall back to the root.
+	let root = Client::tracked(example_rocket(new_store())).expect("Expected to build client");
+	let res = root.post("/set_name/Alice").dispatch();
+	assert_eq!(res.cookies().get("token").unwrap().path(), Some("/"));
+}
+
+#[test]
+fn legacy_cookie_test() {
+	use crate::LegacyCookie;
+
+	let set_cookies = |session_store: SessionStore<String>, path: &str| {
+		let client =
+			Client::untracked(example_rocket(session_store)).expect("Expected to build client");
+		let res = client
+			.post(path.to_owned())
+			.cookie(Cookie::new("sid", "OldSessionToken"))
+			.cookie(Cookie::new("sid_extra", "Unrelated"))
+			.dispatch();
+		res.headers()
+			.get("Set-Cookie")
+			.map(str::to_owned)
+			.collect::<Vec<_>>()
+	};
+	let new_store = || -> SessionStore<String> {
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+			.with_legacy_cookie(LegacyCookie::new("sid").with_path("/old"))
+			.with_legacy_cookie(LegacyCookie::new("session_id").with_domain("example.com"))
+	};
+
+	// The old cookies are removed along with the new one.
+	let cookies = set_cookies(new_store(), "/set_name/Alice");
+	assert_eq!(cookies.len(), 3, "{:?}", cookies);
+	assert!(cookies.iter().any(|cookie| cookie.starts_with("token=")));
+	let removal = |name: &str| {
+		cookies
+			.iter()
+			.find(|cookie| cookie.starts_with(&format!("{}=;", name)))
+			.unwrap_or_else(|| panic!("no removal of `{}` in {:?}", name, cookies))
+			.clone()
+	};
+	let sid = removal("sid");
+	assert!(sid.contains("Path=/old") && sid.contains("Max-Age=0"), "{}", sid);
+	let session_id = removal("session_id");
+	assert!(session_id.contains("Path=/"), "{}", session_id);
+	assert!(session_id.contains("Domain=example.com"), "{}", session_id);
+	assert!(!cookies.iter().any(|cookie| cookie.starts_with("sid_extra")));
+
+	// Responses not issuing the session cookie are left alone.
+	assert!(set_cookies(new_store(), "/missing").is_empty());
+
+	// Unless the cleanup window is still open.
+	let hour = Duration::from_secs(3600);
+	let open = new_store().with_legacy_cleanup_until(SystemTime::now() + hour);
+	assert_eq!(set_cookies(open, "/missing").len(), 2);
+	let open = new_store().with_legacy_cleanup_until(SystemTime::now() + hour);
+	assert_eq!(set_cookies(open, "/set_name/Alice").len(), 3);
+	let closed = new_store().with_legacy_cleanup_until(SystemTime::now() - hour);
+	assert!(set_cookies(closed, "/missing").is_empty());
+
+	// A legacy cookie can't be the session cookie itself.
+	let session_store = new_store().with_legacy_cookie(LegacyCookie::new("token"));
+	match Client::untracked(example_rocket(session_store)) {
+		Ok(_) => panic!("Expected the rocket to fail to ignite"),
+		Err(e) => assert!(matches!(
+			e.kind(),
+			rocket::error::ErrorKind::FailedFairings(_)
+		)),
+	}
+}
+
+#[test]
+fn partitioned_cookie_test() {
+	let set_cookie = |session_store: SessionStore<String>| {
+		let client =
+			Client::untracked(example_rocket(session_store)).expect("Expected to build client");
+		let res = client.post("/set_name/TestingName").dispatch();
+		assert_eq!(res.status(), Status::Ok);
+		res.headers().get_one("Set-Cookie").unwrap().to_owned()
+	};
+	let new_store = || -> SessionStore<String> {
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+	};
+
+	assert!(!set_cookie(new_store()).contains("Partitioned"));
+
+	// Partitioned cookies are made secure even without TLS.
+	let cookie = set_cookie(new_store().with_partitioned_cookie(true));
+	assert!(cookie.contains("; Partitioned"), "{}", cookie);
+	assert!(cookie.contains("; Secure"), "{}", cookie);
+
+	let insecure = new_store()
+		.with_cookie(CookieConfig {
+			secure: Some(false),
+			..CookieConfig::default()
+		})
+		.with_partitioned_cookie(true);
+	match Client::untracked(example_rocket(insecure)) {
+		Ok(_) => panic!("Expected the rocket to fail to ignite"),
+		Err(e) => assert!(matches!(
+			e.kind(),
+			rocket::error::ErrorKind::FailedFairings(_)
+		)),
+	}
+}
+
+/// The value of the documented routes, so other tests can't change the names
+/// of its store.
+#[cfg(feature = "okapi")]
+#[derive(Clone)]
+struct Documented;
+
+#[cfg(feature = "okapi")]
+#[rocket_okapi::openapi]
+#[get("/documented")]
+async fn documented(session: Session<'_, Documented>) -> SessionResult<()> {
+	session.get().await.map(|_| ())
+}
+
+#[cfg(feature = "okapi")]
+#[rocket_okapi::openapi]
+#[get("/documented/bearer")]
+async fn documented_bearer(_session: BearerSession<'_, Documented>) {}
+
+#[cfg(all(feature = "okapi", feature = "csrf"))]
+#[rocket_okapi::openapi]
+#[post("/documented")]
+async fn documented_csrf(_csrf: crate::csrf::CsrfCheck<Documented>, _session: Session<'_, Documented>) {}
+
+#[cfg(feature = "okapi")]
+#[test]
+fn okapi_test() {
+	use rocket_okapi::{
+		okapi::openapi3::{
+			RefOr,
+			SecuritySchemeData,
+		},
+		openapi_get_routes_spec,
+	};
+
+	let store: SessionStore<Documented> =
+		SessionStore::new(MemoryStore::new(), "sid", Duration::from_secs(60));
+	#[cfg(feature = "csrf")]
+	let store = store.with_csrf(
+		crate::csrf::CsrfConfig::new(b"a secret key of at least 32 bytes!")
+			.with_header_name("X-Documented"),
+	);
+	let _fairing = store.fairing();
+
+	let (routes, spec) = openapi_get_routes_spec![documented, documented_bearer];
+	assert_eq!(routes.len(), 2);
+	let schemes = &spec.components.unwrap().security_schemes;
+	assert!(matches!(
+		&schemes["sid"],
+		RefOr::Object(scheme)
+			if matches!(&scheme.data, SecuritySchemeData::ApiKey { name, location } if name == "sid" && location == "cookie")
+	));
+	assert!(matches!(
+		&schemes["bearer"],
+		RefOr::Object(scheme)
+			if matches!(&scheme.data, SecuritySchemeData::Http { scheme, .. } if scheme == "bearer")
+	));
+	let operation = spec.paths["/documented"].get.as_ref().unwrap();
+	assert!(operation.security.as_ref().unwrap()[0].contains_key("sid"));
+	assert!(operation.responses.responses.contains_key("500"));
+
+	#[cfg(feature = "csrf")]
+	{
+		let (_, spec) = openapi_get_routes_spec![documented_csrf];
+		let operation = spec.paths["/documented"].post.as_ref().unwrap();
+		let security = operation.security.as_ref().unwrap();
+		assert!(security.iter().any(|requirement| requirement.contains_key("X-Documented")));
+		assert!(security.iter().any(|requirement| requirement.contains_key("sid")));
+	}
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_test() {
+	use std::io;
+
+	use tracing_subscriber::fmt::{
+		format::FmtSpan,
+		MakeWriter,
+	};
+
+	#[derive(Clone, Default)]
+	struct Output(Arc<Mutex<Vec<u8>>>);
+
+	impl io::Write for Output {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl<'a> MakeWriter<'a> for Output {
+		type Writer = Self;
+
+		fn make_writer(&'a self) -> Self::Writer {
+			self.clone()
+		}
+	}
+
+	let output = Output::default();
+	let subscriber = tracing_subscriber::fmt()
+		.with_writer(output.clone())
+		.with_span_events(FmtSpan::CLOSE)
+		.with_max_level(tracing::Level::DEBUG)
+		.with_ansi(false)
+		.finish();
+	let token = tracing::subscriber::with_default(subscriber, || {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let client = Client::tracked(example_rocket(session_store)).unwrap();
+		client.post("/set_name/TestingName").dispatch();
+		client.get("/get_name").dispatch();
+		client.cookies().get("token").unwrap().value().to_owned()
+	});
+
+	let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+	let get = output
+		.lines()
+		.find(|line| line.contains("session.op=\"get\"") && !line.contains("store="))
+		.expect("a span is emitted for get");
+	assert!(get.contains("session.backend=\"memory\""));
+	assert!(get.contains("session.hit=true"));
+	let digest = get
+		.split("session.token=")
+		.nth(1)
+		.and_then(|rest| rest.split_whitespace().next())
+		.unwrap();
+	let store_span = format!("get{{store=\"memory\" session.token={}}}", digest);
+	assert!(output.contains(&store_span));
+	assert!(!output.contains(&token));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_test() {
+	use std::collections::HashMap;
+
+	use metrics_util::debugging::{
+		DebugValue,
+		DebuggingRecorder,
+	};
+
+	let recorder = DebuggingRecorder::new();
+	let snapshotter = recorder.snapshotter();
+	metrics::with_local_recorder(&recorder, || {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+				.with_metrics_name("primary");
+		let client = Client::tracked(example_rocket(session_store)).unwrap();
+		client.post("/set_name/TestingName").dispatch();
+		client.get("/get_name").dispatch();
+		client.post("/remove_name").dispatch();
+	});
+
+	let metrics: HashMap<String, DebugValue> = snapshotter
+		.snapshot()
+		.into_vec()
+		.into_iter()
+		.map(|(key, _, _, value)| {
+			let key = key.key();
+			let labels: Vec<String> = key
+				.labels()
+				.map(|label| format!("{}={}", label.key(), label.value()))
+				.collect();
+			(format!("{}{{{}}}", key.name(), labels.join(",")), value)
+		})
+		.collect();
+	let counter = |name: &str| match metrics.get(name) {
+		Some(DebugValue::Counter(count)) => *count,
+		_ => 0,
+	};
+
+	for op in ["set", "get", "remove"] {
+		let name = format!(
+			"session_store_operations_total{{store=primary,op={},outcome=ok}}",
+			op
+		);
+		assert_eq!(counter(&name), 1, "{}", name);
+	}
+	assert_eq!(
+		counter("session_requests_total{store=primary,session=with}"),
+		3
+	);
+	assert_eq!(counter("session_cookies_issued_total{store=primary}"), 3);
+	assert!(matches!(
+		metrics.get("session_active_estimate{store=primary}"),
+		Some(DebugValue::Gauge(value)) if value.0 == 0.0
+	));
+	assert!(matches!(
+		metrics.get("session_store_operation_duration_seconds{store=primary,op=get}"),
+		Some(DebugValue::Histogram(values)) if values.len() == 1
+	));
+}
+
+#[post("/forbidden")]
+async fn forbidden(session: Session<'_, String>) -> SessionResult<Status> {
+	session.regenerate_token().await?;
+	Ok(Status::Unauthorized)
+}
+
+#[catch(401)]
+async fn logged_out(request: &Request<'_>) -> String {
+	let session = Session::<String>::of(request)
+		.await
+		.expect("Expected a session store");
+	let name = session.get_opt().await.ok().flatten().unwrap_or_default();
+	session.remove().await.ok();
+	format!("{}:{}", session.id(), name)
+}
+
+#[test]
+fn catcher_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store)
+			.mount("/", routes![forbidden])
+			.register("/", catchers![logged_out]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	client.post("/set_name/TestingName").dispatch();
+	let res1 = client.post("/forbidden").dispatch();
+	assert_eq!(res1.status(), Status::Unauthorized);
+	let token = res1
+		.cookies()
+		.get("token")
+		.expect("Expected a session cookie")
+		.value()
+		.to_owned();
+	assert_eq!(
+		res1.into_string(),
+		Some(format!("{}:TestingName", token)),
+		"the catcher sees the regenerated token and the value"
+	);
+
+	let res2 = client.get("/get_name").dispatch();
+	assert_eq!(res2.status(), Status::NotFound);
+}
+
+#[get("/hash")]
+async fn hash(session: Session<'_, String>) -> SessionResult<Option<String>> {
+	Ok(session.value_hash().await?.map(|hash| hash.to_string()))
+}
+
+#[test]
+fn value_hash_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store).mount("/", routes![hash]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	let res1 = client.get("/hash").dispatch();
+	assert_eq!(res1.status(), Status::NotFound);
+
+	client.post("/set_name/TestingName").dispatch();
+	let hash1 = client.get("/hash").dispatch().into_string();
+	assert!(hash1.is_some());
+	let hash2 = client.get("/hash").dispatch().into_string();
+	assert_eq!(
+		hash1, hash2,
+		"the hash is stable while the value is unchanged"
+	);
+
+	client.post("/set_name/TestingName").dispatch();
+	let hash3 = client.get("/hash").dispatch().into_string();
+	assert_eq!(hash1, hash3, "setting the same value keeps the hash");
+
+	client.post("/set_name/OtherName").dispatch();
+	let hash4 = client.get("/hash").dispatch().into_string();
+	assert_ne!(hash1, hash4, "the hash changes with the value");
+}
+
+#[post("/set_and_hash/<name>")]
+async fn set_and_hash(name: String, session: Session<'_, String>) -> SessionResult<Option<String>> {
+	session.set(name).await?;
+	Ok(session.value_hash().await?.map(|hash| hash.to_string()))
+}
+
+/// A JSON codec counting the values it decodes.
+#[derive(Clone, Default)]
+struct CountingCodec(Arc<AtomicUsize>);
+
+impl SessionCodec<String> for CountingCodec {
+	fn encode(&self, value: &String) -> SessionResult<Vec<u8>> {
+		JsonCodec.encode(value)
+	}
+
+	fn decode(&self, bytes: &[u8]) -> SessionResult<String> {
+		self.0.fetch_add(1, Ordering::SeqCst);
+		JsonCodec.decode(bytes)
+	}
+}
+
+#[test]
+fn stored_value_hash_test() {
+	let codec = CountingCodec::default();
+	let store = Arc::new(CodecStore::new(
+		HashedCodec::new(codec.clone()),
+		MemoryRawStore::new(),
+	));
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600)).with_value_hash();
+		let rocket = example_rocket(session_store).mount("/", routes![hash, set_and_hash]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let decodes = || codec.0.swap(0, Ordering::SeqCst);
+
+	let res = client.get("/hash").dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+
+	let hash1 = client
+		.post("/set_and_hash/TestingName")
+		.dispatch()
+		.into_string();
+	assert!(hash1.is_some());
+	assert_eq!(decodes(), 0, "the hash of the value set isn't read back");
+
+	let hash2 = client.get("/hash").dispatch().into_string();
+	assert_eq!(hash1, hash2, "the hash stored with the value is the same");
+	assert_eq!(decodes(), 0, "the stored hash is read without decoding the value");
+
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	assert_eq!(decodes(), 1, "reading the value still decodes it");
+
+	let hash3 = client
+		.post("/set_and_hash/OtherName")
+		.dispatch()
+		.into_string();
+	assert_ne!(hash1, hash3, "the hash changes with the value");
+	let hash4 = client.get("/hash").dispatch().into_string();
+	assert_eq!(hash3, hash4);
+	assert_eq!(decodes(), 0);
+}
+
+/// A memory store counting the reads reaching it.
+#[derive(Default)]
+struct CountingStore {
+	inner: MemoryStore<String>,
+	gets: AtomicUsize,
+	touches: AtomicUsize,
+	/// Every call made to the store, gets included.
+	calls: AtomicUsize,
+}
+
+impl CountingStore {
+	fn call(&self) {
+		self.calls.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+#[rocket::async_trait]
+impl Store for CountingStore {
+	type Value = String;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<String>> {
+		self.call();
+		self.gets.fetch_add(1, Ordering::SeqCst);
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: String, duration: Duration) -> SessionResult<()> {
+		self.call();
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.call();
+		self.touches.fetch_add(1, Ordering::SeqCst);
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.call();
+		self.inner.remove(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.call();
+		self.inner.ttl(id).await
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.call();
+		self.inner.created_at(id).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.call();
+		self.inner.rename(old_id, new_id, duration).await
+	}
+
+	async fn replace(
+		&self,
+		id: &str,
+		value: String,
+		duration: Duration,
+	) -> SessionResult<Option<String>> {
+		self.call();
+		self.inner.replace(id, value, duration).await
+	}
+}
+
+#[get("/get_twice")]
+async fn get_twice(
+	first: Session<'_, String>,
+	second: Session<'_, String>,
+) -> SessionResult<Option<String>> {
+	first.get().await?;
+	first.get().await?;
+	second.get_opt().await
+}
+
+#[post("/set_and_get/<name>")]
+async fn set_and_get(name: String, session: Session<'_, String>) -> SessionResult<Option<String>> {
+	session.get().await?;
+	session.set(name).await?;
+	session.get_opt().await
+}
+
+#[post("/remove_and_get")]
+async fn remove_and_get(session: Session<'_, String>) -> SessionResult<Option<String>> {
+	session.get().await?;
+	session.remove().await?;
+	session.get_opt().await
+}
+
+#[test]
+fn value_cache_test() {
+	let store = Arc::new(CountingStore::default());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store)
+			.mount("/", routes![get_twice, set_and_get, remove_and_get]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let gets = || store.gets.swap(0, Ordering::SeqCst);
+
+	client.post("/set_name/TestingName").dispatch();
+	gets();
+
+	let res1 = client.get("/get_twice").dispatch();
+	assert_eq!(res1.into_string(), Some("TestingName".into()));
+	assert_eq!(gets(), 1, "the guards of a request share the cached value");
+
+	// Reading after a change doesn't return the stale cached value.
+	let res2 = client.post("/set_and_get/OtherName").dispatch();
+	assert_eq!(res2.into_string(), Some("OtherName".into()));
+
+	let res3 = client.post("/regenerate").dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+	let res4 = client.get("/get_twice").dispatch();
+	assert_eq!(res4.into_string(), Some("OtherName".into()));
+
+	let res5 = client.post("/remove_and_get").dispatch();
+	assert_eq!(res5.status(), Status::NotFound);
+}
+
+#[post("/set_unchecked/<name>")]
+async fn set_unchecked(name: String, session: Session<'_, String>) -> SessionResult<()> {
+	session.set_unchecked(name).await
+}
+
+#[post("/set_checked/<name>")]
+async fn set_checked(name: String, session: Session<'_, String>) -> String {
+	match session.set(name).await {
+		Ok(()) => "stored".into(),
+		Err(SessionError::ValueTooLarge { size, limit }) => format!("{} > {}", size, limit),
+		Err(e) => e.to_string(),
+	}
+}
+
+#[test]
+fn max_value_size_test() {
+	let store = Arc::new(CountingStore::default());
+	let client: Client = {
+		// A name of 18 characters is 20 bytes of JSON.
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600))
+				.with_max_value_size(20);
+		let rocket = example_rocket(session_store).mount("/", routes![set_unchecked, set_checked]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let under = "a".repeat(18);
+	let over = "b".repeat(19);
+
+	let res = client.post(format!("/set_name/{}", under)).dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let calls = store.calls.load(Ordering::SeqCst);
+	let res = client.post(format!("/set_name/{}", over)).dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	assert_eq!(
+		store.calls.load(Ordering::SeqCst),
+		calls,
+		"the store isn't reached"
+	);
+	let res = client.post(format!("/set_checked/{}", under)).dispatch();
+	assert_eq!(res.into_string(), Some("stored".into()));
+	let res = client.post(format!("/set_checked/{}", over)).dispatch();
+	assert_eq!(res.into_string(), Some("21 > 20".into()));
+	let res = client.post(format!("/replace_name/{}", over)).dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	let token = client.cookies().get("token").unwrap().value().to_owned();
+	let res = client.post("/promote/x").dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	assert_eq!(client.cookies().get("token").unwrap().value(), token);
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string(), Some(under));
+
+	let res = client.post(format!("/set_unchecked/{}", over)).dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string(), Some(over));
+}
+
+#[test]
+fn memory_store_debug_test() {
+	let store = MemoryStore::<String>::new();
+	assert_eq!(
+		format!("{:?}", store),
+		"MemoryStore { sessions: 0, expired: 0, estimated_size: 0 }"
+	);
+
+	rocket::execute(async {
+		let hour = Duration::from_secs(3600);
+		store.set("first", "Alice".into(), hour).await.unwrap();
+		store.set("second", "Bob".into(), hour).await.unwrap();
+		store
+			.set("expired", "Carol".into(), Duration::from_millis(1))
+			.await
+			.unwrap();
+	});
+	sleep(Duration::from_millis(10));
+	assert_eq!(
+		format!("{:?}", store),
+		format!(
+			"MemoryStore {{ sessions: 3, expired: 1, estimated_size: {} }}",
+			2 * std::mem::size_of::<String>()
+		)
+	);
+
+}
+
+#[test]
+fn response_hook_test() {
+	let store = Arc::new(CountingStore::default());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600))
+				.with_response_hook(|name, response| {
+					response.set_header(Header::new("X-Session-Name", name.clone()));
+				});
+		let rocket = example_rocket(session_store);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let gets = || store.gets.swap(0, Ordering::SeqCst);
+	let name = |res: &rocket::local::blocking::LocalResponse<'_>| {
+		res.headers().get_one("X-Session-Name").map(str::to_owned)
+	};
+
+	let res1 = client.get("/get_name").dispatch();
+	assert_eq!(name(&res1), None, "the hook only runs on sessions with a value");
+
+	let res2 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(name(&res2).as_deref(), Some("TestingName"));
+	gets();
+
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(name(&res3).as_deref(), Some("TestingName"));
+	assert_eq!(gets(), 1, "the hook reuses the value read by the handler");
+
+	let res4 = client.post("/remove_name").dispatch();
+	assert_eq!(name(&res4), None);
+}
+
+#[test]
+fn regenerate_renames_test() {
+	let store = Arc::new(CountingStore::default());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600));
+		Client::tracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	client.post("/set_name/TestingName").dispatch();
+	store.calls.swap(0, Ordering::SeqCst);
+	let res1 = client.post("/regenerate").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	assert_eq!(
+		store.calls.load(Ordering::SeqCst),
+		1,
+		"the session is moved in a single operation"
+	);
+	let res2 = client.get("/get_name").dispatch();
+	assert_eq!(res2.into_string(), Some("TestingName".into()));
+}
+
+/// A memory store claiming that the first ids it is asked about are in use.
+struct CollidingStore {
+	inner: MemoryStore<String>,
+	/// How many more ids are reported to be in use.
+	collisions: AtomicUsize,
+	contains: AtomicUsize,
+}
+
+impl CollidingStore {
+	fn new(collisions: usize) -> Self {
+		Self {
+			inner: MemoryStore::new(),
+			collisions: AtomicUsize::new(collisions),
+			contains: AtomicUsize::new(0),
+		}
+	}
+}
+
+#[rocket::async_trait]
+impl Store for CollidingStore {
+	type Value = String;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<String>> {
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: String, duration: Duration) -> SessionResult<()> {
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.contains.fetch_add(1, Ordering::SeqCst);
+		let collides = self
+			.collisions
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+			.is_ok();
+		Ok(collides || self.inner.contains(id).await?)
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.inner.rename(old_id, new_id, duration).await
+	}
+}
+
+#[test]
+fn collision_detection_test() {
+	let client = |store: Arc<CollidingStore>, detection: bool| {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store, "token", Duration::from_secs(3600))
+				.with_collision_detection(detection);
+		Client::tracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	// Disabled, the store isn't asked.
+	let store = Arc::new(CollidingStore::new(usize::MAX));
+	let disabled = client(store.clone(), false);
+	assert_eq!(disabled.post("/set_name/Alice").dispatch().status(), Status::Ok);
+	assert_eq!(disabled.post("/regenerate").dispatch().status(), Status::Ok);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 0);
+
+	// Every token collides, so new sessions and regenerations fail.
+	let store = Arc::new(CollidingStore::new(usize::MAX));
+	let colliding = client(store.clone(), true);
+	let res = colliding.post("/set_name/Alice").dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 3);
+	assert_eq!(rocket::execute(store.inner.count()).unwrap(), 0);
+
+	let cookie = seed(&colliding, "Bob");
+	store.contains.store(0, Ordering::SeqCst);
+	let res = colliding.post("/regenerate").cookie(cookie.clone()).dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 3);
+	let res = colliding.get("/get_name").cookie(cookie).dispatch();
+	assert_eq!(res.into_string(), Some("Bob".into()));
+
+	// A token found in use is replaced by the next unused one.
+	let store = Arc::new(CollidingStore::new(2));
+	let retrying = client(store.clone(), true);
+	assert_eq!(retrying.post("/set_name/Alice").dispatch().status(), Status::Ok);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 3);
+	assert_eq!(retrying.get("/get_name").dispatch().into_string(), Some("Alice".into()));
+	assert_eq!(retrying.post("/set_name/Bob").dispatch().status(), Status::Ok);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 3, "only new tokens are checked");
+	assert_eq!(retrying.post("/regenerate").dispatch().status(), Status::Ok);
+	assert_eq!(store.contains.load(Ordering::SeqCst), 4);
+	assert_eq!(retrying.get("/get_name").dispatch().into_string(), Some("Bob".into()));
+}
+
+#[test]
+fn strict_tokens_test() {
+	let planted = "PlantedByAnAttacker00000";
+	let client = |strict: bool| {
+		let memory = Arc::new(MemoryStore::<String>::new());
+		let session_store: SessionStore<String> =
+			SessionStore::new(memory.clone(), "token", Duration::from_secs(3600))
+				.with_strict_tokens(strict);
+		let client =
+			Client::untracked(example_rocket(session_store)).expect("Expected to build client");
+		(client, memory)
+	};
+
+	// By default, the planted token is adopted.
+	let (lax, memory) = client(false);
+	let res = lax.post("/set_name/Alice").cookie(Cookie::new("token", planted)).dispatch();
+	assert_eq!(res.cookies().get("token").unwrap().value(), planted);
+	assert_eq!(rocket::execute(memory.get(planted)).unwrap(), Some("Alice".into()));
+
+	let (strict, memory) = client(true);
+	let res = strict.post("/set_name/Alice").cookie(Cookie::new("token", planted)).dispatch();
+	let cookie = res.cookies().get("token").unwrap().clone();
+	assert_ne!(cookie.value(), planted);
+	assert_eq!(rocket::execute(memory.get(planted)).unwrap(), None);
+	assert_eq!(rocket::execute(memory.get(cookie.value())).unwrap(), Some("Alice".into()));
+
+	// Tokens of stored sessions are kept.
+	let res = strict.post("/set_name/Bob").cookie(cookie.clone()).dispatch();
+	assert_eq!(res.cookies().get("token").unwrap().value(), cookie.value());
+	let res = strict.get("/get_name").cookie(cookie).dispatch();
+	assert_eq!(res.into_string(), Some("Bob".into()));
+}
+
+#[cfg(feature = "csrf")]
+#[post("/csrf/<name>")]
+async fn set_name_csrf(
+	name: &str,
+	_csrf: crate::csrf::CsrfCheck<String>,
+	session: Session<'_, String>,
+) -> SessionResult<()> {
+	session.set(name.into()).await
+}
+
+#[cfg(feature = "csrf")]
+#[test]
+fn csrf_cookie_test() {
+	use crate::csrf::CsrfConfig;
+
+	let config = CsrfConfig::new(b"a secret key of at least 32 bytes!");
+	let session_store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+			.with_csrf(config.clone());
+	let rocket = example_rocket(session_store).mount("/", routes![set_name_csrf]);
+	let client = Client::untracked(rocket).expect("Expected to build client");
+	let pair = |res: &rocket::local::blocking::LocalResponse<'_>| {
+		let token = res.cookies().get("token").unwrap().clone();
+		let csrf = res.cookies().get("csrf_token").unwrap().clone();
+		assert_eq!(csrf.value(), config.token_for(token.value()));
+		assert_eq!(csrf.path(), token.path());
+		assert_eq!(token.http_only(), Some(true));
+		assert_ne!(csrf.http_only(), Some(true));
+		(token, csrf)
+	};
+
+	// The companion cookie is issued with the session cookie.
+	let res = client.post("/set_name/Alice").dispatch();
+	let (token, csrf) = pair(&res);
+
+	// And rotates with it.
+	let res = client.post("/regenerate").cookie(token.clone()).dispatch();
+	let (new_token, new_csrf) = pair(&res);
+	assert_ne!(new_token.value(), token.value());
+	assert_ne!(new_csrf.value(), csrf.value());
+
+	let attempt = |csrf: &Cookie<'static>, header: Option<&str>| {
+		let mut req = client
+			.post("/csrf/Bob")
+			.cookie(new_token.clone())
+			.cookie(csrf.clone());
+		if let Some(header) = header {
+			req = req.header(Header::new("X-CSRF-Token", header.to_owned()));
+		}
+		req.dispatch().status()
+	};
+	assert_eq!(attempt(&new_csrf, None), Status::Forbidden);
+	assert_eq!(attempt(&new_csrf, Some("forged")), Status::Forbidden);
+	// A pair echoed correctly but derived from another token is rejected.
+	assert_eq!(attempt(&csrf, Some(csrf.value())), Status::Forbidden);
+	let res = client.get("/get_name").cookie(new_token.clone()).dispatch();
+	assert_eq!(res.into_string(), Some("Alice".into()));
+
+	assert_eq!(attempt(&new_csrf, Some(new_csrf.value())), Status::Ok);
+	let res = client.get("/get_name").cookie(new_token.clone()).dispatch();
+	assert_eq!(res.into_string(), Some("Bob".into()));
+}
+
+#[test]
+fn rename_concurrent_get_test() {
+	let runtime = rocket::tokio::runtime::Builder::new_multi_thread()
+		.worker_threads(4)
+		.build()
+		.expect("Expected to build runtime");
+	runtime.block_on(async {
+		let store = Arc::new(MemoryStore::<String>::new());
+		let hour = Duration::from_secs(3600);
+		for round in 0..100 {
+			let old = format!("old{}", round);
+			let new = format!("new{}", round);
+			store.set(&old, "TestingName".into(), hour).await.unwrap();
+			let readers: Vec<_> = (0..4)
+				.map(|_| {
+					let (store, old, new) = (store.clone(), old.clone(), new.clone());
+					rocket::tokio::spawn(async move {
+						loop {
+							let before = store.get(&old).await.unwrap();
+							let after = store.get(&new).await.unwrap();
+							for value in before.iter().chain(&after) {
+								assert_eq!(value, "TestingName");
+							}
+							// Once the old id is gone, the new one holds the session.
+							if before.is_none() {
+								assert!(after.is_some(), "the session was under neither id");
+								return;
+							}
+							rocket::tokio::task::yield_now().await;
+						}
+					})
+				})
+				.collect();
+			assert!(store.rename(&old, &new, hour).await.unwrap());
+			for reader in readers {
+				reader.await.unwrap();
+			}
+		}
+	});
+}
+
+#[test]
+fn ttl_jitter_test() {
+	let store = Arc::new(MemoryStore::<String>::new());
+	let hour = Duration::from_secs(3600);
+	let jitter = Duration::from_secs(600);
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", hour).with_ttl_jitter(jitter);
+		Client::untracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	for _ in 0..50 {
+		client.post("/set_name/TestingName").dispatch();
+	}
+	let ttls: Vec<Duration> = rocket::execute(async {
+		let mut ttls = Vec::new();
+		for id in store.list_ids(0, usize::MAX).await.unwrap() {
+			ttls.push(store.ttl(&id).await.unwrap().unwrap());
+		}
+		ttls
+	});
+	assert_eq!(ttls.len(), 50);
+	let min = *ttls.iter().min().unwrap();
+	let max = *ttls.iter().max().unwrap();
+	assert!(min >= hour - jitter - Duration::from_secs(1), "{:?}", min);
+	assert!(max <= hour + jitter, "{:?}", max);
+	assert!(max - min > jitter, "the durations are spread: {:?}", ttls);
+
+	// Short durations never drop to zero.
+	let session_store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_millis(500))
+			.with_ttl_jitter(hour);
+	for _ in 0..50 {
+		let duration = session_store.jittered_duration(None);
+		assert!(duration >= Duration::from_millis(500), "{:?}", duration);
+	}
+}
+
+#[test]
+fn duration_for_test() {
+	let anonymous = Duration::from_secs(30 * 60);
+	let authenticated = Duration::from_secs(14 * 24 * 3600);
+	let memory = Arc::new(MemoryStore::<String>::new());
+	let session_store: SessionStore<String> =
+		SessionStore::new(memory.clone(), "token", Duration::from_secs(3600))
+			.with_renew_cookie_threshold(0.5)
+			.with_duration_for(move |name| match name.as_str() {
+				"anon" => anonymous,
+				_ => authenticated,
+			});
+	let client = Client::tracked(example_rocket(session_store)).expect("Expected to build client");
+	let ttl = |token: &str| rocket::execute(memory.ttl(token)).unwrap().unwrap();
+	let close_to = |ttl: Duration, duration: Duration| {
+		ttl <= duration && ttl > duration - Duration::from_secs(5)
+	};
+
+	let res = client.post("/set_name/anon").dispatch();
+	let cookie = res.cookies().get("token").unwrap();
+	assert_eq!(cookie.max_age(), Some(rocket::time::Duration::minutes(30)));
+	assert!(close_to(ttl(cookie.value()), anonymous));
+
+	// Logging in regenerates the token, then sets the longer lived value.
+	client.post("/regenerate").dispatch();
+	let res = client.post("/set_name/user").dispatch();
+	let cookie = res.cookies().get("token").unwrap();
+	assert_eq!(cookie.max_age(), Some(rocket::time::Duration::days(14)));
+	assert!(close_to(ttl(cookie.value()), authenticated));
+
+	let res = client.post("/refresh").dispatch();
+	let refreshed: u64 = res.into_string().unwrap().parse().unwrap();
+	assert!(close_to(Duration::from_millis(refreshed), authenticated));
+
+	// Regenerating keeps the duration of the value.
+	client.post("/regenerate").dispatch();
+	let token = client.cookies().get("token").unwrap().value().to_owned();
+	assert!(close_to(ttl(&token), authenticated));
+}
+
+#[test]
+fn auto_create_test() {
+	let memory = Arc::new(MemoryStore::<String>::new());
+	let session_store: SessionStore<String> =
+		SessionStore::new(memory.clone(), "token", Duration::from_secs(3600))
+			.with_auto_create(|| "Guest".to_owned());
+	let client =
+		Client::untracked(example_rocket(session_store)).expect("Expected to build client");
+
+	let res = client.get("/get_name").dispatch();
+	let cookie = res.cookies().get("token").unwrap().clone();
+	assert_eq!(res.into_string(), Some("Guest".into()));
+	assert_eq!(rocket::execute(memory.get(cookie.value())).unwrap(), Some("Guest".into()));
+	let res = client.get("/get_name").cookie(cookie).dispatch();
+	assert_eq!(res.into_string(), Some("Guest".into()));
+	assert_eq!(rocket::execute(memory.count()).unwrap(), 1);
+
+	// Setting the value without reading it first stores only that value.
+	let res = client.post("/set_name/Alice").dispatch();
+	let cookie = res.cookies().get("token").unwrap().clone();
+	let res = client.get("/get_name").cookie(cookie).dispatch();
+	assert_eq!(res.into_string(), Some("Alice".into()));
+	assert_eq!(rocket::execute(memory.count()).unwrap(), 2);
+}
+
+#[test]
+fn touch_debounce_test() {
+	let store = Arc::new(CountingStore::default());
+	let duration = Duration::from_millis(400);
+	let debounce = Duration::from_millis(150);
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", duration).with_touch_debounce(debounce);
+		Client::tracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+	let touches = || store.touches.swap(0, Ordering::SeqCst);
+
+	client.post("/set_name/TestingName").dispatch();
+	// Requests keep touching the session for longer than its duration.
+	for _ in 0..50 {
+		let res = client.post("/refresh").dispatch();
+		assert_eq!(res.status(), Status::Ok);
+		let ttl: u64 = res.into_string().unwrap().parse().unwrap();
+		assert!(ttl > (duration - debounce).as_millis() as u64, "{}ms", ttl);
+		sleep(Duration::from_millis(15));
+	}
+	let touched = touches();
+	assert!((2..=12).contains(&touched), "touched {} times", touched);
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string(), Some("TestingName".into()));
+
+	// Removing the session forgets when it was renewed.
+	client.post("/remove_name").dispatch();
+	let res = client.post("/refresh").dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+	assert_eq!(touches(), 1);
+}
+
+#[test]
+fn touch_debounce_jitter_test() {
+	let store = Arc::new(MemoryStore::<String>::new());
+	let hour = Duration::from_secs(3600);
+	let client: Client = {
+		let session_store: SessionStore<String> = SessionStore::new(store.clone(), "token", hour)
+			.with_ttl_jitter(hour / 2)
+			.with_touch_debounce(Duration::from_secs(60));
+		Client::tracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	client.post("/set_name/TestingName").dispatch();
+	let token = client.cookies().get("token").unwrap().value().to_owned();
+	let (_, stored) = rocket::execute(store.get_with_expiry(&token))
+		.unwrap()
+		.unwrap();
+
+	// The skipped touch counts down from the jittered duration applied.
+	let res = client.post("/refresh").dispatch();
+	let skipped: u64 = res.into_string().unwrap().parse().unwrap();
+	let stored = stored.as_millis() as u64;
+	assert!(
+		skipped <= stored + 1000 && stored <= skipped + 1000,
+		"{}ms left in the store, {}ms returned",
+		stored,
+		skipped
+	);
+}
+
+#[get("/no_session")]
+fn no_session() -> &'static str {
+	"no session"
+}
+
+#[test]
+fn fairing_store_calls_test() {
+	let store = Arc::new(CountingStore::default());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store).mount("/", routes![get_twice, no_session]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let calls = || store.calls.swap(0, Ordering::SeqCst);
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert!(res1.headers().get_one("Set-Cookie").is_some());
+	assert_eq!(calls(), 1, "only the handler calls the store");
+
+	let res2 = client.get("/get_twice").dispatch();
+	assert_eq!(res2.into_string(), Some("TestingName".into()));
+	assert_eq!(calls(), 1, "only the handler reads the value");
+
+	let res3 = client.get("/no_session").dispatch();
+	assert_eq!(res3.headers().get_one("Set-Cookie"), None);
+	assert_eq!(
+		calls(),
+		0,
+		"requests without a session guard are left alone"
+	);
+}
+
+#[get("/read_dirty")]
+async fn read_dirty(session: Session<'_, String>) -> SessionResult<String> {
+	session.get().await?;
+	Ok(session.is_dirty().to_string())
+}
+
+#[post("/set_dirty/<name>")]
+async fn set_dirty(
+	name: String,
+	first: Session<'_, String>,
+	second: Session<'_, String>,
+) -> SessionResult<String> {
+	let before = second.is_dirty();
+	first.set(name).await?;
+	Ok(format!("{} {}", before, second.is_dirty()))
+}
+
+#[test]
+fn dirty_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build()
+			.attach(session_store.fairing())
+			.mount("/", routes![read_dirty, set_dirty]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	assert_eq!(
+		client
+			.post("/set_dirty/TestingName")
+			.dispatch()
+			.into_string(),
+		Some("false true".into())
+	);
+	assert_eq!(
+		client.get("/read_dirty").dispatch().into_string(),
+		Some("false".into())
+	);
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let metrics = session_store.metrics_fairing("/metrics");
+		let rocket = example_rocket(session_store).attach(metrics);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	client.get("/get_name").dispatch();
+	client.post("/set_name/TestingName").dispatch();
+	client.get("/get_name").dispatch();
+	client.post("/remove_name").dispatch();
+
+	let res = client.get("/metrics").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let body = res.into_string().unwrap();
+	for line in [
+		"session_store_get_total{result=\"hit\"} 1",
+		"session_store_get_total{result=\"miss\"} 1",
+		"session_store_set_total 1",
+		"session_store_remove_total 1",
+		"session_store_active_sessions 0",
+		"session_store_operation_duration_seconds_count{op=\"get\"} 2",
+	] {
+		assert!(
+			body.lines().any(|l| l == line),
+			"missing {} in\n{}",
+			line,
+			body
+		);
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, rocket::serde::Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Account {
+	name: String,
+	role: String,
+}
+
+fn upgrade_account(version: u32, data: Value) -> Option<Account> {
+	match version {
+		1 => Some(Account {
+			name: serde_json::from_value(data).ok()?,
+			role: "user".into(),
+		}),
+		_ => None,
+	}
+}
+
+async fn stored(raw: &MemoryRawStore, id: &str) -> Value {
+	let bytes = raw.get(id).await.unwrap().unwrap();
+	serde_json::from_slice(&bytes).unwrap()
+}
+
+#[test]
+fn versioned_codec_test() {
+	rocket::async_test(async {
+		let raw = Arc::new(MemoryRawStore::new());
+		let store = CodecStore::new(VersionedCodec::new(2, upgrade_account), raw.clone());
+
+		let v1 = json!({ "v": 1, "data": "Alice" });
+		let duration = Duration::from_secs(60);
+		raw.set("old", serde_json::to_vec(&v1).unwrap(), duration)
+			.await
+			.unwrap();
+		sleep(Duration::from_millis(1_100));
+
+		let account = store.get("old").await.unwrap();
+		let expected = Account {
+			name: "Alice".into(),
+			role: "user".into(),
+		};
+		assert_eq!(account, Some(expected));
+		assert_eq!(
+			stored(&raw, "old").await,
+			json!({ "v": 2, "data": { "name": "Alice", "role": "user" } }),
+			"the upgraded value is written back"
+		);
+		let ttl = raw.ttl("old").await.unwrap().unwrap();
+		assert!(
+			ttl < duration - Duration::from_secs(1),
+			"the expiry is kept"
+		);
+
+		// Values from before the envelope are version 0, which can't be
+		// upgraded here.
+		raw.set("legacy", b"\"Bob\"".to_vec(), duration)
+			.await
+			.unwrap();
+		assert!(store.get("legacy").await.is_err());
+
+		let current = Account {
+			name: "Carol".into(),
+			role: "admin".into(),
+		};
+		store.set("new", current, duration).await.unwrap();
+		assert_eq!(stored(&raw, "new").await["v"], json!(2));
+	});
+}
+
+#[test]
+fn invalid_cookie_name_test() {
+	let session_store: SessionStore<String> =
+		SessionStore::new(MemoryStore::new(), "my session", Duration::from_secs(3600));
+	match Client::tracked(example_rocket(session_store)) {
+		Ok(_) => panic!("Expected the rocket to fail to ignite"),
+		Err(e) => assert!(matches!(
+			e.kind(),
+			rocket::error::ErrorKind::FailedFairings(_)
+		)),
+	}
+}
+
+#[cfg(feature = "admin")]
+fn json_body(res: rocket::local::blocking::LocalResponse<'_>) -> Value {
+	serde_json::from_str(&res.into_string().unwrap()).unwrap()
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn admin_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store)
+			.mount("/admin", crate::admin::routes_with_values::<String>())
+			.mount("/redacted", crate::admin::routes::<String>());
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let sessions = client.rocket().state::<SessionStore<String>>().unwrap();
+	let alice = rocket::execute(seed_session(sessions, "Alice".into()));
+	let bob = rocket::execute(seed_session(sessions, "Bob".into()));
+	let mut ids = vec![alice.value().to_owned(), bob.value().to_owned()];
+	ids.sort();
+
+	let res = client.get("/admin/sessions").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let body: Value = json_body(res);
+	assert_eq!(body["sessions"], json!(ids));
+	assert_eq!(body["total"], json!(2));
+
+	let res = client.get("/admin/sessions?offset=1&limit=5").dispatch();
+	let body: Value = json_body(res);
+	assert_eq!(body["sessions"], json!([ids[1]]));
+	assert_eq!(body["limit"], json!(5));
+
+	let res = client
+		.get(format!("/admin/sessions/{}", alice.value()))
+		.dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let body: Value = json_body(res);
+	assert_eq!(body["id"], json!(alice.value()));
+	assert_eq!(body["value"], json!("Alice"));
+	assert!(body["ttl_secs"].as_u64().unwrap() <= 3600);
+	assert!(body["created_at"].is_u64());
+
+	let res = client
+		.get(format!("/redacted/sessions/{}", alice.value()))
+		.dispatch();
+	let body: Value = json_body(res);
+	assert_eq!(body["value"], json!("[redacted]"));
+
+	let res = client.get("/admin/sessions/missing").dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+
+	let res = client
+		.delete(format!("/admin/sessions/{}", alice.value()))
+		.dispatch();
+	assert_eq!(res.status(), Status::NoContent);
+	let res = client.get("/get_name").cookie(alice.clone()).dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+	let res = client
+		.delete(format!("/admin/sessions/{}", alice.value()))
+		.dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+
+	let body: Value = json_body(client.get("/admin/sessions").dispatch());
+	assert_eq!(body["total"], json!(1));
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_from_env_test() {
+	use ::redis::ConnectionAddr;
+
+	let addr =
+		|store: &RedisStore<String>| store.inner().client().unwrap().get_connection_info().addr.clone();
+
+	std::env::set_var("SESSION_TEST_REDIS_URL", "redis://10.0.0.1:6380/2");
+	let store: RedisStore<String> = RedisStore::from_env("SESSION_TEST_REDIS_URL").unwrap();
+	assert_eq!(addr(&store), ConnectionAddr::Tcp("10.0.0.1".into(), 6380));
+	assert_eq!(store.inner().client().unwrap().get_connection_info().redis.db, 2);
+
+	std::env::set_var("SESSION_TEST_REDIS_INVALID", "http://10.0.0.1");
+	assert!(RedisStore::<String>::from_env("SESSION_TEST_REDIS_INVALID").is_err());
+	assert!(
+		RedisStore::<String>::from_env_or("SESSION_TEST_REDIS_INVALID", "redis://127.0.0.1")
+			.is_err()
+	);
+
+	assert!(RedisStore::<String>::from_env("SESSION_TEST_REDIS_UNSET").is_err());
+	let store: RedisStore<String> =
+		RedisStore::from_env_or("SESSION_TEST_REDIS_UNSET", "redis://127.0.0.1").unwrap();
+	assert_eq!(addr(&store), ConnectionAddr::Tcp("127.0.0.1".into(), 6379));
+}
+
+#[test]
+fn export_test() {
+	rocket::async_test(async {
+		let session_store: SessionStore<Account> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let account = Account {
+			name: "Alice".into(),
+			role: "admin".into(),
+		};
+		let cookie = seed_session(&session_store, account).await;
+		let id = cookie.value();
+		let store = &session_store.store;
+		store.associate_with("alice", id).await.unwrap();
+		store.associate_with("alice", "expired").await.unwrap();
+
+		let mut export = session_store.export(id).await.unwrap().unwrap();
+		let created_at = export["created_at"].take();
+		let expires_in = export["expires_in_secs"].take();
+		assert_eq!(
+			export,
+			json!({
+				"created_at": null,
+				"expires_in_secs": null,
+				"id": id,
+				"value": {"name": "Alice", "role": "admin"},
+			})
+		);
+		let age = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs()
+			- created_at.as_u64().unwrap();
+		assert!(age <= 1);
+		assert!((3599..=3600).contains(&expires_in.as_u64().unwrap()));
+
+		// Exporting doesn't extend the session.
+		sleep(Duration::from_millis(1_100));
+		let export = session_store.export(id).await.unwrap().unwrap();
+		assert!(export["expires_in_secs"].as_u64().unwrap() < 3600);
+
+		let exports = session_store.export_all_for("alice").await.unwrap();
+		assert_eq!(exports.len(), 1);
+		assert_eq!(exports[0]["id"], json!(id));
+		assert_eq!(session_store.export("missing").await.unwrap(), None);
+	});
+}
+
+/// A store whose index of user sessions still lists the sessions removed
+/// since, as when they expire between listing and fetching them.
+#[derive(Default)]
+struct StaleIndexStore {
+	inner: MemoryStore<String>,
+	removed: Mutex<Vec<String>>,
+}
+
+#[rocket::async_trait]
+impl Store for StaleIndexStore {
+	type Value = String;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<String>> {
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: String, duration: Duration) -> SessionResult<()> {
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.removed.lock().unwrap().push(id.to_owned());
+		self.inner.remove(id).await
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		let mut ids = self.inner.list_sessions_for(user_key).await?;
+		ids.extend(self.removed.lock().unwrap().iter().cloned());
+		Ok(ids)
+	}
+}
+
+#[test]
+fn get_all_for_user_test() {
+	let session_store: SessionStore<String> = SessionStore::new(
+		StaleIndexStore::default(),
+		"token",
+		Duration::from_secs(3600),
+	);
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		let store = &session_store.store;
+		for (id, user, value) in [
+			("laptop", "alice", "Alice"),
+			("phone", "alice", "Alice on the phone"),
+			("stolen", "alice", "Alice"),
+			("desktop", "bob", "Bob"),
+		] {
+			store.set(id, value.into(), hour).await.unwrap();
+			store.associate_with(user, id).await.unwrap();
+		}
+		store.remove("stolen").await.unwrap();
+
+		let sessions = session_store.get_all_for_user("alice").await.unwrap();
+		assert_eq!(
+			sessions,
+			[
+				("laptop".to_owned(), "Alice".to_owned()),
+				("phone".to_owned(), "Alice on the phone".to_owned()),
+			]
+		);
+		let sessions = session_store.get_all_for_user("bob").await.unwrap();
+		assert_eq!(sessions, [("desktop".to_owned(), "Bob".to_owned())]);
+		assert!(session_store.get_all_for_user("carol").await.unwrap().is_empty());
+	});
+}
+
+#[test]
+fn migration_test() {
+	rocket::async_test(async {
+		let src: MemoryStore<String> = MemoryStore::new();
+		let dst: MemoryStore<String> = MemoryStore::new();
+		let hour = Duration::from_secs(3600);
+		src.set("alice", "Alice".into(), hour).await.unwrap();
+		src.set("bob", "Bob".into(), hour).await.unwrap();
+		src.set("expired", "Carol".into(), Duration::from_millis(100))
+			.await
+			.unwrap();
+		sleep(Duration::from_millis(200));
+
+		let migrated = crate::migration::export_to(&src, &dst).await.unwrap();
+		assert_eq!(migrated, 2);
+		assert_eq!(dst.get("alice").await.unwrap(), Some("Alice".into()));
+		assert_eq!(dst.get("bob").await.unwrap(), Some("Bob".into()));
+		assert_eq!(dst.get("expired").await.unwrap(), None);
+		assert!(dst.ttl("alice").await.unwrap().unwrap() <= hour);
+		assert_eq!(src.session_count().await, 2, "the source is left as is");
+	});
+}
+
+#[test]
+fn memory_snapshot_test() {
+	#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+	#[serde(crate = "rocket::serde")]
+	struct User {
+		name: String,
+		admin: bool,
+	}
+
+	rocket::async_test(async {
+		let hour = Duration::from_secs(3600);
+		let alice = User {
+			name: "Alice".into(),
+			admin: true,
+		};
+		let snapshot = {
+			let store: SerializingMemoryStore<User> = SerializingMemoryStore::new();
+			store.set("alice", alice.clone(), hour).await.unwrap();
+			store.set("permanent", alice.clone(), NO_EXPIRY).await.unwrap();
+			store.set("expired", alice.clone(), Duration::from_millis(100))
+				.await
+				.unwrap();
+			store.associate_with("alice", "alice").await.unwrap();
+			sleep(Duration::from_millis(200));
+			let snapshot = store.snapshot().await.unwrap();
+			// The snapshot survives being written out and read back.
+			serde_json::to_string(&snapshot).unwrap()
+		};
+
+		let snapshot: Value = serde_json::from_str(&snapshot).unwrap();
+		let store: SerializingMemoryStore<User> =
+			SerializingMemoryStore::from_snapshot(snapshot.clone()).unwrap();
+		assert_eq!(store.get("alice").await.unwrap(), Some(alice.clone()));
+		assert_eq!(store.get("expired").await.unwrap(), None);
+		assert!(store.ttl("alice").await.unwrap().unwrap() <= hour);
+		assert_eq!(store.ttl("permanent").await.unwrap(), Some(NO_EXPIRY));
+		assert!(store.created_at("alice").await.unwrap().is_some());
+		assert_eq!(
+			store.list_sessions_for("alice").await.unwrap(),
+			vec!["alice".to_owned()]
+		);
+
+		// The snapshot doesn't depend on the value type of the store.
+		let loose: MemoryStore<Value> = MemoryStore::from_snapshot(snapshot.clone()).unwrap();
+		assert_eq!(
+			loose.get("alice").await.unwrap(),
+			Some(json!({"name": "Alice", "admin": true}))
+		);
+		assert!(MemoryStore::<u64>::from_snapshot(snapshot).is_err());
+	});
+}
+
+#[get("/bound")]
+async fn get_bound(session: BoundSession<'_, String>) -> SessionResult<Option<String>> {
+	session.get().await
+}
+
+#[post("/bound/<name>")]
+async fn set_bound(name: String, session: BoundSession<'_, String>) -> SessionResult<()> {
+	session.set(name).await
+}
+
+#[get("/bearer")]
+async fn get_bearer(session: BearerSession<'_, String>) -> SessionResult<Option<String>> {
+	session.get_opt().await
+}
+
+#[post("/bearer/<name>")]
+async fn set_bearer(name: String, session: BearerSession<'_, String>) -> SessionResult<()> {
+	session.set(name).await
+}
+
+#[post("/spawn/<name>")]
+async fn set_name_spawned(name: String, session: Session<'_, String>) -> SessionResult<()> {
+	let session = session.to_owned();
+	rocket::tokio::spawn(async move { session.set(name).await })
+		.await
+		.map_err(|_| SessionError::Other)?
+}
+
+#[test]
+fn owned_session_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store).mount("/", routes![set_name_spawned]);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	// The session set by the task is sent to the client by the request.
+	let res = client.post("/spawn/Alice").dispatch();
+	assert!(res.cookies().get("token").is_some());
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string().unwrap(), "Alice");
+
+	client.post("/spawn/Bob").dispatch();
+	let res = client.get("/get_name").dispatch();
+	assert_eq!(res.into_string().unwrap(), "Bob");
+}
+
+#[test]
+fn prefetch_test() {
+	let token = "PrefetchedTokenOfAlice00";
+	let store = Arc::new(CountingStore::default());
+	let session_store: SessionStore<String> =
+		SessionStore::new(store.clone(), "token", Duration::from_secs(3600));
+	let gets = || store.gets.swap(0, Ordering::SeqCst);
+	rocket::async_test(async {
+		store
+			.inner
+			.set(token, "Alice".into(), Duration::from_secs(3600))
+			.await
+			.unwrap();
+
+		// Rendering the layout and the page each read the session.
+		let render = |value: Option<String>| assert_eq!(value.as_deref(), Some("Alice"));
+		render(session_store.store.get(token).await.unwrap());
+		render(session_store.store.get(token).await.unwrap());
+		assert_eq!(gets(), 2);
+
+		// Prefetched as soon as the headers are in, the session is read once.
+		let prefetch = rocket::tokio::spawn(Session::prefetch(&session_store, token));
+		let session = prefetch.await.unwrap().unwrap();
+		render(session.get().await.unwrap());
+		render(session.get().await.unwrap());
+		assert_eq!(gets(), 1);
+		assert_eq!(session.id().token(), token);
+
+		// Changes go to the store and aren't hidden by the prefetched value.
+		session.set("Bob".into()).await.unwrap();
+		assert_eq!(session.get().await.unwrap(), Some("Bob".into()));
+		assert_eq!(store.inner.get(token).await.unwrap(), Some("Bob".into()));
+
+		// Unusable tokens get a new session.
+		let oversized = "x".repeat(1024);
+		let session = Session::prefetch(&session_store, &oversized).await.unwrap();
+		assert_ne!(session.id().token(), oversized);
+		assert_eq!(session.get().await.unwrap(), None);
+
+		let mapped = session_store
+			.clone()
+			.with_key_mapper(|_, key| format!("tenant:{}", key));
+		assert!(Session::prefetch(&mapped, token).await.is_err());
+	});
+}
+
+#[cfg(feature = "auth")]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Visit {
+	user: Option<String>,
+	cart: Vec<String>,
+}
+
+#[cfg(feature = "auth")]
+impl crate::auth::UserSession for Visit {
+	type UserId = String;
+
+	fn user_id(&self) -> Option<String> {
+		self.user.clone()
+	}
+
+	fn with_user_id(self, user_id: String) -> Self {
+		Self {
+			user: Some(user_id),
+			..self
+		}
+	}
+}
+
+#[cfg(feature = "auth")]
+#[post("/auth/cart/<item>")]
+async fn add_to_cart(item: String, session: Session<'_, Visit>) -> SessionResult<()> {
+	let mut visit = session.get_opt().await?.unwrap_or_default();
+	visit.cart.push(item);
+	session.set(visit).await
+}
+
+#[cfg(feature = "auth")]
+#[post("/auth/login/<user>")]
+async fn auth_login(user: String, session: Session<'_, Visit>) -> SessionResult<()> {
+	use crate::auth::SessionAuth;
+	session.login(user).await
+}
+
+#[cfg(feature = "auth")]
+#[post("/auth/logout")]
+async fn auth_logout(session: Session<'_, Visit>) -> SessionResult<()> {
+	use crate::auth::SessionAuth;
+	session.logout().await
+}
+
+#[cfg(feature = "auth")]
+#[get("/auth/me")]
+async fn auth_me(session: Session<'_, Visit>) -> Result<String, crate::auth::Unauthorized> {
+	use crate::auth::SessionAuth;
+	let user = session.current_user().await?;
+	let cart = session
+		.get_opt()
+		.await
+		.ok()
+		.flatten()
+		.unwrap_or_default()
+		.cart;
+	Ok(format!("{} {}", user, cart.join(",")))
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn auth_login_logout_test() {
+	let recording = Arc::new(RecordingStore::new(MemoryStore::<Visit>::new()));
+	let client: Client = {
+		let session_store: SessionStore<Visit> =
+			SessionStore::new(recording.clone(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build().attach(session_store.fairing()).mount(
+			"/",
+			routes![add_to_cart, auth_login, auth_logout, auth_me],
+		);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+
+	client.post("/auth/cart/book").dispatch();
+	let anonymous = client.cookies().get("token").unwrap().value().to_owned();
+	assert_eq!(client.get("/auth/me").dispatch().status(), Status::Unauthorized);
+
+	let res = client.post("/auth/login/alice").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let logged_in = client.cookies().get("token").unwrap().value().to_owned();
+	assert_ne!(logged_in, anonymous);
+	let res = client.get("/auth/me").dispatch();
+	assert_eq!(res.into_string(), Some("alice book".into()));
+
+	// The user was never stored under the token sent before logging in, the
+	// token being regenerated first.
+	let records = recording.records();
+	let login = records.iter().position(|record| match &record.op {
+		RecordedOp::Set { value, .. } => value["user"] == "alice",
+		_ => false,
+	});
+	let regenerated = records.iter().position(|record| match &record.op {
+		RecordedOp::Rename { old_id, .. } => *old_id == anonymous,
+		_ => false,
+	});
+	assert!(regenerated.unwrap() < login.unwrap());
+	assert!(records.iter().all(|record| match &record.op {
+		RecordedOp::Set { id, value, .. } => *id != anonymous || value["user"].is_null(),
+		_ => true,
+	}));
+
+	let res = client.post("/auth/logout").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	let removal = res.cookies().get("token").unwrap();
+	assert_eq!(removal.value(), "");
+	assert!(client.cookies().get("token").is_none());
+	assert_eq!(client.get("/auth/me").dispatch().status(), Status::Unauthorized);
+	assert_eq!(rocket::execute(recording.inner().count()).unwrap(), 0);
+	for token in [anonymous, logged_in] {
+		let res = client.get("/auth/me").cookie(Cookie::new("token", token)).dispatch();
+		assert_eq!(res.status(), Status::Unauthorized);
+	}
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AppSession {
+	user: Option<String>,
+	theme: String,
+}
+
+struct CurrentUser(String);
+
+impl FromSession<AppSession> for CurrentUser {
+	fn from_session(session: &AppSession) -> Option<Self> {
+		session.user.clone().map(CurrentUser)
+	}
+}
+
+#[post("/extract/<user>")]
+async fn extract_login(user: String, session: Session<'_, AppSession>) -> SessionResult<()> {
+	let session_value = AppSession {
+		user: Some(user),
+		theme: "dark".into(),
+	};
+	session.set(session_value).await
+}
+
+#[post("/extract")]
+async fn extract_anonymous(session: Session<'_, AppSession>) -> SessionResult<()> {
+	session.set(AppSession::default()).await
+}
+
+#[get("/extract")]
+async fn extract_dashboard(
+	user: SessionExtract<CurrentUser, AppSession>,
+	session: Session<'_, AppSession>,
+) -> SessionResult<String> {
+	let theme = session.get_opt().await?.unwrap_or_default().theme;
+	Ok(format!("{} {}", user.0, theme))
+}
+
+#[test]
+fn session_extract_test() {
+	let recording = Arc::new(RecordingStore::new(MemoryStore::<AppSession>::new()));
+	let client: Client = {
+		let session_store: SessionStore<AppSession> =
+			SessionStore::new(recording.clone(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build().attach(session_store.fairing()).mount(
+			"/",
+			routes![extract_login, extract_anonymous, extract_dashboard],
+		);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let gets = || {
+		recording
+			.records()
+			.iter()
+			.filter(|record| matches!(record.op, RecordedOp::Get { .. }))
+			.count()
+	};
+
+	assert_eq!(client.get("/extract").dispatch().status(), Status::Unauthorized);
+	client.post("/extract").dispatch();
+	assert_eq!(client.get("/extract").dispatch().status(), Status::Unauthorized);
+
+	client.post("/extract/alice").dispatch();
+	recording.clear();
+	let res = client.get("/extract").dispatch();
+	assert_eq!(res.status(), Status::Ok);
+	assert_eq!(res.into_string(), Some("alice dark".into()));
+	assert_eq!(gets(), 1, "the guards share the value read from the store");
+}
+
+type Scopes = std::collections::HashMap<String, String>;
+
+#[post("/scope/set/<name>/<value>")]
+async fn set_scope(name: &str, value: String, session: Session<'_, Scopes>) -> SessionResult<()> {
+	session.scope(name).set(value).await
+}
+
+#[get("/scope/get/<name>")]
+async fn get_scope(name: &str, session: Session<'_, Scopes>) -> SessionResult<Option<String>> {
+	session.scope(name).get().await
+}
+
+#[post("/scope/remove/<name>")]
+async fn remove_scope(name: &str, session: Session<'_, Scopes>) -> SessionResult<String> {
+	Ok(session.scope(name).remove().await?.to_string())
+}
+
+#[post("/scope/clear")]
+async fn clear_scopes(session: Session<'_, Scopes>) -> SessionResult<()> {
+	session.remove().await?;
+	Ok(())
+}
+
+#[post("/scope/both")]
+async fn set_both_scopes(session: Session<'_, Scopes>) -> SessionResult<String> {
+	let cart = session.scope("cart");
+	let prefs = session.scope("prefs");
+	cart.set("book".into()).await?;
+	prefs.set("dark".into()).await?;
+	cart.set("book,pen".into()).await?;
+	let cart = cart.get().await?.unwrap_or_default();
+	let prefs = prefs.get().await?.unwrap_or_default();
+	Ok(format!("{} {}", cart, prefs))
+}
+
+#[test]
+fn scoped_session_test() {
+	let client: Client = {
+		let session_store: SessionStore<Scopes> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build().attach(session_store.fairing()).mount(
+			"/",
+			routes![set_scope, get_scope, remove_scope, clear_scopes, set_both_scopes],
+		);
+		Client::tracked(rocket).expect("Expected to build client")
+	};
+	let get = |name: &str| client.get(format!("/scope/get/{}", name)).dispatch();
+
+	let res = client.post("/scope/both").dispatch();
+	assert_eq!(res.into_string(), Some("book,pen dark".into()));
+	assert_eq!(get("cart").into_string(), Some("book,pen".into()));
+
+	client.post("/scope/set/prefs/light").dispatch();
+	assert_eq!(get("prefs").into_string(), Some("light".into()));
+	assert_eq!(get("cart").into_string(), Some("book,pen".into()));
+
+	let res = client.post("/scope/remove/cart").dispatch();
+	assert_eq!(res.into_string(), Some("true".into()));
+	assert_eq!(get("cart").status(), Status::NotFound);
+	assert_eq!(get("prefs").into_string(), Some("light".into()));
+	let res = client.post("/scope/remove/cart").dispatch();
+	assert_eq!(res.into_string(), Some("false".into()));
+
+	client.post("/scope/set/cart/book").dispatch();
+	client.post("/scope/clear").dispatch();
+	assert_eq!(get("cart").status(), Status::NotFound);
+	assert_eq!(get("prefs").status(), Status::NotFound);
+}
+
+#[test]
+fn scope_name_test() {
+	let session_store: SessionStore<Scopes> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+	let client = Client::untracked(rocket::build().attach(session_store.fairing()))
+		.expect("Expected to build client");
+	let request = client.get("/");
+	let session = rocket::execute(Session::<Scopes>::of(request.inner())).unwrap();
+	assert_eq!(session.scope("shopping_cart-2").name(), "shopping_cart-2");
+	for name in ["", "cart.items", "cart:items", "panier🛒"] {
+		let scope = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+			session.scope(name);
+		}));
+		assert!(scope.is_err(), "{:?}", name);
+	}
+}
+
+#[test]
+fn bearer_session_test() {
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store).mount("/", routes![get_bearer, set_bearer]);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+	let bearer = |token: &str| Header::new("Authorization", format!("Bearer {}", token));
+
+	let res = client.post("/bearer/Alice").dispatch();
+	assert!(res.cookies().get("token").is_none());
+	let token = res.headers().get_one(TOKEN_HEADER).unwrap().to_owned();
+	let res = client.get("/bearer").header(bearer(&token)).dispatch();
+	assert_eq!(res.headers().get_one(TOKEN_HEADER), Some(token.as_str()));
+	assert_eq!(res.into_string().unwrap(), "Alice");
+
+	// The same session is reachable through the cookie.
+	let cookie = Cookie::new("token", token.clone());
+	let res = client.get("/get_name").cookie(cookie.clone()).dispatch();
+	assert_eq!(res.into_string().unwrap(), "Alice");
+	let res = client.get("/bearer").cookie(cookie).dispatch();
+	assert_eq!(res.into_string().unwrap(), "Alice");
+
+	// The bearer token takes precedence over the cookie.
+	let other = seed(&client, "Bob");
+	let res = client
+		.get("/bearer")
+		.header(bearer(&token))
+		.cookie(other.clone())
+		.dispatch();
+	assert_eq!(res.into_string().unwrap(), "Alice");
+	let res = client
+		.get("/get_name")
+		.header(bearer(&token))
+		.cookie(other)
+		.dispatch();
+	assert_eq!(res.into_string().unwrap(), "Bob");
+
+	let res = client.get("/bearer").header(bearer("bad,token")).dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+	assert_ne!(res.headers().get_one(TOKEN_HEADER), Some("bad,token"));
+}
+
+#[test]
+fn bound_session_test() {
+	let client: Client = {
+		let session_store: BoundSessionStore<String> =
+			BoundSessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build()
+			.attach(session_store.fairing())
+			.mount("/", routes![get_bound, set_bound]);
+		Client::untracked(rocket).expect("Expected to build client")
+	};
+	let firefox = Header::new("User-Agent", "Firefox");
+	let curl = Header::new("User-Agent", "curl");
+
+	let res = client
+		.post("/bound/Alice")
+		.header(firefox.clone())
+		.dispatch();
+	let cookie = res.cookies().get("token").unwrap().clone().into_owned();
+	let res = client
+		.get("/bound")
+		.header(firefox.clone())
+		.cookie(cookie.clone())
+		.dispatch();
+	assert_eq!(res.into_string().unwrap(), "Alice");
+
+	let res = client
+		.get("/bound")
+		.header(curl)
+		.cookie(cookie.clone())
+		.dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+	let new_token = res
+		.cookies()
+		.get("token")
+		.expect("a new session is started");
+	assert_ne!(new_token.value(), cookie.value());
+
+	// The session was dropped, even for the browser it was bound to.
+	let res = client
+		.get("/bound")
+		.header(firefox)
+		.cookie(cookie)
+		.dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+}
+
+#[test]
+fn key_mapper_test() {
+	let store = Arc::new(MemoryStore::<String>::new());
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(store.clone(), "token", Duration::from_secs(3600)).with_key_mapper(
+				|request, key| {
+					let host = request.headers().get_one("Host").unwrap_or_default();
+					format!("{}:{}", host, key)
+				},
+			);
+		Client::untracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+	let tenant_a = Header::new("Host", "a.example.com");
+	let tenant_b = Header::new("Host", "b.example.com");
+	let stored = |key: String| rocket::execute(store.get(&key)).unwrap();
+
+	let res = client
+		.post("/set_name/Alice")
+		.header(tenant_a.clone())
+		.dispatch();
+	let cookie = res.cookies().get("token").unwrap().clone().into_owned();
+	let token = cookie.value().to_owned();
+	assert_eq!(
+		stored(format!("a.example.com:{}", token)),
+		Some("Alice".into())
+	);
+	assert_eq!(
+		stored(token.clone()),
+		None,
+		"the cookie carries the raw token"
+	);
+
+	let res = client
+		.get("/get_name")
+		.header(tenant_b.clone())
+		.cookie(cookie.clone())
+		.dispatch();
+	assert_eq!(res.status(), Status::NotFound);
+	client
+		.post("/set_name/Bob")
+		.header(tenant_b)
+		.cookie(cookie.clone())
+		.dispatch();
+	assert_eq!(
+		stored(format!("b.example.com:{}", token)),
+		Some("Bob".into())
+	);
+	assert_eq!(
+		stored(format!("a.example.com:{}", token)),
+		Some("Alice".into())
+	);
+
+	let res = client
+		.post("/regenerate")
+		.header(tenant_a)
+		.cookie(cookie)
+		.dispatch();
+	let regenerated = res.cookies().get("token").unwrap().value().to_owned();
+	assert_ne!(regenerated, token);
+	assert_eq!(stored(format!("a.example.com:{}", token)), None);
+	assert_eq!(
+		stored(format!("a.example.com:{}", regenerated)),
+		Some("Alice".into())
+	);
+}
+
+/// A memory store buffering writes until it is drained.
+#[derive(Default)]
+struct BufferedStore {
+	inner: Arc<MemoryStore<String>>,
+	pending: Mutex<Vec<(String, String, Duration)>>,
+}
+
+#[rocket::async_trait]
+impl Store for BufferedStore {
+	type Value = String;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<String>> {
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: String, duration: Duration) -> SessionResult<()> {
+		let mut pending = self.pending.lock().unwrap();
+		pending.push((id.to_owned(), value, duration));
+		Ok(())
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(id).await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+		for (id, value, duration) in pending {
+			self.inner.set(&id, value, duration).await?;
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn drain_on_shutdown_test() {
+	let inner = Arc::new(MemoryStore::new());
+	let client: Client = {
+		let store = BufferedStore {
+			inner: inner.clone(),
+			..Default::default()
+		};
+		let session_store: SessionStore<String> =
+			SessionStore::new(store, "token", Duration::from_secs(3600));
+		Client::untracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	let token = {
+		let res = client.post("/set_name/TestingName").dispatch();
+		res.cookies().get("token").unwrap().value().to_owned()
+	};
+	assert_eq!(rocket::execute(inner.get(&token)).unwrap(), None);
+
+	client.terminate();
+	assert_eq!(
+		rocket::execute(inner.get(&token)).unwrap(),
+		Some("TestingName".into())
+	);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_bump_generation_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client).prefix("generation:".to_owned());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(store.set("session", "TestingName".into(), hour)).unwrap();
+	assert_eq!(store.bump_generation(), 1);
+	assert_eq!(
+		store.inner().current_prefix().as_deref(),
+		Some("generation:1:")
+	);
+	assert_eq!(rocket::execute(store.get("session")).unwrap(), None);
+
+	// The old session is left to expire.
+	let old = RedisRawStore::new(store.inner().client().unwrap().clone()).prefix("generation:".to_owned());
+	assert!(rocket::execute(old.get("session")).unwrap().is_some());
+
+	assert_eq!(store.bump_generation(), 2);
+	assert_eq!(
+		store.inner().current_prefix().as_deref(),
+		Some("generation:2:")
+	);
+	store.set_prefix("other:");
+	assert_eq!(store.inner().current_prefix().as_deref(), Some("other:"));
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_database_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let first: RedisStore<String> = RedisStore::new(client.clone())
+		.prefix("database:".to_owned())
+		.with_database(1)
+		.expect("Expected to select the database");
+	let default: RedisStore<String> = RedisStore::new(client).prefix("database:".to_owned());
+	let id = format!("session{}", rand::random::<u64>());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		first.set(&id, "TestingName".into(), hour).await.unwrap();
+		assert_eq!(first.get(&id).await.unwrap(), Some("TestingName".into()));
+		assert_eq!(default.get(&id).await.unwrap(), None);
+		first.remove(&id).await.unwrap();
+	});
+	assert_eq!(first.inner().client().unwrap().get_connection_info().redis.db, 1);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_database_out_of_range_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store = RedisStore::<String>::new(client).with_database(16);
+	assert_eq!(store.err(), Some(SessionError::Other));
+}
+
+#[cfg(feature = "redis-pool")]
+#[test]
+fn redis_pool_database_test() {
+	let pool = deadpool_redis::Config::from_url("redis://127.0.0.1/")
+		.create_pool(Some(deadpool_redis::Runtime::Tokio1))
+		.expect("Expected to create the pool");
+	let store = RedisStore::<String>::from_pool(&pool).with_database(1);
+	assert_eq!(
+		store.err(),
+		Some(SessionError::Other),
+		"the database of a pool is set in its URL"
+	);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_compare_and_swap_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client);
+	let id = format!("session{}", rand::random::<u64>());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		assert!(store.compare_and_swap(&id, None, "a".into(), hour).await.unwrap());
+		assert!(!store.compare_and_swap(&id, None, "b".into(), hour).await.unwrap());
+		assert!(store.compare_and_swap(&id, Some("a".into()), "b".into(), hour).await.unwrap());
+		assert!(!store.compare_and_swap(&id, Some("a".into()), "c".into(), hour).await.unwrap());
+		assert_eq!(store.get(&id).await.unwrap(), Some("b".into()));
+		assert!(store.ttl(&id).await.unwrap().unwrap() > Duration::from_secs(3590));
+		store.remove(&id).await.unwrap();
+	});
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_lock_test() {
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client).prefix("lock:".to_owned());
+	let id = format!("session{}", rand::random::<u64>());
+	let ttl = Duration::from_millis(200);
+
+	rocket::execute(async {
+		assert!(store.acquire_lock(&id, "first", ttl).await.unwrap());
+		assert!(store.acquire_lock(&id, "first", ttl).await.unwrap());
+		assert!(!store.acquire_lock(&id, "second", ttl).await.unwrap());
+		assert!(!store.release_lock(&id, "second").await.unwrap());
+		assert!(store.list_ids(0, 100).await.unwrap().is_empty());
+		assert!(store.release_lock(&id, "first").await.unwrap());
+		assert!(store.acquire_lock(&id, "second", ttl).await.unwrap());
+
+		// The lock expires with its holder still holding it.
+		rocket::tokio::time::sleep(Duration::from_millis(300)).await;
+		assert!(store.acquire_lock(&id, "first", ttl).await.unwrap());
+		assert!(!store.release_lock(&id, "second").await.unwrap());
+	});
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_connection_timeout_test() {
+	// The connection is accepted by the OS but the server never replies.
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	// The `SELECT` of the database 1 is sent while connecting, and the `GET`
+	// of the database 0 once connected.
+	for url in [
+		format!("redis://{}/1", address),
+		format!("redis://{}/", address),
+	] {
+		let client = RedisClient::open(url.as_str()).expect("Couldn't open redis");
+		let store: RedisStore<String> =
+			RedisStore::new(client).with_connection_timeout(Duration::from_millis(100));
+
+		let start = std::time::Instant::now();
+		let result = rocket::execute(store.get("session"));
+		assert_eq!(result, Err(SessionError::Timeout), "{}", url);
+		assert!(start.elapsed() < Duration::from_secs(1), "{}", url);
+	}
+}
+
+/// A redis server recording the commands it gets, replying as if it held
+/// no session. Scripts are run by hash once they were loaded.
+#[cfg(feature = "redis")]
+struct MockRedis {
+	url: String,
+	commands: Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg(feature = "redis")]
+impl MockRedis {
+	fn start() -> Self {
+		use std::io::{
+			BufRead,
+			BufReader,
+			Read,
+			Write,
+		};
+
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("redis://{}/", listener.local_addr().unwrap());
+		let commands = Arc::new(Mutex::new(Vec::new()));
+		let recorded = commands.clone();
+		let loaded = Arc::new(AtomicBool::new(false));
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let recorded = recorded.clone();
+				let loaded = loaded.clone();
+				std::thread::spawn(move || {
+					let mut reader = BufReader::new(stream.try_clone().unwrap());
+					let read_line = |reader: &mut BufReader<_>, line: &mut String| {
+						line.clear();
+						reader.read_line(line).map(|read| read > 0).unwrap_or(false)
+					};
+					let mut line = String::new();
+					while read_line(&mut reader, &mut line) {
+						let args: usize = line.trim()[1..].parse().unwrap();
+						let mut command = Vec::new();
+						for _ in 0..args {
+							read_line(&mut reader, &mut line);
+							let len: usize = line.trim()[1..].parse().unwrap();
+							let mut arg = vec![0; len + 2];
+							reader.read_exact(&mut arg).unwrap();
+							command.push(String::from_utf8_lossy(&arg[..len]).into_owned());
+						}
+						let reply = match command[0].as_str() {
+							"GET" => "$-1\r\n",
+							"EVALSHA" if !loaded.load(Ordering::SeqCst) => {
+								"-NOSCRIPT No matching script.\r\n"
+							}
+							"PTTL" | "EVALSHA" => ":-2\r\n",
+							"DEL" | "EXISTS" => ":0\r\n",
+							"SCRIPT" => {
+								loaded.store(true, Ordering::SeqCst);
+								"+OK\r\n"
+							}
+							_ => "+OK\r\n",
+						};
+						recorded.lock().unwrap().push(command.remove(0));
+						stream.write_all(reply.as_bytes()).unwrap();
+					}
+				});
+			}
+		});
+		Self { url, commands }
+	}
+
+	fn client(&self) -> RedisClient {
+		RedisClient::open(self.url.as_str()).expect("Couldn't open redis")
+	}
+
+	fn take(&self) -> Vec<String> {
+		std::mem::take(&mut self.commands.lock().unwrap())
+	}
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_script_cache_test() {
+	let server = MockRedis::start();
+	let store: RedisStore<String> = RedisStore::new(server.client());
+	let second = Duration::from_secs(1);
+
+	rocket::execute(async {
+		for _ in 0..3 {
+			store
+				.acquire_lock("session", "holder", second)
+				.await
+				.unwrap();
+		}
+	});
+	// The script is loaded once, then only sent by its hash.
+	assert_eq!(
+		server.take(),
+		["EVALSHA", "SCRIPT", "EVALSHA", "EVALSHA", "EVALSHA"]
+	);
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_replica_test() {
+	let master = MockRedis::start();
+	let replicas = [MockRedis::start(), MockRedis::start()];
+	let store: RedisStore<String> = RedisStore::new(master.client())
+		.with_replica(replicas[0].client())
+		.with_replica(replicas[1].client());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		store.set("session", "TestingName".into(), hour).await.unwrap();
+		store.touch("session", hour).await.unwrap();
+		store.contains("session").await.unwrap();
+		store.remove("session").await.unwrap();
+		assert_eq!(
+			master.take(),
+			["SET", "SET", "EXPIRE", "EVALSHA", "SCRIPT", "EVALSHA", "EXISTS", "DEL", "DEL"]
+		);
+		assert!(replicas.iter().all(|replica| replica.take().is_empty()));
+
+		// Reads go to the replicas in turn.
+		assert_eq!(store.get("session").await.unwrap(), None);
+		assert_eq!(store.ttl("session").await.unwrap(), None);
+		assert_eq!(store.get("session").await.unwrap(), None);
+		assert!(master.take().is_empty());
+		assert_eq!(replicas[0].take(), ["GET", "GET"]);
+		assert_eq!(replicas[1].take(), ["PTTL"]);
+	});
+}
+
+#[cfg(feature = "redis")]
+#[test]
+fn redis_audit_stream_test() {
+	use crate::redis::AuditOp;
+
+	let client = RedisClient::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store: RedisStore<String> = RedisStore::new(client)
+		.prefix("audit:".to_owned())
+		.with_audit_stream("session:audit".to_owned());
+	let id = format!("session{}", rand::random::<u64>());
+	let hour = Duration::from_secs(3600);
+
+	rocket::execute(async {
+		store.set(&id, "TestingName".into(), hour).await.unwrap();
+		store.get(&id).await.unwrap();
+		store.touch(&id, hour).await.unwrap();
+		store.remove(&id).await.unwrap();
+		store.get("other").await.unwrap();
+	});
+	let entries = rocket::execute(store.read_audit(&id)).unwrap();
+	let ops: Vec<AuditOp> = entries.iter().map(|entry| entry.op).collect();
+	assert_eq!(
+		ops,
+		[AuditOp::Set, AuditOp::Get, AuditOp::Touch, AuditOp::Remove]
+	);
+	assert!(entries.iter().all(|entry| entry.session_id == id));
+	assert!(entries[0].at <= SystemTime::now());
+}
+
+#[cfg(feature = "redis-pool")]
+#[test]
+fn redis_pool_test() {
+	use deadpool_redis::{
+		Config,
+		Runtime,
+	};
+
+	let hour = Duration::from_secs(3600);
+	rocket::execute(async {
+		let pool = Config::from_url("redis://127.0.0.1/")
+			.create_pool(Some(Runtime::Tokio1))
+			.unwrap();
+		let store: RedisStore<String> = RedisStore::from_pool(&pool).prefix("pool:".to_owned());
+		assert!(store.inner().client().is_none());
+
+		store.set("pooled", "TestingName".into(), hour).await.unwrap();
+		assert_eq!(store.get("pooled").await.unwrap(), Some("TestingName".into()));
+		assert!(store.touch("pooled", hour).await.unwrap().is_some());
+		let ids = ["pooled".to_owned(), "missing".to_owned()];
+		assert_eq!(
+			store.get_many(&ids).await.unwrap(),
+			[Some("TestingName".into()), None]
+		);
+		assert!(store.remove("pooled").await.unwrap());
+		assert_eq!(store.get("pooled").await.unwrap(), None);
+		assert!(store.ping().await.is_ok());
+	});
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn bincode_codec_test() {
+	use crate::codec::{
+		BincodeCodec,
+		SessionCodec,
+	};
+
+	#[derive(Debug, PartialEq, bincode::Encode, bincode::Decode, Serialize, Deserialize)]
+	#[serde(crate = "rocket::serde")]
+	struct UserSession {
+		user_id: u64,
+		role: u8,
+	}
+
+	let session = UserSession {
+		user_id: 1_234_567,
+		role: 2,
+	};
+	let bytes = BincodeCodec.encode(&session).unwrap();
+	let json = JsonCodec.encode(&session).unwrap();
+	assert!(bytes.len() * 4 < json.len(), "{} bytes", bytes.len());
+	assert_eq!(BincodeCodec.decode(&bytes).ok(), Some(session));
+	assert!(SessionCodec::<UserSession>::decode(&BincodeCodec, &json[..1]).is_err());
+}
+
+#[cfg(feature = "rmp-serde")]
+#[test]
+fn msgpack_codec_test() {
+	use crate::codec::{
+		MessagePackCodec,
+		SessionCodec,
+	};
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	#[serde(crate = "rocket::serde")]
+	struct UserSession {
+		user_id: u64,
+		role: u8,
+		name: String,
+	}
+
+	let session = UserSession {
+		user_id: 1_234_567,
+		role: 2,
+		name: "Alice".into(),
+	};
+	let bytes = MessagePackCodec.encode(&session).unwrap();
+	let json = JsonCodec.encode(&session).unwrap();
+	assert!(bytes.len() < json.len(), "{} bytes", bytes.len());
+	assert_eq!(MessagePackCodec.decode(&bytes).ok(), Some(session));
+}
+
+#[test]
+fn clear_expired_test() {
+	rocket::async_test(async {
+		let store = MemoryStore::<String>::new();
+		let hour = Duration::from_secs(3600);
+		let instant = Duration::from_millis(10);
+		for id in ["expired-1", "expired-2", "expired-3"] {
+			store.set(id, "TestingName".into(), instant).await.unwrap();
+		}
+		store.set("live", "TestingName".into(), hour).await.unwrap();
+		rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+
+		assert_eq!(store.clear_expired(2).await.unwrap(), 2);
+		assert_eq!(store.clear_expired(10).await.unwrap(), 1);
+		assert_eq!(store.clear_expired(10).await.unwrap(), 0);
+		assert_eq!(store.get("live").await.unwrap(), Some("TestingName".into()));
+	});
+}
+
+#[test]
+fn cleanup_task_test() {
+	rocket::async_test(async {
+		let memory = Arc::new(MemoryStore::<String>::new());
+		let hour = Duration::from_secs(3600);
+		let instant = Duration::from_millis(10);
+		memory
+			.set("expired", "TestingName".into(), instant)
+			.await
+			.unwrap();
+		memory
+			.set("live", "TestingName".into(), hour)
+			.await
+			.unwrap();
+		let session_store = SessionStore::new(memory.clone(), "token", hour)
+			.with_cleanup_interval(Duration::from_millis(20));
+		let rocket = rocket::build().attach(session_store.fairing());
+		let client = rocket::local::asynchronous::Client::tracked(rocket)
+			.await
+			.expect("Expected to build client");
+
+		rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+		assert_eq!(memory.clear_expired(10).await.unwrap(), 0);
+		assert_eq!(
+			memory.get("live").await.unwrap(),
+			Some("TestingName".into())
+		);
+		client.terminate().await;
+	});
+}
+
+#[post("/locked/<item>?<locked>")]
+async fn append_locked(item: &str, locked: bool, session: Session<'_, String>) -> SessionResult<()> {
+	let lock = match locked {
+		true => Some(session.lock(Duration::from_secs(5)).await?),
+		false => None,
+	};
+	let items = session.get_opt().await?.unwrap_or_default();
+	rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+	session.set(format!("{}{}", items, item)).await?;
+	if let Some(lock) = lock {
+		assert!(lock.release().await?);
+	}
+	Ok(())
+}
+
+#[test]
+fn session_lock_test() {
+	rocket::async_test(async {
+		let session_store: SessionStore<String> =
+			SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = example_rocket(session_store).mount("/", routes![append_locked]);
+		let client = rocket::local::asynchronous::Client::untracked(rocket)
+			.await
+			.expect("Expected to build client");
+		let res = client.post("/set_name/x").dispatch().await;
+		let cookie = res.cookies().get("token").unwrap().clone();
+		let append = |item: &'static str, locked: bool| {
+			client
+				.post(format!("/locked/{}?locked={}", item, locked))
+				.cookie(cookie.clone())
+				.dispatch()
+		};
+		let get = || client.get("/get_name").cookie(cookie.clone()).dispatch();
+
+		// Without the lock, both requests read the same value and the second
+		// write erases the first.
+		rocket::tokio::join!(append("a", false), append("b", false));
+		let items = get().await.into_string().await.unwrap();
+		assert_eq!(items.len(), 2);
+
+		client.post("/set_name/x").cookie(cookie.clone()).dispatch().await;
+		rocket::tokio::join!(append("a", true), append("b", true));
+		let mut items: Vec<char> = get().await.into_string().await.unwrap().chars().collect();
+		items.sort();
+		assert_eq!(items, ['a', 'b', 'x'], "the locked updates are serialized");
+	});
+}
+
+#[test]
+fn session_lock_ttl_test() {
+	rocket::async_test(async {
+		let memory = Arc::new(MemoryStore::<String>::new());
+		let ttl = Duration::from_millis(100);
+		let session_store = SessionStore::new(memory.clone(), "token", Duration::from_secs(3600))
+			.with_lock_ttl(ttl);
+		let rocket = rocket::build().attach(session_store.fairing());
+		let client = rocket::local::asynchronous::Client::untracked(rocket)
+			.await
+			.expect("Expected to build client");
+		let request = client.get("/").cookie(Cookie::new("token", "session"));
+		let session = Session::<String>::of(request.inner()).await.unwrap();
+
+		// A holder that never releases the lock.
+		assert!(memory.acquire_lock("session", "crashed", ttl).await.unwrap());
+		assert!(session.lock(Duration::from_millis(20)).await.is_err());
+		let start = std::time::Instant::now();
+		let lock = session.lock(Duration::from_secs(5)).await.unwrap();
+		assert!(start.elapsed() < Duration::from_secs(1));
+		assert!(!memory.acquire_lock("session", "other", ttl).await.unwrap());
+		assert!(!memory.release_lock("session", "crashed").await.unwrap());
+
+		// Dropping the guard releases the lock in the background.
+		drop(lock);
+		rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+		assert!(memory.acquire_lock("session", "other", ttl).await.unwrap());
+		assert!(memory.release_lock("session", "other").await.unwrap());
+	});
+}
+
+#[post("/increment")]
+async fn increment(session: Session<'_, u64>) -> SessionResult<String> {
+	let count = session.update(|count| count.unwrap_or(0) + 1).await?;
+	Ok(count.to_string())
+}
+
+#[test]
+fn session_update_test() {
+	let runtime = rocket::tokio::runtime::Builder::new_multi_thread()
+		.worker_threads(4)
+		.enable_all()
+		.build()
+		.expect("Expected to build runtime");
+	runtime.block_on(async {
+		let session_store: SessionStore<u64> =
+			SessionStore::new(SerializingMemoryStore::new(), "token", Duration::from_secs(3600));
+		let rocket = rocket::build()
+			.attach(session_store.fairing())
+			.mount("/", routes![increment]);
+		let client = rocket::local::asynchronous::Client::untracked(rocket)
+			.await
+			.expect("Expected to build client");
+		let client = Arc::new(client);
+		let res = client.post("/increment").dispatch().await;
+		let cookie = res.cookies().get("token").unwrap().clone();
+		assert_eq!(res.into_string().await, Some("1".into()));
+
+		// Concurrent updates are retried rather than lost.
+		let tasks: Vec<_> = (0..8)
+			.map(|_| {
+				let (client, cookie) = (client.clone(), cookie.clone());
+				rocket::tokio::spawn(async move {
+					for _ in 0..25 {
+						let res = client.post("/increment").cookie(cookie.clone()).dispatch().await;
+						assert_eq!(res.status(), Status::Ok);
+					}
+				})
+			})
+			.collect();
+		for task in tasks {
+			task.await.unwrap();
+		}
+		let res = client.post("/increment").cookie(cookie).dispatch().await;
+		assert_eq!(res.into_string().await, Some("202".into()));
+	});
+
+	// Stores that can't compare values fail.
+	let session_store: SessionStore<u64> =
+		SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+	let rocket = rocket::build()
+		.attach(session_store.fairing())
+		.mount("/", routes![increment]);
+	let client = Client::tracked(rocket).expect("Expected to build client");
+	let res = client.post("/increment").dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+}
+
+#[post("/transaction/<name>?<action>")]
+async fn set_name_transaction(
+	name: &str,
+	action: &str,
+	session: Session<'_, String>,
+) -> SessionResult<String> {
+	let transaction = session.transaction();
+	transaction.regenerate_token();
+	transaction.set(name.into());
+	let staged = transaction.get().await?.unwrap_or_default();
+	let stored = session.get_opt().await?.unwrap_or_default();
+	match action {
+		"commit" => transaction.commit().await?,
+		"rollback" => transaction.rollback(),
+		_ => {}
+	}
+	Ok(format!("{} {}", staged, stored))
+}
+
+#[test]
+fn session_transaction_test() {
+	let store = Arc::new(CollidingStore::new(0));
+	let session_store: SessionStore<String> =
+		SessionStore::new(store.clone(), "token", Duration::from_secs(3600))
+			.with_collision_detection(true);
+	let rocket = example_rocket(session_store).mount("/", routes![set_name_transaction]);
+	let client = Client::tracked(rocket).expect("Expected to build client");
+	client.post("/set_name/Alice").dispatch();
+	let token = |client: &Client| client.cookies().get("token").unwrap().value().to_owned();
+	let alice = token(&client);
+
+	// The writes are buffered until the transaction is committed.
+	let res = client.post("/transaction/Bob?action=commit").dispatch();
+	assert_eq!(res.into_string(), Some("Bob Alice".into()));
+	assert_ne!(token(&client), alice);
+	assert_eq!(client.get("/get_name").dispatch().into_string(), Some("Bob".into()));
+
+	let bob = token(&client);
+	let res = client.post("/transaction/Carol?action=rollback").dispatch();
+	assert_eq!(res.into_string(), Some("Carol Bob".into()));
+	assert_eq!(token(&client), bob);
+	assert_eq!(client.get("/get_name").dispatch().into_string(), Some("Bob".into()));
+
+	// Left pending, the writes are committed by the fairing.
+	let res = client.post("/transaction/Dave?action=none").dispatch();
+	assert_eq!(res.into_string(), Some("Dave Bob".into()));
+	assert_ne!(token(&client), bob);
+	assert_eq!(client.get("/get_name").dispatch().into_string(), Some("Dave".into()));
+	assert_eq!(rocket::execute(store.inner.count()).unwrap(), 1);
+
+	// A failed regeneration puts the previous value back.
+	let dave = token(&client);
+	store.collisions.store(usize::MAX, Ordering::SeqCst);
+	let res = client.post("/transaction/Eve?action=commit").dispatch();
+	assert_eq!(res.status(), Status::InternalServerError);
+	assert_eq!(token(&client), dave);
+	assert_eq!(client.get("/get_name").dispatch().into_string(), Some("Dave".into()));
+}
+
+#[test]
+fn isolated_store_test() {
+	rocket::async_test(async {
+		let shared = Arc::new(MemoryStore::<String>::new());
+		let first = IsolatedMemoryStore::with_isolation_key(shared.clone(), 1);
+		let second = IsolatedMemoryStore::with_isolation_key(shared.clone(), 2);
+		let hour = Duration::from_secs(3600);
+
+		first.set("session", "Alice".into(), hour).await.unwrap();
+		second.set("session", "Bob".into(), hour).await.unwrap();
+		assert_eq!(first.get("session").await.unwrap(), Some("Alice".into()));
+		assert_eq!(second.get("session").await.unwrap(), Some("Bob".into()));
+		assert_eq!(shared.get("session").await.unwrap(), None);
+
+		first.remove("session").await.unwrap();
+		assert_eq!(second.get("session").await.unwrap(), Some("Bob".into()));
+		assert_eq!(first.count().await.unwrap(), 0);
+		assert_eq!(second.list_ids(0, 10).await.unwrap(), vec!["session"]);
+	});
+}
+
+crate::test_store!(
+	chaos,
+	ChaosStore::new(MemoryStore::<String>::new(), ChaosConfig::default())
+);
+
+#[test]
+fn chaos_store_test() {
+	let config = ChaosConfig {
+		error_rate: 0.25,
+		seed: Some(42),
+		..Default::default()
+	};
+	let outcomes = |store: &ChaosStore<MemoryStore<String>>| {
+		rocket::execute(async {
+			let mut outcomes = Vec::new();
+			for i in 0..1000 {
+				let id = format!("session{}", i % 10);
+				let set = store.set(&id, "TestingName".into(), Duration::from_secs(60));
+				outcomes.push(set.await.is_ok());
+			}
+			outcomes
+		})
+	};
+
+	let store = ChaosStore::new(MemoryStore::new(), config.clone());
+	let first = outcomes(&store);
+	let failures = first.iter().filter(|ok| !**ok).count();
+	assert!((200..300).contains(&failures), "{} failures", failures);
+	// The same seed injects the same faults.
+	assert_eq!(outcomes(&ChaosStore::new(MemoryStore::new(), config)), first);
+
+	let config = ChaosConfig {
+		error_rate: 1.0,
+		min_delay: Duration::from_millis(20),
+		max_delay: Duration::from_millis(30),
+		seed: Some(42),
+	};
+	let store = ChaosStore::new(MemoryStore::<String>::new(), config);
+	let start = std::time::Instant::now();
+	assert!(rocket::execute(store.get("session")).is_err());
+	assert!(start.elapsed() >= Duration::from_millis(20));
+
+	// A failing secondary store is tolerated by a replicated store.
+	let replicated = ReplicatedStore::new(MemoryStore::<String>::new(), store)
+		.with_policy(ReplicationPolicy::RequirePrimary);
+	rocket::execute(replicated.set("session", "TestingName".into(), Duration::from_secs(60)))
+		.unwrap();
+	assert_eq!(
+		rocket::execute(replicated.get("session")).unwrap(),
+		Some("TestingName".into())
+	);
+}
+
+crate::test_store!(recording, RecordingStore::new(MemoryStore::<String>::new()));
+
+#[test]
+fn recording_store_replay_test() {
+	let recording = Arc::new(RecordingStore::new(MemoryStore::<String>::new()));
+	let client: Client = {
+		let session_store: SessionStore<String> =
+			SessionStore::new(recording.clone(), "token", Duration::from_secs(3600));
+		Client::tracked(example_rocket(session_store)).expect("Expected to build client")
+	};
+
+	// Alice logs in, reads her session, regenerates it and logs out, while
+	// Bob stays logged in.
+	client.post("/login/alice/Alice").dispatch();
+	client.get("/get_name").dispatch();
+	client.post("/regenerate").dispatch();
+	client.post("/remove_name").dispatch();
+	client.cookies().remove("token");
+	client.post("/login/bob/Bob").dispatch();
+	client.post("/regenerate").dispatch();
+
+	let records = recording.records();
+	assert!(records.iter().all(|record| record.succeeded));
+	assert!(matches!(records[0].op, RecordedOp::Set { .. }));
+	assert!(records
+		.iter()
+		.any(|record| matches!(record.op, RecordedOp::Rename { .. })));
+
+	let json = recording.export_json().unwrap();
+	let replayed = MemoryStore::<String>::new();
+	let count = rocket::execute(import_and_replay(&json, &replayed)).unwrap();
+	assert!(count > 0 && count < records.len());
+
+	let recorded = recording.inner();
+	rocket::execute(async {
+		let ids = recorded.list_ids(0, 10).await.unwrap();
+		assert_eq!(ids.len(), 1);
+		assert_eq!(replayed.list_ids(0, 10).await.unwrap(), ids);
+		assert_eq!(replayed.get(&ids[0]).await.unwrap(), Some("Bob".into()));
+		for user in ["alice", "bob"] {
+			assert_eq!(
+				replayed.list_sessions_for(user).await.unwrap(),
+				recorded.list_sessions_for(user).await.unwrap()
+			);
+		}
+	});
+	assert!(rocket::execute(import_and_replay("not json", &replayed)).is_err());
+}
+
+#[cfg(feature = "macros")]
+#[crate::session_value]
+#[derive(Debug, PartialEq)]
+struct Profile {
+	name: String,
+	roles: Vec<String>,
+}
+
+#[cfg(feature = "macros")]
+#[test]
+fn session_value_test() {
+	fn assert_session_value<T: crate::SessionValueMarker>() {}
+	assert_session_value::<Profile>();
+
+	rocket::async_test(async {
+		let store = CodecStore::new(JsonCodec, MemoryRawStore::new());
+		let profile = Profile {
+			name: "Alice".into(),
+			roles: vec!["admin".into()],
+		};
+		store
+			.set("profile", profile.clone(), Duration::from_secs(60))
+			.await
+			.unwrap();
+		assert_eq!(store.get("profile").await.unwrap(), Some(profile));
+	});
+}
@@ -0,0 +1,99 @@
+//! Helpers to test session stores and applications, enabled with the
+//! `testing` feature.
+//!
+//! [conformance] holds the tests every [Store](crate::Store) is expected to
+//! pass, which implementors of new backends can run against their own store
+//! with [test_store](crate::test_store).
+//!
+//! [IsolatedStore] namespaces the keys of a store shared between tests, so
+//! that they can run in parallel.
+//!
+//! [ChaosStore] injects latency and errors into the operations of a store,
+//! reproducibly when seeded, to test how an application behaves when its
+//! backend degrades.
+//!
+//! [RecordingStore] records the operations made on a store, which can be
+//! exported as JSON and replayed against another store with
+//! [import_and_replay], to reproduce the state a sequence of requests left
+//! behind.
+//!
+//! [seed_session] and [session_cookie] let tests of an application start
+//! with a session holding a known value, without going through the routes
+//! that would set it. The [SessionStore] is managed by the rocket once the
+//! fairing is attached, so it is reached through the client.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rocket::local::blocking::Client;
+//! # use rocket_session_store::{memory::MemoryStore, testing::seed_session, SessionStore};
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600));
+//! let rocket = rocket::build().attach(store.fairing());
+//! let client = Client::untracked(rocket).expect("valid rocket");
+//!
+//! let sessions = client.rocket().state::<SessionStore<String>>().unwrap();
+//! let cookie = rocket::execute(seed_session(sessions, "alice".into()));
+//! let response = client.get("/profile").cookie(cookie).dispatch();
+//! ```
+//!
+//! With an asynchronous client, the seeding future is awaited instead.
+
+use rocket::http::Cookie;
+
+use crate::{
+	SessionID,
+	SessionStore,
+};
+
+mod chaos;
+pub mod conformance;
+mod isolated;
+mod recording;
+
+pub use chaos::{
+	ChaosConfig,
+	ChaosStore,
+};
+pub use isolated::{
+	IsolatedMemoryStore,
+	IsolatedStore,
+	ISOLATION_KEY,
+};
+pub use recording::{
+	import_and_replay,
+	RecordedOp,
+	RecordingStore,
+	StoreRecord,
+};
+
+/// Store a value under a new session and get the cookie referring to it.
+///
+/// The token is generated as the store would for a client, and the value is
+/// kept for the duration of the store. The cookie can be added to the
+/// requests of a blocking or asynchronous local client.
+///
+/// # Panics
+///
+/// Panics if the value can't be stored.
+pub async fn seed_session<T>(store: &SessionStore<T>, value: T) -> Cookie<'static>
+where
+	T: Send + Sync + 'static,
+{
+	let duration = store.duration_of(Some(&value));
+	let token = match store.store.encode_token(Some(&value), duration) {
+		Ok(Some(token)) => SessionID::plain(token),
+		Ok(None) => store.generate_token(),
+		Err(e) => panic!("could not seed the session: {}", e),
+	};
+	if let Err(e) = store.store.set(token.key(), value, duration).await {
+		panic!("could not seed the session: {}", e);
+	}
+	session_cookie(store, token.as_str())
+}
+
+/// Get the cookie sending the given token to the store.
+pub fn session_cookie<T>(store: &SessionStore<T>, token: &str) -> Cookie<'static> {
+	Cookie::new(store.name.clone(), token.to_owned())
+}
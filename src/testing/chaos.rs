@@ -0,0 +1,257 @@
+use std::{
+	sync::Mutex,
+	time::{
+		Duration,
+		SystemTime,
+	},
+};
+
+use rand::{
+	rngs::StdRng,
+	Rng,
+	SeedableRng,
+};
+use rocket::tokio::time::sleep;
+
+use crate::{
+	ExpiredCleanup,
+	SessionError,
+	SessionResult,
+	Store,
+};
+
+/// The faults injected by a [ChaosStore].
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+	/// The probability of an operation failing, from `0.0` to `1.0`.
+	pub error_rate: f64,
+	/// The least latency added to an operation.
+	pub min_delay: Duration,
+	/// The most latency added to an operation.
+	pub max_delay: Duration,
+	/// The seed of the faults, making them reproducible, or [None] to seed
+	/// them randomly.
+	pub seed: Option<u64>,
+}
+
+/// A store injecting latency and errors into the operations of another one,
+/// to test how an application or the wrappers around a store handle a
+/// degraded backend.
+///
+/// Every operation is delayed by a latency drawn uniformly between
+/// [ChaosConfig::min_delay] and [ChaosConfig::max_delay], then fails with
+/// [SessionError] with a probability of [ChaosConfig::error_rate] without
+/// reaching the wrapped store. Failures are delayed too, so a slow backend
+/// eventually failing is part of the mix.
+///
+/// With a [ChaosConfig::seed], the same sequence of operations meets the
+/// same faults, so a failing test can be replayed. Operations running
+/// concurrently draw their faults in the order they start.
+pub struct ChaosStore<S> {
+	inner: S,
+	config: ChaosConfig,
+	rng: Mutex<StdRng>,
+}
+
+impl<S> ChaosStore<S> {
+	/// Wraps a store, injecting the faults of the configuration.
+	///
+	/// # Panics
+	///
+	/// Panics if the error rate isn't between `0.0` and `1.0`, or if the
+	/// minimum delay is above the maximum one.
+	pub fn new(inner: S, config: ChaosConfig) -> Self {
+		assert!(
+			(0.0..=1.0).contains(&config.error_rate),
+			"the error rate must be between 0 and 1"
+		);
+		assert!(
+			config.min_delay <= config.max_delay,
+			"the minimum delay must not be above the maximum delay"
+		);
+		let rng = match config.seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		};
+		Self {
+			inner,
+			config,
+			rng: Mutex::new(rng),
+		}
+	}
+
+	/// Get the wrapped store.
+	pub fn inner(&self) -> &S {
+		&self.inner
+	}
+
+	/// Get the configuration of the injected faults.
+	pub fn config(&self) -> &ChaosConfig {
+		&self.config
+	}
+
+	/// Delays an operation, then fails it or lets it through.
+	async fn chaos(&self, op: &str) -> SessionResult<()> {
+		let (delay, fail) = {
+			let mut rng = self.rng.lock().unwrap();
+			let delay = rng.gen_range(self.config.min_delay..=self.config.max_delay);
+			(delay, rng.gen_bool(self.config.error_rate))
+		};
+		if !delay.is_zero() {
+			sleep(delay).await;
+		}
+		if fail {
+			log::debug!("injected a failure of {} after {:?}", op, delay);
+			return Err(SessionError::StoreUnavailable);
+		}
+		Ok(())
+	}
+}
+
+#[rocket::async_trait]
+impl<S> Store for ChaosStore<S>
+where
+	S: Store + 'static,
+	S::Value: Send,
+{
+	type Value = S::Value;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
+		self.chaos("get").await?;
+		self.inner.get(id).await
+	}
+
+	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()> {
+		self.chaos("set").await?;
+		self.inner.set(id, value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.chaos("touch").await?;
+		self.inner.touch(id, duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.chaos("remove").await?;
+		self.inner.remove(id).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.chaos("contains").await?;
+		self.inner.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.chaos("ttl").await?;
+		self.inner.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Self::Value, Duration)>> {
+		self.chaos("get_with_expiry").await?;
+		self.inner.get_with_expiry(id).await
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.chaos("created_at").await?;
+		self.inner.created_at(id).await
+	}
+
+	fn encode_token(
+		&self,
+		value: Option<&Self::Value>,
+		duration: Duration,
+	) -> SessionResult<Option<String>> {
+		self.inner.encode_token(value, duration)
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.chaos("associate_with").await?;
+		self.inner.associate_with(user_key, id).await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.chaos("list_sessions_for").await?;
+		self.inner.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.chaos("dissociate").await?;
+		self.inner.dissociate(user_key, id).await
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Self::Value>>> {
+		self.chaos("get_many").await?;
+		self.inner.get_many(ids).await
+	}
+
+	async fn replace(
+		&self,
+		id: &str,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<Option<Self::Value>> {
+		self.chaos("replace").await?;
+		self.inner.replace(id, value, duration).await
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		self.chaos("remove_many").await?;
+		self.inner.remove_many(ids).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.chaos("rename").await?;
+		self.inner.rename(old_id, new_id, duration).await
+	}
+
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<Self::Value>,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		self.chaos("compare_and_swap").await?;
+		self.inner
+			.compare_and_swap(id, expected, value, duration)
+			.await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.chaos("acquire_lock").await?;
+		self.inner.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.chaos("release_lock").await?;
+		self.inner.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.chaos("list_ids").await?;
+		self.inner.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.chaos("count").await?;
+		self.inner.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		self.chaos("drain").await?;
+		self.inner.drain().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.chaos("ping").await?;
+		self.inner.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.inner.expired_cleanup()
+	}
+}
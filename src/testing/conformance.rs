@@ -0,0 +1,695 @@
+//! The behaviours expected from every store.
+//!
+//! Each function builds a rocket around the given store, drives it with a
+//! local client and panics when the store misbehaves, so they are meant to be
+//! called from tests. The [test_store](crate::test_store) macro generates a
+//! test for each of them.
+//!
+//! Backends that lack a capability, such as [Store::ttl], can say so in the
+//! [Options], and the tests relying on it then pass without checking
+//! anything. Tests sharing a backend can be kept apart with
+//! [Options::isolated].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use rocket_session_store::memory::MemoryStore as MyStore;
+//! use rocket_session_store::{test_store, testing::conformance::Options};
+//!
+//! test_store!(my_store, MyStore::<String>::new());
+//!
+//! test_store!(
+//!     my_store_without_users,
+//!     MyStore::<String>::new(),
+//!     Options {
+//!         user_sessions: false,
+//!         ..Options::default()
+//!     }
+//! );
+//! # fn main() {}
+//! ```
+
+use std::{
+	thread::sleep,
+	time::Duration,
+};
+
+use rocket::{
+	get,
+	http::{
+		Cookie,
+		SameSite,
+		Status,
+	},
+	local::blocking::Client,
+	post,
+	routes,
+	Route,
+};
+
+use crate::{
+	testing::{
+		seed_session,
+		IsolatedStore,
+	},
+	CookieConfig,
+	Session,
+	SessionResult,
+	SessionStore,
+	Store,
+	NO_EXPIRY,
+};
+
+/// The capabilities of the store under test.
+///
+/// Every capability is assumed by default.
+#[derive(Clone, Debug)]
+pub struct Options {
+	/// Whether the store expires sessions with a precision under a second.
+	///
+	/// When unset, the tests wait an extra second past expiry times.
+	pub sub_second_ttl: bool,
+	/// Whether the store implements [Store::ttl].
+	pub ttl: bool,
+	/// Whether the store implements [Store::associate_with] and
+	/// [Store::list_sessions_for].
+	pub user_sessions: bool,
+	/// Whether to wrap the store in an [IsolatedStore] with a random key.
+	///
+	/// This keeps tests sharing a backend, such as a single redis server,
+	/// from seeing each other's sessions when they run in parallel. Unset by
+	/// default.
+	pub isolated: bool,
+}
+
+impl Default for Options {
+	fn default() -> Self {
+		Self {
+			sub_second_ttl: true,
+			ttl: true,
+			user_sessions: true,
+			isolated: false,
+		}
+	}
+}
+
+impl Options {
+	/// Wraps the store in an [IsolatedStore] when asked to.
+	fn prepare<S>(&self, store: S) -> Box<dyn Store<Value = S::Value>>
+	where
+		S: Store + 'static,
+		S::Value: Send,
+	{
+		match self.isolated {
+			true => Box::new(IsolatedStore::new(store)),
+			false => Box::new(store),
+		}
+	}
+
+	/// Time to wait for a session with the given duration to expire.
+	fn expiry(&self, duration: Duration) -> Duration {
+		match self.sub_second_ttl {
+			true => duration + Duration::from_millis(100),
+			false => duration + Duration::from_secs(1),
+		}
+	}
+}
+
+/// Generates a module with a test for each behaviour of [conformance].
+///
+/// The store expression is evaluated once per test, and the module imports
+/// everything from its parent, so the expression can use the same names as
+/// the code around the macro. [Options] can be given as a third argument.
+///
+/// [conformance]: crate::testing::conformance
+#[macro_export]
+macro_rules! test_store {
+	($name:ident, $store:expr) => {
+		$crate::test_store!(
+			$name,
+			$store,
+			$crate::testing::conformance::Options::default()
+		);
+	};
+	($name:ident, $store:expr, $options:expr) => {
+		mod $name {
+			use $crate::testing::conformance;
+
+			#[allow(unused_imports)]
+			use super::*;
+
+			#[test]
+			fn basic_test() {
+				conformance::basic($store, &$options);
+			}
+
+			#[test]
+			fn expiration_test() {
+				conformance::expiration($store, &$options);
+			}
+
+			#[test]
+			fn remove_test() {
+				conformance::remove($store, &$options);
+			}
+
+			#[test]
+			fn refresh_test() {
+				conformance::refresh($store, &$options);
+			}
+
+			#[test]
+			fn permanent_test() {
+				conformance::permanent($store, &$options);
+			}
+
+			#[test]
+			fn regenerate_test() {
+				conformance::regenerate($store, &$options);
+			}
+
+			#[test]
+			fn user_limit_test() {
+				conformance::user_limit($store, &$options);
+			}
+
+			#[test]
+			fn renew_cookie_test() {
+				conformance::renew_cookie($store, &$options);
+			}
+
+			#[test]
+			fn get_with_expiry_test() {
+				conformance::get_with_expiry($store, &$options);
+			}
+
+			#[test]
+			fn many_test() {
+				conformance::many($store, &$options);
+			}
+
+			#[test]
+			fn replace_test() {
+				conformance::replace($store, &$options);
+			}
+
+			#[test]
+			fn rename_test() {
+				conformance::rename($store, &$options);
+			}
+
+			#[test]
+			fn cookie_test() {
+				conformance::cookie_config($store, &$options);
+			}
+		}
+	};
+}
+
+#[post("/set_name/<name>")]
+async fn set_name(name: String, session: Session<'_, String>) -> SessionResult<()> {
+	session.set(name).await
+}
+
+#[post("/set_permanent/<name>")]
+async fn set_permanent_name(name: String, session: Session<'_, String>) -> SessionResult<()> {
+	session.set_permanent(name).await
+}
+
+#[post("/replace_name/<name>")]
+async fn replace_name(name: String, session: Session<'_, String>) -> SessionResult<Option<String>> {
+	session.replace(name).await
+}
+
+#[get("/get_name")]
+async fn get_name(session: Session<'_, String>) -> SessionResult<Option<String>> {
+	session.get_opt().await
+}
+
+#[post("/remove_name")]
+async fn remove_name(session: Session<'_, String>) -> SessionResult<()> {
+	session.remove().await?;
+	Ok(())
+}
+
+#[post("/refresh")]
+async fn refresh_name(session: Session<'_, String>) -> SessionResult<Option<String>> {
+	let ttl = session.touch().await?;
+	Ok(ttl.map(|ttl| ttl.as_millis().to_string()))
+}
+
+#[post("/regenerate")]
+async fn regenerate_name(session: Session<'_, String>) -> SessionResult<()> {
+	session.regenerate_token().await
+}
+
+#[post("/login/<user>/<name>")]
+async fn login_limited(
+	user: &str,
+	name: String,
+	session: Session<'_, String>,
+) -> SessionResult<()> {
+	session.set_for_user_limited(user, name, 5).await
+}
+
+/// The routes used by the tests, reading and writing a `String` session.
+///
+/// - `POST /set_name/<name>` sets the value.
+/// - `POST /set_permanent/<name>` sets the value without an expiry.
+/// - `POST /replace_name/<name>` sets the value, returning the previous one
+///   or a 404 status without one.
+/// - `GET /get_name` returns the value, or a 404 status without one.
+/// - `POST /remove_name` removes the session.
+/// - `POST /refresh` touches the session, returning its remaining time to
+///   live in milliseconds, or a 404 status without a session.
+/// - `POST /regenerate` regenerates the token.
+/// - `POST /login/<user>/<name>` sets the value for a user, keeping at most
+///   5 sessions per user.
+pub fn routes() -> Vec<Route> {
+	routes![
+		set_name,
+		set_permanent_name,
+		replace_name,
+		get_name,
+		remove_name,
+		refresh_name,
+		regenerate_name,
+		login_limited
+	]
+}
+
+fn client(session_store: SessionStore<String>, tracked: bool) -> Client {
+	let rocket = rocket::build()
+		.attach(session_store.fairing())
+		.mount("/", routes());
+	match tracked {
+		true => Client::tracked(rocket),
+		false => Client::untracked(rocket),
+	}
+	.expect("Expected to build client")
+}
+
+fn seed(client: &Client, value: &str) -> Cookie<'static> {
+	let store = client
+		.rocket()
+		.state::<SessionStore<String>>()
+		.expect("Expected a session store");
+	rocket::execute(seed_session(store, value.into()))
+}
+
+/// A value set in a session can be read back with the cookie.
+pub fn basic(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(3600)),
+		true,
+	);
+
+	assert_eq!(client.cookies().get("token"), None);
+
+	let res1 = client.get("/get_name").dispatch();
+	assert_eq!(res1.status(), Status::NotFound);
+
+	let res2 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert!(client.cookies().get("token").is_some());
+
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+	assert_eq!(res3.into_string(), Some("TestingName".into()))
+}
+
+/// A session is gone once its duration has passed.
+pub fn expiration(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	let duration = Duration::from_secs(1);
+	let client = client(SessionStore::new(store, "token", duration), true);
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let res2 = client.get("/get_name").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	sleep(options.expiry(duration));
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
+}
+
+/// A removed session has no value, and removing it again removes nothing.
+pub fn remove(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	rocket::async_test(async {
+		let hour = Duration::from_secs(3600);
+		store
+			.set("removed", "TestingName".into(), hour)
+			.await
+			.unwrap();
+		assert!(store.contains("removed").await.unwrap());
+		assert!(store.remove("removed").await.unwrap());
+		assert!(!store.contains("removed").await.unwrap());
+		assert!(!store.remove("removed").await.unwrap());
+		assert!(!store.remove("never-set").await.unwrap());
+	});
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(3600)),
+		true,
+	);
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+
+	assert_eq!(res1.status(), Status::Ok);
+	assert!(client.cookies().get("token").is_some());
+
+	let res2 = client.get("/get_name").dispatch();
+
+	assert_eq!(res2.status(), Status::Ok);
+
+	let res3 = client.post("/remove_name").dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+
+	let res4 = client.get("/get_name").dispatch();
+	assert_eq!(res4.status(), Status::NotFound);
+}
+
+/// Touching a session pushes its expiry back.
+pub fn refresh(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(2)),
+		true,
+	);
+
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	sleep(Duration::from_millis(1_500));
+	let res2 = client.post("/refresh").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	if options.ttl {
+		let ttl: u64 = res2.into_string().unwrap().parse().unwrap();
+		assert!(
+			(1_000..=2_000).contains(&ttl),
+			"touching reported {}ms",
+			ttl
+		);
+	}
+	sleep(Duration::from_millis(1_500));
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+
+	client.post("/remove_name").dispatch();
+	let res4 = client.post("/refresh").dispatch();
+	assert_eq!(res4.status(), Status::NotFound);
+}
+
+/// A session stored without an expiry outlives the duration of the store,
+/// even when touched, until it is set again.
+pub fn permanent(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	if options.ttl {
+		rocket::async_test(async {
+			let second = Duration::from_secs(1);
+			store
+				.set("permanent", "TestingName".into(), NO_EXPIRY)
+				.await
+				.unwrap();
+			assert_eq!(store.ttl("permanent").await.unwrap(), Some(NO_EXPIRY));
+			store.touch("permanent", second).await.unwrap();
+			assert_eq!(store.ttl("permanent").await.unwrap(), Some(NO_EXPIRY));
+			assert_eq!(store.ttl("permanent-missing").await.unwrap(), None);
+			store.remove("permanent").await.unwrap();
+		});
+	}
+	let duration = Duration::from_secs(1);
+	let client = client(SessionStore::new(store, "token", duration), true);
+
+	let res1 = client.post("/set_permanent/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	sleep(options.expiry(duration));
+	let res2 = client.post("/refresh").dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	sleep(options.expiry(duration));
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(res3.into_string(), Some("TestingName".into()));
+
+	let res4 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res4.status(), Status::Ok);
+	sleep(options.expiry(duration));
+	let res5 = client.get("/get_name").dispatch();
+	assert_eq!(res5.status(), Status::NotFound);
+}
+
+/// A regenerated token keeps the value, and the old token loses it.
+pub fn regenerate(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(3600)),
+		false,
+	);
+
+	let old_cookie = seed(&client, "TestingName");
+
+	let res1 = client
+		.post("/regenerate")
+		.cookie(old_cookie.clone())
+		.dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let new_cookie = res1.cookies().get("token").unwrap().clone();
+	assert_ne!(old_cookie.value(), new_cookie.value());
+
+	let res2 = client.get("/get_name").cookie(new_cookie).dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert_eq!(res2.into_string(), Some("TestingName".into()));
+
+	let res3 = client.get("/get_name").cookie(old_cookie).dispatch();
+	assert_eq!(res3.status(), Status::NotFound);
+}
+
+/// Limiting the sessions of a user removes the oldest ones.
+///
+/// Skipped without [Options::user_sessions].
+pub fn user_limit(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	if !options.user_sessions {
+		return;
+	}
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(3600)),
+		false,
+	);
+
+	let tokens: Vec<String> = (0..6)
+		.map(|i| {
+			let res = client
+				.post(format!("/login/limited/Device{}", i))
+				.dispatch();
+			assert_eq!(res.status(), Status::Ok);
+			res.cookies().get("token").unwrap().value().to_owned()
+		})
+		.collect();
+
+	let get_name = |token: &String| {
+		client
+			.get("/get_name")
+			.cookie(Cookie::new("token", token.clone()))
+			.dispatch()
+			.status()
+	};
+	assert_eq!(get_name(&tokens[0]), Status::NotFound);
+	for token in &tokens[1..] {
+		assert_eq!(get_name(token), Status::Ok);
+	}
+}
+
+/// With a renewal threshold, the cookie is only re-issued close to expiry.
+///
+/// Skipped without [Options::ttl].
+pub fn renew_cookie(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	if !options.ttl {
+		return;
+	}
+	// Stores rounding the time to live need more time to go under the
+	// threshold.
+	let (secs, wait) = match options.sub_second_ttl {
+		true => (2, Duration::from_millis(1_200)),
+		false => (4, Duration::from_millis(3_200)),
+	};
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(secs))
+			.with_renew_cookie_threshold(0.5),
+		false,
+	);
+
+	// A new token is always issued.
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+	let cookie = res1.cookies().get("token").unwrap().clone();
+	assert_eq!(
+		cookie.max_age(),
+		Some(rocket::time::Duration::seconds(secs as i64))
+	);
+
+	// Far from expiring, the cookie isn't sent again.
+	let res2 = client.get("/get_name").cookie(cookie.clone()).dispatch();
+	assert_eq!(res2.status(), Status::Ok);
+	assert!(res2.cookies().get("token").is_none());
+
+	// Within the threshold, the cookie is re-issued with the remaining time.
+	sleep(wait);
+	let res3 = client.get("/get_name").cookie(cookie.clone()).dispatch();
+	assert_eq!(res3.status(), Status::Ok);
+	let renewed = res3.cookies().get("token").unwrap();
+	assert_eq!(renewed.value(), cookie.value());
+	let max_age = renewed.max_age().unwrap();
+	assert!(max_age <= rocket::time::Duration::seconds(secs as i64 / 2));
+}
+
+/// The value and remaining time to live are read together.
+///
+/// Skipped without [Options::ttl].
+pub fn get_with_expiry(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	if !options.ttl {
+		return;
+	}
+	rocket::async_test(async {
+		let duration = Duration::from_secs(3600);
+		store
+			.set("expiry", "TestingName".into(), duration)
+			.await
+			.unwrap();
+		let (value, ttl) = store.get_with_expiry("expiry").await.unwrap().unwrap();
+		assert_eq!(value, "TestingName");
+		assert!(ttl <= duration && ttl > duration - Duration::from_secs(5));
+		assert!(store.get_with_expiry("missing").await.unwrap().is_none());
+
+		let duration = Duration::from_secs(1);
+		store
+			.set("expiry", "TestingName".into(), duration)
+			.await
+			.unwrap();
+		sleep(options.expiry(duration));
+		assert!(store.get_with_expiry("expiry").await.unwrap().is_none());
+	});
+}
+
+/// Several sessions are read and removed at once, keeping the order of the
+/// ids.
+pub fn many(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	rocket::async_test(async {
+		let hour = Duration::from_secs(3600);
+		let second = Duration::from_secs(1);
+		store.set("many-1", "First".into(), hour).await.unwrap();
+		store.set("many-2", "Second".into(), hour).await.unwrap();
+		store
+			.set("many-expired", "Expired".into(), second)
+			.await
+			.unwrap();
+		sleep(options.expiry(second));
+
+		let ids: Vec<String> = ["many-2", "many-missing", "many-expired", "many-1"]
+			.into_iter()
+			.map(String::from)
+			.collect();
+		let values = store.get_many(&ids).await.unwrap();
+		assert_eq!(
+			values,
+			vec![Some("Second".into()), None, None, Some("First".into())]
+		);
+		assert!(store.get_many(&[]).await.unwrap().is_empty());
+
+		store.remove_many(&ids[..2]).await.unwrap();
+		let values = store.get_many(&ids).await.unwrap();
+		assert_eq!(values, vec![None, None, None, Some("First".into())]);
+	});
+}
+
+/// Replacing a value returns the previous one.
+pub fn replace(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	rocket::async_test(async {
+		let hour = Duration::from_secs(3600);
+		let replaced = store
+			.replace("replace", "First".into(), hour)
+			.await
+			.unwrap();
+		assert_eq!(replaced, None);
+		let replaced = store
+			.replace("replace", "Second".into(), hour)
+			.await
+			.unwrap();
+		assert_eq!(replaced, Some("First".into()));
+		assert_eq!(store.get("replace").await.unwrap(), Some("Second".into()));
+		store.remove("replace").await.unwrap();
+	});
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(3600)),
+		true,
+	);
+
+	let res1 = client.post("/replace_name/First").dispatch();
+	assert_eq!(res1.status(), Status::NotFound);
+	let res2 = client.post("/replace_name/Second").dispatch();
+	assert_eq!(res2.into_string(), Some("First".into()));
+	let res3 = client.get("/get_name").dispatch();
+	assert_eq!(res3.into_string(), Some("Second".into()));
+}
+
+/// Renaming moves the session to the new id, renewing its expiry.
+pub fn rename(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	rocket::async_test(async {
+		let hour = Duration::from_secs(3600);
+		let renamed = store.rename("rename_old", "rename_new", hour).await.unwrap();
+		assert!(!renamed);
+		assert_eq!(store.get("rename_new").await.unwrap(), None);
+
+		store
+			.set("rename_old", "TestingName".into(), Duration::from_secs(60))
+			.await
+			.unwrap();
+		let renamed = store.rename("rename_old", "rename_new", hour).await.unwrap();
+		assert!(renamed);
+		assert_eq!(store.get("rename_old").await.unwrap(), None);
+		assert_eq!(
+			store.get("rename_new").await.unwrap(),
+			Some("TestingName".into())
+		);
+		if options.ttl {
+			let ttl = store.ttl("rename_new").await.unwrap().unwrap();
+			assert!(ttl > Duration::from_secs(60));
+		}
+		store.remove("rename_new").await.unwrap();
+	});
+}
+
+/// The cookie carries the configured options.
+pub fn cookie_config(store: impl Store<Value = String> + 'static, options: &Options) {
+	let store = options.prepare(store);
+	let client = client(
+		SessionStore::new(store, "token", Duration::from_secs(2)).with_cookie(CookieConfig {
+			path: Some("/".into()),
+			same_site: Some(SameSite::Lax),
+			secure: Some(true),
+			http_only: true,
+			partitioned: false,
+		}),
+		true,
+	);
+
+	// make a request to set a cookie
+	let res1 = client.post("/set_name/TestingName").dispatch();
+	assert_eq!(res1.status(), Status::Ok);
+
+	let cookie_jar = client.cookies();
+	let cookie = cookie_jar.get("token");
+
+	assert!(cookie.is_some());
+
+	let cookie = cookie.unwrap();
+
+	assert_eq!(cookie.path(), Some("/"));
+	assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+	assert_eq!(cookie.secure(), Some(true));
+	assert_eq!(cookie.http_only(), Some(true));
+}
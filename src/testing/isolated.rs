@@ -0,0 +1,234 @@
+use std::{
+	sync::Arc,
+	time::{
+		Duration,
+		SystemTime,
+	},
+};
+
+use rand::{
+	rngs::OsRng,
+	Rng,
+};
+
+use crate::{
+	memory::MemoryStore,
+	ExpiredCleanup,
+	SessionResult,
+	Store,
+};
+
+/// The configuration key holding the isolation key of
+/// [IsolatedStore::from_config].
+pub const ISOLATION_KEY: &str = "session_isolation_key";
+
+/// A store namespacing every key with an isolation key, so that tests
+/// sharing a backend don't see each other's sessions.
+///
+/// Tests running in parallel against the same store, such as a shared
+/// [MemoryStore] or a single redis server, can use the same session ids or
+/// user keys. Wrapping the store with a different isolation key for each
+/// test keeps their sessions apart, so they don't have to run one at a
+/// time. The [test_store](crate::test_store) macro does this with
+/// [Options::isolated](crate::testing::conformance::Options::isolated).
+pub struct IsolatedStore<S> {
+	inner: S,
+	prefix: String,
+}
+
+/// A [MemoryStore] shared between tests, isolated for one of them.
+pub type IsolatedMemoryStore<T> = IsolatedStore<Arc<MemoryStore<T>>>;
+
+impl<S> IsolatedStore<S> {
+	/// Wraps a store, namespacing its keys with the given isolation key.
+	pub fn with_isolation_key(inner: S, key: u64) -> Self {
+		Self {
+			inner,
+			prefix: format!("isolated-{}:", key),
+		}
+	}
+
+	/// Wraps a store, namespacing its keys with a random isolation key.
+	pub fn new(inner: S) -> Self {
+		Self::with_isolation_key(inner, OsRng.gen())
+	}
+
+	/// Wraps a store, namespacing its keys with the isolation key of the
+	/// Rocket configuration.
+	///
+	/// The key is read from `session_isolation_key`, which can be set with
+	/// the `ROCKET_SESSION_ISOLATION_KEY` environment variable. A random key
+	/// is used when it isn't set.
+	pub fn from_config(inner: S) -> Self {
+		match rocket::Config::figment().extract_inner::<u64>(ISOLATION_KEY) {
+			Ok(key) => Self::with_isolation_key(inner, key),
+			Err(_) => Self::new(inner),
+		}
+	}
+
+	/// Get the wrapped store.
+	pub fn inner(&self) -> &S {
+		&self.inner
+	}
+
+	fn key(&self, id: &str) -> String {
+		format!("{}{}", self.prefix, id)
+	}
+
+	fn strip<'a>(&self, key: &'a str) -> Option<&'a str> {
+		key.strip_prefix(&self.prefix)
+	}
+
+	/// Get the ids of the sessions of this isolation key, sorted.
+	async fn own_ids(&self) -> SessionResult<Vec<String>>
+	where
+		S: Store,
+	{
+		let ids = self.inner.list_ids(0, usize::MAX).await?;
+		Ok(ids
+			.iter()
+			.filter_map(|id| self.strip(id))
+			.map(str::to_owned)
+			.collect())
+	}
+}
+
+#[rocket::async_trait]
+impl<S> Store for IsolatedStore<S>
+where
+	S: Store + 'static,
+	S::Value: Send,
+{
+	type Value = S::Value;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
+		self.inner.get(&self.key(id)).await
+	}
+
+	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()> {
+		self.inner.set(&self.key(id), value, duration).await
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		self.inner.touch(&self.key(id), duration).await
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		self.inner.remove(&self.key(id)).await
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.inner.contains(&self.key(id)).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner.ttl(&self.key(id)).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Self::Value, Duration)>> {
+		self.inner.get_with_expiry(&self.key(id)).await
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.inner.created_at(&self.key(id)).await
+	}
+
+	fn encode_token(
+		&self,
+		value: Option<&Self::Value>,
+		duration: Duration,
+	) -> SessionResult<Option<String>> {
+		self.inner.encode_token(value, duration)
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner
+			.associate_with(&self.key(user_key), &self.key(id))
+			.await
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		let ids = self.inner.list_sessions_for(&self.key(user_key)).await?;
+		Ok(ids
+			.iter()
+			.filter_map(|id| self.strip(id))
+			.map(str::to_owned)
+			.collect())
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		self.inner
+			.dissociate(&self.key(user_key), &self.key(id))
+			.await
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Self::Value>>> {
+		let keys: Vec<String> = ids.iter().map(|id| self.key(id)).collect();
+		self.inner.get_many(&keys).await
+	}
+
+	async fn replace(
+		&self,
+		id: &str,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<Option<Self::Value>> {
+		self.inner.replace(&self.key(id), value, duration).await
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		let keys: Vec<String> = ids.iter().map(|id| self.key(id)).collect();
+		self.inner.remove_many(&keys).await
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		self.inner
+			.rename(&self.key(old_id), &self.key(new_id), duration)
+			.await
+	}
+
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<Self::Value>,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		self.inner
+			.compare_and_swap(&self.key(id), expected, value, duration)
+			.await
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.inner.acquire_lock(&self.key(id), holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.inner.release_lock(&self.key(id), holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		let ids = self.own_ids().await?;
+		Ok(ids.into_iter().skip(offset).take(limit).collect())
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		Ok(self.own_ids().await?.len())
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		self.inner.drain().await
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.inner.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.inner.expired_cleanup()
+	}
+}
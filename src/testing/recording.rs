@@ -0,0 +1,420 @@
+use std::{
+	sync::Mutex,
+	time::{
+		Duration,
+		SystemTime,
+	},
+};
+
+use rocket::serde::DeserializeOwned;
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use serde_json::Value;
+
+use crate::{
+	ExpiredCleanup,
+	SessionError,
+	SessionResult,
+	Store,
+};
+
+/// An operation recorded by a [RecordingStore].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum RecordedOp {
+	/// A [Store::get] of the session.
+	Get {
+		/// The id of the session.
+		id: String,
+	},
+	/// A [Store::set] of the value, serialized as JSON.
+	Set {
+		/// The id of the session.
+		id: String,
+		/// The value of the session.
+		value: Value,
+		/// The duration of the session.
+		duration: Duration,
+	},
+	/// A [Store::touch] of the session.
+	Touch {
+		/// The id of the session.
+		id: String,
+		/// The duration of the session.
+		duration: Duration,
+	},
+	/// A [Store::remove] of the session.
+	Remove {
+		/// The id of the session.
+		id: String,
+	},
+	/// A [Store::replace] of the value, serialized as JSON.
+	Replace {
+		/// The id of the session.
+		id: String,
+		/// The new value of the session.
+		value: Value,
+		/// The duration of the session.
+		duration: Duration,
+	},
+	/// A [Store::compare_and_swap] of the value, serialized as JSON.
+	CompareAndSwap {
+		/// The id of the session.
+		id: String,
+		/// The new value of the session.
+		value: Value,
+		/// The duration of the session.
+		duration: Duration,
+		/// Whether the value was set.
+		swapped: bool,
+	},
+	/// A [Store::rename] of the session.
+	Rename {
+		/// The id the session was under.
+		old_id: String,
+		/// The id the session was moved to.
+		new_id: String,
+		/// The duration of the session.
+		duration: Duration,
+	},
+	/// A [Store::remove_many] of the sessions.
+	RemoveMany {
+		/// The ids of the sessions.
+		ids: Vec<String>,
+	},
+	/// A [Store::associate_with] of the session with a user.
+	AssociateWith {
+		/// The key of the user.
+		user_key: String,
+		/// The id of the session.
+		id: String,
+	},
+	/// A [Store::dissociate] of the session from a user.
+	Dissociate {
+		/// The key of the user.
+		user_key: String,
+		/// The id of the session.
+		id: String,
+	},
+	/// A [Store::drain] of the store.
+	Drain,
+}
+
+/// An entry of the log of a [RecordingStore].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoreRecord {
+	/// When the operation completed.
+	pub at: SystemTime,
+	/// The operation, along with its arguments.
+	#[serde(flatten)]
+	pub op: RecordedOp,
+	/// Whether the operation succeeded.
+	pub succeeded: bool,
+}
+
+/// A store recording the operations made on another one, so that they can
+/// be inspected or replayed against another store.
+///
+/// Reads through [Store::get] and every write are recorded, in the order
+/// they complete, with values serialized as JSON. Other reads, such as
+/// [Store::ttl] or [Store::list_ids], reach the wrapped store without being
+/// recorded. The log is kept in memory until it is [cleared](Self::clear).
+pub struct RecordingStore<S> {
+	inner: S,
+	log: Mutex<Vec<StoreRecord>>,
+}
+
+impl<S> RecordingStore<S> {
+	/// Wraps a store, recording its operations.
+	pub fn new(inner: S) -> Self {
+		Self {
+			inner,
+			log: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Get the wrapped store.
+	pub fn inner(&self) -> &S {
+		&self.inner
+	}
+
+	/// Get the operations recorded so far.
+	pub fn records(&self) -> Vec<StoreRecord> {
+		self.log.lock().expect("recording log poisoned").clone()
+	}
+
+	/// Forgets the operations recorded so far.
+	pub fn clear(&self) {
+		self.log.lock().expect("recording log poisoned").clear();
+	}
+
+	/// Get the operations recorded so far as a JSON array, to be replayed
+	/// with [import_and_replay].
+	pub fn export_json(&self) -> serde_json::Result<String> {
+		serde_json::to_string(&*self.log.lock().expect("recording log poisoned"))
+	}
+
+	fn record<R>(&self, op: RecordedOp, result: SessionResult<R>) -> SessionResult<R> {
+		let record = StoreRecord {
+			at: SystemTime::now(),
+			op,
+			succeeded: result.is_ok(),
+		};
+		self.log.lock().expect("recording log poisoned").push(record);
+		result
+	}
+}
+
+/// Serializes a value for the log, recording `null` for values which can't
+/// be serialized.
+fn to_json<T: Serialize>(value: &T) -> Value {
+	serde_json::to_value(value).unwrap_or_else(|e| {
+		log::warn!("could not record a session value: {}", e);
+		Value::Null
+	})
+}
+
+/// Replays the operations exported by [RecordingStore::export_json] against
+/// a store.
+///
+/// Only the writes which succeeded when they were recorded are replayed, in
+/// order, with the durations they were recorded with, so sessions expire
+/// relative to the replay. Returns the number of operations replayed, or
+/// fails on the first one failing.
+pub async fn import_and_replay<S>(json: &str, target: &S) -> SessionResult<usize>
+where
+	S: Store + ?Sized,
+	S::Value: DeserializeOwned + Send,
+{
+	let records: Vec<StoreRecord> = serde_json::from_str(json).map_err(|e| {
+		log::warn!("could not import the recorded operations: {}", e);
+		SessionError::Other
+	})?;
+	let value = |value: Value| {
+		serde_json::from_value::<S::Value>(value).map_err(|e| {
+			log::warn!("could not import a recorded session value: {}", e);
+			SessionError::Other
+		})
+	};
+	let mut replayed = 0;
+	for record in records.into_iter().filter(|record| record.succeeded) {
+		match record.op {
+			RecordedOp::Get { .. } => continue,
+			RecordedOp::Set {
+				id,
+				value: v,
+				duration,
+			} => target.set(&id, value(v)?, duration).await?,
+			RecordedOp::Touch { id, duration } => {
+				target.touch(&id, duration).await?;
+			}
+			RecordedOp::Remove { id } => {
+				target.remove(&id).await?;
+			}
+			RecordedOp::Replace {
+				id,
+				value: v,
+				duration,
+			} => {
+				target.replace(&id, value(v)?, duration).await?;
+			}
+			// The swap isn't replayed, only the value it set.
+			RecordedOp::CompareAndSwap { swapped: false, .. } => continue,
+			RecordedOp::CompareAndSwap {
+				id,
+				value: v,
+				duration,
+				..
+			} => target.set(&id, value(v)?, duration).await?,
+			RecordedOp::Rename {
+				old_id,
+				new_id,
+				duration,
+			} => {
+				target.rename(&old_id, &new_id, duration).await?;
+			}
+			RecordedOp::RemoveMany { ids } => target.remove_many(&ids).await?,
+			RecordedOp::AssociateWith { user_key, id } => {
+				target.associate_with(&user_key, &id).await?;
+			}
+			RecordedOp::Dissociate { user_key, id } => target.dissociate(&user_key, &id).await?,
+			RecordedOp::Drain => target.drain().await?,
+		}
+		replayed += 1;
+	}
+	Ok(replayed)
+}
+
+#[rocket::async_trait]
+impl<S> Store for RecordingStore<S>
+where
+	S: Store + 'static,
+	S::Value: Serialize + Send,
+{
+	type Value = S::Value;
+
+	async fn get(&self, id: &str) -> SessionResult<Option<Self::Value>> {
+		let result = self.inner.get(id).await;
+		self.record(RecordedOp::Get { id: id.to_owned() }, result)
+	}
+
+	async fn set(&self, id: &str, value: Self::Value, duration: Duration) -> SessionResult<()> {
+		let op = RecordedOp::Set {
+			id: id.to_owned(),
+			value: to_json(&value),
+			duration,
+		};
+		let result = self.inner.set(id, value, duration).await;
+		self.record(op, result)
+	}
+
+	async fn touch(&self, id: &str, duration: Duration) -> SessionResult<Option<Duration>> {
+		let result = self.inner.touch(id, duration).await;
+		let op = RecordedOp::Touch {
+			id: id.to_owned(),
+			duration,
+		};
+		self.record(op, result)
+	}
+
+	async fn remove(&self, id: &str) -> SessionResult<bool> {
+		let result = self.inner.remove(id).await;
+		self.record(RecordedOp::Remove { id: id.to_owned() }, result)
+	}
+
+	async fn contains(&self, id: &str) -> SessionResult<bool> {
+		self.inner.contains(id).await
+	}
+
+	async fn ttl(&self, id: &str) -> SessionResult<Option<Duration>> {
+		self.inner.ttl(id).await
+	}
+
+	async fn get_with_expiry(&self, id: &str) -> SessionResult<Option<(Self::Value, Duration)>> {
+		self.inner.get_with_expiry(id).await
+	}
+
+	async fn created_at(&self, id: &str) -> SessionResult<Option<SystemTime>> {
+		self.inner.created_at(id).await
+	}
+
+	fn encode_token(
+		&self,
+		value: Option<&Self::Value>,
+		duration: Duration,
+	) -> SessionResult<Option<String>> {
+		self.inner.encode_token(value, duration)
+	}
+
+	async fn associate_with(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let result = self.inner.associate_with(user_key, id).await;
+		let op = RecordedOp::AssociateWith {
+			user_key: user_key.to_owned(),
+			id: id.to_owned(),
+		};
+		self.record(op, result)
+	}
+
+	async fn list_sessions_for(&self, user_key: &str) -> SessionResult<Vec<String>> {
+		self.inner.list_sessions_for(user_key).await
+	}
+
+	async fn dissociate(&self, user_key: &str, id: &str) -> SessionResult<()> {
+		let result = self.inner.dissociate(user_key, id).await;
+		let op = RecordedOp::Dissociate {
+			user_key: user_key.to_owned(),
+			id: id.to_owned(),
+		};
+		self.record(op, result)
+	}
+
+	async fn get_many(&self, ids: &[String]) -> SessionResult<Vec<Option<Self::Value>>> {
+		self.inner.get_many(ids).await
+	}
+
+	async fn replace(
+		&self,
+		id: &str,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<Option<Self::Value>> {
+		let op = RecordedOp::Replace {
+			id: id.to_owned(),
+			value: to_json(&value),
+			duration,
+		};
+		let result = self.inner.replace(id, value, duration).await;
+		self.record(op, result)
+	}
+
+	async fn remove_many(&self, ids: &[String]) -> SessionResult<()> {
+		let result = self.inner.remove_many(ids).await;
+		self.record(RecordedOp::RemoveMany { ids: ids.to_vec() }, result)
+	}
+
+	async fn rename(&self, old_id: &str, new_id: &str, duration: Duration) -> SessionResult<bool> {
+		let result = self.inner.rename(old_id, new_id, duration).await;
+		let op = RecordedOp::Rename {
+			old_id: old_id.to_owned(),
+			new_id: new_id.to_owned(),
+			duration,
+		};
+		self.record(op, result)
+	}
+
+	async fn compare_and_swap(
+		&self,
+		id: &str,
+		expected: Option<Self::Value>,
+		value: Self::Value,
+		duration: Duration,
+	) -> SessionResult<bool> {
+		let value_json = to_json(&value);
+		let result = self
+			.inner
+			.compare_and_swap(id, expected, value, duration)
+			.await;
+		let op = RecordedOp::CompareAndSwap {
+			id: id.to_owned(),
+			value: value_json,
+			duration,
+			swapped: matches!(result, Ok(true)),
+		};
+		self.record(op, result)
+	}
+
+	async fn acquire_lock(&self, id: &str, holder: &str, ttl: Duration) -> SessionResult<bool> {
+		self.inner.acquire_lock(id, holder, ttl).await
+	}
+
+	async fn release_lock(&self, id: &str, holder: &str) -> SessionResult<bool> {
+		self.inner.release_lock(id, holder).await
+	}
+
+	async fn list_ids(&self, offset: usize, limit: usize) -> SessionResult<Vec<String>> {
+		self.inner.list_ids(offset, limit).await
+	}
+
+	async fn count(&self) -> SessionResult<usize> {
+		self.inner.count().await
+	}
+
+	async fn drain(&self) -> SessionResult<()> {
+		let result = self.inner.drain().await;
+		self.record(RecordedOp::Drain, result)
+	}
+
+	fn backend(&self) -> &'static str {
+		self.inner.backend()
+	}
+
+	async fn ping(&self) -> SessionResult<()> {
+		self.inner.ping().await
+	}
+
+	fn expired_cleanup(&self) -> Option<&dyn ExpiredCleanup> {
+		self.inner.expired_cleanup()
+	}
+}
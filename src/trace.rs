@@ -0,0 +1,71 @@
+//! Spans around session operations, emitted with the `tracing` feature.
+//!
+//! Spans are named `session` and carry the operation, the kind of store, a
+//! short digest of the key of the session and, for reads, whether a value
+//! was found. The token itself is never recorded. Without the feature, these
+//! functions only run the operation.
+//!
+//! The memory and redis stores also emit a debug span named after each
+//! operation on a session, with the kind of store and the same digest.
+
+use std::future::Future;
+
+use crate::{
+	SessionResult,
+	SessionState,
+	Store,
+};
+
+#[cfg(feature = "tracing")]
+pub(crate) async fn traced<T, R>(
+	op: &'static str,
+	store: &dyn Store<Value = T>,
+	state: &SessionState,
+	operation: impl Future<Output = SessionResult<R>>,
+) -> SessionResult<R> {
+	use tracing::Instrument;
+
+	let span = tracing::info_span!(
+		"session",
+		session.op = op,
+		session.backend = store.backend(),
+		session.token = %digest(state.token().key()),
+		session.hit = tracing::field::Empty,
+	);
+	let result = operation.instrument(span.clone()).await;
+	if let Err(ref e) = result {
+		span.in_scope(|| tracing::warn!(error = %e, "session operation failed"));
+	}
+	result
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) async fn traced<T, R>(
+	_op: &'static str,
+	_store: &dyn Store<Value = T>,
+	_state: &SessionState,
+	operation: impl Future<Output = SessionResult<R>>,
+) -> SessionResult<R> {
+	operation.await
+}
+
+/// Records whether a value was found in the current session span.
+pub(crate) fn record_hit(_hit: bool) {
+	#[cfg(feature = "tracing")]
+	tracing::Span::current().record("session.hit", _hit);
+}
+
+/// First bytes of a SHA-256 hash of the key, to tell sessions apart in
+/// traces without revealing them.
+#[cfg(feature = "tracing")]
+pub(crate) fn digest(key: &str) -> String {
+	use sha2::{
+		Digest,
+		Sha256,
+	};
+
+	Sha256::digest(key.as_bytes())[..4]
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
@@ -0,0 +1,226 @@
+//! Writes to a session applied together.
+//!
+//! A handler setting the value and regenerating the token could fail half
+//! way, leaving the value written under the old token. A
+//! [SessionTransaction], started with [Session::transaction], buffers its
+//! writes instead, and applies them when it is
+//! [committed](SessionTransaction::commit). A transaction that isn't
+//! committed or [rolled back](SessionTransaction::rollback) by the handler
+//! is committed by the fairing before the response is sent, and failures
+//! are then logged.
+//!
+//! The transactions of a request share their buffer, so a value set by one
+//! is seen by the others. The writes are collapsed into the last value set
+//! or the removal of the session, and whether the token is regenerated, so
+//! a commit reaches the store as few times as possible. When the value is
+//! set and the token regenerated, the value is written under the new token
+//! and the session under the old token removed with
+//! [Store::rename_with](crate::Store::rename_with),
+//! in a single atomic operation for the stores of this crate, so a failed
+//! commit leaves the session as it was. Stores keeping the default
+//! implementation write the value and then remove the old session, so a
+//! failed removal leaves the value under the new token as well, which the
+//! client never receives.
+//!
+//! Writes made through the [Session] itself, rather than the transaction,
+//! go to the store right away, and its reads don't see the buffered writes.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use rocket::post;
+//! # use rocket_session_store::{Session, SessionResult};
+//! #[post("/login/<user>")]
+//! async fn login(user: String, session: Session<'_, String>) -> SessionResult<()> {
+//!     let transaction = session.transaction();
+//!     transaction.regenerate_token();
+//!     transaction.set(user);
+//!     transaction.commit().await
+//! }
+//! ```
+
+use std::{
+	any::Any,
+	fmt,
+};
+
+use rocket::{
+	futures::future::BoxFuture,
+	Request,
+};
+
+use crate::{
+	Session,
+	SessionResult,
+};
+
+/// The writes buffered by the transactions of a request.
+struct Pending<T> {
+	/// The value to store, or [None] to remove the session, if it was
+	/// written at all.
+	value: Option<Option<T>>,
+	/// Whether the token is regenerated.
+	regenerate: bool,
+}
+
+impl<T> Default for Pending<T> {
+	fn default() -> Self {
+		Self {
+			value: None,
+			regenerate: false,
+		}
+	}
+}
+
+/// Commits the pending writes of a request, from the fairing.
+pub(crate) type PendingCommit = for<'a, 'b> fn(&'a Request<'b>) -> BoxFuture<'a, ()>;
+
+/// The buffer of a request along with how to commit it, kept in its
+/// [SessionState](crate::SessionState).
+pub(crate) struct PendingWrites {
+	writes: Box<dyn Any + Send + Sync>,
+	commit: PendingCommit,
+}
+
+impl fmt::Debug for PendingWrites {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PendingWrites").finish_non_exhaustive()
+	}
+}
+
+impl PendingWrites {
+	/// Get the function committing the writes.
+	pub(crate) fn commit(&self) -> PendingCommit {
+		self.commit
+	}
+}
+
+/// Buffered writes to a session, see the [module](self) documentation.
+pub struct SessionTransaction<'a, T: 'static> {
+	session: &'a Session<'a, T>,
+}
+
+impl<'s, T> Session<'s, T>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	/// Starts a transaction buffering writes to the session.
+	///
+	/// See [SessionTransaction].
+	pub fn transaction(&self) -> SessionTransaction<'_, T> {
+		SessionTransaction { session: self }
+	}
+
+	/// Takes the writes buffered by the transactions of the request.
+	fn take_pending(&self) -> Option<Pending<T>> {
+		let pending = self
+			.state
+			.pending
+			.lock()
+			.expect("session state poisoned")
+			.take()?;
+		pending.writes.downcast().ok().map(|writes| *writes)
+	}
+}
+
+impl<T> SessionTransaction<'_, T>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	/// Runs a function on the buffered writes.
+	fn with_pending<R>(&self, f: impl FnOnce(&mut Pending<T>) -> R) -> R {
+		let mut pending = self
+			.session
+			.state
+			.pending
+			.lock()
+			.expect("session state poisoned");
+		let pending = pending.get_or_insert_with(|| PendingWrites {
+			writes: Box::new(Pending::<T>::default()),
+			commit: commit_pending::<T>,
+		});
+		f(pending
+			.writes
+			.downcast_mut()
+			.expect("pending writes of another value type"))
+	}
+
+	/// Get the session value, as it is once the transaction is committed.
+	///
+	/// The value set by the transaction is returned without reaching the
+	/// store, and [None] once the session is removed by the transaction.
+	pub async fn get(&self) -> SessionResult<Option<T>> {
+		if let Some(value) = self.with_pending(|pending| pending.value.clone()) {
+			return Ok(value);
+		}
+		self.session.get_opt().await
+	}
+
+	/// Sets the session value when the transaction is committed.
+	pub fn set(&self, value: T) {
+		self.with_pending(|pending| pending.value = Some(Some(value)));
+	}
+
+	/// Removes the session when the transaction is committed.
+	///
+	/// This drops the regeneration of the token, as the session is gone.
+	pub fn remove(&self) {
+		self.with_pending(|pending| {
+			pending.value = Some(None);
+			pending.regenerate = false;
+		});
+	}
+
+	/// Regenerates the token when the transaction is committed.
+	pub fn regenerate_token(&self) {
+		self.with_pending(|pending| pending.regenerate = true);
+	}
+
+	/// Applies the buffered writes.
+	///
+	/// On failure, the session is left as it was before the commit, see
+	/// the [module](self) documentation, and the writes are dropped.
+	pub async fn commit(self) -> SessionResult<()> {
+		match self.session.take_pending() {
+			Some(pending) => apply(self.session, pending).await,
+			None => Ok(()),
+		}
+	}
+
+	/// Drops the buffered writes.
+	pub fn rollback(self) {
+		self.session.take_pending();
+	}
+}
+
+/// Applies buffered writes to the session.
+async fn apply<T>(session: &Session<'_, T>, pending: Pending<T>) -> SessionResult<()>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	match (pending.value, pending.regenerate) {
+		(None, false) => Ok(()),
+		(None, true) => session.regenerate_token().await,
+		(Some(None), _) => session.remove().await.map(drop),
+		(Some(Some(value)), false) => session.set(value).await,
+		(Some(Some(value)), true) => session.regenerate_token_to(value).await,
+	}
+}
+
+/// Commits the writes left pending by the handler of a request.
+fn commit_pending<'a, T>(request: &'a Request<'_>) -> BoxFuture<'a, ()>
+where
+	T: Send + Sync + Clone + 'static,
+{
+	Box::pin(async move {
+		let Some(session) = Session::<T>::of(request).await else {
+			return;
+		};
+		let Some(pending) = session.take_pending() else {
+			return;
+		};
+		if let Err(e) = apply(&session, pending).await {
+			log::warn!("could not commit the pending session writes: {}", e);
+		}
+	})
+}
@@ -0,0 +1,43 @@
+//! Validation of session values.
+//!
+//! This module provides [SessionValidator], a check that a
+//! [SessionStore](crate::SessionStore) runs on every value read through
+//! [Session::get](crate::Session::get). Sessions whose value fails the check
+//! are removed, and the value is reported as missing. This is useful when the
+//! session refers to data that may change behind its back, such as a user
+//! that was deleted or banned, without repeating the check in every route.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::{sync::Arc, time::Duration};
+//! # use rocket_session_store::{
+//! #     memory::MemoryStore,
+//! #     validator::SessionValidator,
+//! #     SessionStore,
+//! # };
+//! struct NotBanned;
+//!
+//! #[rocket::async_trait]
+//! impl SessionValidator<String> for NotBanned {
+//!     async fn validate(&self, user: &String) -> bool {
+//!         user != "mallory"
+//!     }
+//! }
+//!
+//! let store: SessionStore<String> =
+//!     SessionStore::new(MemoryStore::new(), "token", Duration::from_secs(3600))
+//!         .with_validator(Arc::new(NotBanned));
+//! ```
+
+/// A check run on session values when they are read.
+#[rocket::async_trait]
+pub trait SessionValidator<T>: Send + Sync {
+	/// Whether the value is still acceptable.
+	///
+	/// Returning `false` removes the session, as if the handler had called
+	/// [Session::remove](crate::Session::remove).
+	async fn validate(&self, value: &T) -> bool
+	where
+		T: Sync;
+}
@@ -0,0 +1,66 @@
+//! Checks that redis keys are written into commands without allocating,
+//! with a global allocator counting the allocations of each thread, which
+//! is why it is a test binary of its own.
+#![cfg(feature = "redis")]
+
+use std::{
+	alloc::{
+		GlobalAlloc,
+		Layout,
+		System,
+	},
+	cell::Cell,
+};
+
+use redis::Client;
+use rocket_session_store::redis::RedisRawStore;
+
+/// Counts the allocations made by the current thread.
+struct CountingAlloc;
+
+thread_local! {
+	static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+#[test]
+fn redis_key_allocations_test() {
+	let allocations = |f: &dyn Fn() -> Vec<u8>| {
+		let before = ALLOCATIONS.with(|n| n.get());
+		let packed = f();
+		(packed, ALLOCATIONS.with(|n| n.get()) - before)
+	};
+	let client = Client::open("redis://127.0.0.1/").expect("Couldn't open redis");
+	let store = RedisRawStore::new(client)
+		.prefix("user:".to_owned())
+		.postfix(":id".to_owned());
+	let keys = store.keys();
+	let id = "0123456789abcdefghijklmn";
+
+	// The key is written into the command like a plain string would be.
+	let (packed, with_key) = allocations(&|| {
+		let mut cmd = redis::cmd("GET");
+		cmd.arg(keys.session(id));
+		cmd.get_packed_command()
+	});
+	let (expected, with_str) = allocations(&|| {
+		let mut cmd = redis::cmd("GET");
+		cmd.arg("user:0123456789abcdefghijklmn:id");
+		cmd.get_packed_command()
+	});
+	assert_eq!(packed, expected);
+	assert_eq!(with_key, with_str);
+}